@@ -0,0 +1,58 @@
+//! Procedural macros of `may`, use them through the re-exports of `may`.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::visit_mut::{self, VisitMut};
+use syn::{parse_macro_input, parse_quote, Expr, ExprClosure, Item, ItemFn, Stmt};
+
+// insert the yield check at the start of each loop body
+struct InjectYield;
+
+impl VisitMut for InjectYield {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        visit_mut::visit_expr_mut(self, expr);
+        let check: Stmt = parse_quote!(::may::maybe_yield!(););
+        match expr {
+            Expr::ForLoop(e) => e.body.stmts.insert(0, check),
+            Expr::While(e) => e.body.stmts.insert(0, check),
+            Expr::Loop(e) => e.body.stmts.insert(0, check),
+            _ => {}
+        }
+    }
+
+    // the closures may run out of the coroutine, e.g. in another thread
+    fn visit_expr_closure_mut(&mut self, _closure: &mut ExprClosure) {}
+
+    // the nested items are not part of the function
+    fn visit_item_mut(&mut self, _item: &mut Item) {}
+}
+
+/// Injects a `maybe_yield!()` check into every loop body of the function.
+///
+/// The loops in the closures and the nested items are left untouched.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// #[may::cooperative]
+/// fn checksum(data: &[u8]) -> u32 {
+///     let mut sum = 0u32;
+///     for b in data {
+///         // `may::maybe_yield!();` is inserted here
+///         sum = sum.wrapping_mul(31).wrapping_add(*b as u32);
+///     }
+///     sum
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn cooperative(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return syn::Error::new_spanned(attr, "`cooperative` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let mut func = parse_macro_input!(item as ItemFn);
+    InjectYield.visit_block_mut(&mut func.block);
+    quote!(#func).into()
+}
@@ -0,0 +1,460 @@
+//! modified from crossbeam array queue, a bounded lock-free ring buffer
+//!
+//! all the slots are allocated once at construction, so the memory usage is
+//! predictable and pushing never allocates.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{self, AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use crossbeam::utils::{Backoff, CachePadded};
+
+/// A slot in the ring.
+struct Slot<T> {
+    /// The current stamp.
+    ///
+    /// If the stamp equals the tail, this slot will be next written to. If it
+    /// equals head + 1, this slot will be next read from.
+    stamp: AtomicUsize,
+
+    /// The value.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded multi-producer multi-consumer queue.
+///
+/// The queue allocates a fixed capacity buffer on construction, which is used
+/// to store pushed elements. The queue cannot hold more elements than the
+/// buffer allows, [`push`] returns the element back if the queue is full.
+///
+/// Unlike [`SegQueue`], no memory is allocated or freed after the queue is
+/// created.
+///
+/// # Examples
+///
+/// ```
+/// use may_queue::array_queue::ArrayQueue;
+///
+/// let q = ArrayQueue::new(2);
+///
+/// assert_eq!(q.push('a'), Ok(()));
+/// assert_eq!(q.push('b'), Ok(()));
+/// assert_eq!(q.push('c'), Err('c'));
+/// assert_eq!(q.pop(), Some('a'));
+/// ```
+///
+/// [`push`]: ArrayQueue::push
+/// [`SegQueue`]: super::seg_queue::SegQueue
+pub struct ArrayQueue<T> {
+    /// The head of the queue.
+    ///
+    /// The lower bits represent the index into the buffer, the upper bits
+    /// represent the lap. Elements are popped from the head.
+    head: CachePadded<AtomicUsize>,
+
+    /// The tail of the queue.
+    ///
+    /// The lower bits represent the index into the buffer, the upper bits
+    /// represent the lap. Elements are pushed into the tail.
+    tail: CachePadded<AtomicUsize>,
+
+    /// The buffer holding the slots.
+    buffer: Box<[Slot<T>]>,
+
+    /// The queue capacity.
+    cap: usize,
+
+    /// A stamp with the value of `{ lap: 1, index: 0 }`.
+    one_lap: usize,
+}
+
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Creates a new queue that holds at most `cap` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::<i32>::new(100);
+    /// ```
+    pub fn new(cap: usize) -> ArrayQueue<T> {
+        assert!(cap > 0, "capacity must be greater than 0");
+
+        // slot `i` is first written when the tail is `{ lap: 0, index: i }`
+        let buffer: Box<[Slot<T>]> = (0..cap)
+            .map(|i| Slot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+
+        // one lap is the smallest power of two greater than `cap`
+        let one_lap = (cap + 1).next_power_of_two();
+
+        ArrayQueue {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            buffer,
+            cap,
+            one_lap,
+        }
+    }
+
+    /// Pushes an element into the queue.
+    ///
+    /// If the queue is full, the element is returned back as `Err`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::new(1);
+    ///
+    /// assert_eq!(q.push(10), Ok(()));
+    /// assert_eq!(q.push(20), Err(20));
+    /// ```
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let backoff = Backoff::new();
+        let mut tail = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            // Deconstruct the tail.
+            let index = tail & (self.one_lap - 1);
+            let lap = tail & !(self.one_lap - 1);
+
+            let new_tail = if index + 1 < self.cap {
+                // Same lap, incremented index.
+                tail + 1
+            } else {
+                // One lap forward, index wraps around to zero.
+                lap.wrapping_add(self.one_lap)
+            };
+
+            // Inspect the corresponding slot.
+            let slot = unsafe { self.buffer.get_unchecked(index) };
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            // If the tail and the stamp match, we may attempt to push.
+            if tail == stamp {
+                // Try moving the tail.
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    new_tail,
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // Write the value into the slot and update the stamp.
+                        unsafe { slot.value.get().write(MaybeUninit::new(value)) };
+                        slot.stamp.store(tail + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(t) => {
+                        tail = t;
+                        backoff.spin();
+                    }
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                atomic::fence(Ordering::SeqCst);
+                let head = self.head.load(Ordering::Relaxed);
+
+                // If the head lags one lap behind the tail as well, the queue is full.
+                if head.wrapping_add(self.one_lap) == tail {
+                    return Err(value);
+                }
+
+                backoff.spin();
+                tail = self.tail.load(Ordering::Relaxed);
+            } else {
+                // Snooze because we need to wait for the stamp to update.
+                backoff.snooze();
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Pops an element from the queue.
+    ///
+    /// If the queue is empty, `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::new(1);
+    /// assert_eq!(q.push(10), Ok(()));
+    ///
+    /// assert_eq!(q.pop(), Some(10));
+    /// assert!(q.pop().is_none());
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        let backoff = Backoff::new();
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            // Deconstruct the head.
+            let index = head & (self.one_lap - 1);
+            let lap = head & !(self.one_lap - 1);
+
+            // Inspect the corresponding slot.
+            let slot = unsafe { self.buffer.get_unchecked(index) };
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            // If the stamp is ahead of the head by 1, we may attempt to pop.
+            if head + 1 == stamp {
+                let new = if index + 1 < self.cap {
+                    // Same lap, incremented index.
+                    head + 1
+                } else {
+                    // One lap forward, index wraps around to zero.
+                    lap.wrapping_add(self.one_lap)
+                };
+
+                // Try moving the head.
+                match self.head.compare_exchange_weak(
+                    head,
+                    new,
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // Read the value from the slot and update the stamp.
+                        let msg = unsafe { slot.value.get().read().assume_init() };
+                        slot.stamp
+                            .store(head.wrapping_add(self.one_lap), Ordering::Release);
+                        return Some(msg);
+                    }
+                    Err(h) => {
+                        head = h;
+                        backoff.spin();
+                    }
+                }
+            } else if stamp == head {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.load(Ordering::Relaxed);
+
+                // If the tail equals the head, that means the queue is empty.
+                if tail == head {
+                    return None;
+                }
+
+                backoff.spin();
+                head = self.head.load(Ordering::Relaxed);
+            } else {
+                // Snooze because we need to wait for the stamp to update.
+                backoff.snooze();
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the capacity of the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::<i32>::new(100);
+    ///
+    /// assert_eq!(q.capacity(), 100);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns `true` if the queue is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::new(100);
+    ///
+    /// assert!(q.is_empty());
+    /// q.push(1).unwrap();
+    /// assert!(!q.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        let head = self.head.load(Ordering::SeqCst);
+        let tail = self.tail.load(Ordering::SeqCst);
+
+        // Is the tail lagging one lap behind head?
+        //
+        // Note: If the head changes just before we load the tail, that means there was a moment
+        // when the queue was not empty, so it is safe to just return `false`.
+        tail == head
+    }
+
+    /// Returns `true` if the queue is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::new(1);
+    ///
+    /// assert!(!q.is_full());
+    /// q.push(1).unwrap();
+    /// assert!(q.is_full());
+    /// ```
+    pub fn is_full(&self) -> bool {
+        let tail = self.tail.load(Ordering::SeqCst);
+        let head = self.head.load(Ordering::SeqCst);
+
+        // Is the head lagging one lap behind tail?
+        //
+        // Note: If the tail changes just before we load the head, that means there was a moment
+        // when the queue was not full, so it is safe to just return `false`.
+        head.wrapping_add(self.one_lap) == tail
+    }
+
+    /// Returns the number of elements in the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::array_queue::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::new(100);
+    /// assert_eq!(q.len(), 0);
+    ///
+    /// q.push(10).unwrap();
+    /// assert_eq!(q.len(), 1);
+    ///
+    /// q.push(20).unwrap();
+    /// assert_eq!(q.len(), 2);
+    /// ```
+    pub fn len(&self) -> usize {
+        loop {
+            // Load the tail, then load the head.
+            let tail = self.tail.load(Ordering::SeqCst);
+            let head = self.head.load(Ordering::SeqCst);
+
+            // If the tail didn't change, we've got consistent values to work with.
+            if self.tail.load(Ordering::SeqCst) == tail {
+                let hix = head & (self.one_lap - 1);
+                let tix = tail & (self.one_lap - 1);
+
+                return if hix < tix {
+                    tix - hix
+                } else if hix > tix {
+                    self.cap - hix + tix
+                } else if tail == head {
+                    0
+                } else {
+                    self.cap
+                };
+            }
+        }
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        // Get the index of the head.
+        let head = *self.head.get_mut();
+        let hix = head & (self.one_lap - 1);
+
+        // Loop over all slots that hold a value and drop them.
+        for i in 0..self.len() {
+            // Compute the index of the next slot holding a value.
+            let index = if hix + i < self.cap {
+                hix + i
+            } else {
+                hix + i - self.cap
+            };
+
+            unsafe {
+                let slot = self.buffer.get_unchecked_mut(index);
+                (*slot.value.get()).assume_init_drop();
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for ArrayQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("ArrayQueue { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn array_queue_wrap_around() {
+        let q = ArrayQueue::new(3);
+        for i in 0..10 {
+            q.push(i).unwrap();
+            q.push(i + 100).unwrap();
+            assert_eq!(q.len(), 2);
+            assert_eq!(q.pop(), Some(i));
+            assert_eq!(q.pop(), Some(i + 100));
+            assert!(q.is_empty());
+        }
+        // the left elements are dropped with the queue
+        let q = ArrayQueue::new(2);
+        q.push(Arc::new(0)).unwrap();
+        q.push(Arc::new(1)).unwrap();
+        let v = q.pop().unwrap();
+        q.push(v.clone()).unwrap();
+        drop(q);
+        assert_eq!(Arc::strong_count(&v), 1);
+    }
+
+    #[test]
+    fn array_queue_threads() {
+        let nthreads = 4;
+        let nmsgs = 10000;
+        let q = Arc::new(ArrayQueue::new(8));
+
+        let producers: Vec<_> = (0..nthreads)
+            .map(|_| {
+                let q = q.clone();
+                thread::spawn(move || {
+                    for i in 0..nmsgs {
+                        let mut v = i;
+                        while let Err(e) = q.push(v) {
+                            v = e;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut sum = 0;
+        let mut n = 0;
+        while n < nthreads * nmsgs {
+            match q.pop() {
+                Some(v) => {
+                    sum += v;
+                    n += 1;
+                    assert!(q.len() <= 8);
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producers.into_iter().for_each(|p| p.join().unwrap());
+        assert_eq!(sum, nthreads * nmsgs * (nmsgs - 1) / 2);
+        assert!(q.pop().is_none());
+    }
+}
@@ -1,15 +1,34 @@
+//! the lock free queues of may
+//!
+//! [`seg_queue`], [`array_queue`] and [`split_spsc`] only depend on `core`
+//! and `alloc`, they can be used without `std` by disabling the default
+//! `std` feature, e.g. on embedded targets. the backoff of them only spins
+//! instead of yielding the thread then. the other queues need `std`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![cfg_attr(all(nightly, test), feature(test))]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
 mod block_node;
 
+pub mod array_queue;
+#[cfg(feature = "std")]
 pub mod mpmc_bounded;
+#[cfg(feature = "std")]
 pub mod mpsc_list;
+#[cfg(feature = "std")]
 pub mod mpsc_list_v1;
+pub mod seg_queue;
+pub mod split_spsc;
+#[cfg(feature = "std")]
 pub mod spsc;
+pub mod spsc_seg_queue;
 
+#[cfg(feature = "std")]
 pub use crate::block_node::BLOCK_SIZE;
 
-#[cfg(test)]
+#[cfg(all(nightly, test, feature = "std"))]
 mod test_queue {
     pub trait ScBlockPop<T> {
         fn block_pop(&self) -> T;
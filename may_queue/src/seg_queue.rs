@@ -0,0 +1,1235 @@
+//! modified from crossbeam seg queue to support bulk pop
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use crossbeam::utils::{Backoff, CachePadded};
+use smallvec::SmallVec;
+
+// Bits indicating the state of a slot:
+// * If a value has been written into the slot, `WRITE` is set.
+// * If a value has been read from the slot, `READ` is set.
+// * If the block is being destroyed, `DESTROY` is set.
+const WRITE: usize = 1;
+const READ: usize = 2;
+const DESTROY: usize = 4;
+
+/// The default number of indices a block covers, see [`SegQueue`].
+pub const DEFAULT_LAP: usize = 32;
+// How many lower bits are reserved for metadata.
+const SHIFT: usize = 1;
+// Indicates that the block is not the last one.
+const HAS_NEXT: usize = 1;
+
+/// The watermark notification of the queue.
+struct Watermark {
+    /// The length that triggers the callback.
+    level: usize,
+
+    /// Whether the callback would be triggered, reset after triggered and
+    /// set again when the length drops below the level.
+    armed: AtomicBool,
+
+    /// The callback.
+    callback: Box<dyn Fn() + Send + Sync>,
+}
+
+/// A slot in a block.
+struct Slot<T> {
+    /// The value.
+    value: UnsafeCell<MaybeUninit<T>>,
+
+    /// The state of the slot.
+    state: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits until a value is written into the slot.
+    fn wait_write(&self) {
+        let backoff = Backoff::new();
+        while self.state.load(Ordering::Acquire) & WRITE == 0 {
+            backoff.snooze();
+        }
+    }
+}
+
+/// A block in a linked list.
+///
+/// Each block covers one "lap" of indices and can hold up to `LAP - 1` values, the last slot
+/// is never used. `LAP` must be a power of 2 and at least 2.
+struct Block<T, const LAP: usize> {
+    /// The next block in the linked list.
+    next: AtomicPtr<Block<T, LAP>>,
+
+    /// Slots for values.
+    slots: [Slot<T>; LAP],
+}
+
+impl<T, const LAP: usize> Block<T, LAP> {
+    /// The maximum number of values a block can hold.
+    const CAP: usize = {
+        assert!(
+            LAP >= 2 && LAP.is_power_of_two(),
+            "lap must be a power of 2"
+        );
+        LAP - 1
+    };
+
+    /// Creates an empty block that starts at `start_index`.
+    fn new() -> Block<T, LAP> {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: core::array::from_fn(|_| Slot::new()),
+        }
+    }
+
+    /// Waits until the next pointer is set.
+    fn wait_next(&self) -> *mut Block<T, LAP> {
+        let backoff = Backoff::new();
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Sets the `DESTROY` bit in slots starting from `start` and destroys the block.
+    unsafe fn destroy(this: *mut Block<T, LAP>, start: usize) {
+        // It is not necessary to set the `DESTROY` bit in the last slot because that slot has
+        // begun destruction of the block.
+        for i in start..Self::CAP - 1 {
+            let slot = (*this).slots.get_unchecked(i);
+
+            // Mark the `DESTROY` bit if a thread is still using the slot.
+            if slot.state.load(Ordering::Acquire) & READ == 0
+                && slot.state.fetch_or(DESTROY, Ordering::AcqRel) & READ == 0
+            {
+                // If a thread is still using the slot, it will continue destruction of the block.
+                return;
+            }
+        }
+
+        // No thread is using the block, now it is safe to destroy it.
+        drop(Box::from_raw(this));
+    }
+}
+
+impl<T, const LAP: usize> Block<T, LAP> {
+    fn copy_to_bulk(this: *mut Block<T, LAP>, mut start: usize, end: usize) -> SmallVec<[T; LAP]> {
+        let mut ret = SmallVec::<[T; LAP]>::new();
+        while start < end {
+            // Read the value.
+            let slot = unsafe { (*this).slots.get_unchecked(start) };
+            slot.wait_write();
+            let value = unsafe { slot.value.get().read().assume_init() };
+            ret.push(value);
+            if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                unsafe { Block::destroy(this, start + 1) };
+            }
+            start += 1;
+        }
+        ret
+    }
+}
+
+/// A position in a queue.
+struct Position<T, const LAP: usize> {
+    /// The index in the queue.
+    index: AtomicUsize,
+
+    /// The block in the linked list.
+    block: AtomicPtr<Block<T, LAP>>,
+}
+
+/// A multi-producer multi-consumer queue.
+///
+/// This queue is implemented as a linked list of segments, where each segment is a small buffer
+/// that can hold a handful of elements. There is no limit to how many elements can be in the queue
+/// at a time, unless it's created by [`with_capacity`]. However, since segments need to be
+/// dynamically allocated as elements get pushed, this queue is somewhat slower than
+/// [`ArrayQueue`].
+///
+/// [`with_capacity`]: SegQueue::with_capacity
+/// [`ArrayQueue`]: crate::array_queue::ArrayQueue
+///
+/// # Examples
+///
+/// ```
+/// use may_queue::seg_queue::SegQueue;
+///
+/// let q = SegQueue::new();
+///
+/// q.push('a').unwrap();
+/// q.push('b').unwrap();
+///
+/// assert_eq!(q.pop(), Some('a'));
+/// assert_eq!(q.pop(), Some('b'));
+/// assert!(q.pop().is_none());
+/// ```
+///
+/// # Block size
+///
+/// Each segment covers `LAP` indices and holds `LAP - 1` elements, `LAP` must be a power of 2
+/// and the default is [`DEFAULT_LAP`]. Small blocks waste less memory for the large elements,
+/// while large blocks allocate less often for the tiny elements that are pushed at a high rate.
+///
+/// ```
+/// use may_queue::seg_queue::SegQueue;
+///
+/// let q = SegQueue::<[u8; 4096], 4>::unbounded();
+/// q.push([0; 4096]).unwrap();
+///
+/// let q = SegQueue::<u32, 256>::bounded(1024);
+/// q.push(1).unwrap();
+/// ```
+pub struct SegQueue<T, const LAP: usize = DEFAULT_LAP> {
+    /// The head of the queue.
+    head: CachePadded<Position<T, LAP>>,
+
+    /// The tail of the queue.
+    tail: CachePadded<Position<T, LAP>>,
+
+    /// The max number of elements, `usize::MAX` if unbounded.
+    cap: usize,
+
+    /// The number of the reserved slots, only used when bounded.
+    count: CachePadded<AtomicUsize>,
+
+    /// The optional watermark notification, null if not set.
+    watermark: AtomicPtr<Watermark>,
+
+    /// Indicates that dropping a `SegQueue<T>` may drop values of type `T`.
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send, const LAP: usize> Send for SegQueue<T, LAP> {}
+unsafe impl<T: Send, const LAP: usize> Sync for SegQueue<T, LAP> {}
+
+impl<T> SegQueue<T> {
+    /// Creates a new unbounded queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::<i32>::new();
+    /// ```
+    pub const fn new() -> SegQueue<T> {
+        SegQueue::unbounded()
+    }
+
+    /// Creates a new queue that holds at most `cap` elements.
+    ///
+    /// [`push`] fails when the queue is full, and [`force_push`] overwrites
+    /// the oldest element instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::with_capacity(2);
+    ///
+    /// assert_eq!(q.push(1), Ok(()));
+    /// assert_eq!(q.push(2), Ok(()));
+    /// assert_eq!(q.push(3), Err(3));
+    /// assert_eq!(q.capacity(), Some(2));
+    /// ```
+    ///
+    /// [`push`]: SegQueue::push
+    /// [`force_push`]: SegQueue::force_push
+    pub const fn with_capacity(cap: usize) -> SegQueue<T> {
+        SegQueue::bounded(cap)
+    }
+}
+
+impl<T, const LAP: usize> SegQueue<T, LAP> {
+    /// Creates a new unbounded queue with the block size of `LAP`.
+    pub const fn unbounded() -> SegQueue<T, LAP> {
+        SegQueue {
+            head: CachePadded::new(Position {
+                block: AtomicPtr::new(ptr::null_mut()),
+                index: AtomicUsize::new(0),
+            }),
+            tail: CachePadded::new(Position {
+                block: AtomicPtr::new(ptr::null_mut()),
+                index: AtomicUsize::new(0),
+            }),
+            cap: usize::MAX,
+            count: CachePadded::new(AtomicUsize::new(0)),
+            watermark: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a new queue with the block size of `LAP` that holds at most
+    /// `cap` elements, see [`with_capacity`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is 0.
+    ///
+    /// [`with_capacity`]: SegQueue::with_capacity
+    pub const fn bounded(cap: usize) -> SegQueue<T, LAP> {
+        assert!(cap > 0, "capacity must be greater than 0");
+        let mut q = SegQueue::unbounded();
+        q.cap = cap;
+        q
+    }
+
+    /// Returns the capacity of the queue, `None` if it's unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.is_bounded().then_some(self.cap)
+    }
+
+    /// Returns `true` if the queue is bounded and full.
+    pub fn is_full(&self) -> bool {
+        self.is_bounded() && self.count.load(Ordering::SeqCst) >= self.cap
+    }
+
+    #[inline]
+    fn is_bounded(&self) -> bool {
+        self.cap != usize::MAX
+    }
+
+    // reserve a slot for the bounded queue
+    #[inline]
+    fn reserve(&self) -> bool {
+        if !self.is_bounded() {
+            return true;
+        }
+        let mut count = self.count.load(Ordering::Relaxed);
+        loop {
+            if count >= self.cap {
+                return false;
+            }
+            match self.count.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(c) => count = c,
+            }
+        }
+    }
+
+    // release the slots of the popped elements
+    #[inline]
+    fn release(&self, n: usize) {
+        if self.is_bounded() {
+            self.count.fetch_sub(n, Ordering::SeqCst);
+        }
+    }
+
+    /// Pushes an element into the queue.
+    ///
+    /// If the queue is bounded and full, the element is returned back as
+    /// `Err`. Pushing into an unbounded queue never fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    ///
+    /// q.push(10).unwrap();
+    /// q.push(20).unwrap();
+    /// ```
+    pub fn push(&self, value: T) -> Result<(), T> {
+        if !self.reserve() {
+            return Err(value);
+        }
+        self.push_unchecked(value);
+        self.notify_watermark();
+        Ok(())
+    }
+
+    /// Pushes an element into the queue, the oldest element is popped and
+    /// returned if the queue is bounded and full.
+    ///
+    /// This is useful for the lossy buffers that only keep the latest
+    /// elements, e.g. the telemetry samples.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::with_capacity(2);
+    ///
+    /// assert_eq!(q.force_push(1), None);
+    /// assert_eq!(q.force_push(2), None);
+    /// assert_eq!(q.force_push(3), Some(1));
+    /// assert_eq!(q.pop(), Some(2));
+    /// assert_eq!(q.pop(), Some(3));
+    /// ```
+    pub fn force_push(&self, value: T) -> Option<T> {
+        let backoff = Backoff::new();
+        loop {
+            if self.reserve() {
+                self.push_unchecked(value);
+                self.notify_watermark();
+                return None;
+            }
+            // take over the slot of the oldest element
+            if let Some(old) = self.pop_unchecked() {
+                self.push_unchecked(value);
+                return Some(old);
+            }
+            // the reserved slots are not written yet
+            backoff.snooze();
+        }
+    }
+
+    // push without checking the capacity
+    fn push_unchecked(&self, value: T) {
+        let backoff = Backoff::new();
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+
+        loop {
+            // Calculate the offset of the index into the block.
+            let offset = (tail >> SHIFT) % LAP;
+
+            // If we reached the end of the block, wait until the next one is installed.
+            if offset == Block::<T, LAP>::CAP {
+                backoff.snooze();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // If we're going to have to install the next block, allocate it in advance in order to
+            // make the wait for other threads as short as possible.
+            if offset + 1 == Block::<T, LAP>::CAP && next_block.is_none() {
+                next_block = Some(Box::new(Block::<T, LAP>::new()));
+            }
+
+            // If this is the first push operation, we need to allocate the first block.
+            if block.is_null() {
+                let new = Box::into_raw(Box::new(Block::<T, LAP>::new()));
+
+                if self
+                    .tail
+                    .block
+                    .compare_exchange(block, new, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    self.head.block.store(new, Ordering::Release);
+                    block = new;
+                } else {
+                    next_block = unsafe { Some(Box::from_raw(new)) };
+                    tail = self.tail.index.load(Ordering::Acquire);
+                    block = self.tail.block.load(Ordering::Acquire);
+                    continue;
+                }
+            }
+
+            let new_tail = tail + (1 << SHIFT);
+
+            // Try advancing the tail forward.
+            match self.tail.index.compare_exchange_weak(
+                tail,
+                new_tail,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    // If we've reached the end of the block, install the next one.
+                    if offset + 1 == Block::<T, LAP>::CAP {
+                        let next_block = Box::into_raw(next_block.unwrap());
+                        let next_index = new_tail.wrapping_add(1 << SHIFT);
+
+                        self.tail.block.store(next_block, Ordering::Release);
+                        self.tail.index.store(next_index, Ordering::Release);
+                        (*block).next.store(next_block, Ordering::Release);
+                    }
+
+                    // Write the value into the slot.
+                    let slot = (*block).slots.get_unchecked(offset);
+                    slot.value.get().write(MaybeUninit::new(value));
+                    slot.state.fetch_or(WRITE, Ordering::Release);
+
+                    return;
+                },
+                Err(t) => {
+                    tail = t;
+                    block = self.tail.block.load(Ordering::Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    /// Pops an element from the queue.
+    ///
+    /// If the queue is empty, `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    ///
+    /// q.push(10).unwrap();
+    /// assert_eq!(q.pop(), Some(10));
+    /// assert!(q.pop().is_none());
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        let value = self.pop_unchecked();
+        if value.is_some() {
+            self.release(1);
+            self.rearm_watermark();
+        }
+        value
+    }
+
+    // pop without releasing the slot
+    fn pop_unchecked(&self) -> Option<T> {
+        let backoff = Backoff::new();
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+
+        loop {
+            // Calculate the offset of the index into the block.
+            let offset = (head >> SHIFT) % LAP;
+
+            // If we reached the end of the block, wait until the next one is installed.
+            if offset == Block::<T, LAP>::CAP {
+                backoff.snooze();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            let mut new_head = head + (1 << SHIFT);
+
+            if new_head & HAS_NEXT == 0 {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.index.load(Ordering::Relaxed);
+
+                // If the tail equals the head, that means the queue is empty.
+                if head >> SHIFT == tail >> SHIFT {
+                    return None;
+                }
+
+                // If head and tail are not in the same block, set `HAS_NEXT` in head.
+                if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
+                    new_head |= HAS_NEXT;
+                }
+            }
+
+            // The block can be null here only if the first push operation is in progress. In that
+            // case, just wait until it gets initialized.
+            if block.is_null() {
+                backoff.snooze();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // Try moving the head index forward.
+            match self.head.index.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    // If we've reached the end of the block, move to the next one.
+                    if offset + 1 == Block::<T, LAP>::CAP {
+                        let next = (*block).wait_next();
+                        let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
+                        if !(*next).next.load(Ordering::Relaxed).is_null() {
+                            next_index |= HAS_NEXT;
+                        }
+
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(next_index, Ordering::Release);
+                    }
+
+                    // Read the value.
+                    let slot = (*block).slots.get_unchecked(offset);
+                    slot.wait_write();
+                    let value = slot.value.get().read().assume_init();
+
+                    // Destroy the block if we've reached the end, or if another thread wanted to
+                    // destroy but couldn't because we were busy reading from the slot.
+                    if offset + 1 == Block::<T, LAP>::CAP {
+                        Block::destroy(block, 0);
+                    } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                        Block::destroy(block, offset + 1);
+                    }
+
+                    return Some(value);
+                },
+                Err(h) => {
+                    head = h;
+                    block = self.head.block.load(Ordering::Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    /// Pops a block of elements from the queue.
+    ///
+    /// If the queue is empty, `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    ///
+    /// q.push(10).unwrap();
+    /// q.push(11).unwrap();
+    /// let mut bulk = q.pop_bulk().unwrap();
+    /// assert_eq!(bulk.pop(), Some(11));
+    /// assert_eq!(bulk.pop(), Some(10));
+    /// assert_eq!(bulk.pop(), None);
+    /// assert_eq!(q.pop_bulk(), None);
+    /// q.push(12).unwrap();
+    /// q.push(13).unwrap();
+    /// let mut bulk = q.pop_bulk().unwrap();
+    /// assert_eq!(bulk.pop(), Some(13));
+    /// assert_eq!(bulk.pop(), Some(12));
+    /// assert_eq!(bulk.pop(), None);
+    /// ```
+    pub fn pop_bulk(&self) -> Option<SmallVec<[T; LAP]>> {
+        let backoff = Backoff::new();
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+
+        loop {
+            // Calculate the offset of the index into the block.
+            let offset = (head >> SHIFT) % LAP;
+
+            // If we reached the end of the block, wait until the next one is installed.
+            if offset == Block::<T, LAP>::CAP {
+                backoff.snooze();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            let mut new_head = head + (1 << SHIFT);
+
+            if new_head & HAS_NEXT == 0 {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.index.load(Ordering::Relaxed);
+
+                // If the tail equals the head, that means the queue is empty.
+                if head >> SHIFT == tail >> SHIFT {
+                    return None;
+                }
+
+                // If head and tail are not in the same block, set `HAS_NEXT` in head.
+                if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
+                    new_head = head | (Block::<T, LAP>::CAP << SHIFT) | HAS_NEXT;
+                } else {
+                    // take all the elements in the same block
+                    new_head = tail;
+                }
+            }
+
+            // The block can be null here only if the first push operation is in progress. In that
+            // case, just wait until it gets initialized.
+            if block.is_null() {
+                backoff.snooze();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // Try moving the head index forward.
+            match self.head.index.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    let end = (new_head >> SHIFT) % LAP;
+                    // If we've reached the end of the block, move to the next one.
+                    if end == Block::<T, LAP>::CAP {
+                        let next = (*block).wait_next();
+                        let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
+                        if !(*next).next.load(Ordering::Relaxed).is_null() {
+                            next_index |= HAS_NEXT;
+                        }
+
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(next_index, Ordering::Release);
+                    }
+
+                    let value = Block::copy_to_bulk(block, offset, end);
+                    self.release(value.len());
+                    self.rearm_watermark();
+
+                    // Destroy the block if we've reached the end, or if another thread wanted to
+                    // destroy but couldn't because we were busy reading from the slot.
+                    if end == Block::<T, LAP>::CAP {
+                        Block::destroy(block, 0);
+                    }
+
+                    return Some(value);
+                },
+                Err(h) => {
+                    head = h;
+                    block = self.head.block.load(Ordering::Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    /// Returns a reference to the element at the head of the queue without
+    /// removing it.
+    ///
+    /// If the queue is empty, `None` is returned.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be the only consumer of the queue, and the element
+    /// must not be popped while the returned reference is alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    ///
+    /// assert_eq!(unsafe { q.peek() }, None);
+    /// q.push(10).unwrap();
+    /// q.push(20).unwrap();
+    /// assert_eq!(unsafe { q.peek() }, Some(&10));
+    /// assert_eq!(q.pop(), Some(10));
+    /// assert_eq!(unsafe { q.peek() }, Some(&20));
+    /// ```
+    pub unsafe fn peek(&self) -> Option<&T> {
+        let backoff = Backoff::new();
+        loop {
+            let head = self.head.index.load(Ordering::Acquire);
+            let block = self.head.block.load(Ordering::Acquire);
+
+            // Calculate the offset of the index into the block.
+            let offset = (head >> SHIFT) % LAP;
+
+            // If we reached the end of the block, wait until the next one is installed.
+            if offset == Block::<T, LAP>::CAP {
+                backoff.snooze();
+                continue;
+            }
+
+            atomic::fence(Ordering::SeqCst);
+            let tail = self.tail.index.load(Ordering::Relaxed);
+
+            // If the tail equals the head, that means the queue is empty.
+            if head >> SHIFT == tail >> SHIFT {
+                return None;
+            }
+
+            // The block can be null here only if the first push operation is in progress.
+            if block.is_null() {
+                backoff.snooze();
+                continue;
+            }
+
+            let slot = (*block).slots.get_unchecked(offset);
+            slot.wait_write();
+            return Some(&*(*slot.value.get()).as_ptr());
+        }
+    }
+
+    /// Returns an iterator that walks the elements from head to tail without
+    /// consuming them.
+    ///
+    /// The exclusive borrow guarantees that no one is pushing or popping
+    /// the queue during the iteration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let mut q = SegQueue::new();
+    ///
+    /// for i in 0..100 {
+    ///     q.push(i).unwrap();
+    /// }
+    /// for v in q.iter_mut() {
+    ///     *v *= 2;
+    /// }
+    /// assert_eq!(q.len(), 100);
+    /// assert!(q.iter_mut().map(|v| *v).eq((0..100).map(|i| i * 2)));
+    /// assert_eq!(q.pop(), Some(0));
+    /// assert_eq!(q.pop(), Some(2));
+    /// ```
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, LAP> {
+        // Erase the lower bits.
+        let head = *self.head.index.get_mut() & !((1 << SHIFT) - 1);
+        let tail = *self.tail.index.get_mut() & !((1 << SHIFT) - 1);
+        IterMut {
+            head,
+            tail,
+            block: *self.head.block.get_mut(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the callback that is called when the queue length reaches
+    /// `level`.
+    ///
+    /// The callback is called by the pushing thread once the length reaches
+    /// the level, and it's not called again until the length drops below the
+    /// level by popping. This lets a batch consumer park until enough elements
+    /// are queued instead of polling [`len`]. The callback should be cheap,
+    /// e.g. wake up the consumer.
+    ///
+    /// The watermark can only be set once, returns `false` if it's already
+    /// set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = Arc::new(SegQueue::new());
+    /// let consumer = thread::current();
+    /// assert!(q.set_watermark(8, move || consumer.unpark()));
+    ///
+    /// let q1 = q.clone();
+    /// thread::spawn(move || {
+    ///     for i in 0..8 {
+    ///         q1.push(i).unwrap();
+    ///     }
+    /// });
+    /// // park until a batch is ready
+    /// while q.len() < 8 {
+    ///     thread::park();
+    /// }
+    /// assert_eq!(q.pop_bulk().unwrap().len(), 8);
+    /// ```
+    ///
+    /// [`len`]: SegQueue::len
+    pub fn set_watermark<F>(&self, level: usize, callback: F) -> bool
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(level > 0, "watermark level must be greater than 0");
+        let watermark = Box::into_raw(Box::new(Watermark {
+            level,
+            armed: AtomicBool::new(true),
+            callback: Box::new(callback),
+        }));
+        let ret = self.watermark.compare_exchange(
+            ptr::null_mut(),
+            watermark,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        if ret.is_err() {
+            drop(unsafe { Box::from_raw(watermark) });
+            return false;
+        }
+        // the queue may already reach the level
+        self.notify_watermark();
+        true
+    }
+
+    // the watermark is never changed once set, and freed with the queue
+    #[inline]
+    fn get_watermark(&self) -> Option<&Watermark> {
+        unsafe { self.watermark.load(Ordering::Acquire).as_ref() }
+    }
+
+    // call the watermark callback if the level is reached
+    #[inline]
+    fn notify_watermark(&self) {
+        if let Some(w) = self.get_watermark() {
+            if w.armed.load(Ordering::SeqCst)
+                && self.len() >= w.level
+                && w.armed.swap(false, Ordering::SeqCst)
+            {
+                (w.callback)();
+            }
+        }
+    }
+
+    // re-arm the watermark if the length drops below the level
+    #[inline]
+    fn rearm_watermark(&self) {
+        if let Some(w) = self.get_watermark() {
+            if !w.armed.load(Ordering::SeqCst) && self.len() < w.level {
+                w.armed.store(true, Ordering::SeqCst);
+                // the pushes in between may not see the re-armed watermark
+                self.notify_watermark();
+            }
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    ///
+    /// assert!(q.is_empty());
+    /// q.push(1).unwrap();
+    /// assert!(!q.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        let head = self.head.index.load(Ordering::SeqCst);
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        head >> SHIFT == tail >> SHIFT
+    }
+
+    /// Returns the number of elements in the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may_queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    /// assert_eq!(q.len(), 0);
+    ///
+    /// q.push(10).unwrap();
+    /// assert_eq!(q.len(), 1);
+    ///
+    /// q.push(20).unwrap();
+    /// assert_eq!(q.len(), 2);
+    /// ```
+    pub fn len(&self) -> usize {
+        loop {
+            // Load the tail index, then load the head index.
+            let mut tail = self.tail.index.load(Ordering::SeqCst);
+            let mut head = self.head.index.load(Ordering::SeqCst);
+
+            // If the tail index didn't change, we've got consistent indices to work with.
+            if self.tail.index.load(Ordering::SeqCst) == tail {
+                // Erase the lower bits.
+                tail &= !((1 << SHIFT) - 1);
+                head &= !((1 << SHIFT) - 1);
+
+                // Fix up indices if they fall onto block ends.
+                if (tail >> SHIFT) & (LAP - 1) == LAP - 1 {
+                    tail = tail.wrapping_add(1 << SHIFT);
+                }
+                if (head >> SHIFT) & (LAP - 1) == LAP - 1 {
+                    head = head.wrapping_add(1 << SHIFT);
+                }
+
+                // Rotate indices so that head falls into the first block.
+                let lap = (head >> SHIFT) / LAP;
+                tail = tail.wrapping_sub((lap * LAP) << SHIFT);
+                head = head.wrapping_sub((lap * LAP) << SHIFT);
+
+                // Remove the lower bits.
+                tail >>= SHIFT;
+                head >>= SHIFT;
+
+                // Return the difference minus the number of blocks between tail and head.
+                return tail - head - tail / LAP;
+            }
+        }
+    }
+}
+
+impl<T, const LAP: usize> Drop for SegQueue<T, LAP> {
+    fn drop(&mut self) {
+        let mut head = *self.head.index.get_mut();
+        let mut tail = *self.tail.index.get_mut();
+        let mut block = *self.head.block.get_mut();
+
+        // Erase the lower bits.
+        head &= !((1 << SHIFT) - 1);
+        tail &= !((1 << SHIFT) - 1);
+
+        unsafe {
+            // Drop all values between `head` and `tail` and deallocate the heap-allocated blocks.
+            while head != tail {
+                let offset = (head >> SHIFT) % LAP;
+
+                if offset < Block::<T, LAP>::CAP {
+                    // Drop the value in the slot.
+                    let slot = (*block).slots.get_unchecked(offset);
+                    let p = &mut *slot.value.get();
+                    p.as_mut_ptr().drop_in_place();
+                } else {
+                    // Deallocate the block and move to the next one.
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+
+                head = head.wrapping_add(1 << SHIFT);
+            }
+
+            // Deallocate the last remaining block.
+            if !block.is_null() {
+                drop(Box::from_raw(block));
+            }
+
+            // Free the watermark if it's set.
+            let watermark = *self.watermark.get_mut();
+            if !watermark.is_null() {
+                drop(Box::from_raw(watermark));
+            }
+        }
+    }
+}
+
+impl<T, const LAP: usize> fmt::Debug for SegQueue<T, LAP> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("SegQueue { .. }")
+    }
+}
+
+impl<T, const LAP: usize> Default for SegQueue<T, LAP> {
+    fn default() -> SegQueue<T, LAP> {
+        SegQueue::unbounded()
+    }
+}
+
+impl<T, const LAP: usize> IntoIterator for SegQueue<T, LAP> {
+    type Item = T;
+
+    type IntoIter = IntoIter<T, LAP>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { value: self }
+    }
+}
+
+#[derive(Debug)]
+pub struct IntoIter<T, const LAP: usize = DEFAULT_LAP> {
+    value: SegQueue<T, LAP>,
+}
+
+impl<T, const LAP: usize> Iterator for IntoIter<T, LAP> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = &mut self.value;
+        let head = *value.head.index.get_mut();
+        let tail = *value.tail.index.get_mut();
+        if head >> SHIFT == tail >> SHIFT {
+            None
+        } else {
+            let block = *value.head.block.get_mut();
+            let offset = (head >> SHIFT) % LAP;
+
+            // SAFETY: We have mutable access to this, so we can read without
+            // worrying about concurrency. Furthermore, we know this is
+            // initialized because it is the value pointed at by `value.head`
+            // and this is a non-empty queue.
+            let item = unsafe {
+                let slot = (*block).slots.get_unchecked(offset);
+                let p = &mut *slot.value.get();
+                p.as_mut_ptr().read()
+            };
+            if offset + 1 == Block::<T, LAP>::CAP {
+                // Deallocate the block and move to the next one.
+                // SAFETY: The block is initialized because we've been reading
+                // from it this entire time. We can drop it b/c everything has
+                // been read out of it, so nothing is pointing to it anymore.
+                unsafe {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    *value.head.block.get_mut() = next;
+                }
+                // The last value in a block is empty, so skip it
+                *value.head.index.get_mut() = head.wrapping_add(2 << SHIFT);
+                // Double-check that we're pointing to the first item in a block.
+                debug_assert_eq!((*value.head.index.get_mut() >> SHIFT) % LAP, 0);
+            } else {
+                *value.head.index.get_mut() = head.wrapping_add(1 << SHIFT);
+            }
+            Some(item)
+        }
+    }
+}
+
+/// A mutable iterator over the elements of a `SegQueue`.
+///
+/// This is created by [`SegQueue::iter_mut`].
+#[derive(Debug)]
+pub struct IterMut<'a, T, const LAP: usize = DEFAULT_LAP> {
+    head: usize,
+    tail: usize,
+    block: *mut Block<T, LAP>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T, const LAP: usize> Iterator for IterMut<'a, T, LAP> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.head != self.tail {
+            let offset = (self.head >> SHIFT) % LAP;
+            self.head = self.head.wrapping_add(1 << SHIFT);
+
+            // SAFETY: the queue is exclusively borrowed, so all the slots
+            // between head and tail are initialized and no one would touch them
+            unsafe {
+                if offset < Block::<T, LAP>::CAP {
+                    let slot = (*self.block).slots.get_unchecked(offset);
+                    return Some(&mut *(*slot.value.get()).as_mut_ptr());
+                }
+                // The last index in a block is empty, move to the next block.
+                self.block = (*self.block).next.load(Ordering::Relaxed);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn bounded_pop_bulk_release() {
+        let q = SegQueue::with_capacity(40);
+        for i in 0..40 {
+            q.push(i).unwrap();
+        }
+        assert!(q.is_full());
+        assert_eq!(q.push(40), Err(40));
+        assert_eq!(q.pop_bulk().unwrap().len(), DEFAULT_LAP - 1);
+        for i in 40..40 + DEFAULT_LAP - 1 {
+            q.push(i).unwrap();
+        }
+        assert!(q.is_full());
+        assert_eq!(q.len(), 40);
+    }
+
+    #[test]
+    fn block_size() {
+        fn check<const LAP: usize>() {
+            let mut q = SegQueue::<usize, LAP>::unbounded();
+            for i in 0..100 {
+                q.push(i).unwrap();
+            }
+            assert_eq!(q.len(), 100);
+            // a bulk is the rest of the head block
+            let n = (LAP - 1).min(100);
+            assert_eq!(q.pop_bulk().unwrap().len(), n);
+            assert_eq!(q.iter_mut().count(), 100 - n);
+            let rest: Vec<_> = q.into_iter().collect();
+            assert_eq!(rest, (n..100).collect::<Vec<_>>());
+
+            let q = Arc::new(SegQueue::<usize, LAP>::bounded(64));
+            let producers: Vec<_> = (0..4)
+                .map(|_| {
+                    let q = q.clone();
+                    thread::spawn(move || {
+                        for i in 0..1000 {
+                            while q.push(i).is_err() {
+                                thread::yield_now();
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let mut popped = 0;
+            while popped < 4000 {
+                popped += q.pop_bulk().map_or(0, |b| b.len());
+            }
+            producers.into_iter().for_each(|p| p.join().unwrap());
+            assert!(q.is_empty());
+        }
+        check::<2>();
+        check::<4>();
+        check::<256>();
+    }
+
+    #[test]
+    fn watermark_rearm() {
+        let q = SegQueue::new();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let h = hits.clone();
+        assert!(q.set_watermark(3, move || {
+            h.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert!(!q.set_watermark(1, || {}));
+
+        for i in 0..5 {
+            q.push(i).unwrap();
+        }
+        // only triggered once when the level is reached
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        q.pop().unwrap();
+        q.pop().unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        // drops below the level and reaches it again
+        q.pop().unwrap();
+        q.push(5).unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn bounded_force_push_threads() {
+        let nthreads = 4;
+        let nmsgs = 10000;
+        let q = Arc::new(SegQueue::with_capacity(16));
+
+        let producers: Vec<_> = (0..nthreads)
+            .map(|_| {
+                let q = q.clone();
+                thread::spawn(move || (0..nmsgs).filter(|i| q.force_push(*i).is_some()).count())
+            })
+            .collect();
+
+        let mut popped = 0;
+        for _ in 0..nmsgs {
+            if q.pop().is_some() {
+                popped += 1;
+            }
+            assert!(q.len() <= 16);
+        }
+
+        let evicted: usize = producers.into_iter().map(|p| p.join().unwrap()).sum();
+        assert_eq!(popped + evicted + q.len(), nthreads * nmsgs);
+        assert!(q.len() <= 16);
+    }
+}
@@ -0,0 +1,185 @@
+//! the single-producer single-consumer queue with split handles
+//!
+//! the underlying segment queue doesn't synchronize the producers with each
+//! other, nor the consumers. [`Spsc::split`] gives out exactly one handle for
+//! each side, the handles can be sent to another thread but can't be shared,
+//! so the contract is checked by the compiler.
+
+use core::cell::Cell;
+use core::fmt;
+use core::marker::PhantomData;
+
+use alloc::sync::Arc;
+
+use crate::spsc_seg_queue::SegQueue;
+
+// makes the handles `Send` but `!Sync`
+type NotSync = PhantomData<Cell<()>>;
+
+/// An unbounded single-producer single-consumer queue.
+///
+/// The queue is used through the handles returned by [`split`].
+///
+/// [`split`]: Spsc::split
+///
+/// # Examples
+///
+/// ```rust
+/// use may_queue::split_spsc::Spsc;
+///
+/// let (tx, rx) = Spsc::new().split();
+/// let h = std::thread::spawn(move || {
+///     for i in 0..100 {
+///         tx.push(i);
+///     }
+/// });
+/// h.join().unwrap();
+///
+/// assert_eq!(rx.len(), 100);
+/// assert_eq!(rx.pop(), Some(0));
+/// ```
+///
+/// The handles can't be shared between threads:
+///
+/// ```compile_fail
+/// use may_queue::split_spsc::Spsc;
+///
+/// let (tx, _rx) = Spsc::<i32>::new().split();
+/// let tx = std::sync::Arc::new(tx);
+/// std::thread::spawn(move || tx.push(1));
+/// ```
+pub struct Spsc<T> {
+    queue: SegQueue<T>,
+}
+
+/// The producer handle of a [`Spsc`] queue.
+pub struct SpscProducer<T> {
+    queue: Arc<SegQueue<T>>,
+    _marker: NotSync,
+}
+
+/// The consumer handle of a [`Spsc`] queue.
+pub struct SpscConsumer<T> {
+    queue: Arc<SegQueue<T>>,
+    _marker: NotSync,
+}
+
+impl<T> Spsc<T> {
+    /// Creates a new empty queue.
+    pub const fn new() -> Self {
+        Spsc {
+            queue: SegQueue::new(),
+        }
+    }
+
+    /// Splits the queue into the producer and the consumer handles.
+    pub fn split(self) -> (SpscProducer<T>, SpscConsumer<T>) {
+        let queue = Arc::new(self.queue);
+        let producer = SpscProducer {
+            queue: queue.clone(),
+            _marker: PhantomData,
+        };
+        let consumer = SpscConsumer {
+            queue,
+            _marker: PhantomData,
+        };
+        (producer, consumer)
+    }
+}
+
+impl<T> Default for Spsc<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SpscProducer<T> {
+    /// Pushes an element into the queue.
+    pub fn push(&self, value: T) {
+        self.queue.push(value)
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns `true` if the consumer is dropped.
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.queue) == 1
+    }
+}
+
+impl<T> SpscConsumer<T> {
+    /// Pops an element from the queue, `None` if it's empty.
+    pub fn pop(&self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns `true` if the producer is dropped, the elements that are
+    /// already pushed can still be popped.
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.queue) == 1
+    }
+}
+
+impl<T> fmt::Debug for Spsc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Spsc { .. }")
+    }
+}
+
+impl<T> fmt::Debug for SpscProducer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("SpscProducer { .. }")
+    }
+}
+
+impl<T> fmt::Debug for SpscConsumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("SpscConsumer { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spsc_handles() {
+        let (tx, rx) = Spsc::new().split();
+        let h = std::thread::spawn(move || {
+            for i in 0..1000 {
+                tx.push(i);
+            }
+            tx
+        });
+        let mut next = 0;
+        while next < 1000 {
+            if let Some(v) = rx.pop() {
+                assert_eq!(v, next);
+                next += 1;
+            }
+        }
+        let tx = h.join().unwrap();
+        assert!(rx.is_empty());
+        assert!(!tx.is_closed());
+        drop(rx);
+        assert!(tx.is_closed());
+    }
+}
@@ -5,6 +5,7 @@ use core::mem::MaybeUninit;
 use core::ptr;
 use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
+use alloc::boxed::Box;
 use crossbeam::utils::{Backoff, CachePadded};
 
 // Bits indicating the state of a slot:
@@ -13,10 +14,8 @@ use crossbeam::utils::{Backoff, CachePadded};
 // * If the block is being destroyed, `DESTROY` is set.
 const WRITE: usize = 1;
 
-// Each block covers one "lap" of indices.
-const LAP: usize = 32;
-// The maximum number of values a block can hold.
-const BLOCK_CAP: usize = LAP - 1;
+/// The default number of indices a block covers, see [`SegQueue`].
+pub const DEFAULT_LAP: usize = 32;
 // How many lower bits are reserved for metadata.
 const SHIFT: usize = 1;
 // Indicates that the block is not the last one.
@@ -32,10 +31,12 @@ struct Slot<T> {
 }
 
 impl<T> Slot<T> {
-    const UNINIT: Self = Self {
-        value: UnsafeCell::new(MaybeUninit::uninit()),
-        state: AtomicUsize::new(0),
-    };
+    fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicUsize::new(0),
+        }
+    }
 
     /// Waits until a value is written into the slot.
     fn wait_write(&self) {
@@ -48,26 +49,36 @@ impl<T> Slot<T> {
 
 /// A block in a linked list.
 ///
-/// Each block in the list can hold up to `BLOCK_CAP` values.
-struct Block<T> {
+/// Each block covers one "lap" of indices and can hold up to `LAP - 1` values, the last slot
+/// is never used. `LAP` must be a power of 2 and at least 2.
+struct Block<T, const LAP: usize> {
     /// The next block in the linked list.
-    next: AtomicPtr<Block<T>>,
+    next: AtomicPtr<Block<T, LAP>>,
 
     /// Slots for values.
-    slots: [Slot<T>; BLOCK_CAP],
+    slots: [Slot<T>; LAP],
 }
 
-impl<T> Block<T> {
+impl<T, const LAP: usize> Block<T, LAP> {
+    /// The maximum number of values a block can hold.
+    const CAP: usize = {
+        assert!(
+            LAP >= 2 && LAP.is_power_of_two(),
+            "lap must be a power of 2"
+        );
+        LAP - 1
+    };
+
     /// Creates an empty block that starts at `start_index`.
-    fn new() -> Block<T> {
+    fn new() -> Block<T, LAP> {
         Self {
             next: AtomicPtr::new(ptr::null_mut()),
-            slots: [Slot::UNINIT; BLOCK_CAP],
+            slots: core::array::from_fn(|_| Slot::new()),
         }
     }
 
     /// Waits until the next pointer is set.
-    fn wait_next(&self) -> *mut Block<T> {
+    fn wait_next(&self) -> *mut Block<T, LAP> {
         let backoff = Backoff::new();
         loop {
             let next = self.next.load(Ordering::Acquire);
@@ -79,95 +90,74 @@ impl<T> Block<T> {
     }
 
     /// Sets the `DESTROY` bit in slots starting from `start` and destroys the block.
-    unsafe fn destroy(this: *mut Block<T>) {
+    unsafe fn destroy(this: *mut Block<T, LAP>) {
         // No thread is using the block, now it is safe to destroy it.
         drop(Box::from_raw(this));
     }
 }
 
 /// A position in a queue.
-struct Position<T> {
+struct Position<T, const LAP: usize> {
     /// The index in the queue.
     index: AtomicUsize,
 
     /// The block in the linked list.
-    block: AtomicPtr<Block<T>>,
+    block: AtomicPtr<Block<T, LAP>>,
 }
 
-impl<T> Position<T> {
+impl<T, const LAP: usize> Position<T, LAP> {
+    // the index and the block are only changed by the owner side, so the
+    // relaxed ordering is enough for its own accesses
     fn load_index(&self) -> usize {
-        #[allow(clippy::cast_ref_to_mut)]
-        let index = unsafe { &mut *(&self.index as *const _ as *mut AtomicUsize) };
-        *index.get_mut()
+        self.index.load(Ordering::Relaxed)
     }
 
     fn set_index(&self, index: usize) {
-        #[allow(clippy::cast_ref_to_mut)]
-        let idx = unsafe { &mut *(&self.index as *const _ as *mut AtomicUsize) };
-        *idx.get_mut() = index;
+        self.index.store(index, Ordering::Relaxed);
     }
 
-    fn load_block(&self) -> *mut Block<T> {
-        #[allow(clippy::cast_ref_to_mut)]
-        let block = unsafe { &mut *(&self.block as *const _ as *mut AtomicPtr<Block<T>>) };
-        *block.get_mut()
+    fn load_block(&self) -> *mut Block<T, LAP> {
+        self.block.load(Ordering::Relaxed)
     }
 
-    fn set_block(&self, block: *mut Block<T>) {
-        #[allow(clippy::cast_ref_to_mut)]
-        let blk = unsafe { &mut *(&self.block as *const _ as *mut AtomicPtr<Block<T>>) };
-        *blk.get_mut() = block;
+    fn set_block(&self, block: *mut Block<T, LAP>) {
+        self.block.store(block, Ordering::Relaxed);
     }
 }
 
-/// An unbounded multi-producer multi-consumer queue.
+/// An unbounded single-producer single-consumer queue.
 ///
 /// This queue is implemented as a linked list of segments, where each segment is a small buffer
-/// that can hold a handful of elements. There is no limit to how many elements can be in the queue
-/// at a time. However, since segments need to be dynamically allocated as elements get pushed,
-/// this queue is somewhat slower than [`ArrayQueue`].
-///
-/// [`ArrayQueue`]: super::ArrayQueue
-///
-/// # Examples
-///
-/// ```
-/// use may::sync::queue::spsc_seg_queue::SegQueue;
+/// that can hold a handful of elements. The indices are not updated atomically, so `push` must
+/// only be called by one thread at a time, and so is `pop`. The public [`Spsc`] enforces this
+/// with its handles.
 ///
-/// let q = SegQueue::new();
-///
-/// q.push('a');
-/// q.push('b');
-///
-/// assert_eq!(q.pop(), Some('a'));
-/// assert_eq!(q.pop(), Some('b'));
-/// assert!(q.pop().is_none());
-/// ```
-pub struct SegQueue<T> {
+/// [`Spsc`]: crate::split_spsc::Spsc
+pub struct SegQueue<T, const LAP: usize = DEFAULT_LAP> {
     /// The head of the queue.
-    head: CachePadded<Position<T>>,
+    head: CachePadded<Position<T, LAP>>,
 
     /// The tail of the queue.
-    tail: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T, LAP>>,
 
     /// Indicates that dropping a `SegQueue<T>` may drop values of type `T`.
     _marker: PhantomData<T>,
 }
 
-unsafe impl<T: Send> Send for SegQueue<T> {}
-unsafe impl<T: Send> Sync for SegQueue<T> {}
+unsafe impl<T: Send, const LAP: usize> Send for SegQueue<T, LAP> {}
+unsafe impl<T: Send, const LAP: usize> Sync for SegQueue<T, LAP> {}
 
 impl<T> SegQueue<T> {
     /// Creates a new unbounded queue.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use may::sync::queue::spsc_seg_queue::SegQueue;
-    ///
-    /// let q = SegQueue::<i32>::new();
-    /// ```
     pub const fn new() -> SegQueue<T> {
+        SegQueue::unbounded()
+    }
+}
+
+impl<T, const LAP: usize> SegQueue<T, LAP> {
+    /// Creates a new unbounded queue with the block size of `LAP`, which must
+    /// be a power of 2, the default is [`DEFAULT_LAP`].
+    pub const fn unbounded() -> SegQueue<T, LAP> {
         SegQueue {
             head: CachePadded::new(Position {
                 block: AtomicPtr::new(ptr::null_mut()),
@@ -182,17 +172,6 @@ impl<T> SegQueue<T> {
     }
 
     /// Pushes an element into the queue.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use may::sync::queue::spsc_seg_queue::SegQueue;
-    ///
-    /// let q = SegQueue::new();
-    ///
-    /// q.push(10);
-    /// q.push(20);
-    /// ```
     pub fn push(&self, value: T) {
         // let backoff = Backoff::new();
         let tail = self.tail.load_index();
@@ -205,13 +184,13 @@ impl<T> SegQueue<T> {
 
         // If we're going to have to install the next block, allocate it in advance in order to
         // make the wait for other threads as short as possible.
-        if offset + 1 == BLOCK_CAP && next_block.is_none() {
-            next_block = Some(Box::new(Block::<T>::new()));
+        if offset + 1 == Block::<T, LAP>::CAP && next_block.is_none() {
+            next_block = Some(Box::new(Block::<T, LAP>::new()));
         }
 
         // If this is the first push operation, we need to allocate the first block.
         if block.is_null() {
-            let new = Box::into_raw(Box::new(Block::<T>::new()));
+            let new = Box::into_raw(Box::new(Block::<T, LAP>::new()));
             self.tail.set_block(new);
             self.head.block.store(new, Ordering::Release);
             block = new;
@@ -221,7 +200,7 @@ impl<T> SegQueue<T> {
         self.tail.index.store(new_tail, Ordering::Release);
 
         // If we've reached the end of the block, install the next one.
-        if offset + 1 == BLOCK_CAP {
+        if offset + 1 == Block::<T, LAP>::CAP {
             let next_block = Box::into_raw(next_block.unwrap());
             let next_index = new_tail.wrapping_add(1 << SHIFT);
 
@@ -241,18 +220,6 @@ impl<T> SegQueue<T> {
     /// Pops an element from the queue.
     ///
     /// If the queue is empty, `None` is returned.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use may::sync::queue::spsc_seg_queue::SegQueue;
-    ///
-    /// let q = SegQueue::new();
-    ///
-    /// q.push(10);
-    /// assert_eq!(q.pop(), Some(10));
-    /// assert!(q.pop().is_none());
-    /// ```
     pub fn pop(&self) -> Option<T> {
         let backoff = Backoff::new();
         let mut head = self.head.load_index();
@@ -292,7 +259,7 @@ impl<T> SegQueue<T> {
 
             unsafe {
                 // If we've reached the end of the block, move to the next one.
-                if offset + 1 == BLOCK_CAP {
+                if offset + 1 == Block::<T, LAP>::CAP {
                     let next = (*block).wait_next();
                     let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
                     if !(*next).next.load(Ordering::Relaxed).is_null() {
@@ -310,7 +277,7 @@ impl<T> SegQueue<T> {
 
                 // Destroy the block if we've reached the end, or if another thread wanted to
                 // destroy but couldn't because we were busy reading from the slot.
-                if offset + 1 == BLOCK_CAP {
+                if offset + 1 == Block::<T, LAP>::CAP {
                     Block::destroy(block);
                 }
 
@@ -320,18 +287,6 @@ impl<T> SegQueue<T> {
     }
 
     /// Returns `true` if the queue is empty.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use may::sync::queue::spsc_seg_queue::SegQueue;
-    ///
-    /// let q = SegQueue::new();
-    ///
-    /// assert!(q.is_empty());
-    /// q.push(1);
-    /// assert!(!q.is_empty());
-    /// ```
     pub fn is_empty(&self) -> bool {
         let head = self.head.index.load(Ordering::SeqCst);
         let tail = self.tail.index.load(Ordering::SeqCst);
@@ -339,21 +294,6 @@ impl<T> SegQueue<T> {
     }
 
     /// Returns the number of elements in the queue.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use may::sync::queue::spsc_seg_queue::SegQueue;
-    ///
-    /// let q = SegQueue::new();
-    /// assert_eq!(q.len(), 0);
-    ///
-    /// q.push(10);
-    /// assert_eq!(q.len(), 1);
-    ///
-    /// q.push(20);
-    /// assert_eq!(q.len(), 2);
-    /// ```
     pub fn len(&self) -> usize {
         loop {
             // Load the tail index, then load the head index.
@@ -390,7 +330,7 @@ impl<T> SegQueue<T> {
     }
 }
 
-impl<T> Drop for SegQueue<T> {
+impl<T, const LAP: usize> Drop for SegQueue<T, LAP> {
     fn drop(&mut self) {
         let mut head = *self.head.index.get_mut();
         let mut tail = *self.tail.index.get_mut();
@@ -405,7 +345,7 @@ impl<T> Drop for SegQueue<T> {
             while head != tail {
                 let offset = (head >> SHIFT) % LAP;
 
-                if offset < BLOCK_CAP {
+                if offset < Block::<T, LAP>::CAP {
                     // Drop the value in the slot.
                     let slot = (*block).slots.get_unchecked(offset);
                     let p = &mut *slot.value.get();
@@ -428,22 +368,22 @@ impl<T> Drop for SegQueue<T> {
     }
 }
 
-impl<T> fmt::Debug for SegQueue<T> {
+impl<T, const LAP: usize> fmt::Debug for SegQueue<T, LAP> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.pad("SegQueue { .. }")
     }
 }
 
-impl<T> Default for SegQueue<T> {
-    fn default() -> SegQueue<T> {
-        SegQueue::new()
+impl<T, const LAP: usize> Default for SegQueue<T, LAP> {
+    fn default() -> SegQueue<T, LAP> {
+        SegQueue::unbounded()
     }
 }
 
-impl<T> IntoIterator for SegQueue<T> {
+impl<T, const LAP: usize> IntoIterator for SegQueue<T, LAP> {
     type Item = T;
 
-    type IntoIter = IntoIter<T>;
+    type IntoIter = IntoIter<T, LAP>;
 
     fn into_iter(self) -> Self::IntoIter {
         IntoIter { value: self }
@@ -451,11 +391,11 @@ impl<T> IntoIterator for SegQueue<T> {
 }
 
 #[derive(Debug)]
-pub struct IntoIter<T> {
-    value: SegQueue<T>,
+pub struct IntoIter<T, const LAP: usize = DEFAULT_LAP> {
+    value: SegQueue<T, LAP>,
 }
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, const LAP: usize> Iterator for IntoIter<T, LAP> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -477,7 +417,7 @@ impl<T> Iterator for IntoIter<T> {
                 let p = &mut *slot.value.get();
                 p.as_mut_ptr().read()
             };
-            if offset + 1 == BLOCK_CAP {
+            if offset + 1 == Block::<T, LAP>::CAP {
                 // Deallocate the block and move to the next one.
                 // SAFETY: The block is initialized because we've been reading
                 // from it this entire time. We can drop it b/c everything has
@@ -0,0 +1,243 @@
+//! automatic worker scaling
+//!
+//! the autoscaler is a thread of the scheduler that samples the run queue
+//! latency, which is the time a probe coroutine waits in the ready queues
+//! before it's run. when the latency exceeds the threshold a retired worker
+//! is activated, and when it stays below half of the threshold for a while
+//! a worker is retired, the gap between the two limits keeps the workers
+//! number from flapping.
+//!
+//! the workers are activated and retired the same way as
+//! [`Runtime::set_workers`], so the worker threads must be started for the
+//! max number up front, see [`RuntimeConfig::max_workers`] and
+//! [`Config::set_max_workers`].
+//!
+//! [`Runtime::set_workers`]: crate::Runtime::set_workers
+//! [`RuntimeConfig::max_workers`]: crate::RuntimeConfig::max_workers
+//! [`Config::set_max_workers`]: crate::Config::set_max_workers
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::coroutine::Builder;
+use crate::scheduler::{self, Scheduler};
+
+/// The settings of the automatic worker scaling.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::{AutoscaleConfig, Runtime, RuntimeConfig};
+///
+/// // 2 workers at night, up to 8 under the load of the day
+/// let autoscale = AutoscaleConfig::new(Duration::from_millis(2))
+///     .min_workers(2)
+///     .scale_down_after(Duration::from_secs(60));
+/// let config = RuntimeConfig::new()
+///     .workers(2)
+///     .max_workers(8)
+///     .autoscale(autoscale);
+/// let rt = Runtime::new(config);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AutoscaleConfig {
+    threshold: Duration,
+    min_workers: usize,
+    max_workers: usize,
+    interval: Duration,
+    scale_down_after: Duration,
+}
+
+impl AutoscaleConfig {
+    /// Creates the settings that add a worker when the run queue latency
+    /// exceeds the threshold.
+    pub fn new(threshold: Duration) -> Self {
+        AutoscaleConfig {
+            threshold,
+            min_workers: 1,
+            max_workers: 0,
+            interval: Duration::from_millis(100),
+            scale_down_after: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets the min number of the workers, the default is 1.
+    pub fn min_workers(mut self, workers: usize) -> Self {
+        self.min_workers = workers.max(1);
+        self
+    }
+
+    /// Sets the max number of the workers, the default is 0, which means
+    /// all the worker threads of the scheduler.
+    pub fn max_workers(mut self, workers: usize) -> Self {
+        self.max_workers = workers;
+        self
+    }
+
+    /// Sets the time between two samples of the latency, the default is
+    /// 100ms.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Sets how long the latency must stay below half of the threshold
+    /// before a worker is retired, the default is 10s.
+    pub fn scale_down_after(mut self, dur: Duration) -> Self {
+        self.scale_down_after = dur;
+        self
+    }
+}
+
+// decides the workers number by the latency samples
+struct Scaler {
+    threshold: Duration,
+    min: usize,
+    max: usize,
+    scale_down_after: Duration,
+    // the latency is below the low limit since then
+    calm_since: Option<Instant>,
+}
+
+impl Scaler {
+    fn new(config: &AutoscaleConfig, workers: usize) -> Self {
+        let max = match config.max_workers {
+            0 => workers,
+            n => n.min(workers),
+        };
+        Scaler {
+            threshold: config.threshold,
+            min: config.min_workers.min(max),
+            max,
+            scale_down_after: config.scale_down_after,
+            calm_since: None,
+        }
+    }
+
+    // returns the new workers number
+    fn next(&mut self, active: usize, latency: Duration, now: Instant) -> usize {
+        if latency > self.threshold {
+            self.calm_since = None;
+            return (active + 1).min(self.max);
+        }
+        if latency >= self.threshold / 2 {
+            self.calm_since = None;
+            return active;
+        }
+        let since = *self.calm_since.get_or_insert(now);
+        if active > self.min && now - since >= self.scale_down_after {
+            // the next one needs another calm period
+            self.calm_since = Some(now);
+            return active - 1;
+        }
+        active
+    }
+}
+
+// the probe coroutine that is waiting to run
+struct Probe {
+    start: Instant,
+    // the latency in ns plus 1, 0 if it's not run yet
+    latency: Arc<AtomicU64>,
+}
+
+impl Probe {
+    fn spawn(sched: &'static Scheduler) -> Option<Probe> {
+        let start = Instant::now();
+        let latency = Arc::new(AtomicU64::new(0));
+        let ret = latency.clone();
+        let builder = Builder::new().name("autoscale probe".into());
+        let spawned = unsafe {
+            builder.scheduler(sched).spawn(move || {
+                let ns = start.elapsed().as_nanos() as u64;
+                ret.store(ns + 1, Ordering::Release);
+            })
+        };
+        match spawned {
+            Ok(_) => Some(Probe { start, latency }),
+            Err(e) => {
+                error!("failed to spawn the autoscale probe, err = {}", e);
+                None
+            }
+        }
+    }
+
+    // the latency if it's run, or else the time it has been waiting
+    fn latency(&self) -> (Duration, bool) {
+        match self.latency.load(Ordering::Acquire) {
+            0 => (self.start.elapsed(), false),
+            ns => (Duration::from_nanos(ns - 1), true),
+        }
+    }
+}
+
+// start the autoscaler thread of the scheduler
+pub(crate) fn start(sched: &'static Scheduler, config: AutoscaleConfig) {
+    thread::spawn(move || {
+        scheduler::set_current_sched(sched);
+        let mut scaler = Scaler::new(&config, sched.workers());
+        let mut probe = None;
+        loop {
+            thread::sleep(config.interval);
+            let Some(p) = probe.take().or_else(|| Probe::spawn(sched)) else {
+                continue;
+            };
+            let (latency, done) = p.latency();
+            if !done {
+                probe = Some(p);
+                // a fresh probe only counts when it's late
+                if latency <= scaler.threshold {
+                    continue;
+                }
+            }
+            let active = sched.active_workers();
+            let workers = scaler.next(active, latency, Instant::now());
+            if workers != active {
+                info!(
+                    "autoscale workers {} -> {}, latency = {:?}",
+                    active, workers, latency
+                );
+                sched.set_active_workers(workers);
+                // the waiting probe may be stuck behind a busy worker, measure
+                // the new workers with a fresh one
+                probe = None;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaler_hysteresis() {
+        let config = AutoscaleConfig::new(Duration::from_millis(10))
+            .min_workers(2)
+            .scale_down_after(Duration::from_secs(1));
+        let mut scaler = Scaler::new(&config, 4);
+        let t0 = Instant::now();
+        let high = Duration::from_millis(20);
+        let mid = Duration::from_millis(7);
+        let low = Duration::from_millis(1);
+
+        assert_eq!(scaler.next(2, high, t0), 3);
+        assert_eq!(scaler.next(3, high, t0), 4);
+        // capped by the max
+        assert_eq!(scaler.next(4, high, t0), 4);
+
+        // not calm enough
+        assert_eq!(scaler.next(4, low, t0), 4);
+        assert_eq!(scaler.next(4, mid, t0 + Duration::from_secs(2)), 4);
+        assert_eq!(scaler.next(4, low, t0 + Duration::from_secs(2)), 4);
+        assert_eq!(scaler.next(4, low, t0 + Duration::from_secs(3)), 3);
+        // each retire needs another calm period
+        assert_eq!(scaler.next(3, low, t0 + Duration::from_millis(3500)), 3);
+        assert_eq!(scaler.next(3, low, t0 + Duration::from_secs(4)), 2);
+        // capped by the min
+        assert_eq!(scaler.next(2, low, t0 + Duration::from_secs(10)), 2);
+    }
+}
@@ -0,0 +1,176 @@
+//! a thread pool used to run blocking operations
+//!
+//! the threads are created on demand and exit after being idle for a while,
+//! a coroutine that submits a job is parked until the job is done
+//!
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::coroutine_impl::is_coroutine;
+use crate::sync::Blocker;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+
+// the max number of the blocking threads
+const MAX_THREADS: usize = 512;
+// how long an idle thread would wait for a new job before exit
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    tx: Sender<Job>,
+    rx: Receiver<Job>,
+    // number of threads that are waiting for jobs
+    idle: AtomicUsize,
+    // number of jobs that are not taken by a thread yet
+    pending: AtomicUsize,
+    // number of alive threads
+    threads: AtomicUsize,
+}
+
+impl Pool {
+    fn new() -> Self {
+        let (tx, rx) = unbounded();
+        Pool {
+            tx,
+            rx,
+            idle: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            threads: AtomicUsize::new(0),
+        }
+    }
+
+    fn submit(&'static self, job: Job) {
+        // count the job before it can be taken
+        let pending = self.pending.fetch_add(1, Ordering::SeqCst) + 1;
+        // send the job first, so an idle thread that is about to exit can see it
+        self.tx.send(job).expect("blocking pool is closed");
+        // a thread is still counted as idle right after it takes a job, but
+        // the job is counted as pending until then, so a new thread is spawned
+        // unless there is an idle thread for each of the pending jobs
+        if pending <= self.idle.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let threads = self.threads.fetch_add(1, Ordering::SeqCst);
+        if threads >= MAX_THREADS {
+            // the job would be picked up by a busy thread later
+            self.threads.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+
+        let ret = thread::Builder::new()
+            .name("may-blocking".to_owned())
+            .spawn(move || self.run());
+        if let Err(e) = ret {
+            self.threads.fetch_sub(1, Ordering::SeqCst);
+            error!("failed to spawn blocking thread, err = {:?}", e);
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            self.idle.fetch_add(1, Ordering::SeqCst);
+            let job = self.rx.recv_timeout(KEEP_ALIVE);
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+
+            let job = match job {
+                Ok(job) => job,
+                Err(RecvTimeoutError::Timeout) => {
+                    // re-check the jobs that are submitted while we are idle
+                    match self.rx.try_recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            // not idle any more, see `submit`
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            job();
+        }
+        self.threads.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn get_pool() -> &'static Pool {
+    lazy_static::lazy_static! {
+        static ref POOL: Pool = Pool::new();
+    }
+    &POOL
+}
+
+/// Runs a blocking closure in a dedicated thread pool and waits for its
+/// result.
+///
+/// Calling the blocking functions, e.g. FFI or the crates that use the std
+/// io, directly in a coroutine would block the worker thread and all the
+/// coroutines scheduled on it. In a coroutine context, this parks only the
+/// calling coroutine until the closure is done. In a thread context, the
+/// closure is run directly in the current thread.
+///
+/// A panic of the closure is propagated to the caller.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::coroutine::spawn_blocking;
+///
+/// let h = may::go!(|| {
+///     spawn_blocking(|| {
+///         // a blocking call that would otherwise block the worker
+///         std::thread::sleep(std::time::Duration::from_millis(10));
+///         42
+///     })
+/// });
+/// assert_eq!(h.join().unwrap(), 42);
+/// ```
+pub fn spawn_blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    run_blocking(f)
+}
+
+/// run the closure in the blocking thread pool and wait for its result
+///
+/// in a coroutine context the coroutine is parked until the closure is done,
+/// in a thread context the closure is run directly in the current thread.
+/// a panic of the closure is propagated to the caller.
+pub(crate) fn run_blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    if !is_coroutine() {
+        return f();
+    }
+
+    let blocker = Blocker::current();
+    let ret = Arc::new(AtomicCell::new(None));
+
+    let their_blocker = blocker.clone();
+    let their_ret = ret.clone();
+    get_pool().submit(Box::new(move || {
+        let r = panic::catch_unwind(AssertUnwindSafe(f));
+        their_ret.store(Some(r));
+        their_blocker.unpark();
+    }));
+
+    loop {
+        // cancel would panic here, the job is still running to its end
+        blocker.park(None).ok();
+        if let Some(r) = ret.take() {
+            match r {
+                Ok(r) => return r,
+                Err(e) => panic::resume_unwind(e),
+            }
+        }
+    }
+}
@@ -1,14 +1,15 @@
 use std::io;
+use std::panic::{self, AssertUnwindSafe, UnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 
-use crate::coroutine_impl::CoroutineImpl;
+use crate::coroutine_impl::{current, is_coroutine, CoroutineImpl};
 #[cfg(feature = "io_cancel")]
 use crate::io::cancel::CancelIoImpl;
 use crate::likely::unlikely;
 use crate::scheduler::get_scheduler;
-use crate::sync::AtomicOption;
+use crate::sync::{AtomicOption, Cancelled};
 use crate::yield_now::{get_co_para, set_co_para};
 use generator::Error;
 
@@ -31,6 +32,87 @@ pub fn trigger_cancel_panic() -> ! {
     std::panic::panic_any(Error::Cancel);
 }
 
+/// Runs the closure and returns `Err(Cancelled)` if it's interrupted by
+/// [`Coroutine::cancel`].
+///
+/// The cancel interrupts the blocking API that the coroutine is waiting on,
+/// including the io, `sleep`, the channels, `Mutex::lock`, `RwLock`,
+/// `Condvar::wait` and `JoinHandle::wait`, by unwinding the coroutine. This
+/// stops the unwinding at the closure, so the coroutine can clean up and
+/// return a value instead of being torn down by a panic.
+///
+/// The cancel request is still pending after it's caught, any blocking API
+/// called later by the coroutine is interrupted again. So only non-blocking
+/// cleanup should be done before the coroutine returns. Other panics are
+/// propagated as is.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::coroutine::catch_cancel;
+/// use may::sync::{mpsc::channel, Cancelled};
+///
+/// let (tx, rx) = channel::<u32>();
+/// let h = may::go!(move || {
+///     let ret = catch_cancel(|| rx.recv());
+///     assert_eq!(ret, Err(Cancelled));
+///     "cleaned up"
+/// });
+///
+/// std::thread::sleep(std::time::Duration::from_millis(10));
+/// unsafe { h.coroutine().cancel() };
+/// assert_eq!(h.join().unwrap(), "cleaned up");
+/// drop(tx);
+/// ```
+///
+/// [`Coroutine::cancel`]: crate::coroutine::Coroutine::cancel
+pub fn catch_cancel<F, R>(f: F) -> Result<R, Cancelled>
+where
+    F: FnOnce() -> R,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(r) => Ok(r),
+        Err(e) => match e.downcast_ref::<Error>() {
+            Some(Error::Cancel) => Err(Cancelled),
+            _ => panic::resume_unwind(e),
+        },
+    }
+}
+
+/// Runs the closure as a cancel-safe section of the coroutine.
+///
+/// When [`JoinHandle::cancel_graceful`] is called, the blocking operation
+/// that the closure is waiting on is interrupted, the closure is unwound and
+/// `Err(Cancelled)` is returned. If the request is already pending, the
+/// closure is not run at all. Outside of the sections the coroutine is never
+/// interrupted by the request.
+///
+/// The closure must be `UnwindSafe`, so that the state it mutates through
+/// references can't be observed half updated after the cancellation. The
+/// channels and the locks of `may::sync` are unwind safe, an interrupted
+/// operation of them never loses the data. Wrap the closure with
+/// `AssertUnwindSafe` if other state is known to be consistent at every
+/// blocking point. In a thread context the closure is just run.
+///
+/// [`JoinHandle::cancel_graceful`]: crate::coroutine::JoinHandle::cancel_graceful
+pub fn cancel_safe<F, R>(f: F) -> Result<R, Cancelled>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    if !is_coroutine() {
+        return Ok(f());
+    }
+    current().graceful_token().run(f)
+}
+
+/// Returns true if the current coroutine is requested to stop by
+/// [`JoinHandle::cancel_graceful`], always false in a thread context.
+///
+/// [`JoinHandle::cancel_graceful`]: crate::coroutine::JoinHandle::cancel_graceful
+pub fn is_cancel_requested() -> bool {
+    is_coroutine() && current().is_cancel_requested()
+}
+
 pub trait CancelIo {
     type Data;
     fn new() -> Self;
@@ -124,7 +206,7 @@ impl<T: CancelIo> CancelImpl<T> {
                 co.take(Ordering::Acquire)
                     .map(|mut co| {
                         // set the cancel result for the coroutine
-                        set_co_para(&mut co, io::Error::new(io::ErrorKind::Other, "Canceled"));
+                        set_co_para(&mut co, io::Error::other("Canceled"));
                         get_scheduler().schedule(co);
                     })
                     .unwrap_or(())
@@ -134,7 +216,6 @@ impl<T: CancelIo> CancelImpl<T> {
     }
 
     // clear the cancel bit so that we can reuse the cancel
-    #[cfg(unix)]
     pub fn clear_cancel_bit(&self) {
         self.state.fetch_and(!1, Ordering::Release);
     }
@@ -152,12 +233,28 @@ impl<T: CancelIo> CancelImpl<T> {
         self.co.swap(co, Ordering::Release);
     }
 
-    // clear the cancel io data
+    // clear the cancel io and co data
     // should be called after io completion
     pub fn clear(&self) {
-        // if self.co.take(Ordering::Acquire).is_none() {
+        // a stale co left by the last park would hide the next io cancel
+        self.co.take(Ordering::Acquire);
         self.io.clear();
-        // }
+    }
+}
+
+// clears the io and co registration when dropped, so that it's done even if
+// the coroutine is unwound by the cancel panic
+pub struct ClearGuard<'a, T: CancelIo>(&'a CancelImpl<T>);
+
+impl<T: CancelIo> CancelImpl<T> {
+    pub fn clear_guard(&self) -> ClearGuard<'_, T> {
+        ClearGuard(self)
+    }
+}
+
+impl<'a, T: CancelIo> Drop for ClearGuard<'a, T> {
+    fn drop(&mut self) {
+        self.0.clear();
     }
 }
 
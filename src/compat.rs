@@ -0,0 +1,394 @@
+//! adapters to use the may streams in the tokio ecosystem
+//!
+//! [`TokioIo`] wraps a [`TcpStream`] or a [`UnixStream`] as a tokio
+//! `AsyncRead + AsyncWrite`, so the existing tower and hyper middleware can
+//! be reused in a may based server during the migration.
+//!
+//! the adapter is driven by two pump coroutines, one reads ahead a chunk from
+//! the stream and the other writes the buffered data, they wake up the tokio
+//! task by its waker when the data or the buffer space is ready. the pumps
+//! exit after the adapter is dropped and the buffered data is written.
+//!
+//! only available with the `compat` feature.
+//!
+//! [`TcpStream`]: crate::net::TcpStream
+//! [`UnixStream`]: crate::os::unix::net::UnixStream
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::net::TcpStream;
+use crate::sync::Parker;
+
+// the size of the chunk read ahead by the read pump
+const READ_CHUNK: usize = 16 * 1024;
+// the max size of the data buffered for the write pump
+const MAX_WRITE_BUF: usize = 64 * 1024;
+
+/// A stream that can be bridged by [`TokioIo`].
+pub trait CompatStream: Read + Write + Send + Sized + 'static {
+    /// Creates a new handle of the same stream for the pump coroutines.
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// Shuts down the read, write, or both halves of the stream.
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+impl CompatStream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+#[cfg(unix)]
+impl CompatStream for crate::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        crate::os::unix::net::UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        crate::os::unix::net::UnixStream::shutdown(self, how)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // the chunk read by the read pump, consumed from `read_pos`
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    read_eof: bool,
+    read_err: Option<io::Error>,
+    read_waker: Option<Waker>,
+    reader: Option<Parker>,
+
+    // the data waiting for the write pump
+    write_buf: Vec<u8>,
+    // the write pump is writing the data taken from `write_buf`
+    writing: bool,
+    write_err: Option<io::Error>,
+    shutdown: bool,
+    shutdown_done: bool,
+    write_waker: Option<Waker>,
+    writer: Option<Parker>,
+
+    // the adapter is dropped
+    closed: bool,
+}
+
+impl State {
+    fn wake_read(&mut self) {
+        if let Some(w) = self.read_waker.take() {
+            w.wake();
+        }
+    }
+
+    fn wake_write(&mut self) {
+        if let Some(w) = self.write_waker.take() {
+            w.wake();
+        }
+    }
+
+    fn unpark_reader(&self) {
+        if let Some(p) = self.reader.as_ref() {
+            p.unpark();
+        }
+    }
+
+    fn unpark_writer(&self) {
+        if let Some(p) = self.writer.as_ref() {
+            p.unpark();
+        }
+    }
+
+    fn take_write_err(&mut self) -> Option<io::Error> {
+        let err = self.write_err.take()?;
+        // the later writes fail too
+        self.write_err = Some(io::Error::new(err.kind(), "stream write failed"));
+        Some(err)
+    }
+}
+
+type Shared = Arc<Mutex<State>>;
+
+fn read_pump<T: CompatStream>(mut stream: T, shared: Shared) {
+    let parker = Parker::new();
+    shared.lock().reader = Some(parker.clone());
+    let mut buf = vec![0u8; READ_CHUNK];
+    loop {
+        // wait until the previous chunk is consumed
+        loop {
+            let state = shared.lock();
+            if state.closed {
+                return;
+            }
+            if state.read_pos == state.read_buf.len() {
+                break;
+            }
+            drop(state);
+            parker.park();
+        }
+
+        let ret = stream.read(&mut buf);
+        let mut state = shared.lock();
+        let done = match ret {
+            Ok(0) => {
+                state.read_eof = true;
+                true
+            }
+            Ok(n) => {
+                state.read_buf.clear();
+                state.read_buf.extend_from_slice(&buf[..n]);
+                state.read_pos = 0;
+                false
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                state.read_err = Some(e);
+                true
+            }
+        };
+        state.wake_read();
+        if done {
+            return;
+        }
+    }
+}
+
+fn write_pump<T: CompatStream>(mut stream: T, shared: Shared) {
+    let parker = Parker::new();
+    shared.lock().writer = Some(parker.clone());
+    loop {
+        let data = loop {
+            let mut state = shared.lock();
+            if !state.write_buf.is_empty() {
+                state.writing = true;
+                let data = mem::take(&mut state.write_buf);
+                // there is room for the next writes
+                state.wake_write();
+                break Some(data);
+            }
+            if state.shutdown || state.closed {
+                break None;
+            }
+            drop(state);
+            parker.park();
+        };
+
+        let Some(data) = data else {
+            let ret = stream.shutdown(Shutdown::Write);
+            let mut state = shared.lock();
+            if let Err(e) = ret {
+                state.write_err = Some(e);
+            }
+            state.shutdown_done = true;
+            state.wake_write();
+            return;
+        };
+
+        let ret = stream.write_all(&data).and_then(|_| stream.flush());
+        let mut state = shared.lock();
+        state.writing = false;
+        let failed = ret.is_err();
+        if let Err(e) = ret {
+            state.write_err = Some(e);
+        }
+        state.wake_write();
+        if failed {
+            return;
+        }
+    }
+}
+
+/// A tokio `AsyncRead + AsyncWrite` adapter of a may stream.
+///
+/// It can be polled from any tokio runtime, the io is done by the pump
+/// coroutines on the may scheduler.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use may::compat::TokioIo;
+/// use may::net::TcpStream;
+///
+/// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+/// let io = TokioIo::new(stream).unwrap();
+/// // hand over `io` to a hyper connection on the tokio runtime
+/// # drop(io);
+/// ```
+pub struct TokioIo<T: CompatStream> {
+    stream: T,
+    shared: Shared,
+}
+
+impl<T: CompatStream> TokioIo<T> {
+    /// Creates the adapter and spawns its pump coroutines.
+    pub fn new(stream: T) -> io::Result<Self> {
+        let shared = Shared::default();
+        let reader = stream.try_clone()?;
+        let writer = stream.try_clone()?;
+        let s = shared.clone();
+        go!(move || read_pump(reader, s));
+        let s = shared.clone();
+        go!(move || write_pump(writer, s));
+        Ok(TokioIo { stream, shared })
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+}
+
+impl<T: CompatStream> AsyncRead for TokioIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut state = self.shared.lock();
+        let avail = state.read_buf.len() - state.read_pos;
+        if avail > 0 {
+            let n = avail.min(buf.remaining());
+            let pos = state.read_pos;
+            buf.put_slice(&state.read_buf[pos..pos + n]);
+            state.read_pos += n;
+            if state.read_pos == state.read_buf.len() {
+                state.unpark_reader();
+            }
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(e) = state.read_err.take() {
+            return Poll::Ready(Err(e));
+        }
+        if state.read_eof {
+            return Poll::Ready(Ok(()));
+        }
+        state.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T: CompatStream> AsyncWrite for TokioIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.lock();
+        if let Some(e) = state.take_write_err() {
+            return Poll::Ready(Err(e));
+        }
+        if state.shutdown {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stream is shut down",
+            )));
+        }
+        let room = MAX_WRITE_BUF.saturating_sub(state.write_buf.len());
+        if room == 0 {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = room.min(data.len());
+        state.write_buf.extend_from_slice(&data[..n]);
+        state.unpark_writer();
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.shared.lock();
+        if let Some(e) = state.take_write_err() {
+            return Poll::Ready(Err(e));
+        }
+        if state.write_buf.is_empty() && !state.writing {
+            return Poll::Ready(Ok(()));
+        }
+        state.write_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.shared.lock();
+        if state.shutdown_done {
+            return match state.write_err.take() {
+                Some(e) => Poll::Ready(Err(e)),
+                None => Poll::Ready(Ok(())),
+            };
+        }
+        if let Some(e) = state.take_write_err() {
+            return Poll::Ready(Err(e));
+        }
+        // the buffered data is written before the shutdown
+        state.shutdown = true;
+        state.unpark_writer();
+        state.write_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T: CompatStream> Drop for TokioIo<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.unpark_reader();
+        state.unpark_writer();
+        drop(state);
+        // wake up the read pump that is blocked in the read
+        self.stream.shutdown(Shutdown::Read).ok();
+    }
+}
+
+impl<T: CompatStream + fmt::Debug> fmt::Debug for TokioIo<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TokioIo")
+            .field("stream", &self.stream)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::TcpListener;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn tokio_io_echo() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h = go!(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut data = Vec::new();
+            s.read_to_end(&mut data).unwrap();
+            s.write_all(&data).unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut io = TokioIo::new(stream).unwrap();
+        let data = vec![7u8; MAX_WRITE_BUF * 3 + 5];
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let echo = rt.block_on(async {
+            io.write_all(&data).await.unwrap();
+            io.shutdown().await.unwrap();
+            let mut echo = Vec::new();
+            io.read_to_end(&mut echo).await.unwrap();
+            echo
+        });
+        assert_eq!(echo, data);
+        h.join().unwrap();
+    }
+}
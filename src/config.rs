@@ -1,16 +1,108 @@
 //! `May` Configuration interface
 //!
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::any::Any;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::autoscale::AutoscaleConfig;
+use crate::coroutine_impl::Coroutine;
+use crate::steal::{RoundRobin, StealPolicy};
 
 // default stack size, in usize
 // windows has a minimal size as 0x4a8!!!!
 const DEFAULT_STACK_SIZE: usize = 0x1000;
 const DEFAULT_POOL_CAPACITY: usize = 100;
+// default poll timeout of an idle worker, in ns
+const DEFAULT_POLL_TIMEOUT: u64 = 1_000_000_000;
+// default max number of the io events fetched by one poll
+const DEFAULT_POLL_BATCH: usize = 128;
+// default polls per second of a worker that are reported as busy polling
+const DEFAULT_BUSY_POLL_WATCHDOG: usize = 200_000;
+// default number of the `maybe_yield!` checks before the coroutine yields
+const DEFAULT_YIELD_BUDGET: usize = 1024;
 
 static WORKERS: AtomicUsize = AtomicUsize::new(0);
+static MAX_WORKERS: AtomicUsize = AtomicUsize::new(0);
+static IO_THREADS: AtomicUsize = AtomicUsize::new(0);
 static STACK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_STACK_SIZE);
 static POOL_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_POOL_CAPACITY);
+static ACCEPT_EXCLUSIVE: AtomicBool = AtomicBool::new(false);
+static PER_WORKER_POLLER: AtomicBool = AtomicBool::new(false);
+static SPIN_COUNT: AtomicUsize = AtomicUsize::new(0);
+static ADAPTIVE_SPIN: AtomicBool = AtomicBool::new(false);
+static POLL_TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_POLL_TIMEOUT);
+static HIGH_RES_TIMER: AtomicBool = AtomicBool::new(false);
+static POLL_BATCH: AtomicUsize = AtomicUsize::new(DEFAULT_POLL_BATCH);
+static BLOCK_THRESHOLD: AtomicU64 = AtomicU64::new(0);
+static YIELD_BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_YIELD_BUDGET);
+static WAKE_LIFO: AtomicBool = AtomicBool::new(false);
+static BUSY_POLL_WORKERS: parking_lot::RwLock<Vec<usize>> = parking_lot::const_rwlock(Vec::new());
+static BUSY_POLL_WATCHDOG: AtomicUsize = AtomicUsize::new(DEFAULT_BUSY_POLL_WATCHDOG);
+static PANIC_POLICY: parking_lot::RwLock<PanicPolicy> =
+    parking_lot::const_rwlock(PanicPolicy::Continue);
+static STEAL_POLICY: parking_lot::RwLock<Option<Arc<dyn StealPolicy>>> =
+    parking_lot::const_rwlock(None);
+static AUTOSCALE: parking_lot::RwLock<Option<AutoscaleConfig>> = parking_lot::const_rwlock(None);
+
+// the callback of `PanicPolicy::Restart`
+type RestartFn = Arc<dyn Fn(&Coroutine, &(dyn Any + Send)) + Send + Sync>;
+
+/// What to do when a coroutine panics.
+///
+/// The cancellation of a coroutine is not a panic, the policy doesn't apply.
+#[derive(Clone, Default)]
+pub enum PanicPolicy {
+    /// The panic is logged and only the panicked coroutine is terminated,
+    /// the panic is returned by joining the coroutine. This is the default.
+    #[default]
+    Continue,
+    /// Aborts the process after the panic is logged.
+    Abort,
+    /// Calls the callback with the panicked coroutine and the panic payload
+    /// after the coroutine is terminated, e.g. to spawn a new one for the
+    /// failed service.
+    ///
+    /// The callback runs in the worker thread that runs the coroutine, so it
+    /// should return quickly.
+    Restart(RestartFn),
+}
+
+impl PanicPolicy {
+    /// Creates a `Restart` policy from the callback.
+    pub fn restart<F>(f: F) -> Self
+    where
+        F: Fn(&Coroutine, &(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        PanicPolicy::Restart(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for PanicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PanicPolicy::Continue => f.write_str("Continue"),
+            PanicPolicy::Abort => f.write_str("Abort"),
+            PanicPolicy::Restart(_) => f.write_str("Restart(..)"),
+        }
+    }
+}
+
+/// Where a woken coroutine is queued on the worker that wakes it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WakeOrder {
+    /// Queued at the back of the run queue, so the ready coroutines are run
+    /// in the order that they are woken. This is the default.
+    #[default]
+    Fifo,
+    /// Run next by the worker, while the data that the waker just touched is
+    /// still in the cache. This lowers the latency of the request-response
+    /// like workloads at the cost of fairness, so a coroutine run by this
+    /// order more than a few times in a row is queued at the back instead.
+    Lifo,
+}
 
 /// `May` Configuration type
 pub struct Config;
@@ -23,17 +115,43 @@ pub fn config() -> Config {
 /// the config should be called at the program beginning
 ///
 /// successive call would not tack effect for that the scheduler
-/// is already started
+/// is already started, except the ones documented as adjustable at runtime:
+/// the workers number, the poll timeout and the pool capacity
 impl Config {
     /// set the worker thread number
     ///
     /// the minimum worker thread is 1, if you pass 0 to it, will use internal default
+    ///
+    /// this can be adjusted after the scheduler is started, up to the max
+    /// workers number. the retired workers stop taking new coroutines, the
+    /// queued ones are moved to the active workers, they still poll the io
+    /// registered on them and run the coroutines pinned to them
     pub fn set_workers(&self, workers: usize) -> &Self {
         info!("set workers={:?}", workers);
         WORKERS.store(workers, Ordering::Relaxed);
+        if let Some(s) = crate::scheduler::started_scheduler() {
+            s.set_active_workers(self.get_workers());
+        }
+        self
+    }
+
+    /// set the max worker thread number that `set_workers` can adjust to at
+    /// runtime
+    ///
+    /// the worker threads are started for the max number, the ones above the
+    /// workers number stay idle until they are activated. the default is the
+    /// workers number, which means the workers number can only go down
+    pub fn set_max_workers(&self, workers: usize) -> &Self {
+        info!("set max workers={:?}", workers);
+        MAX_WORKERS.store(workers, Ordering::Relaxed);
         self
     }
 
+    /// get the max worker thread number
+    pub fn get_max_workers(&self) -> usize {
+        MAX_WORKERS.load(Ordering::Relaxed).max(self.get_workers())
+    }
+
     /// get the normal workers number
     pub fn get_workers(&self) -> usize {
         let workers = WORKERS.load(Ordering::Relaxed);
@@ -52,9 +170,31 @@ impl Config {
         self
     }
 
+    /// set the number of the dedicated io threads
+    ///
+    /// by default the io pollers run in the worker threads. with a non zero
+    /// value, the pollers run in the dedicated threads instead, they only wait
+    /// for the io events and dispatch the ready coroutines to the workers.
+    /// this is only supported on unix, the value is ignored on windows
+    pub fn set_io_threads(&self, io_threads: usize) -> &Self {
+        info!("set io threads={:?}", io_threads);
+        IO_THREADS.store(io_threads, Ordering::Relaxed);
+        self
+    }
+
+    /// get the number of the dedicated io threads
+    pub fn get_io_threads(&self) -> usize {
+        if cfg!(unix) {
+            IO_THREADS.load(Ordering::Relaxed)
+        } else {
+            0
+        }
+    }
+
     /// set cached coroutine pool number
     ///
-    /// if you pass 0 to it, will use internal default
+    /// if you pass 0 to it, will use internal default. it takes effect at
+    /// runtime, the pool stops caching the finished coroutines when it's full
     pub fn set_pool_capacity(&self, capacity: usize) -> &Self {
         info!("set pool capacity={:?}", capacity);
         POOL_CAPACITY.store(capacity, Ordering::Release);
@@ -84,4 +224,289 @@ impl Config {
     pub fn get_stack_size(&self) -> usize {
         STACK_SIZE.load(Ordering::Acquire)
     }
+
+    /// set whether the tcp listeners are polled by all the workers exclusively
+    ///
+    /// when enabled, a listener is registered to the epoll instance of every
+    /// worker with `EPOLLEXCLUSIVE`, so a new connection only wakes up one of
+    /// the idle workers instead of all of them. if the kernel rejects the flag
+    /// the listener is registered in the normal way.
+    ///
+    /// this only takes effect on linux, and only for the listeners that are
+    /// created after the call. the default is false.
+    pub fn set_accept_exclusive(&self, exclusive: bool) -> &Self {
+        info!("set accept exclusive={:?}", exclusive);
+        ACCEPT_EXCLUSIVE.store(exclusive, Ordering::Relaxed);
+        self
+    }
+
+    /// get whether the tcp listeners are polled by all the workers exclusively
+    pub fn get_accept_exclusive(&self) -> bool {
+        ACCEPT_EXCLUSIVE.load(Ordering::Relaxed)
+    }
+
+    /// set whether the io is registered to the poller of the current worker
+    ///
+    /// each worker has its own epoll or kqueue instance. by default the
+    /// pollers are shared, the fds are spread over all of them by the fd
+    /// number, so the events of a connection may be dispatched by another
+    /// worker than the one that accepted it. when enabled, a new fd created in
+    /// a coroutine is registered to the poller of the worker running it, e.g.
+    /// the accepting worker for an accepted connection, and its events wake up
+    /// the coroutine on the same worker.
+    ///
+    /// the fds created out of the workers are still spread by the fd number,
+    /// and with dedicated io threads the workers don't poll, so it only picks
+    /// the io thread. this only takes effect on unix, and only for the fds
+    /// that are registered after the call. the default is false.
+    pub fn set_per_worker_poller(&self, enable: bool) -> &Self {
+        info!("set per worker poller={:?}", enable);
+        PER_WORKER_POLLER.store(enable, Ordering::Relaxed);
+        self
+    }
+
+    /// get whether the io is registered to the poller of the current worker
+    pub fn get_per_worker_poller(&self) -> bool {
+        PER_WORKER_POLLER.load(Ordering::Relaxed)
+    }
+
+    /// set the max spin iterations of an idle worker before it's parked
+    ///
+    /// an idle worker spins to check the ready coroutines before parking in
+    /// the poller, which reduces the wakeup latency at the cost of idle cpu.
+    /// the default is 0, which parks the worker right away.
+    pub fn set_spin_count(&self, count: usize) -> &Self {
+        info!("set spin count={:?}", count);
+        SPIN_COUNT.store(count, Ordering::Relaxed);
+        self
+    }
+
+    /// get the max spin iterations of an idle worker before it's parked
+    pub fn get_spin_count(&self) -> usize {
+        SPIN_COUNT.load(Ordering::Relaxed)
+    }
+
+    /// set whether the spin iterations are adjusted adaptively
+    ///
+    /// when enabled, each worker measures how soon it's woken up after being
+    /// parked, it spins longer if new work comes shortly after it gives up
+    /// spinning, and spins less if it's idle for long. the spin iterations
+    /// never exceed the `spin_count`. the default is false.
+    pub fn set_adaptive_spin(&self, adaptive: bool) -> &Self {
+        info!("set adaptive spin={:?}", adaptive);
+        ADAPTIVE_SPIN.store(adaptive, Ordering::Relaxed);
+        self
+    }
+
+    /// get whether the spin iterations are adjusted adaptively
+    pub fn get_adaptive_spin(&self) -> bool {
+        ADAPTIVE_SPIN.load(Ordering::Relaxed)
+    }
+
+    /// set the max time that an idle worker is parked in the poller
+    ///
+    /// the worker would also be woken up by new events and timers, so this
+    /// only limits how long an idle worker sleeps. the default is 1 second.
+    /// if you pass 0 to it, will use internal default. it takes effect at
+    /// runtime, after the workers are woken up next time
+    pub fn set_poll_timeout(&self, timeout: Duration) -> &Self {
+        info!("set poll timeout={:?}", timeout);
+        let ns = timeout.as_nanos().min(u64::MAX as u128) as u64;
+        POLL_TIMEOUT.store(ns, Ordering::Relaxed);
+        self
+    }
+
+    /// get the max time that an idle worker is parked in the poller
+    pub fn get_poll_timeout(&self) -> Duration {
+        match POLL_TIMEOUT.load(Ordering::Relaxed) {
+            0 => Duration::from_nanos(DEFAULT_POLL_TIMEOUT),
+            ns => Duration::from_nanos(ns),
+        }
+    }
+
+    /// set the workers that busy poll
+    ///
+    /// a busy polling worker never parks, it keeps polling the io events with
+    /// zero timeout and running the ready coroutines, which cuts the wakeup
+    /// latency to microseconds at the cost of a full cpu core per worker.
+    /// the indices not below the workers number are ignored. it only applies
+    /// to the workers started after it's set, so set it before the scheduler
+    /// starts. the default is empty
+    pub fn set_busy_poll_workers(&self, workers: &[usize]) -> &Self {
+        info!("set busy poll workers={:?}", workers);
+        *BUSY_POLL_WORKERS.write() = workers.to_vec();
+        self
+    }
+
+    /// get the workers that busy poll
+    pub fn get_busy_poll_workers(&self) -> Vec<usize> {
+        BUSY_POLL_WORKERS.read().clone()
+    }
+
+    /// set the polls per second that a worker is reported as busy polling
+    ///
+    /// a worker that is not set to busy poll by `set_busy_poll_workers` but
+    /// polls more often than this is logged with a warning at most once a
+    /// minute, it's usually a coroutine that spins on `yield_now` or an io
+    /// that is always ready. the default is 200000, 0 disables the watchdog
+    pub fn set_busy_poll_watchdog(&self, polls_per_sec: usize) -> &Self {
+        info!("set busy poll watchdog={:?}", polls_per_sec);
+        BUSY_POLL_WATCHDOG.store(polls_per_sec, Ordering::Relaxed);
+        self
+    }
+
+    /// get the polls per second that a worker is reported as busy polling,
+    /// 0 means disabled
+    pub fn get_busy_poll_watchdog(&self) -> usize {
+        BUSY_POLL_WATCHDOG.load(Ordering::Relaxed)
+    }
+
+    /// set the max number of the io events fetched by one poll
+    ///
+    /// a bigger batch needs fewer poll syscalls when there are lots of ready
+    /// connections, at the cost of the latency of the ready coroutines that
+    /// are already queued. the default is 128, if you pass 0 to it, will use
+    /// internal default. it only applies to the pollers started after it's
+    /// set, so set it before the scheduler starts
+    pub fn set_poll_batch(&self, batch: usize) -> &Self {
+        info!("set poll batch={:?}", batch);
+        POLL_BATCH.store(batch, Ordering::Relaxed);
+        self
+    }
+
+    /// get the max number of the io events fetched by one poll
+    pub fn get_poll_batch(&self) -> usize {
+        match POLL_BATCH.load(Ordering::Relaxed) {
+            0 => DEFAULT_POLL_BATCH,
+            n => n,
+        }
+    }
+
+    /// set whether the pollers wait for the timers with the kernel timers
+    ///
+    /// by default the poller timeout is rounded up to milliseconds on linux,
+    /// so the io timeouts may fire up to a millisecond late. when enabled,
+    /// each poller arms a timerfd on linux or an `EVFILT_TIMER` on macos and
+    /// freebsd for the nearest deadline, at the cost of an extra syscall per
+    /// poll. it only applies to the pollers created after it's set, so set it
+    /// before the scheduler starts. the default is false
+    pub fn set_high_res_timer(&self, enable: bool) -> &Self {
+        info!("set high resolution timer={:?}", enable);
+        HIGH_RES_TIMER.store(enable, Ordering::Relaxed);
+        self
+    }
+
+    /// get whether the pollers wait for the timers with the kernel timers
+    pub fn get_high_res_timer(&self) -> bool {
+        HIGH_RES_TIMER.load(Ordering::Relaxed)
+    }
+
+    /// set the threshold to detect the coroutines that block the worker
+    ///
+    /// when a coroutine runs longer than the threshold without yielding, a
+    /// warning with the coroutine name is logged after it yields. the
+    /// blocking calls should be moved to `coroutine::spawn_blocking`.
+    /// this is for debugging, the default is 0 which disables the detection
+    pub fn set_block_threshold(&self, threshold: Duration) -> &Self {
+        info!("set block threshold={:?}", threshold);
+        let ns = threshold.as_nanos().min(u64::MAX as u128) as u64;
+        BLOCK_THRESHOLD.store(ns, Ordering::Relaxed);
+        self
+    }
+
+    /// get the threshold to detect the blocking coroutines, 0 means disabled
+    pub fn get_block_threshold(&self) -> Duration {
+        Duration::from_nanos(BLOCK_THRESHOLD.load(Ordering::Relaxed))
+    }
+
+    /// set how many `maybe_yield!` checks a coroutine can pass before it
+    /// yields, counted from the last time it's resumed
+    ///
+    /// a smaller budget lets the cpu bound loops yield more often, at the
+    /// cost of more context switches. this can be changed at any time, the
+    /// default is 1024, 0 disables the yields of `maybe_yield!`
+    pub fn set_yield_budget(&self, budget: usize) -> &Self {
+        info!("set yield budget={:?}", budget);
+        YIELD_BUDGET.store(budget, Ordering::Relaxed);
+        self
+    }
+
+    /// get the number of the `maybe_yield!` checks before the yield
+    pub fn get_yield_budget(&self) -> usize {
+        YIELD_BUDGET.load(Ordering::Relaxed)
+    }
+
+    /// set where a woken coroutine is queued on the worker that wakes it
+    ///
+    /// this applies to all the coroutines that don't set their own order by
+    /// `Builder::wake_order`, and only to the unparks on a worker of the same
+    /// scheduler, e.g. a coroutine that sends to a channel waking up the
+    /// receiver. the other wakeups, including the io events and the timers,
+    /// always go to the back of a run queue. the order is taken when the
+    /// coroutine is spawned, the default is `WakeOrder::Fifo`
+    pub fn set_wake_order(&self, order: WakeOrder) -> &Self {
+        info!("set wake order={:?}", order);
+        WAKE_LIFO.store(order == WakeOrder::Lifo, Ordering::Relaxed);
+        self
+    }
+
+    /// get where a woken coroutine is queued
+    pub fn get_wake_order(&self) -> WakeOrder {
+        if WAKE_LIFO.load(Ordering::Relaxed) {
+            WakeOrder::Lifo
+        } else {
+            WakeOrder::Fifo
+        }
+    }
+
+    /// set what to do when a coroutine panics
+    ///
+    /// this applies to all the coroutines that don't set their own policy by
+    /// `Builder::panic_policy`. unlike the other configurations, this can be
+    /// changed at any time. the default is `PanicPolicy::Continue`
+    pub fn set_panic_policy(&self, policy: PanicPolicy) -> &Self {
+        info!("set panic policy={:?}", policy);
+        *PANIC_POLICY.write() = policy;
+        self
+    }
+
+    /// get the policy when a coroutine panics
+    pub fn get_panic_policy(&self) -> PanicPolicy {
+        PANIC_POLICY.read().clone()
+    }
+
+    /// set the policy of choosing the worker to steal the coroutines from
+    ///
+    /// it only applies to the global scheduler, set it before the scheduler
+    /// starts. the default is `steal::RoundRobin`
+    pub fn set_steal_policy<P: StealPolicy>(&self, policy: P) -> &Self {
+        info!("set steal policy={}", std::any::type_name::<P>());
+        *STEAL_POLICY.write() = Some(Arc::new(policy));
+        self
+    }
+
+    /// get the policy of choosing the worker to steal the coroutines from
+    pub fn get_steal_policy(&self) -> Arc<dyn StealPolicy> {
+        STEAL_POLICY
+            .read()
+            .clone()
+            .unwrap_or_else(|| Arc::new(RoundRobin))
+    }
+
+    /// set the automatic worker scaling of the global scheduler
+    ///
+    /// the workers number is adjusted between the min workers of the
+    /// autoscale config and the max workers by the run queue latency. it
+    /// only applies when the scheduler starts, and `set_max_workers` should
+    /// be set too for the workers number to go up. the default is none
+    pub fn set_autoscale(&self, autoscale: AutoscaleConfig) -> &Self {
+        info!("set autoscale={:?}", autoscale);
+        *AUTOSCALE.write() = Some(autoscale);
+        self
+    }
+
+    /// get the automatic worker scaling of the global scheduler
+    pub fn get_autoscale(&self) -> Option<AutoscaleConfig> {
+        AUTOSCALE.read().clone()
+    }
 }
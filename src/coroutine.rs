@@ -1,10 +1,18 @@
 // re-export coroutine interface
-pub use crate::cancel::trigger_cancel_panic;
+pub use crate::blocking_pool::spawn_blocking;
+pub use crate::cancel::{cancel_safe, catch_cancel, is_cancel_requested, trigger_cancel_panic};
+#[cfg(feature = "leak_detect")]
+pub use crate::coroutine_impl::leaked;
+#[cfg(feature = "co_stats")]
+pub use crate::coroutine_impl::CoStats;
 pub use crate::coroutine_impl::{
-    current, is_coroutine, park, park_timeout, spawn, Builder, Coroutine,
+    current, is_coroutine, park, park_timeout, spawn, spawn_local, unparker, Builder, Coroutine,
+    Unparker,
 };
-pub use crate::join::JoinHandle;
+pub use crate::group::{Group, GroupStats};
+pub use crate::join::{wait_all, JoinHandle, JoinStatus};
+pub use crate::join_set::JoinSet;
 pub use crate::park::ParkError;
 pub use crate::scoped::scope;
 pub use crate::sleep::sleep;
-pub use crate::yield_now::yield_now;
+pub use crate::yield_now::{maybe_yield, yield_now};
@@ -1,21 +1,29 @@
+use std::any::Any;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io;
-use std::sync::Arc;
-use std::time::Duration;
+use std::panic;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use crate::cancel::Cancel;
-use crate::config::config;
+use crate::config::{config, PanicPolicy, WakeOrder};
+use crate::group::Group;
 use crate::join::{make_join_handle, Join, JoinHandle};
 use crate::local::get_co_local_data;
 use crate::local::CoroutineLocal;
+use crate::metrics::{self, Counter};
 use crate::park::Park;
-use crate::scheduler::get_scheduler;
+use crate::scheduler::{current_worker_id, get_scheduler, is_current_scheduler, Scheduler};
+use crate::sync::CancellationToken;
+use crate::yield_now::reset_yield_checks;
 use crossbeam::atomic::AtomicCell;
 use generator::{Generator, Gn};
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Coroutine framework types
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Coroutine framework types
+// /////////////////////////////////////////////////////////////////////////////
 
 pub type EventResult = io::Error;
 
@@ -48,31 +56,36 @@ pub trait EventSource {
     }
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Coroutine destruction
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Coroutine destruction
+// /////////////////////////////////////////////////////////////////////////////
 
 pub struct Done;
 
 impl Done {
     fn drop_coroutine(co: CoroutineImpl) {
         // assert!(co.is_done(), "unfinished coroutine detected");
+        metrics::inc(Counter::Finished);
         // just consume the coroutine
         // destroy the local storage
         let local = unsafe { Box::from_raw(get_co_local(&co)) };
+        let id = local.get_co().id();
         let name = local.get_co().name();
 
         // recycle the coroutine
         let (size, used) = co.stack_usage();
         if used == size {
-            eprintln!("stack overflow detected, size={}", size);
+            eprintln!(
+                "stack overflow detected, coroutine id = {}, name = {:?}, size={}",
+                id, name, size
+            );
             ::std::process::exit(1);
         }
         // show the actual used stack size in debug log
         if local.get_co().stack_size() & 1 == 1 {
             println!(
-                "coroutine name = {:?}, stack size = {},  used size = {}",
-                name, size, used
+                "coroutine id = {}, name = {:?}, stack size = {},  used size = {}",
+                id, name, size, used
             );
         }
 
@@ -98,16 +111,158 @@ fn get_co_local(co: &CoroutineImpl) -> *mut CoroutineLocal {
     co.get_local_data() as *mut CoroutineLocal
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Coroutine
-/// /////////////////////////////////////////////////////////////////////////////
+// where the coroutine is queued, resolved when it's spawned
+#[derive(Clone, Copy)]
+pub(crate) struct Route {
+    // the scheduler that runs the coroutine
+    pub sched: &'static Scheduler,
+    // the worker that the coroutine is pinned to
+    pub worker: Option<usize>,
+    // where the coroutine is queued when it's woken
+    pub wake_order: WakeOrder,
+}
+
+// get the route of the coroutine, the coroutines without the local storage
+// can be queued anywhere
+#[inline]
+pub(crate) fn route(co: &CoroutineImpl) -> Option<Route> {
+    let local = get_co_local(co);
+    if local.is_null() {
+        return None;
+    }
+    Some(unsafe { &*local }.get_co().inner.route)
+}
+
+// get the scheduler that the coroutine belongs to
+#[inline]
+pub(crate) fn home_scheduler(co: &CoroutineImpl) -> Option<&'static Scheduler> {
+    let local = get_co_local(co);
+    if local.is_null() {
+        return None;
+    }
+    Some(unsafe { &*local }.get_co().inner.route.sched)
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Coroutine
+// /////////////////////////////////////////////////////////////////////////////
+
+/// The runtime statistics of a coroutine.
+///
+/// Only available with the `co_stats` feature.
+#[cfg(feature = "co_stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoStats {
+    /// The total time that the coroutine has been running on the workers.
+    pub cpu_time: Duration,
+    /// The number of times that the coroutine yielded back to the scheduler.
+    pub yields: u64,
+    /// The total time that the coroutine waited in the run queues after it's
+    /// ready to run.
+    pub sched_delay: Duration,
+}
+
+// the statistics recorded by the scheduler, all in ns
+#[cfg(feature = "co_stats")]
+#[derive(Default)]
+struct StatsRecord {
+    cpu_time: AtomicU64,
+    yields: AtomicU64,
+    sched_delay: AtomicU64,
+    // the time when the coroutine is pushed to a run queue, 0 if not queued
+    ready_at: AtomicU64,
+}
+
+// where and when the coroutine is spawned, only recorded with `leak_detect`
+#[derive(Clone, Copy)]
+struct SpawnSite {
+    #[cfg(feature = "leak_detect")]
+    location: &'static panic::Location<'static>,
+    #[cfg(feature = "leak_detect")]
+    at: Instant,
+}
+
+impl SpawnSite {
+    #[inline]
+    #[track_caller]
+    fn caller() -> Self {
+        SpawnSite {
+            #[cfg(feature = "leak_detect")]
+            location: panic::Location::caller(),
+            #[cfg(feature = "leak_detect")]
+            at: Instant::now(),
+        }
+    }
+}
+
+// the coroutines that are not done yet, by their ids
+#[cfg(feature = "leak_detect")]
+static LIVE: parking_lot::Mutex<BTreeMap<u64, Coroutine>> =
+    parking_lot::const_mutex(BTreeMap::new());
+
+/// Returns the coroutines that are still alive after `older_than` since they
+/// are spawned, the oldest ones first.
+///
+/// It's for finding the forgotten background loops, see
+/// [`Coroutine::spawn_location`] for where they come from.
+///
+/// Only available with the `leak_detect` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::coroutine;
+///
+/// let h = may::go!(|| coroutine::park());
+/// coroutine::sleep(Duration::from_millis(10));
+/// let leaked = coroutine::leaked(Duration::from_millis(10));
+/// let co = leaked.iter().find(|co| co.id() == h.coroutine().id()).unwrap();
+/// println!("leaked coroutine spawned at {}", co.spawn_location());
+/// h.coroutine().unpark();
+/// h.join().unwrap();
+/// ```
+#[cfg(feature = "leak_detect")]
+pub fn leaked(older_than: Duration) -> Vec<Coroutine> {
+    let mut leaked: Vec<Coroutine> = LIVE
+        .lock()
+        .values()
+        .filter(|co| co.inner.site.at.elapsed() >= older_than)
+        .cloned()
+        .collect();
+    leaked.sort_by_key(|co| co.inner.site.at);
+    leaked
+}
 
 /// The internal representation of a `Coroutine` handle
 struct Inner {
+    id: u64,
     name: Option<String>,
+    metadata: BTreeMap<String, String>,
     stack_size: usize,
+    panic_policy: Option<PanicPolicy>,
+    route: Route,
+    group: Option<Group>,
     park: Park,
     cancel: Cancel,
+    // the graceful cancel request, created on demand
+    graceful: OnceLock<CancellationToken>,
+    #[cfg_attr(not(feature = "leak_detect"), allow(dead_code))]
+    site: SpawnSite,
+    #[cfg(feature = "co_stats")]
+    stats: StatsRecord,
+}
+
+// the settings of a coroutine, resolved from its `Builder`
+struct Settings {
+    name: Option<String>,
+    metadata: BTreeMap<String, String>,
+    stack_size: usize,
+    worker: Option<usize>,
+    panic_policy: Option<PanicPolicy>,
+    wake_order: Option<WakeOrder>,
+    sched: &'static Scheduler,
+    group: Option<Group>,
 }
 
 #[derive(Clone)]
@@ -118,13 +273,39 @@ pub struct Coroutine {
 
 impl Coroutine {
     // Used only internally to construct a coroutine object without spawning
-    fn new(name: Option<String>, stack_size: usize) -> Coroutine {
+    #[track_caller]
+    fn new(settings: Settings) -> Coroutine {
+        let Settings {
+            name,
+            metadata,
+            stack_size,
+            worker,
+            panic_policy,
+            wake_order,
+            sched,
+            group,
+        } = settings;
+        // the id 0 is never used
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
         Coroutine {
             inner: Arc::new(Inner {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
                 name,
+                metadata,
                 stack_size,
+                panic_policy,
+                route: Route {
+                    sched,
+                    worker,
+                    wake_order: wake_order.unwrap_or_else(|| config().get_wake_order()),
+                },
+                group,
                 park: Park::new(),
                 cancel: Cancel::new(),
+                graceful: OnceLock::new(),
+                site: SpawnSite::caller(),
+                #[cfg(feature = "co_stats")]
+                stats: StatsRecord::default(),
             }),
         }
     }
@@ -139,21 +320,131 @@ impl Coroutine {
         self.inner.park.unpark();
     }
 
+    /// Creates an [`Unparker`] that can wake up this coroutine.
+    pub fn unparker(&self) -> Unparker {
+        Unparker { co: self.clone() }
+    }
+
     /// cancel a coroutine
     /// # Safety
     ///
     /// This function would force a coroutine exist when next scheduling
     /// And would drop all the resource tha the coroutine currently holding
     /// This may have unexpected side effects if you are not fully aware it
+    ///
+    /// the blocking io, sleep, channels and sync primitives that the
+    /// coroutine is waiting on are interrupted, use [`catch_cancel`] to get
+    /// a `Cancelled` error instead of unwinding the whole coroutine
+    ///
+    /// [`catch_cancel`]: crate::coroutine::catch_cancel
     pub unsafe fn cancel(&self) {
         self.inner.cancel.cancel();
     }
 
+    /// Requests the coroutine to stop at a point that it chooses.
+    ///
+    /// Unlike [`cancel`], the coroutine is never unwound outside of
+    /// [`cancel_safe`]. The blocking operation that the coroutine is waiting
+    /// on within `cancel_safe` is interrupted and `cancel_safe` returns
+    /// `Err(Cancelled)`, the request stays pending until the coroutine
+    /// enters `cancel_safe` otherwise. The coroutine can also poll it by
+    /// [`is_cancel_requested`]. A coroutine that never does either just runs
+    /// to completion.
+    ///
+    /// [`cancel`]: Coroutine::cancel
+    /// [`cancel_safe`]: crate::coroutine::cancel_safe
+    /// [`is_cancel_requested`]: crate::coroutine::is_cancel_requested
+    pub fn cancel_graceful(&self) {
+        self.graceful_token().cancel();
+    }
+
+    /// Returns true if [`cancel_graceful`] is called on the coroutine.
+    ///
+    /// [`cancel_graceful`]: Coroutine::cancel_graceful
+    pub fn is_cancel_requested(&self) -> bool {
+        self.inner
+            .graceful
+            .get()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    pub(crate) fn graceful_token(&self) -> &CancellationToken {
+        self.inner.graceful.get_or_init(CancellationToken::new)
+    }
+
     /// Gets the coroutine name.
     pub fn name(&self) -> Option<&str> {
         self.inner.name.as_deref()
     }
 
+    /// Gets the coroutine id, which is unique within the process.
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// Gets the metadata value of the key that is set by the `Builder`.
+    pub fn meta(&self, key: &str) -> Option<&str> {
+        self.inner.metadata.get(key).map(|v| v.as_str())
+    }
+
+    /// Gets all the metadata that is set by the `Builder`.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.inner.metadata
+    }
+
+    /// Gets the index of the worker that the coroutine is pinned to.
+    ///
+    /// Returns `None` if the coroutine is not pinned, in which case it can
+    /// be run by any of the workers.
+    pub fn pinned_worker(&self) -> Option<usize> {
+        self.inner.route.worker
+    }
+
+    /// Gets the group that the coroutine is spawned in.
+    pub fn group(&self) -> Option<&Group> {
+        self.inner.group.as_ref()
+    }
+
+    /// Gets the runtime statistics of the coroutine.
+    ///
+    /// Only available with the `co_stats` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let h = may::go!(|| {
+    ///     may::coroutine::yield_now();
+    ///     may::coroutine::current().stats()
+    /// });
+    /// let stats = h.join().unwrap();
+    /// assert!(stats.yields >= 1);
+    /// ```
+    #[cfg(feature = "co_stats")]
+    pub fn stats(&self) -> CoStats {
+        let stats = &self.inner.stats;
+        CoStats {
+            cpu_time: Duration::from_nanos(stats.cpu_time.load(Ordering::Relaxed)),
+            yields: stats.yields.load(Ordering::Relaxed),
+            sched_delay: Duration::from_nanos(stats.sched_delay.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Gets the source location that spawned the coroutine.
+    ///
+    /// Only available with the `leak_detect` feature.
+    #[cfg(feature = "leak_detect")]
+    pub fn spawn_location(&self) -> &'static panic::Location<'static> {
+        self.inner.site.location
+    }
+
+    /// Gets the time when the coroutine is spawned.
+    ///
+    /// Only available with the `leak_detect` feature.
+    #[cfg(feature = "leak_detect")]
+    pub fn spawned_at(&self) -> Instant {
+        self.inner.site.at
+    }
+
     /// Get the internal cancel
     #[cfg(unix)]
     #[cfg(feature = "io_cancel")]
@@ -168,6 +459,61 @@ impl fmt::Debug for Coroutine {
     }
 }
 
+/// A handle that can unpark a specific coroutine.
+///
+/// This is the coroutine counterpart of unparking a `std::thread::Thread`.
+/// The handle is cheap to clone and can be sent to other coroutines or
+/// threads, which can then wake up the owner after it called [`park`] or
+/// [`park_timeout`].
+///
+/// Like the thread version, each coroutine has a single token. Calling
+/// `unpark` before the coroutine parks makes the next `park` return
+/// immediately, and multiple `unpark` calls don't accumulate tokens.
+///
+/// # Examples
+///
+/// ```
+/// use may::coroutine;
+///
+/// let (tx, rx) = may::sync::mpsc::channel();
+/// let h = unsafe {
+///     coroutine::spawn(move || {
+///         tx.send(coroutine::unparker()).unwrap();
+///         coroutine::park();
+///     })
+/// };
+///
+/// let unparker = rx.recv().unwrap();
+/// unparker.clone().unpark();
+/// h.join().unwrap();
+/// ```
+///
+/// [`park`]: fn.park.html
+/// [`park_timeout`]: fn.park_timeout.html
+#[derive(Clone)]
+pub struct Unparker {
+    co: Coroutine,
+}
+
+impl Unparker {
+    /// Atomically makes the coroutine's token available if it is not already.
+    #[inline]
+    pub fn unpark(&self) {
+        self.co.unpark();
+    }
+
+    /// Gets the handle of the coroutine that this unparker wakes up.
+    pub fn coroutine(&self) -> &Coroutine {
+        &self.co
+    }
+}
+
+impl fmt::Debug for Unparker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Unparker").field("co", &self.co).finish()
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Builder
 ////////////////////////////////////////////////////////////////////////////////
@@ -177,10 +523,13 @@ impl fmt::Debug for Coroutine {
 ///
 /// Methods can be chained on it in order to configure it.
 ///
-/// The two configurations available are:
+/// The configurations available are:
 ///
 /// - [`name`]: specifies an [associated name for the coroutine][naming-coroutines]
 /// - [`stack_size`]: specifies the [desired stack size for the coroutine][stack-size]
+/// - [`meta`]: attaches a key-value pair to the coroutine, e.g. a request id
+/// - [`pin_to_worker`]: pins the coroutine to a worker thread
+/// - [`group`]: limits the scheduling of the coroutine with a group
 ///
 /// The [`spawn`] method will take ownership of the builder and create an
 /// `io::Result` to the coroutine handle with the given configuration.
@@ -210,6 +559,9 @@ impl fmt::Debug for Coroutine {
 /// [`coroutine::spawn`]: ./fn.spawn.html
 /// [`stack_size`]: ./struct.Builder.html#method.stack_size
 /// [`name`]: ./struct.Builder.html#method.name
+/// [`meta`]: ./struct.Builder.html#method.meta
+/// [`pin_to_worker`]: ./struct.Builder.html#method.pin_to_worker
+/// [`group`]: ./struct.Builder.html#method.group
 /// [`spawn`]: ./struct.Builder.html#method.spawn
 /// [naming-coroutines]: ./index.html#naming-coroutine
 /// [stack-size]: ./index.html#stack-siz
@@ -219,6 +571,27 @@ pub struct Builder {
     name: Option<String>,
     // The size of the stack for the spawned coroutine
     stack_size: Option<usize>,
+    // The key-value pairs attached to the coroutine
+    metadata: BTreeMap<String, String>,
+    // The worker that the coroutine is pinned to
+    pin: Option<Pin>,
+    // The policy when the coroutine panics, use the global one if not set
+    panic_policy: Option<PanicPolicy>,
+    // Where the coroutine is queued when it's woken, use the global one if not set
+    wake_order: Option<WakeOrder>,
+    // The scheduler to run the coroutine, use the current one if not set
+    sched: Option<&'static Scheduler>,
+    // The group that limits the scheduling of the coroutine
+    group: Option<Group>,
+}
+
+// the worker to pin the coroutine
+#[derive(Debug, Clone, Copy)]
+enum Pin {
+    // the worker of the spawning thread
+    Current,
+    // the worker of the index
+    Worker(usize),
 }
 
 impl Builder {
@@ -228,9 +601,21 @@ impl Builder {
         Builder {
             name: None,
             stack_size: None,
+            metadata: BTreeMap::new(),
+            pin: None,
+            panic_policy: None,
+            wake_order: None,
+            sched: None,
+            group: None,
         }
     }
 
+    // spawn the coroutine into the scheduler of a runtime
+    pub(crate) fn scheduler(mut self, sched: &'static Scheduler) -> Builder {
+        self.sched = Some(sched);
+        self
+    }
+
     /// Names the thread-to-be. Currently the name is used for identification
     /// only in panic messages.
     pub fn name(mut self, name: String) -> Builder {
@@ -244,9 +629,137 @@ impl Builder {
         self
     }
 
+    /// Attaches a key-value pair to the coroutine-to-be.
+    ///
+    /// The metadata can be queried from the coroutine handle, and it's
+    /// logged together with the coroutine id and name when the coroutine
+    /// panics, which is useful to correlate coroutines with requests.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::coroutine;
+    ///
+    /// let builder = coroutine::Builder::new().meta("request_id", "42");
+    /// let h = unsafe {
+    ///     builder
+    ///         .spawn(|| {
+    ///             let co = coroutine::current();
+    ///             assert_eq!(co.meta("request_id"), Some("42"));
+    ///         })
+    ///         .unwrap()
+    /// };
+    /// assert_eq!(h.coroutine().meta("request_id"), Some("42"));
+    /// h.join().unwrap();
+    /// ```
+    pub fn meta<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Builder {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Pins the coroutine-to-be to the worker thread of the given index.
+    ///
+    /// A pinned coroutine is always run by the same worker and would never
+    /// be stolen by other workers, so it can safely use the thread local
+    /// caches of the worker and spawn `!Send` coroutines by [`spawn_local`].
+    ///
+    /// The index must be less than the number of workers, or else the spawn
+    /// would fail with an `InvalidInput` error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::coroutine;
+    ///
+    /// let h = unsafe {
+    ///     coroutine::Builder::new()
+    ///         .pin_to_worker(0)
+    ///         .spawn(|| coroutine::current().pinned_worker())
+    ///         .unwrap()
+    /// };
+    /// assert_eq!(h.join().unwrap(), Some(0));
+    /// ```
+    ///
+    /// [`spawn_local`]: ./fn.spawn_local.html
+    pub fn pin_to_worker(mut self, idx: usize) -> Builder {
+        self.pin = Some(Pin::Worker(idx));
+        self
+    }
+
+    /// Pins the coroutine-to-be to the worker thread that spawns it.
+    ///
+    /// If the spawning thread is not a worker, e.g. the main thread, one of
+    /// the workers is picked. See [`pin_to_worker`] for more details.
+    ///
+    /// [`pin_to_worker`]: #method.pin_to_worker
+    pub fn pin_to_current_worker(mut self) -> Builder {
+        self.pin = Some(Pin::Current);
+        self
+    }
+
+    /// Sets what to do when the coroutine-to-be panics, which overrides the
+    /// global policy set by `config().set_panic_policy`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::coroutine;
+    /// use may::PanicPolicy;
+    ///
+    /// let (tx, rx) = may::sync::mpsc::channel();
+    /// let policy = PanicPolicy::restart(move |co, _panic| {
+    ///     tx.send(co.name().map(|s| s.to_owned())).unwrap();
+    /// });
+    /// let h = unsafe {
+    ///     coroutine::Builder::new()
+    ///         .name("service".to_owned())
+    ///         .panic_policy(policy)
+    ///         .spawn(|| -> () { panic!("service failed") })
+    ///         .unwrap()
+    /// };
+    /// assert!(h.join().is_err());
+    /// assert_eq!(rx.recv().unwrap().as_deref(), Some("service"));
+    /// ```
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Builder {
+        self.panic_policy = Some(policy);
+        self
+    }
+
+    /// Sets where the coroutine-to-be is queued when it's woken, which
+    /// overrides the global order set by `config().set_wake_order`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::coroutine;
+    /// use may::WakeOrder;
+    ///
+    /// let h = unsafe {
+    ///     coroutine::Builder::new()
+    ///         .wake_order(WakeOrder::Lifo)
+    ///         .spawn(|| coroutine::sleep(std::time::Duration::from_millis(1)))
+    ///         .unwrap()
+    /// };
+    /// h.join().unwrap();
+    /// ```
+    pub fn wake_order(mut self, order: WakeOrder) -> Builder {
+        self.wake_order = Some(order);
+        self
+    }
+
+    /// Spawns the coroutine-to-be in the group, which limits its scheduling
+    /// together with the other members, see [`Group`].
+    ///
+    /// [`Group`]: crate::coroutine::Group
+    pub fn group(mut self, group: &Group) -> Builder {
+        self.group = Some(group.clone());
+        self
+    }
+
     /// Spawns a new coroutine, and returns a join handle for it.
     /// The join handle can be used to block on
     /// termination of the child coroutine, including recovering its panics.
+    #[track_caller]
     fn spawn_impl<F, T>(self, f: F) -> io::Result<(CoroutineImpl, JoinHandle<T>)>
     where
         F: FnOnce() -> T + Send + 'static,
@@ -254,9 +767,33 @@ impl Builder {
     {
         static DONE: Done = Done {};
 
-        let sched = get_scheduler();
-        let Builder { name, stack_size } = self;
+        let Builder {
+            name,
+            stack_size,
+            metadata,
+            pin,
+            panic_policy,
+            wake_order,
+            sched,
+            group,
+        } = self;
+        let sched = sched.unwrap_or_else(get_scheduler);
         let stack_size = stack_size.unwrap_or_else(|| config().get_stack_size());
+        let worker = match pin {
+            None => None,
+            Some(Pin::Current) => Some(
+                current_worker_id()
+                    .filter(|_| is_current_scheduler(sched))
+                    .unwrap_or_else(|| next_worker_id(sched.active_workers())),
+            ),
+            Some(Pin::Worker(idx)) if idx < sched.workers() => Some(idx),
+            Some(Pin::Worker(idx)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid worker index {}", idx),
+                ))
+            }
+        };
 
         // create a join resource, shared by waited coroutine and *this* coroutine
         let panic = Arc::new(AtomicCell::new(None));
@@ -270,6 +807,8 @@ impl Builder {
         };
 
         let closure = move || {
+            #[cfg(all(feature = "core_dump", unix))]
+            crate::debug::save_stack_top();
             // trigger the JoinHandler
             // we must declare the variable before calling f so that stack is prepared
             // to unwind these local data. for the panic err we would set it in the
@@ -278,6 +817,15 @@ impl Builder {
             // set the return packet
             their_packet.swap(Some(f()));
 
+            // it's not alive any more when the join returns
+            if let Some(local) = get_co_local_data() {
+                let co = unsafe { local.as_ref() }.get_co();
+                #[cfg(feature = "leak_detect")]
+                LIVE.lock().remove(&co.id());
+                if let Some(group) = co.group() {
+                    group.remove_member(co.id());
+                }
+            }
             their_join.trigger();
             subscriber
         };
@@ -290,9 +838,28 @@ impl Builder {
             Gn::new_opt(stack_size, closure)
         };
 
-        let handle = Coroutine::new(name, stack_size);
+        metrics::inc(Counter::Spawned);
+        let handle = Coroutine::new(Settings {
+            name,
+            metadata,
+            stack_size,
+            worker,
+            panic_policy,
+            wake_order,
+            sched,
+            group,
+        });
+        if let Some(group) = handle.group() {
+            group.add_member(&handle);
+        }
+        #[cfg(feature = "leak_detect")]
+        LIVE.lock().insert(handle.id(), handle.clone());
         // create the local storage
-        let local = CoroutineLocal::new(handle.clone(), join.clone());
+        #[allow(unused_mut)]
+        let mut local = CoroutineLocal::new(handle.clone(), join.clone());
+        // register the coroutine for the debuggers, removed with the local storage
+        #[cfg(all(feature = "core_dump", unix))]
+        local.set_registration(crate::debug::Registration::new(handle.id(), handle.name()));
         // attache the local storage to the coroutine
         co.set_local_data(Box::into_raw(local) as *mut u8);
 
@@ -339,6 +906,7 @@ impl Builder {
     /// [`TLS`]: ./index.html#TLS
     /// [`go!`]: ../macro.go.html
     /// [`spawn`]: ./fn.spawn.html
+    #[track_caller]
     pub unsafe fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
     where
         F: FnOnce() -> T + Send + 'static,
@@ -347,7 +915,7 @@ impl Builder {
         // we will still get optimizations in spawn_impl
         let (co, handle) = self.spawn_impl(f)?;
 
-        // put the coroutine to ready list
+        // put the coroutine to ready list, it's sent to its own scheduler
         get_scheduler().schedule_global(co);
 
         Ok(handle)
@@ -361,6 +929,7 @@ impl Builder {
     /// Cancel would drop all the resource of the coroutine.
     /// Normally this is safe but for some cases you should
     /// take care of the side effect
+    #[track_caller]
     pub unsafe fn spawn_local<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
     where
         F: FnOnce() -> T + Send + 'static,
@@ -374,6 +943,12 @@ impl Builder {
     }
 }
 
+// pick a worker in round robin
+fn next_worker_id(workers: usize) -> usize {
+    static NEXT_WORKER_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed) % workers
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Free functions
 ////////////////////////////////////////////////////////////////////////////////
@@ -424,6 +999,7 @@ impl Builder {
 /// [`join`]: struct.JoinHandle.html#method.join
 /// [`Builder::spawn`]: struct.Builder.html#method.spawn
 /// [`Builder`]: struct.Builder.html
+#[track_caller]
 pub unsafe fn spawn<F, T>(f: F) -> JoinHandle<T>
 where
     F: FnOnce() -> T + Send + 'static,
@@ -432,6 +1008,78 @@ where
     Builder::new().spawn(f).unwrap()
 }
 
+// wrapper to send the `!Send` closure to a coroutine on the same worker
+struct LocalFn<F>(F);
+
+unsafe impl<F> Send for LocalFn<F> {}
+
+impl<F: FnOnce() -> T, T> LocalFn<F> {
+    fn call(self) -> T {
+        (self.0)()
+    }
+}
+
+/// Spawns a new coroutine that is pinned to the current worker, returning a
+/// [`JoinHandle`] for it.
+///
+/// Unlike [`spawn`], the closure doesn't need to be `Send`, so the coroutines
+/// on the same worker can share `Rc` based data structures with each other.
+///
+/// # Safety
+///
+/// The same as [`spawn`].
+///
+/// # Panics
+///
+/// This function panics if it's not called in a coroutine that is pinned to
+/// the current worker, see [`Builder::pin_to_worker`].
+///
+/// # Examples
+///
+/// ```
+/// use std::rc::Rc;
+/// use may::coroutine;
+///
+/// let h = unsafe {
+///     coroutine::Builder::new()
+///         .pin_to_worker(0)
+///         .spawn(|| {
+///             let data = Rc::new(42);
+///             let their_data = data.clone();
+///             let h = coroutine::spawn_local(move || *their_data + 1);
+///             h.join().unwrap() + *data
+///         })
+///         .unwrap()
+/// };
+/// assert_eq!(h.join().unwrap(), 85);
+/// ```
+///
+/// [`JoinHandle`]: struct.JoinHandle.html
+/// [`spawn`]: fn.spawn.html
+/// [`Builder::pin_to_worker`]: struct.Builder.html#method.pin_to_worker
+#[track_caller]
+pub unsafe fn spawn_local<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + 'static,
+    T: Send + 'static,
+{
+    let worker = match get_co_local_data() {
+        Some(local) => local.as_ref().get_co().pinned_worker(),
+        None => None,
+    };
+    // the spawning coroutine would never leave the worker
+    assert!(
+        worker.is_some() && worker == current_worker_id(),
+        "`spawn_local` must be called in a pinned coroutine"
+    );
+
+    let f = LocalFn(f);
+    Builder::new()
+        .pin_to_current_worker()
+        .spawn(move || f.call())
+        .unwrap()
+}
+
 /// Gets a handle to the coroutine that invokes it.
 /// it will panic if you call it in a thread context
 #[inline]
@@ -442,6 +1090,13 @@ pub fn current() -> Coroutine {
     }
 }
 
+/// Gets an [`Unparker`] for the coroutine that invokes it.
+/// it will panic if you call it in a thread context
+#[inline]
+pub fn unparker() -> Unparker {
+    current().unparker()
+}
+
 /// if current context is coroutine
 #[inline]
 pub fn is_coroutine() -> bool {
@@ -499,19 +1154,176 @@ pub fn park_timeout(dur: Duration) {
     park_timeout_impl(Some(dur));
 }
 
+// get the handle of the coroutine
+#[inline]
+fn co_handle(co: &CoroutineImpl) -> Option<&Coroutine> {
+    let local = get_co_local(co);
+    if local.is_null() {
+        return None;
+    }
+    Some(unsafe { &*local }.get_co())
+}
+
+// warn if the coroutine holds the worker for too long
+#[cold]
+fn check_blocking(co: &CoroutineImpl, elapsed: Duration, threshold: Duration) {
+    if elapsed > threshold {
+        warn!(
+            "coroutine {:?} blocked the worker for {:?}, consider to use `spawn_blocking`",
+            co_handle(co)
+                .and_then(|co| co.name())
+                .unwrap_or("<unnamed>"),
+            elapsed
+        );
+    }
+}
+
+// record the time when the coroutine is pushed to a run queue
+#[inline]
+pub(crate) fn mark_ready(_co: &CoroutineImpl) {
+    #[cfg(feature = "co_stats")]
+    if let Some(co) = co_handle(_co) {
+        let now = crate::timeout_list::now();
+        co.inner.stats.ready_at.store(now, Ordering::Relaxed);
+    }
+}
+
+// record the statistics of one run of the coroutine
+#[cfg(feature = "co_stats")]
+fn record_run(co: &CoroutineImpl, elapsed: Duration, yielded: bool) {
+    let co = match co_handle(co) {
+        Some(co) => co,
+        None => return,
+    };
+    let stats = &co.inner.stats;
+    let ns = elapsed.as_nanos() as u64;
+    let ready_at = stats.ready_at.swap(0, Ordering::Relaxed);
+    if ready_at != 0 {
+        let run_at = crate::timeout_list::now().saturating_sub(ns);
+        let delay = run_at.saturating_sub(ready_at);
+        stats.sched_delay.fetch_add(delay, Ordering::Relaxed);
+    }
+    stats.cpu_time.fetch_add(ns, Ordering::Relaxed);
+    if yielded {
+        stats.yields.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// the message of the panic payload if it's a string
+fn panic_message(panic: &(dyn Any + Send)) -> Option<&str> {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(|s| s.as_str()))
+}
+
+// apply the panic policy of the panicked coroutine
+#[cold]
+fn on_panic(co: &Coroutine, panic: &(dyn Any + Send)) {
+    let policy = match co.inner.panic_policy {
+        Some(ref policy) => policy.clone(),
+        None => config().get_panic_policy(),
+    };
+    match policy {
+        PanicPolicy::Continue => {}
+        PanicPolicy::Abort => {
+            error!("coroutine panicked, id = {}, aborting", co.id());
+            std::process::abort();
+        }
+        PanicPolicy::Restart(f) => {
+            // don't let the callback kill the worker
+            let ret = panic::catch_unwind(panic::AssertUnwindSafe(|| f(co, panic)));
+            if ret.is_err() {
+                error!("panic restart callback panicked, id = {}", co.id());
+            }
+        }
+    }
+}
+
 /// run the coroutine
 #[inline]
 pub(crate) fn run_coroutine(mut co: CoroutineImpl) {
-    match co.resume() {
+    if let Some(route) = route(&co) {
+        // the coroutine of a runtime can only be run by the threads of it
+        if !is_current_scheduler(route.sched) {
+            return route.sched.schedule_global(co);
+        }
+        // the pinned coroutine can only be run by its own worker
+        if let Some(worker) = route.worker {
+            if current_worker_id() != Some(worker) {
+                return route.sched.schedule_pinned(co, worker);
+            }
+        }
+    }
+
+    // the group may put it aside until there is room for it
+    let group = co_handle(&co).and_then(|h| h.group().cloned());
+    if let Some(ref group) = group {
+        co = match group.admit(co) {
+            Some(co) => co,
+            None => return,
+        };
+    }
+
+    metrics::inc(Counter::Resumed);
+    let threshold = config().get_block_threshold();
+    let timed = cfg!(feature = "co_stats") || !threshold.is_zero() || group.is_some();
+    let start = timed.then(Instant::now);
+    #[cfg(feature = "tracing")]
+    let spans = unsafe { get_co_local(&co).as_ref() }.map(|local| local.get_spans());
+    #[cfg(feature = "tracing")]
+    if let Some(spans) = spans {
+        spans.restore();
+    }
+    reset_yield_checks();
+    let ret = co.resume();
+    #[cfg(feature = "tracing")]
+    if let Some(spans) = spans {
+        spans.save();
+    }
+    if let Some(start) = start {
+        let elapsed = start.elapsed();
+        #[cfg(feature = "co_stats")]
+        record_run(&co, elapsed, ret.is_some());
+        if !threshold.is_zero() {
+            check_blocking(&co, elapsed, threshold);
+        }
+        if let Some(ref group) = group {
+            group.leave(elapsed);
+        }
+    }
+
+    match ret {
         Some(ev) => ev.subscribe(co),
         None => {
             // panic happened here
             let local = unsafe { &mut *get_co_local(&co) };
             let join = local.get_join();
-            // set the panic data
+            // set the panic data, the cancellation doesn't have panic data
             if let Some(panic) = co.get_panic_data() {
+                // the cancelled coroutine unwinds with the cancel error
+                let cancelled = matches!(
+                    panic.downcast_ref::<generator::Error>(),
+                    Some(generator::Error::Cancel)
+                );
+                if !cancelled {
+                    let h = local.get_co();
+                    error!(
+                        "coroutine panicked, id = {}, name = {:?}, metadata = {:?}, panic = {:?}",
+                        h.id(),
+                        h.name(),
+                        h.metadata(),
+                        panic_message(&*panic)
+                    );
+                    on_panic(h, &*panic);
+                }
                 join.set_panic_data(panic);
             }
+            #[cfg(feature = "leak_detect")]
+            LIVE.lock().remove(&local.get_co().id());
+            if let Some(group) = local.get_co().group() {
+                group.remove_member(local.get_co().id());
+            }
             // trigger the join here
             join.trigger();
             Done::drop_coroutine(co);
@@ -1,5 +1,6 @@
 use std::panic;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, TryRecvError};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -9,12 +10,211 @@ use crate::coroutine_impl::{
 };
 use crate::join::JoinHandle;
 use crate::scoped::spawn_unsafe;
-use crate::sync::Mutex;
+use crate::sync::{mpmc, mpsc, spsc, Mutex};
 use crate::sync::{AtomicOption, Blocker};
+use crate::time::Interval;
 use crate::yield_now::yield_with;
 
 use crossbeam::queue::SegQueue;
 
+/// A type that can wait for its readiness in a select coroutine.
+///
+/// The select coroutines added by [`Cqueue::add_select`] call [`select`] as
+/// the top half, and the output is passed to the bottom half. The select
+/// coroutine is cancelled when the cqueue is dropped, so [`select`] should
+/// park with the coroutine primitives of this crate to be cancelable.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::cqueue::{self, Selectable};
+/// use may::sync::mpsc::{channel, Receiver};
+///
+/// // a third-party type that opts into the selection
+/// struct Lines(Receiver<String>);
+///
+/// impl Selectable for Lines {
+///     type Output = Option<String>;
+///
+///     fn try_select(&mut self) -> Option<Self::Output> {
+///         self.0.try_select().map(Result::ok)
+///     }
+///
+///     fn select(&mut self) -> Self::Output {
+///         self.0.recv().ok()
+///     }
+/// }
+///
+/// let (tx, rx) = channel();
+/// let mut lines = Lines(rx);
+/// tx.send("hello".to_owned()).unwrap();
+/// cqueue::scope(|cqueue| {
+///     cqueue.add_select(0, &mut lines, |line| {
+///         assert_eq!(line.as_deref(), Some("hello"));
+///     });
+///     assert_eq!(cqueue.poll(None).unwrap().token, 0);
+/// });
+/// ```
+///
+/// [`select`]: Selectable::select
+pub trait Selectable {
+    /// The output when it's ready.
+    type Output;
+
+    /// Returns the output if it's ready now, this never blocks.
+    fn try_select(&mut self) -> Option<Self::Output>;
+
+    /// Parks the caller until it's ready, returns the output.
+    fn select(&mut self) -> Self::Output;
+}
+
+impl<S: Selectable + ?Sized> Selectable for &mut S {
+    type Output = S::Output;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        (**self).try_select()
+    }
+
+    fn select(&mut self) -> Self::Output {
+        (**self).select()
+    }
+}
+
+// a message or the disconnection is ready
+fn recv_ready<T>(ret: Result<T, TryRecvError>) -> Option<Result<T, RecvError>> {
+    match ret {
+        Ok(v) => Some(Ok(v)),
+        Err(TryRecvError::Empty) => None,
+        Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+    }
+}
+
+/// Ready when a message is received or all the senders are dropped.
+impl<T> Selectable for mpsc::Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        recv_ready(self.try_recv())
+    }
+
+    fn select(&mut self) -> Self::Output {
+        self.recv()
+    }
+}
+
+/// Ready when the channel is closed, the sender never blocks on sending.
+impl<T> Selectable for mpsc::Sender<T> {
+    type Output = ();
+
+    fn try_select(&mut self) -> Option<()> {
+        self.is_closed().then_some(())
+    }
+
+    fn select(&mut self) {
+        self.closed()
+    }
+}
+
+/// Ready when a message is received or all the senders are dropped.
+impl<T> Selectable for mpmc::Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        recv_ready(self.try_recv())
+    }
+
+    fn select(&mut self) -> Self::Output {
+        self.recv()
+    }
+}
+
+/// Ready when a message is received or the sender is dropped.
+impl<T> Selectable for spsc::Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        recv_ready(self.try_recv())
+    }
+
+    fn select(&mut self) -> Self::Output {
+        self.recv()
+    }
+}
+
+/// Ready when the coroutine is finished, call `join` to get the result.
+impl<T> Selectable for JoinHandle<T> {
+    type Output = ();
+
+    fn try_select(&mut self) -> Option<()> {
+        self.is_done().then_some(())
+    }
+
+    fn select(&mut self) {
+        self.wait()
+    }
+}
+
+/// Ready on the next tick, the output is the deadline of the tick.
+impl Selectable for Interval {
+    type Output = Instant;
+
+    fn try_select(&mut self) -> Option<Instant> {
+        self.try_tick()
+    }
+
+    fn select(&mut self) -> Instant {
+        self.tick()
+    }
+}
+
+/// Ready when an event of the cqueue is polled, or all its select coroutines
+/// are finished.
+///
+/// This makes a cqueue one selectable handle in another cqueue, e.g. a
+/// library multiplexes its internal events with a cqueue and the caller
+/// selects on it together with its own events. The bottom half of the inner
+/// event is already run when the output is passed to the outer one. The
+/// inner cqueue should not be polled elsewhere at the same time.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate may;
+///
+/// use may::cqueue;
+/// use may::sync::mpsc::channel;
+///
+/// fn main() {
+///     let (tx, rx) = channel();
+///     tx.send(1).unwrap();
+///     cqueue::scope(|inner| {
+///         cqueue_add_oneshot!(inner, 7, _ = rx.recv() => {});
+///         let mut inner = inner;
+///         cqueue::scope(|outer| {
+///             outer.add_select(0, &mut inner, |ev| {
+///                 assert_eq!(ev.unwrap().token, 7);
+///             });
+///             assert_eq!(outer.poll(None).unwrap().token, 0);
+///         });
+///     });
+/// }
+/// ```
+impl<T> Selectable for &Cqueue<T> {
+    type Output = Result<Event<T>, PollError>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        match self.try_poll() {
+            Err(PollError::Timeout) => None,
+            ret => Some(ret),
+        }
+    }
+
+    fn select(&mut self) -> Self::Output {
+        self.poll(None)
+    }
+}
+
 /// This enumeration is the list of the possible reasons that `poll`
 /// could not return Event when called.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -44,9 +244,9 @@ enum EventKind {
 /// The event that `poll` would return, events are generated when a select coroutine
 /// has finished it's top half
 #[derive(Debug)]
-pub struct Event {
+pub struct Event<T = usize> {
     /// the token associated with the select coroutine
-    pub token: usize,
+    pub token: T,
     /// the select coroutine can use it to pass extra data with the caller
     pub extra: usize,
     /// id of the select coroutine, used internally to locate the JoinHandle
@@ -57,7 +257,7 @@ pub struct Event {
     co: Option<CoroutineImpl>,
 }
 
-impl Event {
+impl<T> Event<T> {
     /// continue the select coroutine with it's bottom half
     /// when `poll` got a Normal event, should always call it first
     fn continue_bottom(&mut self) {
@@ -71,35 +271,46 @@ impl Event {
 /// you can only use the `remove` method to manually delete the coroutine
 pub struct Selector {
     co: Coroutine,
+    done: Arc<AtomicBool>,
 }
 
 impl Selector {
     /// terminate the select coroutine
     /// this would remove the selector from the associated cqueue
+    /// the slot of the selector is reused by the later `add`
     pub fn remove(self) {
-        unsafe { self.co.cancel() };
+        if !self.is_done() {
+            unsafe { self.co.cancel() };
+        }
+    }
+
+    /// return true if the select coroutine is finished or removed
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
     }
 }
 
 /// each select coroutine would use this struct to communicate with
 /// the cqueue. the struct is created in `add` for each select coroutine
-pub struct EventSender<'a> {
+pub struct EventSender<'a, T: Clone = usize> {
     // index of the select coroutine
     id: usize,
     // associated token, passed from `add`
-    token: usize,
+    token: T,
     // the select coroutine can use it to pass extra data to the caller
     extra: AtomicUsize,
+    // set when the select coroutine is finished
+    done: Arc<AtomicBool>,
     // the mpsc event queue to collect the events
-    cqueue: &'a Cqueue,
+    cqueue: &'a Cqueue<T>,
 }
 
-unsafe impl<'a> Send for EventSender<'a> {}
+unsafe impl<'a, T: Clone + Send> Send for EventSender<'a, T> {}
 
-impl<'a> EventSender<'a> {
+impl<'a, T: Clone> EventSender<'a, T> {
     /// get the token
-    pub fn get_token(&self) -> usize {
-        self.token
+    pub fn get_token(&self) -> T {
+        self.token.clone()
     }
 
     /// send out the event
@@ -111,11 +322,11 @@ impl<'a> EventSender<'a> {
     }
 }
 
-impl<'a> EventSource for EventSender<'a> {
+impl<'a, T: Clone> EventSource for EventSender<'a, T> {
     fn subscribe(&mut self, co: CoroutineImpl) {
         self.cqueue.ev_queue.push(Event {
             id: self.id,
-            token: self.token,
+            token: self.token.clone(),
             extra: self.extra.load(Ordering::Relaxed),
             kind: EventKind::Normal,
             co: Some(co),
@@ -130,12 +341,13 @@ impl<'a> EventSource for EventSender<'a> {
     }
 }
 
-impl<'a> Drop for EventSender<'a> {
+impl<'a, T: Clone> Drop for EventSender<'a, T> {
     // when the select coroutine finished will trigger this drop
     fn drop(&mut self) {
+        self.done.store(true, Ordering::Release);
         self.cqueue.ev_queue.push(Event {
             id: self.id,
-            token: self.token,
+            token: self.token.clone(),
             extra: self.extra.load(Ordering::Relaxed),
             kind: EventKind::Done,
             co: None,
@@ -147,55 +359,112 @@ impl<'a> Drop for EventSender<'a> {
     }
 }
 
+// the select coroutine handles, the slots of the finished ones are reused
+#[derive(Default)]
+struct Slots {
+    handles: Vec<Option<JoinHandle<()>>>,
+    free: Vec<usize>,
+}
+
 /// cqueue interface for general select model
-pub struct Cqueue {
+///
+/// the token type `T` is delivered with each event, it can be an enum that
+/// carries the data needed to handle the event. the default is `usize`
+pub struct Cqueue<T = usize> {
     // the mpsc queue that transfer event
-    ev_queue: SegQueue<Event>,
+    ev_queue: SegQueue<Event<T>>,
     // thread/coroutine for wake up
     to_wake: AtomicOption<Arc<Blocker>>,
     // track how many coroutines left
     cnt: AtomicUsize,
     // store the select coroutine handles
-    selectors: Mutex<Vec<Option<JoinHandle<()>>>>,
-    // total created select coroutines
-    total: AtomicUsize,
+    selectors: Mutex<Slots>,
     // panic status
     is_panicking: AtomicBool,
 }
 
-impl Cqueue {
+impl<T: Clone + Send> Cqueue<T> {
     /// register a select coroutine with the cqueue
     /// should use `cqueue_add` and `cqueue_add_oneshot` macros to
     /// create select coroutines correctly
-    fn add_impl<'a, F>(&self, token: usize, f: F) -> Selector
+    fn add_impl<'a, F>(&self, token: T, f: F) -> Selector
     where
-        F: FnOnce(EventSender) + Send + 'a,
+        F: FnOnce(EventSender<T>) + Send + 'a,
     {
+        let mut slots = self.selectors.lock().unwrap();
+        let id = match slots.free.pop() {
+            Some(id) => id,
+            None => {
+                slots.handles.push(None);
+                slots.handles.len() - 1
+            }
+        };
+        let done = Arc::new(AtomicBool::new(false));
         let sender = EventSender {
-            id: self.total.load(Ordering::Relaxed),
+            id,
             token,
             extra: 0.into(),
+            done: done.clone(),
             cqueue: self,
         };
         let h = unsafe { spawn_unsafe(move || f(sender)) };
         let co = h.coroutine().clone();
         self.cnt.fetch_add(1, Ordering::Relaxed);
-
-        self.total.fetch_add(1, Ordering::Relaxed);
-        self.selectors.lock().unwrap().push(Some(h));
-        Selector { co }
+        slots.handles[id] = Some(h);
+        Selector { co, done }
     }
 
     /// register a select coroutine with the cqueue
     /// should use `cqueue_add` and `cqueue_add_oneshot` macros to
     /// create select coroutines correctly
-    pub fn add<'a, F>(&self, token: usize, f: F) -> Selector
+    ///
+    /// the selectors can be added at any time, even when the cqueue is
+    /// polled. to re-arm a finished selector, just add it again
+    pub fn add<'a, F>(&self, token: T, f: F) -> Selector
     where
-        F: FnOnce(EventSender) + Send + 'a,
+        F: FnOnce(EventSender<T>) + Send + 'a,
     {
         self.add_impl(token, f)
     }
 
+    /// register a select coroutine that waits for the selectable object
+    ///
+    /// the output of [`Selectable::select`] is passed to `f`, which is run
+    /// as the bottom half when the event is polled
+    pub fn add_select<'a, S, F>(&self, token: T, s: &'a mut S, f: F) -> Selector
+    where
+        S: Selectable + Send + ?Sized + 'a,
+        F: FnOnce(S::Output) + Send + 'a,
+    {
+        self.add_impl(token, move |es| {
+            let out = s.select();
+            es.send(0);
+            f(out)
+        })
+    }
+}
+
+impl<T> Cqueue<T> {
+    fn new() -> Self {
+        Cqueue {
+            ev_queue: SegQueue::new(),
+            to_wake: AtomicOption::none(),
+            cnt: AtomicUsize::new(0),
+            selectors: Mutex::new(Slots::default()),
+            is_panicking: AtomicBool::new(false),
+        }
+    }
+
+    /// return the number of the select coroutines that are not finished
+    pub fn len(&self) -> usize {
+        self.cnt.load(Ordering::Relaxed)
+    }
+
+    /// return true if there is no running select coroutine
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     // when the select coroutine is done, check the panic status
     // if it's panicked, re throw the panic data
     fn check_panic(&self, id: usize) {
@@ -204,11 +473,13 @@ impl Cqueue {
         }
 
         use generator::Error;
-        match self.selectors.lock().unwrap()[id]
-            .take()
-            .expect("join handler not set")
-            .join()
-        {
+        let handle = {
+            let mut slots = self.selectors.lock().unwrap();
+            let handle = slots.handles[id].take().expect("join handler not set");
+            slots.free.push(id);
+            handle
+        };
+        match handle.join() {
             Ok(_) => {}
             Err(panic) => {
                 if let Some(err) = panic.downcast_ref::<Error>() {
@@ -223,12 +494,30 @@ impl Cqueue {
         }
     }
 
+    /// poll an event that is ready without blocking
+    ///
+    /// returns `Err(PollError::Timeout)` if there is no event yet, the same
+    /// as `poll` with a zero timeout but never parks the caller
+    pub fn try_poll(&self) -> Result<Event<T>, PollError> {
+        loop {
+            match self.ev_queue.pop() {
+                Some(ev) if ev.kind == EventKind::Done => self.check_panic(ev.id),
+                Some(mut ev) => {
+                    ev.continue_bottom();
+                    return Ok(ev);
+                }
+                None if self.cnt.load(Ordering::Relaxed) == 0 => return Err(PollError::Finished),
+                None => return Err(PollError::Timeout),
+            }
+        }
+    }
+
     /// poll an event that is ready to process
     /// when the event is returned the bottom half is already run
     /// the API is "completion" mode
     /// if any panic in select coroutine detected during the poll
     /// it will propagate the panic to the caller
-    pub fn poll(&self, timeout: Option<Duration>) -> Result<Event, PollError> {
+    pub fn poll(&self, timeout: Option<Duration>) -> Result<Event<T>, PollError> {
         macro_rules! run_ev {
             ($ev:ident) => {{
                 if $ev.kind == EventKind::Done {
@@ -240,7 +529,7 @@ impl Cqueue {
             }};
         }
 
-        let deadline = timeout.map(|dur| Instant::now() + dur);
+        let deadline = timeout.map(|dur| crate::time::now() + dur);
         loop {
             match self.ev_queue.pop() {
                 Some(mut ev) => run_ev!(ev),
@@ -267,14 +556,14 @@ impl Cqueue {
 
             // check the timeout
             match deadline {
-                Some(d) if Instant::now() >= d => return Err(PollError::Timeout),
+                Some(d) if crate::time::now() >= d => return Err(PollError::Timeout),
                 _ => {}
             }
         }
     }
 }
 
-impl Drop for Cqueue {
+impl<T> Drop for Cqueue<T> {
     // this would cancel all unfinished select coroutines
     // and wait until all of them return back
     fn drop(&mut self) {
@@ -282,6 +571,7 @@ impl Drop for Cqueue {
         self.selectors
             .lock()
             .unwrap()
+            .handles
             .iter()
             .map(|j| j.as_ref())
             .fold((), |_, join| match join {
@@ -313,13 +603,48 @@ pub fn scope<'a, F, R>(f: F) -> R
 where
     F: FnOnce(&Cqueue) -> R + 'a,
 {
-    let cqueue = Cqueue {
-        ev_queue: SegQueue::new(),
-        to_wake: AtomicOption::none(),
-        cnt: AtomicUsize::new(0),
-        selectors: Mutex::new(Vec::new()),
-        total: AtomicUsize::new(0),
-        is_panicking: AtomicBool::new(false),
-    };
+    typed_scope(f)
+}
+
+/// Create a new `scope` whose select coroutines use the token type `T`.
+///
+/// The token is delivered with each event, so an enum token can carry the
+/// data the poller needs to handle the event.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate may;
+///
+/// use may::cqueue;
+/// use may::sync::mpsc::channel;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// enum Token {
+///     Data(&'static str),
+///     Quit,
+/// }
+///
+/// fn main() {
+///     let (tx, rx) = channel();
+///     tx.send(1).unwrap();
+///
+///     cqueue::typed_scope(|cqueue| {
+///         cqueue_add_oneshot!(cqueue, Token::Data("rx"), _ = rx.recv() => {});
+///         let ev = cqueue.poll(None).unwrap();
+///         assert_eq!(ev.token, Token::Data("rx"));
+///
+///         cqueue_add_oneshot!(cqueue, Token::Quit, _ = () => {});
+///         let ev = cqueue.poll(None).unwrap();
+///         assert_eq!(ev.token, Token::Quit);
+///     });
+/// }
+/// ```
+pub fn typed_scope<'a, T, F, R>(f: F) -> R
+where
+    F: FnOnce(&Cqueue<T>) -> R + 'a,
+{
+    let cqueue = Cqueue::new();
     f(&cqueue)
 }
@@ -0,0 +1,434 @@
+//! post-mortem debugging of the coroutines
+//!
+//! a debugger only sees the os threads in a core dump, the suspended
+//! coroutines live in their own stacks that no thread points to. with the
+//! `core_dump` feature every live coroutine is kept in a list that starts at
+//! the `MAY_COROUTINES` symbol, with its id, name, stack boundaries and the
+//! stack pointer of its last yield. the list is made of plain C structs, so
+//! it can be read from a core dump without the debug info.
+//!
+//! the stack top is taken from the entry frame of the coroutine when it first
+//! runs, and the bottom is below it by the stack size. a coroutine that never
+//! ran has an empty stack range.
+//!
+//! [`emit_debugger_script`] generates a python script for gdb and lldb that
+//! adds two commands:
+//!
+//! - `may-coroutines` lists the live coroutines
+//! - `may-bt <id>` prints the code addresses found on the stack of the
+//!   coroutine from its last yield, the most recent first
+//!
+//! the backtrace is made by scanning the stack rather than unwinding it, so
+//! it may contain stale frames. a running coroutine is shown from its last
+//! yield, its current frames are in the backtrace of the thread running it.
+
+use std::mem::{self, MaybeUninit};
+use std::ops::Range;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::local::get_co_local_data;
+
+// the head of the live coroutine list, read by the debugger scripts
+#[no_mangle]
+static MAY_COROUTINES: AtomicPtr<Record> = AtomicPtr::new(ptr::null_mut());
+// serialize the changes of the list
+static LOCK: parking_lot::Mutex<()> = parking_lot::const_mutex(());
+
+// a live coroutine, the layout is read by the debugger scripts
+#[repr(C)]
+struct Record {
+    next: AtomicPtr<Record>,
+    prev: AtomicPtr<Record>,
+    id: u64,
+    // set when the coroutine first runs
+    stack_low: AtomicUsize,
+    stack_high: AtomicUsize,
+    // the stack pointer of the last yield, 0 if it never yielded
+    sp: AtomicUsize,
+    name_ptr: *const u8,
+    name_len: usize,
+    // owns the bytes of the name
+    name: Option<Box<str>>,
+}
+
+// the record of a coroutine in the list, removed when dropped
+pub(crate) struct Registration(NonNull<Record>);
+
+// the links are only changed with the lock held, the others are atomic
+unsafe impl Send for Registration {}
+unsafe impl Sync for Registration {}
+
+impl Registration {
+    pub(crate) fn new(id: u64, name: Option<&str>) -> Self {
+        let name: Option<Box<str>> = name.map(Into::into);
+        let (name_ptr, name_len) = match name {
+            Some(ref name) => (name.as_ptr(), name.len()),
+            None => (ptr::null(), 0),
+        };
+        let record = Box::into_raw(Box::new(Record {
+            next: AtomicPtr::new(ptr::null_mut()),
+            prev: AtomicPtr::new(ptr::null_mut()),
+            id,
+            stack_low: AtomicUsize::new(0),
+            stack_high: AtomicUsize::new(0),
+            sp: AtomicUsize::new(0),
+            name_ptr,
+            name_len,
+            name,
+        }));
+
+        let _guard = LOCK.lock();
+        let head = MAY_COROUTINES.load(Ordering::Relaxed);
+        unsafe { &*record }.next.store(head, Ordering::Relaxed);
+        if let Some(head) = unsafe { head.as_ref() } {
+            head.prev.store(record, Ordering::Relaxed);
+        }
+        MAY_COROUTINES.store(record, Ordering::Release);
+        Registration(unsafe { NonNull::new_unchecked(record) })
+    }
+
+    fn record(&self) -> &Record {
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let guard = LOCK.lock();
+        let record = self.record();
+        let next = record.next.load(Ordering::Relaxed);
+        let prev = record.prev.load(Ordering::Relaxed);
+        if let Some(next) = unsafe { next.as_ref() } {
+            next.prev.store(prev, Ordering::Relaxed);
+        }
+        match unsafe { prev.as_ref() } {
+            Some(prev) => prev.next.store(next, Ordering::Relaxed),
+            None => MAY_COROUTINES.store(next, Ordering::Release),
+        }
+        drop(guard);
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+// record the stack range of the running coroutine from its entry frame
+#[inline(never)]
+pub(crate) fn save_stack_top() {
+    let marker = 0usize;
+    let top = std::hint::black_box(&marker) as *const usize as usize;
+    if let Some(local) = get_co_local_data() {
+        let local = unsafe { local.as_ref() };
+        if let Some(registration) = local.registration() {
+            // the lowest bit of the size is the flag of the stack usage log
+            let size = (local.get_co().stack_size() & !1) * mem::size_of::<usize>();
+            let record = registration.record();
+            record
+                .stack_low
+                .store(top.saturating_sub(size), Ordering::Relaxed);
+            record.stack_high.store(top, Ordering::Relaxed);
+        }
+    }
+}
+
+// record the stack pointer of the running coroutine before it yields
+#[inline(never)]
+pub(crate) fn save_sp() {
+    let marker = 0usize;
+    let sp = std::hint::black_box(&marker) as *const usize as usize;
+    if let Some(local) = get_co_local_data() {
+        if let Some(registration) = unsafe { local.as_ref() }.registration() {
+            registration.record().sp.store(sp, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A live coroutine in the registry, see [`coroutines`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoroutineStack {
+    /// The id of the coroutine.
+    pub id: u64,
+    /// The name of the coroutine.
+    pub name: Option<String>,
+    /// The address range of the coroutine stack, empty if it never ran.
+    pub stack: Range<usize>,
+    /// The stack pointer of the last yield, 0 if it never yielded.
+    pub sp: usize,
+}
+
+/// Returns the live coroutines, the most recently spawned first.
+pub fn coroutines() -> Vec<CoroutineStack> {
+    let _guard = LOCK.lock();
+    let mut ret = Vec::new();
+    let mut cur = MAY_COROUTINES.load(Ordering::Acquire);
+    while let Some(record) = unsafe { cur.as_ref() } {
+        ret.push(CoroutineStack {
+            id: record.id,
+            name: record.name.as_deref().map(Into::into),
+            stack: record.stack_low.load(Ordering::Relaxed)
+                ..record.stack_high.load(Ordering::Relaxed),
+            sp: record.sp.load(Ordering::Relaxed),
+        });
+        cur = record.next.load(Ordering::Relaxed);
+    }
+    ret
+}
+
+/// Returns a python script that adds the `may-coroutines` and `may-bt`
+/// commands to gdb and lldb.
+///
+/// The script has the record layout of this build, save it along with the
+/// binary. Load it by `source may.py` in gdb, or by
+/// `command script import may.py` in lldb.
+///
+/// # Examples
+///
+/// ```rust
+/// let script = may::debug::emit_debugger_script();
+/// std::fs::write(std::env::temp_dir().join("may.py"), script).unwrap();
+/// ```
+pub fn emit_debugger_script() -> String {
+    let record = MaybeUninit::<Record>::uninit();
+    let base = record.as_ptr();
+    let offset = |field: *const u8| field as usize - base as usize;
+    let fields = unsafe {
+        [
+            ("NEXT", offset(ptr::addr_of!((*base).next).cast())),
+            ("ID", offset(ptr::addr_of!((*base).id).cast())),
+            ("STACK_LOW", offset(ptr::addr_of!((*base).stack_low).cast())),
+            (
+                "STACK_HIGH",
+                offset(ptr::addr_of!((*base).stack_high).cast()),
+            ),
+            ("SP", offset(ptr::addr_of!((*base).sp).cast())),
+            ("NAME_PTR", offset(ptr::addr_of!((*base).name_ptr).cast())),
+            ("NAME_LEN", offset(ptr::addr_of!((*base).name_len).cast())),
+        ]
+    };
+    let endian = if cfg!(target_endian = "little") {
+        "<"
+    } else {
+        ">"
+    };
+    let mut script = SCRIPT
+        .replace("@WORD@", &mem::size_of::<usize>().to_string())
+        .replace("@ENDIAN@", endian);
+    for (name, offset) in fields {
+        script = script.replace(&format!("@{}@", name), &offset.to_string());
+    }
+    script
+}
+
+const SCRIPT: &str = r##"# the may coroutine commands for gdb and lldb
+#
+# generated by `may::debug::emit_debugger_script()` for the build that uses
+# it, load it by `source <file>` in gdb or `command script import <file>` in
+# lldb
+#
+#   may-coroutines    list the live coroutines
+#   may-bt <id>       print the code addresses on the stack of a coroutine
+
+import re
+import struct
+
+WORD = @WORD@
+WORD_FMT = "@ENDIAN@" + ("Q" if WORD == 8 else "I")
+ID_FMT = "@ENDIAN@Q"
+OFF_NEXT = @NEXT@
+OFF_ID = @ID@
+OFF_STACK_LOW = @STACK_LOW@
+OFF_STACK_HIGH = @STACK_HIGH@
+OFF_SP = @SP@
+OFF_NAME_PTR = @NAME_PTR@
+OFF_NAME_LEN = @NAME_LEN@
+MAX_FRAMES = 64
+
+try:
+    import gdb
+except ImportError:
+    gdb = None
+    import lldb
+
+
+class GdbTarget(object):
+    def head(self):
+        out = gdb.execute("info address MAY_COROUTINES", to_string=True)
+        found = re.search(r"0x[0-9a-fA-F]+", out)
+        if found is None:
+            raise gdb.GdbError("MAY_COROUTINES not found, is the core_dump feature on?")
+        return int(found.group(0), 16)
+
+    def read(self, addr, size):
+        return bytes(gdb.selected_inferior().read_memory(addr, size))
+
+    def symbol(self, pc):
+        out = gdb.execute("info symbol %#x" % pc, to_string=True)
+        if out.startswith("No symbol") or ".text" not in out:
+            return None
+        name = out.split(" in section")[0].strip()
+        sal = gdb.find_pc_line(pc)
+        if sal.symtab is not None:
+            name += " at %s:%d" % (sal.symtab.filename, sal.line)
+        return name
+
+
+class LldbTarget(object):
+    def __init__(self, debugger):
+        self.target = debugger.GetSelectedTarget()
+        self.process = self.target.GetProcess()
+
+    def head(self):
+        for ctx in self.target.FindSymbols("MAY_COROUTINES"):
+            return ctx.GetSymbol().GetStartAddress().GetLoadAddress(self.target)
+        raise RuntimeError("MAY_COROUTINES not found, is the core_dump feature on?")
+
+    def read(self, addr, size):
+        error = lldb.SBError()
+        data = self.process.ReadMemory(addr, size, error)
+        if not error.Success():
+            raise RuntimeError(error.GetCString())
+        return data
+
+    def symbol(self, pc):
+        addr = self.target.ResolveLoadAddress(pc)
+        sym = addr.GetSymbol()
+        if not sym.IsValid() or sym.GetType() != lldb.eSymbolTypeCode:
+            return None
+        start = sym.GetStartAddress().GetLoadAddress(self.target)
+        name = "%s + %d" % (sym.GetName(), pc - start)
+        line = addr.GetLineEntry()
+        if line.IsValid():
+            name += " at %s:%d" % (line.GetFileSpec(), line.GetLine())
+        return name
+
+
+def word(t, addr):
+    return struct.unpack(WORD_FMT, t.read(addr, WORD))[0]
+
+
+def records(t):
+    cur = word(t, t.head())
+    while cur:
+        rec = {
+            "id": struct.unpack(ID_FMT, t.read(cur + OFF_ID, 8))[0],
+            "low": word(t, cur + OFF_STACK_LOW),
+            "high": word(t, cur + OFF_STACK_HIGH),
+            "sp": word(t, cur + OFF_SP),
+            "name": "<unnamed>",
+        }
+        name_len = word(t, cur + OFF_NAME_LEN)
+        if name_len:
+            name = t.read(word(t, cur + OFF_NAME_PTR), name_len)
+            rec["name"] = name.decode("utf-8", "replace")
+        yield rec
+        cur = word(t, cur + OFF_NEXT)
+
+
+def list_coroutines(t):
+    lines = ["%-10s %-24s %-32s %s" % ("id", "name", "stack", "used")]
+    for rec in records(t):
+        stack = "%#x-%#x" % (rec["low"], rec["high"])
+        used = rec["high"] - rec["sp"] if rec["sp"] else 0
+        lines.append("%-10d %-24s %-32s %d" % (rec["id"], rec["name"], stack, used))
+    return "\n".join(lines)
+
+
+def backtrace(t, co_id):
+    for rec in records(t):
+        if rec["id"] == co_id:
+            break
+    else:
+        return "no coroutine %d" % co_id
+    if not rec["sp"]:
+        return "coroutine %d has not yielded yet" % co_id
+    data = t.read(rec["sp"], rec["high"] - rec["sp"])
+    lines = []
+    for i in range(0, len(data) - WORD + 1, WORD):
+        pc = struct.unpack(WORD_FMT, data[i:i + WORD])[0]
+        sym = t.symbol(pc) if pc else None
+        if sym is not None:
+            lines.append("#%-3d %#x in %s" % (len(lines), pc, sym))
+            if len(lines) == MAX_FRAMES:
+                break
+    return "\n".join(lines)
+
+
+if gdb is not None:
+    class MayCoroutines(gdb.Command):
+        """List the live may coroutines."""
+
+        def __init__(self):
+            super(MayCoroutines, self).__init__("may-coroutines", gdb.COMMAND_STACK)
+
+        def invoke(self, arg, from_tty):
+            gdb.write(list_coroutines(GdbTarget()) + "\n")
+
+    class MayBacktrace(gdb.Command):
+        """Print the code addresses on the stack of a may coroutine: may-bt <id>"""
+
+        def __init__(self):
+            super(MayBacktrace, self).__init__("may-bt", gdb.COMMAND_STACK)
+
+        def invoke(self, arg, from_tty):
+            gdb.write(backtrace(GdbTarget(), int(arg, 0)) + "\n")
+
+    MayCoroutines()
+    MayBacktrace()
+else:
+    def may_coroutines(debugger, command, result, internal_dict):
+        result.AppendMessage(list_coroutines(LldbTarget(debugger)))
+
+    def may_bt(debugger, command, result, internal_dict):
+        result.AppendMessage(backtrace(LldbTarget(debugger), int(command, 0)))
+
+    def __lldb_init_module(debugger, internal_dict):
+        for cmd, func in (("may-coroutines", "may_coroutines"), ("may-bt", "may_bt")):
+            debugger.HandleCommand("command script add -f %s.%s %s" % (__name__, func, cmd))
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // wait until the coroutine is in the registry as expected
+    fn wait_for<F: Fn(&[CoroutineStack]) -> bool>(f: F) -> Vec<CoroutineStack> {
+        for _ in 0..1000 {
+            let cos = coroutines();
+            if f(&cos) {
+                return cos;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("the registry is not updated");
+    }
+
+    #[test]
+    fn registry() {
+        let (tx, rx) = crate::sync::mpsc::channel::<()>();
+        let h = unsafe {
+            crate::coroutine::Builder::new()
+                .name("dumped".to_owned())
+                .spawn(move || rx.recv().unwrap())
+                .unwrap()
+        };
+        let id = h.coroutine().id();
+
+        // it's parked on the channel
+        let cos = wait_for(|cos| cos.iter().any(|co| co.id == id && co.sp != 0));
+        let co = cos.into_iter().find(|co| co.id == id).unwrap();
+        assert_eq!(co.name.as_deref(), Some("dumped"));
+        assert!(co.stack.contains(&co.sp));
+
+        tx.send(()).unwrap();
+        h.join().unwrap();
+        wait_for(|cos| cos.iter().all(|co| co.id != id));
+    }
+
+    #[test]
+    fn debugger_script() {
+        let script = emit_debugger_script();
+        assert!(script.contains("MAY_COROUTINES"));
+        assert!(script.contains(&format!("WORD = {}", mem::size_of::<usize>())));
+        assert!(!script.contains('@'));
+    }
+}
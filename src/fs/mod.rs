@@ -0,0 +1,352 @@
+//! Filesystem manipulation operations
+//!
+//! The regular files can't be driven by the io event loop, so all the
+//! operations in this module are executed in a dedicated blocking thread pool
+//! while the calling coroutine is parked. This would not stall the worker
+//! threads and other coroutines when accessing the disk.
+//!
+//! In a thread context the operations are executed directly in the calling
+//! thread, just like `std::fs`.
+//!
+//! [`watch`] reports the changes of a file or directory, e.g. to hot reload
+//! the config in a coroutine.
+
+use std::fs::{self as std_fs, DirEntry, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::blocking_pool::run_blocking;
+
+mod watch;
+
+pub use self::watch::{watch, WatchEvent, WatchKind, Watcher};
+
+/// A reference to an open file on the filesystem.
+///
+/// The methods are the counterparts of `std::fs::File`, the difference is
+/// that each operation is offloaded to the blocking thread pool so that the
+/// calling coroutine doesn't block its worker thread.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::{Read, Write};
+/// use may::fs::File;
+///
+/// let mut f = File::create("foo.txt").unwrap();
+/// f.write_all(b"hello").unwrap();
+/// f.sync_all().unwrap();
+///
+/// let mut data = Vec::new();
+/// File::open("foo.txt").unwrap().read_to_end(&mut data).unwrap();
+/// assert_eq!(data, b"hello");
+/// ```
+#[derive(Debug)]
+pub struct File {
+    sys: Arc<std_fs::File>,
+}
+
+impl File {
+    /// Attempts to open a file in read-only mode.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        run_blocking(move || std_fs::File::open(path)).map(File::from_std)
+    }
+
+    /// Opens a file in write-only mode, create it if not exist or truncate it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        run_blocking(move || std_fs::File::create(path)).map(File::from_std)
+    }
+
+    /// Opens a file at `path` with the options specified by `options`.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        let options = options.clone();
+        run_blocking(move || options.open(path)).map(File::from_std)
+    }
+
+    /// Converts a `std::fs::File` to a `File`.
+    pub fn from_std(file: std_fs::File) -> File {
+        File {
+            sys: Arc::new(file),
+        }
+    }
+
+    /// Gets the underlying `std::fs::File`.
+    #[inline]
+    pub fn inner(&self) -> &std_fs::File {
+        &self.sys
+    }
+
+    // run the blocking operation with a shared ref of the file
+    fn with_file<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&std_fs::File) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let file = self.sys.clone();
+        run_blocking(move || f(&file))
+    }
+
+    /// Attempts to sync all OS-internal metadata and data to disk.
+    pub fn sync_all(&self) -> io::Result<()> {
+        self.with_file(|f| f.sync_all())
+    }
+
+    /// Synchronizes the file content to disk, without the metadata.
+    pub fn sync_data(&self) -> io::Result<()> {
+        self.with_file(|f| f.sync_data())
+    }
+
+    /// Truncates or extends the underlying file.
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        self.with_file(move |f| f.set_len(size))
+    }
+
+    /// Queries metadata about the underlying file.
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        self.with_file(|f| f.metadata())
+    }
+
+    /// Reads all bytes until EOF and appends them to `buf`.
+    ///
+    /// Unlike the default `Read::read_to_end`, the whole read is done in a
+    /// single blocking job.
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let ret = self.with_file(|mut f| {
+            let mut data = Vec::new();
+            f.read_to_end(&mut data).map(|_| data)
+        })?;
+        buf.extend_from_slice(&ret);
+        Ok(ret.len())
+    }
+
+    /// Reads a number of bytes starting from a given offset.
+    ///
+    /// The current cursor of the file is not affected.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let len = buf.len();
+        let data = self.with_file(move |f| {
+            let mut data = vec![0; len];
+            read_at(f, &mut data, offset).map(|n| {
+                data.truncate(n);
+                data
+            })
+        })?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    /// Writes the whole buffer starting from a given offset.
+    ///
+    /// The current cursor of the file is not affected.
+    pub fn write_all_at(&self, buf: &[u8], mut offset: u64) -> io::Result<()> {
+        let data = buf.to_vec();
+        self.with_file(move |f| {
+            let mut buf = &data[..];
+            while !buf.is_empty() {
+                match write_at(f, buf, offset) {
+                    Ok(0) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "failed to write whole buffer",
+                        ))
+                    }
+                    Ok(n) => {
+                        buf = &buf[n..];
+                        offset += n as u64;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(unix)]
+fn read_at(f: &std_fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(f, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_at(f: &std_fs::File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::write_at(f, buf, offset)
+}
+
+// windows moves the cursor when read/write with offset
+#[cfg(windows)]
+fn read_at(f: &std_fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(f, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(f: &std_fs::File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(f, buf, offset)
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len();
+        let data = self.with_file(move |mut f| {
+            let mut data = vec![0; len];
+            f.read(&mut data).map(|n| {
+                data.truncate(n);
+                data
+            })
+        })?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        File::read_to_end(self, buf)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let data = buf.to_vec();
+        self.with_file(move |mut f| f.write(&data))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let data = buf.to_vec();
+        self.with_file(move |mut f| f.write_all(&data))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_file(|mut f| f.flush())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.with_file(move |mut f| f.seek(pos))
+    }
+}
+
+impl From<std_fs::File> for File {
+    fn from(file: std_fs::File) -> File {
+        File::from_std(file)
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for File {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        std::os::unix::io::AsRawFd::as_raw_fd(&*self.sys)
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for File {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        std::os::windows::io::AsRawHandle::as_raw_handle(&*self.sys)
+    }
+}
+
+/// Reads the entire contents of a file into a bytes vector.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::read(path))
+}
+
+/// Reads the entire contents of a file into a string.
+pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::read_to_string(path))
+}
+
+/// Writes a slice as the entire contents of a file.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    let contents = contents.as_ref().to_vec();
+    run_blocking(move || std_fs::write(path, contents))
+}
+
+/// Given a path, query the file system to get information about it.
+pub fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::metadata(path))
+}
+
+/// Returns all the entries within a directory.
+///
+/// Unlike `std::fs::read_dir`, the entries are collected in a single blocking
+/// job instead of being returned by an iterator.
+pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<Vec<DirEntry>> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::read_dir(path)?.collect())
+}
+
+/// Recursively create a directory and all of its parent components.
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::create_dir_all(path))
+}
+
+/// Removes a file from the filesystem.
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::remove_file(path))
+}
+
+/// Removes a directory at this path, after removing all its contents.
+pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::remove_dir_all(path))
+}
+
+/// Rename a file or directory to a new name.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    run_blocking(move || std_fs::rename(from, to))
+}
+
+/// Returns the canonical, absolute form of a path.
+pub fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let path = path.as_ref().to_owned();
+    run_blocking(move || std_fs::canonicalize(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_read_write() {
+        let dir = tempdir::TempDir::new("may_fs").unwrap();
+        let path = dir.path().join("test.txt");
+
+        let h = go!(move || {
+            let mut f = File::create(&path).unwrap();
+            f.write_all(b"hello world").unwrap();
+            f.write_all_at(b"HELLO", 0).unwrap();
+            f.sync_all().unwrap();
+            assert_eq!(f.metadata().unwrap().len(), 11);
+
+            let mut f = File::open(&path).unwrap();
+            let mut buf = [0; 5];
+            assert_eq!(f.read_at(&mut buf, 6).unwrap(), 5);
+            assert_eq!(&buf, b"world");
+            let mut data = Vec::new();
+            f.read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"HELLO world");
+
+            let names: Vec<_> = read_dir(path.parent().unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|e| e.file_name())
+                .collect();
+            assert_eq!(names, vec!["test.txt"]);
+            remove_file(&path).unwrap();
+            assert!(read(&path).is_err());
+        });
+        h.join().unwrap();
+    }
+}
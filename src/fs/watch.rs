@@ -0,0 +1,137 @@
+//! watching the changes of a file or directory
+//!
+//! on linux the inotify fd is registered to the reactor, [`Watcher::recv`]
+//! parks the coroutine until the kernel reports a change. the kqueue
+//! `EVFILT_VNODE` of the bsds and the `ReadDirectoryChangesW` of windows
+//! can't be waited by the reactor, they wait in the blocking thread pool
+//! instead, still no thread is dedicated to a watcher.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[path = "watch/inotify.rs"]
+mod sys;
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+#[path = "watch/kqueue.rs"]
+mod sys;
+
+#[cfg(windows)]
+#[path = "watch/windows.rs"]
+mod sys;
+
+/// The kind of a change reported by [`Watcher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+    /// A file or directory is created.
+    Create,
+    /// The content is modified.
+    Modify,
+    /// A file or directory is removed.
+    Remove,
+    /// A file or directory is renamed or moved.
+    Rename,
+    /// Any other change, e.g. the metadata is changed or the events
+    /// overflowed in the kernel.
+    Other,
+}
+
+/// A change reported by [`Watcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The kind of the change.
+    pub kind: WatchKind,
+    /// The path of the changed entry. It's the watched path itself if the
+    /// platform doesn't report the entry names, e.g. kqueue.
+    pub path: PathBuf,
+}
+
+/// Watches a file or directory, created by [`watch`].
+///
+/// The directory is not watched recursively, only the changes of its
+/// entries are reported.
+pub struct Watcher {
+    sys: sys::Watcher,
+    events: VecDeque<WatchEvent>,
+}
+
+/// Starts watching the changes of a file or directory.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use may::fs;
+///
+/// let h = may::go!(|| {
+///     let mut watcher = fs::watch("app.toml").unwrap();
+///     loop {
+///         let ev = watcher.recv().unwrap();
+///         println!("{:?} is changed, reload the config", ev.path);
+///     }
+/// });
+/// # drop(h);
+/// ```
+pub fn watch<P: AsRef<Path>>(path: P) -> io::Result<Watcher> {
+    let sys = sys::Watcher::new(path.as_ref())?;
+    Ok(Watcher {
+        sys,
+        events: VecDeque::new(),
+    })
+}
+
+impl Watcher {
+    /// Receives the next change, parking the caller until there is one.
+    ///
+    /// This works in both coroutine and thread contexts.
+    pub fn recv(&mut self) -> io::Result<WatchEvent> {
+        loop {
+            if let Some(ev) = self.events.pop_front() {
+                return Ok(ev);
+            }
+            self.sys.read(&mut self.events)?;
+        }
+    }
+
+    /// Returns the watched path.
+    pub fn path(&self) -> &Path {
+        self.sys.path()
+    }
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("path", &self.path())
+            .field("pending", &self.events.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_dir_create() {
+        let dir = tempdir::TempDir::new("may_watch").unwrap();
+        let path = dir.path().to_owned();
+        let mut watcher = watch(&path).unwrap();
+        let h = go!(move || watcher.recv().unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(path.join("a.txt"), b"hello").unwrap();
+        let ev = h.join().unwrap();
+        // kqueue only reports the directory is written
+        assert!(ev.kind == WatchKind::Create || ev.kind == WatchKind::Modify);
+        assert!(ev.path.starts_with(dir.path()));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+
+use super::{WatchEvent, WatchKind};
+use crate::io::CoIo;
+
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_ATTRIB
+    | libc::IN_DELETE
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVE_SELF;
+
+// big enough for at least one event with the longest name
+const BUF_SIZE: usize = 4096;
+
+pub struct Watcher {
+    io: CoIo<File>,
+    path: PathBuf,
+    buf: Vec<u8>,
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> io::Result<Watcher> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains nul"))?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), MASK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let io = CoIo::new(file).map_err(io::Error::from)?;
+        Ok(Watcher {
+            io,
+            path: path.to_owned(),
+            buf: vec![0; BUF_SIZE],
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&mut self, events: &mut VecDeque<WatchEvent>) -> io::Result<()> {
+        let n = self.io.read(&mut self.buf)?;
+        let head = mem::size_of::<libc::inotify_event>();
+        let mut pos = 0;
+        while pos + head <= n {
+            let ev = unsafe {
+                (self.buf.as_ptr().add(pos) as *const libc::inotify_event).read_unaligned()
+            };
+            let name = &self.buf[pos + head..pos + head + ev.len as usize];
+            pos += head + ev.len as usize;
+
+            let kind = if ev.mask & libc::IN_CREATE != 0 {
+                WatchKind::Create
+            } else if ev.mask & libc::IN_MODIFY != 0 {
+                WatchKind::Modify
+            } else if ev.mask & (libc::IN_DELETE | libc::IN_DELETE_SELF) != 0 {
+                WatchKind::Remove
+            } else if ev.mask & (libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_MOVE_SELF) != 0
+            {
+                WatchKind::Rename
+            } else if ev.mask & libc::IN_IGNORED != 0 {
+                // the watch is removed, a remove event is already reported
+                continue;
+            } else {
+                WatchKind::Other
+            };
+            // the name is padded with nul bytes
+            let name = name.split(|b| *b == 0).next().unwrap_or_default();
+            let path = if name.is_empty() {
+                self.path.clone()
+            } else {
+                self.path.join(OsStr::from_bytes(name))
+            };
+            events.push_back(WatchEvent { kind, path });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+
+use super::{WatchEvent, WatchKind};
+use crate::blocking_pool::run_blocking;
+
+const NOTES: u32 = libc::NOTE_WRITE
+    | libc::NOTE_EXTEND
+    | libc::NOTE_ATTRIB
+    | libc::NOTE_DELETE
+    | libc::NOTE_RENAME
+    | libc::NOTE_REVOKE;
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const OPEN_FLAGS: libc::c_int = libc::O_EVTONLY | libc::O_CLOEXEC;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_CLOEXEC;
+
+pub struct Watcher {
+    kq: Arc<File>,
+    // the watched fd must be kept open
+    _target: File,
+    path: PathBuf,
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+// wait for the vnode events and returns their flags
+fn wait_notes(kq: &File) -> io::Result<Vec<u32>> {
+    let mut evs: [libc::kevent; 8] = unsafe { std::mem::zeroed() };
+    let n = loop {
+        let ret = unsafe {
+            libc::kevent(
+                kq.as_raw_fd(),
+                ptr::null(),
+                0,
+                evs.as_mut_ptr(),
+                evs.len() as libc::c_int,
+                ptr::null(),
+            )
+        };
+        match cvt(ret) {
+            Ok(n) => break n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    };
+    Ok(evs[..n].iter().map(|ev| ev.fflags as u32).collect())
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> io::Result<Watcher> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains nul"))?;
+        let fd = cvt(unsafe { libc::open(c_path.as_ptr(), OPEN_FLAGS) })?;
+        let target = unsafe { File::from_raw_fd(fd) };
+        let kq = cvt(unsafe { libc::kqueue() })?;
+        let kq = unsafe { File::from_raw_fd(kq) };
+        cvt(unsafe { libc::fcntl(kq.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+        let mut kev: libc::kevent = unsafe { std::mem::zeroed() };
+        kev.ident = fd as libc::uintptr_t;
+        kev.filter = libc::EVFILT_VNODE;
+        kev.flags = libc::EV_ADD | libc::EV_CLEAR;
+        kev.fflags = NOTES as _;
+        cvt(unsafe { libc::kevent(kq.as_raw_fd(), &kev, 1, ptr::null_mut(), 0, ptr::null()) })?;
+
+        Ok(Watcher {
+            kq: Arc::new(kq),
+            _target: target,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&mut self, events: &mut VecDeque<WatchEvent>) -> io::Result<()> {
+        let kq = self.kq.clone();
+        let notes = run_blocking(move || wait_notes(&kq))?;
+        for note in notes {
+            let kind = if note & (libc::NOTE_DELETE | libc::NOTE_REVOKE) != 0 {
+                WatchKind::Remove
+            } else if note & libc::NOTE_RENAME != 0 {
+                WatchKind::Rename
+            } else if note & (libc::NOTE_WRITE | libc::NOTE_EXTEND) != 0 {
+                WatchKind::Modify
+            } else {
+                WatchKind::Other
+            };
+            events.push_back(WatchEvent {
+                kind,
+                path: self.path.clone(),
+            });
+        }
+        Ok(())
+    }
+}
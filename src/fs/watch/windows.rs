@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+
+use windows_sys::Win32::Foundation::*;
+use windows_sys::Win32::Storage::FileSystem::*;
+
+use super::{WatchEvent, WatchKind};
+use crate::blocking_pool::run_blocking;
+
+const FILTER: u32 = FILE_NOTIFY_CHANGE_FILE_NAME
+    | FILE_NOTIFY_CHANGE_DIR_NAME
+    | FILE_NOTIFY_CHANGE_ATTRIBUTES
+    | FILE_NOTIFY_CHANGE_SIZE
+    | FILE_NOTIFY_CHANGE_LAST_WRITE;
+
+const BUF_SIZE: usize = 16 * 1024;
+
+pub struct Watcher {
+    dir: Arc<File>,
+    dir_path: PathBuf,
+    // only the changes of this entry are reported if a file is watched
+    file_name: Option<OsString>,
+    path: PathBuf,
+}
+
+// wait for the changes of the directory and returns the actions and names
+fn read_changes(dir: &File) -> io::Result<Vec<(u32, OsString)>> {
+    // the records are aligned to 4 bytes
+    let mut buf = vec![0u32; BUF_SIZE / 4];
+    let mut n = 0u32;
+    let ret = unsafe {
+        ReadDirectoryChangesW(
+            dir.as_raw_handle() as HANDLE,
+            buf.as_mut_ptr() as *mut _,
+            BUF_SIZE as u32,
+            0,
+            FILTER,
+            &mut n,
+            ptr::null_mut(),
+            None,
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut changes = Vec::new();
+    // zero bytes returned means the buffer overflowed
+    if n == 0 {
+        changes.push((0, OsString::new()));
+        return Ok(changes);
+    }
+    let base = buf.as_ptr() as *const u8;
+    let mut pos = 0;
+    loop {
+        let info = unsafe { &*(base.add(pos) as *const FILE_NOTIFY_INFORMATION) };
+        let name = unsafe {
+            std::slice::from_raw_parts(info.FileName.as_ptr(), info.FileNameLength as usize / 2)
+        };
+        changes.push((info.Action, OsString::from_wide(name)));
+        if info.NextEntryOffset == 0 {
+            break;
+        }
+        pos += info.NextEntryOffset as usize;
+    }
+    Ok(changes)
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> io::Result<Watcher> {
+        // a file is watched by its parent directory
+        let (dir_path, file_name) = if std::fs::metadata(path)?.is_dir() {
+            (path.to_owned(), None)
+        } else {
+            let dir = match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+                _ => PathBuf::from("."),
+            };
+            (dir, path.file_name().map(|n| n.to_owned()))
+        };
+
+        let wide: Vec<u16> = dir_path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                ptr::null(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                0,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let dir = unsafe { File::from_raw_handle(handle as _) };
+        Ok(Watcher {
+            dir: Arc::new(dir),
+            dir_path,
+            file_name,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&mut self, events: &mut VecDeque<WatchEvent>) -> io::Result<()> {
+        let dir = self.dir.clone();
+        let changes = run_blocking(move || read_changes(&dir))?;
+        for (action, name) in changes {
+            if let Some(file_name) = self.file_name.as_ref() {
+                if action != 0 && &name != file_name {
+                    continue;
+                }
+            }
+            let kind = match action {
+                FILE_ACTION_ADDED => WatchKind::Create,
+                FILE_ACTION_REMOVED => WatchKind::Remove,
+                FILE_ACTION_MODIFIED => WatchKind::Modify,
+                FILE_ACTION_RENAMED_OLD_NAME | FILE_ACTION_RENAMED_NEW_NAME => WatchKind::Rename,
+                _ => WatchKind::Other,
+            };
+            let path = if name.is_empty() {
+                self.path.clone()
+            } else {
+                self.dir_path.join(name)
+            };
+            events.push_back(WatchEvent { kind, path });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+//! Generators built on coroutines
+//!
+//! A generator runs its producing closure in a coroutine and hands the values
+//! to the consumer through an iterator. The producer is parked when it's too
+//! far ahead of the consumer, so a slow consumer never blocks a worker thread
+//! or makes the buffered values grow without bound.
+//!
+
+use std::fmt;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::cancel::trigger_cancel_panic;
+use crate::join::JoinHandle;
+use crate::sync::spsc::{channel, Receiver, Sender};
+use crate::sync::Semphore;
+
+struct Shared {
+    // how many values the producer can send in advance
+    credits: Semphore,
+    // set when the consumer is dropped
+    closed: AtomicBool,
+}
+
+/// The producing side of a [`Gn`], passed to the generator closure.
+pub struct Scope<T> {
+    tx: Sender<T>,
+    shared: Arc<Shared>,
+}
+
+impl<T> Scope<T> {
+    /// Yields a value to the consumer.
+    ///
+    /// The producing coroutine is parked if the buffer is full. If the
+    /// consumer is already dropped, the producing coroutine is unwound just
+    /// like being cancelled, so the rest of the closure would not run.
+    pub fn yield_(&self, v: T) {
+        self.shared.credits.wait();
+        if self.shared.closed.load(Ordering::Acquire) || self.tx.send(v).is_err() {
+            trigger_cancel_panic();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Scope<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Scope { .. }")
+    }
+}
+
+/// A generator whose values are produced by a coroutine.
+///
+/// The generator is consumed as an `Iterator`, the iteration ends when the
+/// producing closure returns. If the closure panics, the panic is propagated
+/// to the consumer when it reaches the end of the values.
+///
+/// Dropping the generator stops the producer at its next `yield_`.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::generator::Gn;
+///
+/// let fib = Gn::new(|s| {
+///     let (mut a, mut b) = (0u64, 1u64);
+///     while b < 100 {
+///         s.yield_(b);
+///         let t = a + b;
+///         a = b;
+///         b = t;
+///     }
+/// });
+///
+/// let v: Vec<_> = fib.collect();
+/// assert_eq!(v, [1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89]);
+/// ```
+pub struct Gn<T> {
+    rx: Receiver<T>,
+    shared: Arc<Shared>,
+    buffer: usize,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> Gn<T> {
+    /// Creates a lazy generator.
+    ///
+    /// The producer only runs ahead to the next value when the consumer asks
+    /// for it, just like a normal generator.
+    pub fn new<F>(f: F) -> Gn<T>
+    where
+        F: FnOnce(&Scope<T>) + Send + 'static,
+    {
+        Gn::with_buffer(0, f)
+    }
+
+    /// Creates a generator that buffers at most `buffer` values.
+    ///
+    /// The producer can run ahead of the consumer until `buffer` values are
+    /// pending, after that it is parked until the consumer takes one.
+    pub fn with_buffer<F>(buffer: usize, f: F) -> Gn<T>
+    where
+        F: FnOnce(&Scope<T>) + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let shared = Arc::new(Shared {
+            credits: Semphore::new(buffer),
+            closed: AtomicBool::new(false),
+        });
+        let scope = Scope {
+            tx,
+            shared: shared.clone(),
+        };
+        let handle = go!(move || f(&scope));
+        Gn {
+            rx,
+            shared,
+            buffer,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl<T> Iterator for Gn<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        // the lazy generator requests the value on demand
+        if self.buffer == 0 && self.handle.is_some() {
+            self.shared.credits.post();
+        }
+
+        match self.rx.recv() {
+            Ok(v) => {
+                if self.buffer != 0 {
+                    self.shared.credits.post();
+                }
+                Some(v)
+            }
+            Err(_) => {
+                // the producer is done, propagate its panic if any
+                if let Some(Err(e)) = self.handle.take().map(|h| h.join()) {
+                    if !matches!(
+                        e.downcast_ref::<generator::Error>(),
+                        Some(generator::Error::Cancel)
+                    ) {
+                        panic::resume_unwind(e);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<T> Drop for Gn<T> {
+    fn drop(&mut self) {
+        // wake up the producer to let it exit
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.credits.post();
+    }
+}
+
+impl<T> fmt::Debug for Gn<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Gn").field("buffer", &self.buffer).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn generator_buffer() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let their_produced = produced.clone();
+        let mut g = Gn::with_buffer(4, move |s| {
+            for i in 0..100 {
+                s.yield_(i);
+                their_produced.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        assert_eq!(g.next(), Some(0));
+        crate::coroutine::sleep(std::time::Duration::from_millis(50));
+        // the producer is parked by the full buffer
+        assert!(produced.load(Ordering::Relaxed) <= 5);
+        assert_eq!(g.sum::<usize>(), 4950);
+    }
+
+    #[test]
+    fn generator_drop() {
+        let done = Arc::new(AtomicBool::new(false));
+        let their_done = done.clone();
+        let mut g = Gn::new(move |s| {
+            for i in 0..1000 {
+                s.yield_(i);
+            }
+            their_done.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(g.next(), Some(0));
+        drop(g);
+        // the producer is unwound and drops its captured data
+        while Arc::strong_count(&done) > 1 {
+            crate::coroutine::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(!done.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "producer panic")]
+    fn generator_panic() {
+        let g = Gn::new(|s| {
+            s.yield_(1);
+            panic!("producer panic");
+        });
+        for _ in g {}
+    }
+}
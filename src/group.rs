@@ -0,0 +1,386 @@
+//! coroutine groups with the scheduling limits
+//!
+//! the coroutines spawned by [`Builder::group`] share the limits of the
+//! group. before a member is resumed by a worker the group is checked, a
+//! member that would exceed the max parallelism or the cpu share is put
+//! aside in the group instead of running. the ones put aside are scheduled
+//! again when a running member yields, or when the next cpu time window
+//! starts.
+//!
+//! the cpu time is only accounted when the member yields, so a member that
+//! runs for long without yielding can exceed the share within a window.
+//!
+//! [`Builder::group`]: crate::coroutine::Builder::group
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam::queue::SegQueue;
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::{home_scheduler, Builder, Coroutine, CoroutineImpl};
+use crate::scheduler::{get_scheduler, Scheduler};
+use crate::sleep::sleep;
+use crate::timeout_list;
+
+// the cpu share is checked per window of this length
+const WINDOW_NS: u64 = 100_000_000;
+// the cpu share is stored in parts per million
+const PPM: u64 = 1_000_000;
+
+/// The statistics of a [`Group`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    /// The number of the members that are not done yet.
+    pub live: usize,
+    /// The number of the members ever spawned.
+    pub spawned: u64,
+    /// The total time that the members have been running on the workers.
+    pub cpu_time: Duration,
+    /// The number of times that a ready member is put aside by the limits.
+    pub throttled: u64,
+}
+
+#[derive(Default)]
+struct Window {
+    start: u64,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    // 0 for no limit
+    max_parallelism: AtomicUsize,
+    // in ppm of all the worker time, 0 for no limit
+    cpu_share: AtomicU64,
+    // the members being run by the workers
+    running: AtomicUsize,
+    window: Mutex<Window>,
+    // the ready members that are put aside
+    pending: SegQueue<CoroutineImpl>,
+    // a coroutine is going to release the pending ones in the next window
+    releasing: AtomicBool,
+    members: Mutex<HashMap<u64, Coroutine>>,
+    spawned: AtomicU64,
+    cpu_time: AtomicU64,
+    throttled: AtomicU64,
+}
+
+/// A group of coroutines that share the scheduling limits.
+///
+/// The group has no limit when created. It's cheap to clone, all the clones
+/// refer to the same group.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::coroutine::{self, Builder, Group};
+///
+/// // the background jobs take at most 20% of the worker time, and one
+/// // worker at a time
+/// let background = Group::new();
+/// background.set_cpu_share(0.2);
+/// background.set_max_parallelism(1);
+///
+/// let handles: Vec<_> = (0..4)
+///     .map(|_| {
+///         let builder = Builder::new().group(&background);
+///         unsafe { builder.spawn(|| coroutine::yield_now()).unwrap() }
+///     })
+///     .collect();
+/// for h in handles {
+///     h.join().unwrap();
+/// }
+/// assert_eq!(background.stats().spawned, 4);
+/// ```
+#[derive(Clone, Default)]
+pub struct Group {
+    inner: Arc<Inner>,
+}
+
+impl Group {
+    /// Creates a group without any limit.
+    pub fn new() -> Group {
+        Group::default()
+    }
+
+    /// Sets the max number of the members that run at the same time, 0
+    /// means no limit.
+    ///
+    /// It takes effect the next time a member is resumed.
+    pub fn set_max_parallelism(&self, n: usize) -> &Self {
+        self.inner.max_parallelism.store(n, Ordering::Relaxed);
+        self.wake_pending();
+        self
+    }
+
+    /// Gets the max number of the members that run at the same time.
+    pub fn get_max_parallelism(&self) -> usize {
+        self.inner.max_parallelism.load(Ordering::Relaxed)
+    }
+
+    /// Sets the share of the total worker time that the members can take,
+    /// in the range of `(0, 1]`, 0 means no limit.
+    ///
+    /// The share is enforced per window of 100ms. It takes effect the next
+    /// time a member is resumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `share` is not in the range of `[0, 1]`.
+    pub fn set_cpu_share(&self, share: f64) -> &Self {
+        assert!((0.0..=1.0).contains(&share), "invalid cpu share {}", share);
+        let ppm = (share * PPM as f64) as u64;
+        self.inner.cpu_share.store(ppm, Ordering::Relaxed);
+        self.wake_pending();
+        self
+    }
+
+    /// Gets the share of the total worker time that the members can take.
+    pub fn get_cpu_share(&self) -> f64 {
+        self.inner.cpu_share.load(Ordering::Relaxed) as f64 / PPM as f64
+    }
+
+    /// Gets the statistics of the group.
+    pub fn stats(&self) -> GroupStats {
+        let inner = &self.inner;
+        GroupStats {
+            live: inner.members.lock().len(),
+            spawned: inner.spawned.load(Ordering::Relaxed),
+            cpu_time: Duration::from_nanos(inner.cpu_time.load(Ordering::Relaxed)),
+            throttled: inner.throttled.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the handles of the members that are not done yet.
+    pub fn members(&self) -> Vec<Coroutine> {
+        self.inner.members.lock().values().cloned().collect()
+    }
+
+    /// Requests all the members to stop, see [`Coroutine::cancel_graceful`].
+    pub fn cancel_graceful(&self) {
+        for co in self.members() {
+            co.cancel_graceful();
+        }
+    }
+
+    /// Cancels all the members, see [`Coroutine::cancel`].
+    ///
+    /// # Safety
+    ///
+    /// The same as [`Coroutine::cancel`], the resources held by the members
+    /// are dropped by unwinding.
+    pub unsafe fn cancel(&self) {
+        for co in self.members() {
+            co.cancel();
+        }
+    }
+
+    // register a new member
+    pub(crate) fn add_member(&self, co: &Coroutine) {
+        self.inner.spawned.fetch_add(1, Ordering::Relaxed);
+        self.inner.members.lock().insert(co.id(), co.clone());
+    }
+
+    // check the limits before resuming the member, returns the coroutine if
+    // it can run now, or else it's put aside
+    pub(crate) fn admit(&self, co: CoroutineImpl) -> Option<CoroutineImpl> {
+        let sched = home_scheduler(&co).unwrap_or_else(get_scheduler);
+        if self.try_enter(sched) {
+            return Some(co);
+        }
+        self.inner.throttled.fetch_add(1, Ordering::Relaxed);
+        self.inner.pending.push(co);
+        // the running ones may have left before the push
+        self.wake_pending();
+        None
+    }
+
+    // the member is done, called before its join handle is triggered
+    pub(crate) fn remove_member(&self, id: u64) {
+        self.inner.members.lock().remove(&id);
+    }
+
+    // called after the member yields or is done
+    pub(crate) fn leave(&self, elapsed: Duration) {
+        let ns = elapsed.as_nanos() as u64;
+        self.inner.cpu_time.fetch_add(ns, Ordering::Relaxed);
+        if self.inner.cpu_share.load(Ordering::Relaxed) != 0 {
+            let now = timeout_list::now();
+            let mut window = self.inner.window.lock();
+            roll_window(&mut window, now);
+            window.used += ns;
+        }
+        self.inner.running.fetch_sub(1, Ordering::SeqCst);
+        self.wake_pending();
+    }
+
+    fn try_enter(&self, sched: &'static Scheduler) -> bool {
+        if self.budget_left(sched).is_some() {
+            return false;
+        }
+        let max = self.inner.max_parallelism.load(Ordering::Relaxed);
+        let running = &self.inner.running;
+        if max == 0 {
+            running.fetch_add(1, Ordering::SeqCst);
+            return true;
+        }
+        let mut cur = running.load(Ordering::SeqCst);
+        while cur < max {
+            match running.compare_exchange_weak(cur, cur + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return true,
+                Err(n) => cur = n,
+            }
+        }
+        false
+    }
+
+    // returns the time left in the current window if the cpu budget is used up
+    fn budget_left(&self, sched: &'static Scheduler) -> Option<u64> {
+        let share = self.inner.cpu_share.load(Ordering::Relaxed);
+        if share == 0 {
+            return None;
+        }
+        let now = timeout_list::now();
+        let mut window = self.inner.window.lock();
+        roll_window(&mut window, now);
+        let budget = share * WINDOW_NS / PPM * sched.active_workers() as u64;
+        (window.used >= budget).then(|| WINDOW_NS - (now - window.start))
+    }
+
+    // schedule a pending member if there is room for it
+    fn wake_pending(&self) {
+        let Some(co) = self.inner.pending.pop() else {
+            return;
+        };
+        let sched = home_scheduler(&co).unwrap_or_else(get_scheduler);
+        if let Some(left) = self.budget_left(sched) {
+            self.inner.pending.push(co);
+            self.release_later(sched, left);
+            return;
+        }
+        let co = if self.is_full() {
+            self.inner.pending.push(co);
+            // a member may have left before the push
+            if self.is_full() {
+                return;
+            }
+            match self.inner.pending.pop() {
+                Some(co) => co,
+                None => return,
+            }
+        } else {
+            co
+        };
+        // the limits are checked again before it runs
+        sched.schedule(co);
+    }
+
+    fn is_full(&self) -> bool {
+        let max = self.inner.max_parallelism.load(Ordering::Relaxed);
+        max != 0 && self.inner.running.load(Ordering::SeqCst) >= max
+    }
+
+    // release all the pending members when the next window starts
+    fn release_later(&self, sched: &'static Scheduler, left: u64) {
+        if self.inner.releasing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let group = self.clone();
+        let builder = Builder::new().scheduler(sched);
+        let ret = unsafe {
+            builder.spawn(move || {
+                sleep(Duration::from_nanos(left));
+                group.inner.releasing.store(false, Ordering::Release);
+                while let Some(co) = group.inner.pending.pop() {
+                    sched.schedule(co);
+                }
+            })
+        };
+        if let Err(e) = ret {
+            self.inner.releasing.store(false, Ordering::Release);
+            error!("failed to spawn the group release coroutine, err = {}", e);
+        }
+    }
+}
+
+// start a new window if the current one is expired
+fn roll_window(window: &mut Window, now: u64) {
+    if now.saturating_sub(window.start) >= WINDOW_NS {
+        window.start = now;
+        window.used = 0;
+    }
+}
+
+impl fmt::Debug for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Group")
+            .field("max_parallelism", &self.get_max_parallelism())
+            .field("cpu_share", &self.get_cpu_share())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn group_max_parallelism() {
+        let group = Group::new();
+        group.set_max_parallelism(1);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let running = running.clone();
+                let peak = peak.clone();
+                let builder = Builder::new().group(&group);
+                unsafe {
+                    builder.spawn(move || {
+                        let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(n, Ordering::SeqCst);
+                        // hold the worker for a while
+                        let start = Instant::now();
+                        while start.elapsed() < Duration::from_millis(5) {}
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                }
+                .unwrap()
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+        let stats = group.stats();
+        assert_eq!(stats.spawned, 4);
+        assert_eq!(stats.live, 0);
+        // the last one may not be accounted yet
+        assert!(stats.cpu_time >= Duration::from_millis(15));
+    }
+
+    #[test]
+    fn group_cancel() {
+        let group = Group::new();
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let builder = Builder::new().group(&group);
+                unsafe { builder.spawn(crate::coroutine::park) }.unwrap()
+            })
+            .collect();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(group.stats().live, 3);
+        unsafe { group.cancel() };
+        for h in handles {
+            assert!(h.join().is_err());
+        }
+        assert_eq!(group.stats().live, 0);
+        assert!(group.members().is_empty());
+    }
+}
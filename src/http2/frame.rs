@@ -0,0 +1,201 @@
+//! the http/2 frame codec, see RFC 9113 section 4 and 6
+
+use std::io::{self, Read, Write};
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const FLAG_END_STREAM: u8 = 0x1;
+pub const FLAG_ACK: u8 = 0x1;
+pub const FLAG_END_HEADERS: u8 = 0x4;
+pub const FLAG_PADDED: u8 = 0x8;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+// the default and the min of the max frame size
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+// the largest max frame size that can be set
+pub const MAX_MAX_FRAME_SIZE: usize = 16_777_215;
+// the initial flow control window of the connection and the streams
+pub const DEFAULT_WINDOW: i64 = 65_535;
+// the largest flow control window
+pub const MAX_WINDOW: i64 = 0x7fff_ffff;
+
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+#[derive(Debug)]
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a frame, the payload must not be larger than `max_size`.
+pub fn read_frame<R: Read>(r: &mut R, max_size: usize) -> io::Result<Frame> {
+    let mut head = [0u8; 9];
+    r.read_exact(&mut head)?;
+    let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+    if len > max_size {
+        return Err(invalid_data("http2 frame is too large"));
+    }
+    let stream_id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    Ok(Frame {
+        kind: head[3],
+        flags: head[4],
+        stream_id,
+        payload,
+    })
+}
+
+/// Writes a frame, the payload must fit in the max frame size of the peer.
+pub fn write_frame<W: Write + ?Sized>(
+    w: &mut W,
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: &[u8],
+) -> io::Result<()> {
+    let len = (payload.len() as u32).to_be_bytes();
+    let id = stream_id.to_be_bytes();
+    let head = [
+        len[1], len[2], len[3], kind, flags, id[0], id[1], id[2], id[3],
+    ];
+    let mut buf = Vec::with_capacity(head.len() + payload.len());
+    buf.extend_from_slice(&head);
+    buf.extend_from_slice(payload);
+    w.write_all(&buf)?;
+    w.flush()
+}
+
+/// Removes the padding of a DATA, HEADERS or PUSH_PROMISE frame.
+pub fn strip_padding(frame: &mut Frame) -> io::Result<()> {
+    if !frame.has(FLAG_PADDED) {
+        return Ok(());
+    }
+    let pad = *frame
+        .payload
+        .first()
+        .ok_or_else(|| invalid_data("invalid http2 padding"))? as usize;
+    if pad + 1 > frame.payload.len() {
+        return Err(invalid_data("invalid http2 padding"));
+    }
+    let end = frame.payload.len() - pad;
+    frame.payload.truncate(end);
+    frame.payload.remove(0);
+    Ok(())
+}
+
+/// Encodes the settings as the payload of a SETTINGS frame.
+pub fn encode_settings(settings: &[(u16, u32)]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(settings.len() * 6);
+    for (id, value) in settings {
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+    buf
+}
+
+/// Decodes the payload of a SETTINGS frame.
+pub fn decode_settings(payload: &[u8]) -> io::Result<Vec<(u16, u32)>> {
+    if payload.len() % 6 != 0 {
+        return Err(invalid_data("invalid http2 settings frame"));
+    }
+    Ok(payload
+        .chunks(6)
+        .map(|c| {
+            let id = u16::from_be_bytes([c[0], c[1]]);
+            let value = u32::from_be_bytes([c[2], c[3], c[4], c[5]]);
+            (id, value)
+        })
+        .collect())
+}
+
+/// Reads a big endian u32 at the offset of the payload.
+pub fn read_u32(payload: &[u8], offset: usize) -> io::Result<u32> {
+    payload
+        .get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid_data("http2 frame is too short"))
+}
+
+// append an integer with the prefix of `bits`, see RFC 7541 section 5.1
+fn encode_int(buf: &mut Vec<u8>, first: u8, bits: u32, mut value: usize) {
+    let max = (1usize << bits) - 1;
+    if value < max {
+        buf.push(first | value as u8);
+        return;
+    }
+    buf.push(first | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Encodes the headers as the literals without indexing, so the encoder
+/// doesn't depend on the dynamic table size of the peer.
+pub fn encode_headers<'a, I>(headers: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut buf = Vec::new();
+    for (name, value) in headers {
+        buf.push(0);
+        for s in [name, value] {
+            encode_int(&mut buf, 0, 7, s.len());
+            buf.extend_from_slice(s.as_bytes());
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_block() {
+        let long = "x".repeat(200);
+        let block = encode_headers([(":path", "/"), ("long", long.as_str())]);
+        let mut decoder = hpack::Decoder::new();
+        let headers = decoder.decode(&block).unwrap();
+        assert_eq!(headers[0], (b":path".to_vec(), b"/".to_vec()));
+        assert_eq!(headers[1], (b"long".to_vec(), long.into_bytes()));
+    }
+
+    #[test]
+    fn frame_padding() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, DATA, FLAG_PADDED, 3, &[2, b'h', b'i', 0, 0]).unwrap();
+        let mut frame = read_frame(&mut &buf[..], DEFAULT_MAX_FRAME_SIZE).unwrap();
+        assert_eq!(frame.stream_id, 3);
+        strip_padding(&mut frame).unwrap();
+        assert_eq!(frame.payload, b"hi");
+        assert!(read_frame(&mut &buf[..], 4).is_err());
+    }
+}
@@ -0,0 +1,911 @@
+//! http/2 connections on top of the coroutine io
+//!
+//! [`Connection`] runs the http/2 framing layer on a stream that can be split
+//! into the read and write halves, e.g. a [`TcpStream`]. a reader coroutine
+//! of the connection receives the frames, answers the settings and the pings,
+//! and dispatches the headers and the data to the streams. the streams are
+//! used with the blocking calls, which park the coroutine until the peer
+//! sends something or the flow control window opens, so a grpc style service
+//! can run one coroutine per stream.
+//!
+//! the header blocks are sent as literals without indexing and the received
+//! ones are decoded by the `hpack` crate. the server push and the stream
+//! priorities are not supported, and the pseudo header fields like `:path`
+//! are passed in the headers as they are.
+//!
+//! only available with the `http2` feature.
+//!
+//! [`TcpStream`]: crate::net::TcpStream
+
+mod frame;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use self::frame::*;
+use crate::io::SplitIo;
+use crate::join::JoinHandle;
+use crate::sync::{Condvar, Mutex, MutexGuard};
+
+/// The header fields of a request, a response or the trailers.
+pub type Headers = Vec<(String, String)>;
+
+/// The graceful shutdown, or the stream is done.
+pub const NO_ERROR: u32 = 0x0;
+/// The peer violates the protocol.
+pub const PROTOCOL_ERROR: u32 = 0x1;
+/// An unexpected internal error.
+pub const INTERNAL_ERROR: u32 = 0x2;
+/// The peer violates the flow control.
+pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+/// The stream is refused before any processing.
+pub const REFUSED_STREAM: u32 = 0x7;
+/// The stream is no longer needed.
+pub const CANCEL: u32 = 0x8;
+
+// the max size of a header block with its continuations
+const MAX_HEADER_BLOCK: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+struct StreamState {
+    send_window: i64,
+    head: Option<Headers>,
+    trailers: Option<Headers>,
+    // the received data not read yet
+    data: VecDeque<u8>,
+    // the end of stream is received
+    recv_closed: bool,
+    // the end of stream is sent
+    send_closed: bool,
+    // the error code if it's reset by either side
+    reset: Option<u32>,
+}
+
+impl StreamState {
+    fn new(send_window: i64) -> Self {
+        StreamState {
+            send_window,
+            head: None,
+            trailers: None,
+            data: VecDeque::new(),
+            recv_closed: false,
+            send_closed: false,
+            reset: None,
+        }
+    }
+}
+
+struct State {
+    streams: HashMap<u32, StreamState>,
+    // the new streams of the peer waiting to be accepted
+    accept: VecDeque<u32>,
+    next_id: u32,
+    last_peer_id: u32,
+    send_window: i64,
+    // the settings of the peer
+    initial_window: i64,
+    max_frame_size: usize,
+    goaway: bool,
+    // the connection is broken
+    error: Option<(io::ErrorKind, String)>,
+}
+
+impl State {
+    fn check(&self) -> io::Result<()> {
+        match self.error {
+            Some((kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // get the stream that is not reset
+    fn stream(&mut self, id: u32) -> io::Result<&mut StreamState> {
+        let s = self
+            .streams
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "http2 stream is closed"))?;
+        match s.reset {
+            Some(code) => Err(reset_error(code)),
+            None => Ok(s),
+        }
+    }
+}
+
+fn reset_error(code: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        format!("http2 stream is reset, code = {}", code),
+    )
+}
+
+// the lock is still usable if a holder is cancelled
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct Shared {
+    role: Role,
+    state: Mutex<State>,
+    cond: Condvar,
+    // the header blocks and their stream ids must be sent in order, so the
+    // writer is locked before the state
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Shared {
+    // wait until `f` returns the result, it's checked after each change
+    fn wait<T>(&self, mut f: impl FnMut(&mut State) -> Option<io::Result<T>>) -> io::Result<T> {
+        let mut state = lock(&self.state);
+        loop {
+            if let Some(ret) = f(&mut *state) {
+                return ret;
+            }
+            state = self.cond.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        f(&mut *lock(&self.state));
+        self.cond.notify_all();
+    }
+
+    fn fail(&self, kind: io::ErrorKind, msg: &str) {
+        self.update(|state| {
+            if state.error.is_none() {
+                state.error = Some((kind, msg.to_owned()));
+            }
+        });
+    }
+
+    fn write_frame(&self, kind: u8, flags: u8, id: u32, payload: &[u8]) -> io::Result<()> {
+        write_frame(&mut **lock(&self.writer), kind, flags, id, payload)
+    }
+
+    fn window_update(&self, id: u32, n: usize) -> io::Result<()> {
+        self.write_frame(WINDOW_UPDATE, 0, id, &(n as u32).to_be_bytes())
+    }
+
+    // give the consumed bytes back to the peer
+    fn release(&self, id: u32, n: usize) -> io::Result<()> {
+        if n == 0 {
+            return Ok(());
+        }
+        self.window_update(0, n)?;
+        let open = lock(&self.state)
+            .streams
+            .get(&id)
+            .is_some_and(|s| !s.recv_closed && s.reset.is_none());
+        if open {
+            self.window_update(id, n)?;
+        }
+        Ok(())
+    }
+
+    fn on_frame<R: Read>(
+        &self,
+        mut frame: Frame,
+        r: &mut R,
+        decoder: &mut hpack::Decoder,
+    ) -> io::Result<()> {
+        match frame.kind {
+            DATA => {
+                let len = frame.payload.len();
+                let end_stream = frame.has(FLAG_END_STREAM);
+                strip_padding(&mut frame)?;
+                self.on_data(frame.stream_id, frame.payload, len, end_stream)
+            }
+            HEADERS => {
+                let id = frame.stream_id;
+                let end_stream = frame.has(FLAG_END_STREAM);
+                let mut end_headers = frame.has(FLAG_END_HEADERS);
+                strip_padding(&mut frame)?;
+                if frame.has(FLAG_PRIORITY) {
+                    if frame.payload.len() < 5 {
+                        return Err(invalid_data("invalid http2 headers frame"));
+                    }
+                    frame.payload.drain(..5);
+                }
+                let mut block = frame.payload;
+                while !end_headers {
+                    let next = read_frame(r, DEFAULT_MAX_FRAME_SIZE)?;
+                    if next.kind != CONTINUATION || next.stream_id != id {
+                        return Err(invalid_data("http2 continuation frame is expected"));
+                    }
+                    if block.len() + next.payload.len() > MAX_HEADER_BLOCK {
+                        return Err(invalid_data("http2 header block is too large"));
+                    }
+                    block.extend_from_slice(&next.payload);
+                    end_headers = next.has(FLAG_END_HEADERS);
+                }
+                let headers = decode_headers(decoder, &block)?;
+                self.on_headers(id, headers, end_stream);
+                Ok(())
+            }
+            RST_STREAM => {
+                let code = read_u32(&frame.payload, 0)?;
+                self.update(|state| {
+                    if let Some(s) = state.streams.get_mut(&frame.stream_id) {
+                        s.reset = Some(code);
+                    }
+                });
+                Ok(())
+            }
+            SETTINGS if frame.has(FLAG_ACK) => Ok(()),
+            SETTINGS => {
+                self.on_settings(&decode_settings(&frame.payload)?)?;
+                self.write_frame(SETTINGS, FLAG_ACK, 0, &[])
+            }
+            PING if frame.payload.len() != 8 => Err(invalid_data("invalid http2 ping frame")),
+            PING if frame.has(FLAG_ACK) => Ok(()),
+            PING => self.write_frame(PING, FLAG_ACK, 0, &frame.payload),
+            GOAWAY => {
+                let last = read_u32(&frame.payload, 0)? & 0x7fff_ffff;
+                let ours = (self.role == Role::Client) as u32;
+                self.update(|state| {
+                    state.goaway = true;
+                    // the streams above the last one are not processed
+                    for (id, s) in state.streams.iter_mut() {
+                        if *id > last && *id % 2 == ours && s.reset.is_none() {
+                            s.reset = Some(REFUSED_STREAM);
+                        }
+                    }
+                });
+                Ok(())
+            }
+            WINDOW_UPDATE => {
+                let inc = (read_u32(&frame.payload, 0)? & 0x7fff_ffff) as i64;
+                if inc == 0 {
+                    return Err(invalid_data("invalid http2 window update"));
+                }
+                let mut overflow = false;
+                self.update(|state| {
+                    let window = match frame.stream_id {
+                        0 => &mut state.send_window,
+                        id => match state.streams.get_mut(&id) {
+                            Some(s) => &mut s.send_window,
+                            None => return,
+                        },
+                    };
+                    *window += inc;
+                    overflow = *window > MAX_WINDOW;
+                });
+                if overflow {
+                    return Err(invalid_data("http2 flow control window overflows"));
+                }
+                Ok(())
+            }
+            PUSH_PROMISE => Err(invalid_data("http2 server push is disabled")),
+            CONTINUATION => Err(invalid_data("unexpected http2 continuation frame")),
+            // the priorities and the unknown frames are ignored
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&self, id: u32, data: Vec<u8>, len: usize, end_stream: bool) -> io::Result<()> {
+        let mut taken = false;
+        self.update(|state| {
+            if let Some(s) = state.streams.get_mut(&id) {
+                if !s.recv_closed && s.reset.is_none() {
+                    s.data.extend(&data);
+                    s.recv_closed = end_stream;
+                    taken = true;
+                }
+            }
+        });
+        // the padding, or the data of a closed stream, is given back now
+        match taken {
+            true => self.release(id, len - data.len()),
+            false if len > 0 => self.window_update(0, len),
+            false => Ok(()),
+        }
+    }
+
+    fn on_headers(&self, id: u32, headers: Headers, end_stream: bool) {
+        let role = self.role;
+        self.update(|state| {
+            if let Some(s) = state.streams.get_mut(&id) {
+                match s.head {
+                    None => s.head = Some(headers),
+                    Some(_) => s.trailers = Some(headers),
+                }
+                s.recv_closed |= end_stream;
+            } else if role == Role::Server && id % 2 == 1 && id > state.last_peer_id {
+                state.last_peer_id = id;
+                let mut s = StreamState::new(state.initial_window);
+                s.head = Some(headers);
+                s.recv_closed = end_stream;
+                state.streams.insert(id, s);
+                state.accept.push_back(id);
+            }
+            // or else it's the headers of a closed stream
+        });
+    }
+
+    fn on_settings(&self, settings: &[(u16, u32)]) -> io::Result<()> {
+        let mut ret = Ok(());
+        self.update(|state| {
+            for &(id, value) in settings {
+                match id {
+                    SETTINGS_INITIAL_WINDOW_SIZE if value as i64 > MAX_WINDOW => {
+                        ret = Err(invalid_data("invalid http2 initial window size"));
+                    }
+                    SETTINGS_INITIAL_WINDOW_SIZE => {
+                        let delta = value as i64 - state.initial_window;
+                        state.initial_window = value as i64;
+                        for s in state.streams.values_mut() {
+                            s.send_window += delta;
+                        }
+                    }
+                    SETTINGS_MAX_FRAME_SIZE => {
+                        let size = value as usize;
+                        if !(DEFAULT_MAX_FRAME_SIZE..=MAX_MAX_FRAME_SIZE).contains(&size) {
+                            ret = Err(invalid_data("invalid http2 max frame size"));
+                        }
+                        state.max_frame_size = size;
+                    }
+                    _ => {}
+                }
+            }
+        });
+        ret
+    }
+}
+
+fn decode_headers(decoder: &mut hpack::Decoder, block: &[u8]) -> io::Result<Headers> {
+    let headers = decoder
+        .decode(block)
+        .map_err(|e| invalid_data(&format!("http2 header block error: {:?}", e)))?;
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            let name = String::from_utf8(name);
+            let value = String::from_utf8(value);
+            match (name, value) {
+                (Ok(name), Ok(value)) => Ok((name, value)),
+                _ => Err(invalid_data("invalid utf-8 http2 header")),
+            }
+        })
+        .collect()
+}
+
+// send a header block, split by the max frame size of the peer
+fn write_headers(
+    w: &mut dyn Write,
+    id: u32,
+    block: &[u8],
+    end_stream: bool,
+    max_frame_size: usize,
+) -> io::Result<()> {
+    let mut chunks = block.chunks(max_frame_size).peekable();
+    let mut kind = HEADERS;
+    let mut flags = if end_stream { FLAG_END_STREAM } else { 0 };
+    loop {
+        let chunk = chunks.next().unwrap_or(&[]);
+        if chunks.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+        write_frame(w, kind, flags, id, chunk)?;
+        if flags & FLAG_END_HEADERS != 0 {
+            return Ok(());
+        }
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+fn encode<I, K, V>(headers: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let headers: Vec<(K, V)> = headers.into_iter().collect();
+    encode_headers(headers.iter().map(|(k, v)| (k.as_ref(), v.as_ref())))
+}
+
+// receive the frames until the connection is closed
+fn read_loop<R: Read>(shared: &Shared, mut r: R) {
+    // fail the streams even if the coroutine is cancelled
+    struct Closer<'a>(&'a Shared);
+
+    impl Drop for Closer<'_> {
+        fn drop(&mut self) {
+            self.0.fail(
+                io::ErrorKind::ConnectionAborted,
+                "http2 connection is closed",
+            );
+        }
+    }
+
+    let _closer = Closer(shared);
+    let mut decoder = hpack::Decoder::new();
+    let err = loop {
+        let ret = read_frame(&mut r, DEFAULT_MAX_FRAME_SIZE)
+            .and_then(|frame| shared.on_frame(frame, &mut r, &mut decoder));
+        if let Err(e) = ret {
+            break e;
+        }
+    };
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            shared.fail(io::ErrorKind::UnexpectedEof, "http2 connection is closed")
+        }
+        io::ErrorKind::InvalidData => {
+            let last = lock(&shared.state).last_peer_id;
+            let _ = shared.write_frame(GOAWAY, 0, 0, &goaway(last, PROTOCOL_ERROR));
+            shared.fail(err.kind(), &err.to_string());
+        }
+        kind => shared.fail(kind, &err.to_string()),
+    }
+}
+
+fn goaway(last_id: u32, code: u32) -> Vec<u8> {
+    let mut payload = last_id.to_be_bytes().to_vec();
+    payload.extend_from_slice(&code.to_be_bytes());
+    payload
+}
+
+/// An http/2 connection.
+///
+/// Dropping the connection sends a GOAWAY frame and stops the reader
+/// coroutine, the streams fail after that, so keep it until they are done.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+/// use may::http2::Connection;
+/// use may::net::{TcpListener, TcpStream};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let h = may::go!(move || {
+///     let (s, _) = listener.accept().unwrap();
+///     let conn = Connection::server(s).unwrap();
+///     // a coroutine per stream in a real server
+///     while let Some(mut stream) = conn.accept().unwrap() {
+///         let headers = stream.headers().unwrap();
+///         let mut body = Vec::new();
+///         stream.read_to_end(&mut body).unwrap();
+///         stream.send_headers([(":status", "200")], false).unwrap();
+///         stream.send_data(&body, false).unwrap();
+///         stream.send_headers([("grpc-status", "0")], true).unwrap();
+///         assert_eq!(headers[0], (":method".into(), "POST".into()));
+///     }
+/// });
+///
+/// let conn = Connection::client(TcpStream::connect(addr).unwrap()).unwrap();
+/// let request = [
+///     (":method", "POST"),
+///     (":scheme", "http"),
+///     (":path", "/echo.Echo/Say"),
+///     (":authority", "localhost"),
+///     ("content-type", "application/grpc"),
+/// ];
+/// let mut stream = conn.request(request, false).unwrap();
+/// stream.send_data(b"hello", true).unwrap();
+/// assert_eq!(stream.headers().unwrap()[0].1, "200");
+/// let mut body = Vec::new();
+/// stream.read_to_end(&mut body).unwrap();
+/// assert_eq!(body, b"hello");
+/// assert_eq!(stream.trailers().unwrap().unwrap()[0].1, "0");
+/// drop(stream);
+/// drop(conn);
+/// h.join().unwrap();
+/// ```
+pub struct Connection {
+    shared: Arc<Shared>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Connection {
+    /// Starts a client connection, the preface is sent on the stream.
+    pub fn client<S>(stream: S) -> io::Result<Connection>
+    where
+        S: SplitIo + Read + Write + Send + 'static,
+    {
+        let (r, mut w) = stream.split()?;
+        w.write_all(PREFACE)?;
+        let settings = encode_settings(&[(SETTINGS_ENABLE_PUSH, 0)]);
+        write_frame(&mut w, SETTINGS, 0, 0, &settings)?;
+        Ok(Connection::start(Role::Client, r, w))
+    }
+
+    /// Starts a server connection on an accepted stream, the preface of the
+    /// client is received first.
+    pub fn server<S>(stream: S) -> io::Result<Connection>
+    where
+        S: SplitIo + Read + Write + Send + 'static,
+    {
+        let (mut r, mut w) = stream.split()?;
+        let mut preface = [0u8; 24];
+        r.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(invalid_data("invalid http2 preface"));
+        }
+        write_frame(&mut w, SETTINGS, 0, 0, &[])?;
+        Ok(Connection::start(Role::Server, r, w))
+    }
+
+    fn start<R, W>(role: Role, r: R, w: W) -> Connection
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let state = State {
+            streams: HashMap::new(),
+            accept: VecDeque::new(),
+            next_id: if role == Role::Client { 1 } else { 2 },
+            last_peer_id: 0,
+            send_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            goaway: false,
+            error: None,
+        };
+        let shared = Arc::new(Shared {
+            role,
+            state: Mutex::new(state),
+            cond: Condvar::new(),
+            writer: Mutex::new(Box::new(w)),
+        });
+        let s = shared.clone();
+        let reader = go!(move || read_loop(&s, r));
+        Connection {
+            shared,
+            reader: Some(reader),
+        }
+    }
+
+    /// Sends the request headers on a new stream, `end_stream` is true if
+    /// there is no request body.
+    pub fn request<I, K, V>(&self, headers: I, end_stream: bool) -> io::Result<Stream>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        if self.shared.role != Role::Client {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only the http2 client sends requests",
+            ));
+        }
+        let block = encode(headers);
+        let mut w = lock(&self.shared.writer);
+        let (id, max_frame_size) = {
+            let mut state = lock(&self.shared.state);
+            state.check()?;
+            if state.goaway || state.next_id > 0x7fff_ffff {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "http2 connection is going away",
+                ));
+            }
+            let id = state.next_id;
+            state.next_id += 2;
+            let mut s = StreamState::new(state.initial_window);
+            s.send_closed = end_stream;
+            state.streams.insert(id, s);
+            (id, state.max_frame_size)
+        };
+        write_headers(&mut **w, id, &block, end_stream, max_frame_size)?;
+        Ok(Stream {
+            shared: self.shared.clone(),
+            id,
+        })
+    }
+
+    /// Waits for a new stream of the client, returns `None` when the
+    /// connection is closed or going away.
+    pub fn accept(&self) -> io::Result<Option<Stream>> {
+        let id = self.shared.wait(|state| {
+            while let Some(id) = state.accept.pop_front() {
+                let reset = state.streams.get(&id).is_some_and(|s| s.reset.is_some());
+                if !reset {
+                    return Some(Ok(Some(id)));
+                }
+                // reset before it's accepted
+                state.streams.remove(&id);
+            }
+            if state.goaway {
+                return Some(Ok(None));
+            }
+            match state.check() {
+                Ok(()) => None,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Some(Ok(None)),
+                Err(e) => Some(Err(e)),
+            }
+        })?;
+        Ok(id.map(|id| Stream {
+            shared: self.shared.clone(),
+            id,
+        }))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let last = lock(&self.shared.state).last_peer_id;
+        let _ = self
+            .shared
+            .write_frame(GOAWAY, 0, 0, &goaway(last, NO_ERROR));
+        if let Some(reader) = self.reader.take() {
+            unsafe { reader.coroutine().cancel() };
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = lock(&self.shared.state);
+        f.debug_struct("Connection")
+            .field("role", &self.shared.role)
+            .field("streams", &state.streams.len())
+            .field("goaway", &state.goaway)
+            .finish()
+    }
+}
+
+/// A stream of an http/2 [`Connection`].
+///
+/// The received data is also read by the [`Read`] impl. Dropping a stream
+/// that is not closed in both directions resets it.
+pub struct Stream {
+    shared: Arc<Shared>,
+    id: u32,
+}
+
+impl Stream {
+    /// Returns the stream id.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Waits for the headers of the peer, the request headers on the server
+    /// and the response headers on the client.
+    pub fn headers(&self) -> io::Result<Headers> {
+        self.shared.wait(|state| {
+            let s = match state.stream(self.id) {
+                Ok(s) => s,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(ref head) = s.head {
+                return Some(Ok(head.clone()));
+            }
+            if s.recv_closed {
+                return Some(Err(invalid_data("http2 stream has no headers")));
+            }
+            state.check().err().map(Err)
+        })
+    }
+
+    /// Sends the headers, the response headers on the server or the trailers
+    /// on both sides. `end_stream` must be true for the trailers.
+    pub fn send_headers<I, K, V>(&self, headers: I, end_stream: bool) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let block = encode(headers);
+        let mut w = lock(&self.shared.writer);
+        let max_frame_size = {
+            let mut state = lock(&self.shared.state);
+            state.check()?;
+            let s = state.stream(self.id)?;
+            if s.send_closed {
+                return Err(closed_error());
+            }
+            s.send_closed = end_stream;
+            state.max_frame_size
+        };
+        write_headers(&mut **w, self.id, &block, end_stream, max_frame_size)
+    }
+
+    /// Sends the data, parking the caller while the flow control window of
+    /// the peer is used up.
+    pub fn send_data(&self, mut data: &[u8], end_stream: bool) -> io::Result<()> {
+        if data.is_empty() && !end_stream {
+            return Ok(());
+        }
+        loop {
+            let len = data.len();
+            let n = self.shared.wait(|state| {
+                if let Err(e) = state.check() {
+                    return Some(Err(e));
+                }
+                let conn_window = state.send_window;
+                let max = state.max_frame_size as i64;
+                let s = match state.stream(self.id) {
+                    Ok(s) => s,
+                    Err(e) => return Some(Err(e)),
+                };
+                if s.send_closed {
+                    return Some(Err(closed_error()));
+                }
+                let n = (len as i64).min(conn_window).min(s.send_window).min(max);
+                if n <= 0 && len > 0 {
+                    return None;
+                }
+                let n = n.max(0);
+                s.send_window -= n;
+                s.send_closed = end_stream && n as usize == len;
+                state.send_window -= n;
+                Some(Ok(n as usize))
+            })?;
+            let (chunk, rest) = data.split_at(n);
+            let flags = match end_stream && rest.is_empty() {
+                true => FLAG_END_STREAM,
+                false => 0,
+            };
+            self.shared.write_frame(DATA, flags, self.id, chunk)?;
+            if rest.is_empty() {
+                return Ok(());
+            }
+            data = rest;
+        }
+    }
+
+    // take at most `max` bytes of the received data, none at the end
+    fn take_data(&self, max: usize) -> io::Result<Option<Vec<u8>>> {
+        let data = self.shared.wait(|state| {
+            let s = match state.stream(self.id) {
+                Ok(s) => s,
+                Err(e) => return Some(Err(e)),
+            };
+            if !s.data.is_empty() {
+                let n = max.min(s.data.len());
+                return Some(Ok(Some(s.data.drain(..n).collect::<Vec<_>>())));
+            }
+            if s.recv_closed {
+                return Some(Ok(None));
+            }
+            state.check().err().map(Err)
+        })?;
+        if let Some(ref data) = data {
+            self.shared.release(self.id, data.len())?;
+        }
+        Ok(data)
+    }
+
+    /// Receives the next chunk of the data, returns `None` at the end of the
+    /// stream.
+    pub fn recv_data(&self) -> io::Result<Option<Vec<u8>>> {
+        self.take_data(usize::MAX)
+    }
+
+    /// Waits for the end of the stream and returns the trailers if any.
+    pub fn trailers(&self) -> io::Result<Option<Headers>> {
+        self.shared.wait(|state| {
+            let s = match state.stream(self.id) {
+                Ok(s) => s,
+                Err(e) => return Some(Err(e)),
+            };
+            if s.recv_closed {
+                return Some(Ok(s.trailers.clone()));
+            }
+            state.check().err().map(Err)
+        })
+    }
+
+    /// Resets the stream with the error code, e.g. [`CANCEL`].
+    pub fn reset(&self, code: u32) -> io::Result<()> {
+        let mut w = lock(&self.shared.writer);
+        lock(&self.shared.state).stream(self.id)?.reset = Some(code);
+        self.shared.cond.notify_all();
+        write_frame(&mut **w, RST_STREAM, 0, self.id, &code.to_be_bytes())
+    }
+}
+
+fn closed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "http2 stream is closed for sending",
+    )
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.take_data(buf.len())? {
+            Some(data) => {
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let (code, unread) = {
+            let mut state = lock(&self.shared.state);
+            let alive = state.error.is_none();
+            match state.streams.remove(&self.id) {
+                Some(s) => {
+                    let code = match (s.send_closed, s.recv_closed) {
+                        _ if s.reset.is_some() || !alive => None,
+                        (true, true) => None,
+                        (true, false) => Some(NO_ERROR),
+                        _ => Some(CANCEL),
+                    };
+                    (code, s.data.len())
+                }
+                None => (None, 0),
+            }
+        };
+        if let Some(code) = code {
+            let _ = self
+                .shared
+                .write_frame(RST_STREAM, 0, self.id, &code.to_be_bytes());
+        }
+        if unread > 0 {
+            let _ = self.shared.window_update(0, unread);
+        }
+    }
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stream").field("id", &self.id).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn http2_flow_control() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // larger than the initial windows and the max frame size
+        let body: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+        let expected = body.clone();
+
+        let h = go!(move || {
+            let (s, _) = listener.accept().unwrap();
+            let conn = Connection::server(s).unwrap();
+            let mut stream = conn.accept().unwrap().unwrap();
+            assert_eq!(stream.id(), 1);
+            let headers = stream.headers().unwrap();
+            assert_eq!(headers[0], (":path".into(), "/big".into()));
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).unwrap();
+            assert_eq!(data, expected);
+            stream.send_headers([(":status", "200")], false).unwrap();
+            stream.send_data(&data, false).unwrap();
+            stream.send_headers([("grpc-status", "0")], true).unwrap();
+            // the client goes away
+            assert!(conn.accept().unwrap().is_none());
+        });
+
+        let conn = Connection::client(TcpStream::connect(addr).unwrap()).unwrap();
+        let stream = conn.request([(":path", "/big")], false).unwrap();
+        // a stream that is reset by dropping it
+        let other = conn.request([(":path", "/other")], true).unwrap();
+        drop(other);
+        stream.send_data(&body, true).unwrap();
+        assert!(stream.send_data(b"late", false).is_err());
+        assert_eq!(stream.headers().unwrap()[0].1, "200");
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.recv_data().unwrap() {
+            data.extend_from_slice(&chunk);
+        }
+        assert_eq!(data, body);
+        let trailers = stream.trailers().unwrap().unwrap();
+        assert_eq!(trailers, [("grpc-status".to_owned(), "0".to_owned())]);
+        drop(stream);
+        drop(conn);
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! a pool of fixed size read buffers
+//!
+//! the reads borrow a buffer from the [`BufPool`] instead of allocating one,
+//! the [`PooledBuf`] goes back to the pool when it's dropped. a coroutine
+//! that finds the pool empty is parked until a buffer is returned.
+//!
+//! all the buffers are carved from one allocation that is never moved or
+//! freed while the pool is alive, and each of them has a fixed index, see
+//! [`BufPool::iovecs`] and [`PooledBuf::index`].
+
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use crate::sync::queue::array_queue::ArrayQueue;
+use crate::sync::Semphore;
+
+struct Inner {
+    // the start of the memory of all the buffers
+    mem: *mut u8,
+    buf_size: usize,
+    count: usize,
+    // the indexes of the free buffers
+    free: ArrayQueue<usize>,
+    // the number of the free buffers, the borrowers wait on it
+    available: Semphore,
+}
+
+// the buffers are only accessed through the `PooledBuf` that owns the index
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let len = self.buf_size * self.count;
+        let mem = std::ptr::slice_from_raw_parts_mut(self.mem, len);
+        unsafe { drop(Box::from_raw(mem)) };
+    }
+}
+
+impl Inner {
+    fn take(self: &Arc<Self>) -> PooledBuf {
+        // a permit always comes with a free index
+        let index = self.free.pop().expect("buffer pool is out of sync");
+        PooledBuf {
+            pool: self.clone(),
+            index,
+            len: 0,
+        }
+    }
+}
+
+/// A pool of fixed size buffers for the reads.
+///
+/// The pool is cheap to clone, the clones share the same buffers.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::io::BufPool;
+///
+/// let pool = BufPool::new(4, 1024);
+/// let mut data: &[u8] = b"hello";
+/// let buf = pool.read_from(&mut data).unwrap();
+/// assert_eq!(&buf[..], b"hello");
+/// assert_eq!(pool.available(), 3);
+/// drop(buf);
+/// assert_eq!(pool.available(), 4);
+/// ```
+#[derive(Clone)]
+pub struct BufPool {
+    inner: Arc<Inner>,
+}
+
+impl BufPool {
+    /// Creates a pool of `count` buffers of `buf_size` bytes each.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` or `buf_size` is zero.
+    pub fn new(count: usize, buf_size: usize) -> Self {
+        assert!(count > 0, "buffer pool needs at least one buffer");
+        assert!(buf_size > 0, "buffer size must not be zero");
+        let len = count
+            .checked_mul(buf_size)
+            .expect("buffer pool is too large");
+        let mem = Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8;
+        let free = ArrayQueue::new(count);
+        for i in 0..count {
+            free.push(i).ok();
+        }
+        BufPool {
+            inner: Arc::new(Inner {
+                mem,
+                buf_size,
+                count,
+                free,
+                available: Semphore::new(count),
+            }),
+        }
+    }
+
+    /// Borrows a buffer, parks until one is returned if the pool is empty.
+    pub fn get(&self) -> PooledBuf {
+        self.inner.available.wait();
+        self.inner.take()
+    }
+
+    /// Borrows a buffer if there is a free one.
+    pub fn try_get(&self) -> Option<PooledBuf> {
+        if self.inner.available.try_wait() {
+            Some(self.inner.take())
+        } else {
+            None
+        }
+    }
+
+    /// Borrows a buffer and does one read into it.
+    ///
+    /// The returned buffer holds the data that is read, it's empty at the
+    /// end of the stream.
+    pub fn read_from<R: Read + ?Sized>(&self, r: &mut R) -> io::Result<PooledBuf> {
+        let mut buf = self.get();
+        let n = r.read(buf.as_mut_buf())?;
+        buf.set_len(n);
+        Ok(buf)
+    }
+
+    /// Returns the size of each buffer.
+    pub fn buf_size(&self) -> usize {
+        self.inner.buf_size
+    }
+
+    /// Returns the number of the buffers.
+    pub fn count(&self) -> usize {
+        self.inner.count
+    }
+
+    /// Returns the number of the free buffers.
+    pub fn available(&self) -> usize {
+        self.inner.available.get_value()
+    }
+
+    /// Returns the memory of all the buffers in the order of their indexes.
+    ///
+    /// The memory is valid until the last clone of the pool and the last
+    /// borrowed buffer are dropped.
+    #[cfg(unix)]
+    pub fn iovecs(&self) -> Vec<libc::iovec> {
+        let inner = &self.inner;
+        (0..inner.count)
+            .map(|i| libc::iovec {
+                iov_base: unsafe { inner.mem.add(i * inner.buf_size) } as *mut libc::c_void,
+                iov_len: inner.buf_size,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for BufPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufPool")
+            .field("buf_size", &self.buf_size())
+            .field("count", &self.count())
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+/// A buffer borrowed from a [`BufPool`].
+///
+/// It derefs to the filled part of the buffer, and goes back to the pool
+/// when it's dropped.
+pub struct PooledBuf {
+    pool: Arc<Inner>,
+    index: usize,
+    len: usize,
+}
+
+impl PooledBuf {
+    /// Returns the index of the buffer in the pool.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the size of the whole buffer.
+    pub fn capacity(&self) -> usize {
+        self.pool.buf_size
+    }
+
+    /// Returns the whole buffer to read into, see [`PooledBuf::set_len`].
+    pub fn as_mut_buf(&mut self) -> &mut [u8] {
+        let size = self.pool.buf_size;
+        unsafe { std::slice::from_raw_parts_mut(self.pool.mem.add(self.index * size), size) }
+    }
+
+    /// Sets the length of the filled part.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the capacity.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity(), "len is larger than the buffer");
+        self.len = len;
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let start = self.index * self.pool.buf_size;
+        unsafe { std::slice::from_raw_parts(self.pool.mem.add(start), self.len) }
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        &mut self.as_mut_buf()[..len]
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        self.pool.free.push(self.index).ok();
+        self.pool.available.post();
+    }
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("index", &self.index)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn buf_pool() {
+        let pool = BufPool::new(2, 8);
+        let mut a = pool.get();
+        let b = pool.try_get().unwrap();
+        assert_ne!(a.index(), b.index());
+        assert!(pool.try_get().is_none());
+        a.as_mut_buf().copy_from_slice(b"12345678");
+        a.set_len(3);
+        assert_eq!(&a[..], b"123");
+
+        let p = pool.clone();
+        let h = go!(move || {
+            // parked until a buffer is returned
+            let mut data: &[u8] = b"hello world";
+            let buf = p.read_from(&mut data).unwrap();
+            assert_eq!(&buf[..], b"hello wo");
+            buf.index()
+        });
+        crate::sleep::sleep(Duration::from_millis(20));
+        assert!(!h.is_done());
+        let index = a.index();
+        drop(a);
+        assert_eq!(h.join().unwrap(), index);
+        assert_eq!(pool.available(), 1);
+
+        #[cfg(unix)]
+        {
+            let iovecs = pool.iovecs();
+            assert_eq!(iovecs.len(), 2);
+            assert_eq!(iovecs[b.index()].iov_base as *const u8, b.as_ptr());
+        }
+    }
+}
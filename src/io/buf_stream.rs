@@ -0,0 +1,357 @@
+//! buffered reader and writer on one stream
+//!
+//! a request/response protocol reads and writes the same stream in turns,
+//! wrapping it in `std::io::BufReader` and `BufWriter` needs two handles and
+//! the responses are stuck in the writer until it's flushed by hand.
+//! [`BufStream`] keeps both buffers on one stream, and with
+//! [`BufStream::flush_on_yield`] the pending response is flushed before the
+//! stream waits for the next request.
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+#[cfg(feature = "io_timeout")]
+use std::time::Duration;
+
+#[cfg(feature = "io_timeout")]
+use super::TimeoutIo;
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A stream with a read-ahead buffer and a write buffer.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::io::{BufRead, Write};
+/// use may::io::BufStream;
+/// use may::net::TcpStream;
+///
+/// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+/// let mut stream = BufStream::new(stream).flush_on_yield(true);
+/// loop {
+///     // the 4 bytes length header
+///     let head = stream.fill_buf_at_least(4).unwrap();
+///     let len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as usize;
+///     let body = stream.fill_buf_at_least(4 + len).unwrap()[4..4 + len].to_vec();
+///     stream.consume(4 + len);
+///     // the response is flushed before waiting for the next request
+///     stream.write_all(&body).unwrap();
+/// }
+/// ```
+pub struct BufStream<S: Read + Write> {
+    inner: S,
+    // the read-ahead data is `rbuf[pos..filled]`
+    rbuf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    wbuf: Vec<u8>,
+    wcap: usize,
+    flush_on_yield: bool,
+}
+
+impl<S: Read + Write> BufStream<S> {
+    /// Creates a new stream with the buffers of 8 KiB.
+    pub fn new(inner: S) -> Self {
+        BufStream::with_capacity(DEFAULT_BUF_SIZE, DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Creates a new stream with the capacities of the read and the write
+    /// buffers.
+    pub fn with_capacity(read_cap: usize, write_cap: usize, inner: S) -> Self {
+        BufStream {
+            inner,
+            rbuf: vec![0; read_cap.max(1)],
+            pos: 0,
+            filled: 0,
+            wbuf: Vec::with_capacity(write_cap),
+            wcap: write_cap,
+            flush_on_yield: false,
+        }
+    }
+
+    /// Sets whether the buffered writes are flushed before reading from the
+    /// underlying stream, the default is false.
+    ///
+    /// A reading coroutine may park until the peer sends more data, which
+    /// never happens if the peer is waiting for the data still in the write
+    /// buffer.
+    pub fn flush_on_yield(mut self, enable: bool) -> Self {
+        self.flush_on_yield = enable;
+        self
+    }
+
+    /// Reads until at least `n` bytes are buffered, returns all the
+    /// buffered data.
+    ///
+    /// The read buffer grows if it's smaller than `n`. An error of
+    /// [`io::ErrorKind::UnexpectedEof`] is returned if the stream ends
+    /// before that, the data read so far is kept in the buffer.
+    pub fn fill_buf_at_least(&mut self, n: usize) -> io::Result<&[u8]> {
+        self.fill_with(n, |s, buf| s.read(buf))?;
+        Ok(self.buffer())
+    }
+
+    /// Returns the data in the read buffer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.rbuf[self.pos..self.filled]
+    }
+
+    /// Returns the number of the bytes in the write buffer.
+    pub fn write_buffered(&self) -> usize {
+        self.wbuf.len()
+    }
+
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// It's inadvisable to directly read from or write to the underlying
+    /// stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Flushes the write buffer and returns the underlying stream, the data
+    /// in the read buffer is lost.
+    pub fn into_inner(mut self) -> io::Result<S> {
+        self.flush_buf()?;
+        let mut this = std::mem::ManuallyDrop::new(self);
+        // the other fields are dropped here, the stream is moved out
+        unsafe {
+            std::ptr::drop_in_place(&mut this.rbuf);
+            std::ptr::drop_in_place(&mut this.wbuf);
+            Ok(std::ptr::read(&this.inner))
+        }
+    }
+
+    // read until `n` bytes are buffered with the read function
+    fn fill_with<F>(&mut self, n: usize, mut read: F) -> io::Result<()>
+    where
+        F: FnMut(&mut S, &mut [u8]) -> io::Result<usize>,
+    {
+        if self.filled - self.pos >= n {
+            return Ok(());
+        }
+        if self.flush_on_yield {
+            self.flush_buf()?;
+        }
+        // move the data to the front to make room
+        if self.pos > 0 {
+            self.rbuf.copy_within(self.pos..self.filled, 0);
+            self.filled -= self.pos;
+            self.pos = 0;
+        }
+        if self.rbuf.len() < n {
+            self.rbuf.resize(n, 0);
+        }
+        while self.filled < n {
+            match read(&mut self.inner, &mut self.rbuf[self.filled..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(m) => self.filled += m,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let mut ret = Ok(());
+        while written < self.wbuf.len() {
+            match self.inner.write(&self.wbuf[written..]) {
+                Ok(0) => {
+                    ret = Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write the buffered data",
+                    ));
+                    break;
+                }
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+        }
+        // keep the data not written for the next flush
+        self.wbuf.drain(..written);
+        ret
+    }
+}
+
+#[cfg(feature = "io_timeout")]
+impl<S: Read + Write + TimeoutIo> BufStream<S> {
+    /// Reads the exact number of bytes to fill `buf`, fails with an error of
+    /// [`io::ErrorKind::TimedOut`] if they don't arrive within `dur`.
+    ///
+    /// The data read before the timeout is kept in the buffer, so the read
+    /// can be retried.
+    pub fn read_exact_timeout(&mut self, buf: &mut [u8], dur: Duration) -> io::Result<()> {
+        let deadline = crate::time::now() + dur;
+        self.fill_with(buf.len(), |s, b| {
+            let left = deadline.saturating_duration_since(crate::time::now());
+            if left.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+            }
+            s.read_timeout_op(b, left)
+        })?;
+        buf.copy_from_slice(&self.rbuf[self.pos..self.pos + buf.len()]);
+        self.pos += buf.len();
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for BufStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // bypass the buffer for the big reads
+        if self.pos == self.filled && buf.len() >= self.rbuf.len() {
+            if self.flush_on_yield {
+                self.flush_buf()?;
+            }
+            return self.inner.read(buf);
+        }
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> BufRead for BufStream<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            if self.flush_on_yield {
+                self.flush_buf()?;
+            }
+            self.filled = self.inner.read(&mut self.rbuf)?;
+            self.pos = 0;
+        }
+        Ok(self.buffer())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+impl<S: Read + Write> Write for BufStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.wbuf.len() + buf.len() > self.wcap {
+            self.flush_buf()?;
+        }
+        if buf.len() >= self.wcap {
+            self.inner.write(buf)
+        } else {
+            self.wbuf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<S: Read + Write> Drop for BufStream<S> {
+    fn drop(&mut self) {
+        // don't do io when unwinding, e.g. the coroutine is cancelled
+        if !std::thread::panicking() {
+            let _ = self.flush_buf();
+        }
+    }
+}
+
+impl<S: Read + Write + fmt::Debug> fmt::Debug for BufStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufStream")
+            .field("inner", &self.inner)
+            .field("read_buffered", &(self.filled - self.pos))
+            .field("write_buffered", &self.wbuf.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // records the writes that happen before each read
+    struct Mock {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        written_at_read: Vec<usize>,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.written_at_read.push(self.output.len());
+            // at most 3 bytes per read
+            let n = buf.len().min(3);
+            self.input.read(&mut buf[..n])
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mock(input: &[u8]) -> Mock {
+        Mock {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+            written_at_read: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn buf_stream_fill_at_least() {
+        let mut s = BufStream::with_capacity(4, 16, mock(b"hello world"));
+        assert_eq!(s.fill_buf_at_least(2).unwrap(), b"hel");
+        // grows the buffer
+        assert_eq!(s.fill_buf_at_least(8).unwrap(), b"hello wo");
+        s.consume(6);
+        let mut rest = String::new();
+        s.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world");
+        let e = s.fill_buf_at_least(1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn buf_stream_flush_on_yield() {
+        let mut s = BufStream::new(mock(b"abcdef")).flush_on_yield(true);
+        s.write_all(b"req").unwrap();
+        assert_eq!(s.get_ref().output, b"");
+        let mut buf = [0u8; 2];
+        s.read_exact(&mut buf).unwrap();
+        // flushed before the first read, the buffered ones don't flush
+        s.write_all(b"2").unwrap();
+        s.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(s.get_ref().written_at_read, [3]);
+        s.read_exact(&mut buf).unwrap();
+        assert_eq!(s.get_ref().written_at_read, [3, 4]);
+
+        let mut s = BufStream::new(mock(b"")).flush_on_yield(false);
+        s.write_all(b"req").unwrap();
+        s.fill_buf().unwrap();
+        assert_eq!(s.get_ref().written_at_read, [0]);
+        assert_eq!(s.into_inner().unwrap().output, b"req");
+    }
+}
@@ -0,0 +1,339 @@
+//! buffered writer that flushes with vectored writes
+//!
+//! the buffered data is kept in a list of chunks instead of a single fixed
+//! buffer, so the owned frames can be queued without copying. a flush writes
+//! as many chunks as possible with one `write_vectored` call, for the
+//! coroutine io types that is one `writev` per readiness event.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, IoSlice, Write};
+
+use smallvec::SmallVec;
+
+// the max number of slices passed to one `write_vectored`
+const MAX_SLICES: usize = 32;
+const DEFAULT_CHUNK_SIZE: usize = 4096;
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// Writes all the slices into the writer.
+///
+/// This is the stable counterpart of `Write::write_all_vectored`, it keeps
+/// calling `write_vectored` until all the data is written. The slices are
+/// modified to track the progress, so their content is unspecified after the
+/// call.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::IoSlice;
+/// use may::io::write_all_vectored;
+///
+/// let mut out = Vec::new();
+/// let mut bufs = [IoSlice::new(b"hello "), IoSlice::new(b"world")];
+/// write_all_vectored(&mut out, &mut bufs).unwrap();
+/// assert_eq!(out, b"hello world");
+/// ```
+pub fn write_all_vectored<W: Write + ?Sized>(
+    w: &mut W,
+    mut bufs: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    // skip the empty slices at the beginning
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match w.write_vectored(bufs) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A buffered writer that flushes the buffered data with vectored writes.
+///
+/// Similar to `std::io::BufWriter`, small writes are coalesced into chunks.
+/// In addition, owned buffers can be appended with [`queue`] without copying.
+/// The buffered data is written out by [`flush`], or automatically when more
+/// than `capacity` bytes are pending, each underlying write sends as many
+/// chunks as possible with a single `write_vectored`.
+///
+/// The buffered data is flushed when the writer is dropped, the errors are
+/// ignored. Call [`flush`] explicitly to handle them.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Write;
+/// use may::io::CoBufWriter;
+///
+/// let mut w = CoBufWriter::new(Vec::new());
+/// w.write_all(b"header;").unwrap();
+/// w.queue(b"payload".to_vec()).unwrap();
+/// w.flush().unwrap();
+/// assert_eq!(w.get_ref(), b"header;payload");
+/// ```
+///
+/// [`queue`]: #method.queue
+/// [`flush`]: #method.flush
+pub struct CoBufWriter<W: Write> {
+    inner: W,
+    chunks: VecDeque<Vec<u8>>,
+    // bytes of the first chunk that are already written
+    written: usize,
+    // bytes that are not written yet
+    pending: usize,
+    chunk_size: usize,
+    capacity: usize,
+    // a written chunk kept to avoid allocation
+    spare: Option<Vec<u8>>,
+}
+
+impl<W: Write> CoBufWriter<W> {
+    /// Creates a new writer with the default capacity of 64 KiB.
+    pub fn new(inner: W) -> CoBufWriter<W> {
+        CoBufWriter::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    /// Creates a new writer that flushes once `capacity` bytes are pending.
+    pub fn with_capacity(capacity: usize, inner: W) -> CoBufWriter<W> {
+        CoBufWriter {
+            inner,
+            chunks: VecDeque::new(),
+            written: 0,
+            pending: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE.min(capacity.max(1)),
+            capacity,
+            spare: None,
+        }
+    }
+
+    /// Appends an owned buffer without copying it.
+    ///
+    /// The pending data is flushed first if the capacity would be exceeded.
+    pub fn queue(&mut self, buf: Vec<u8>) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if self.pending + buf.len() > self.capacity {
+            self.flush_buf()?;
+        }
+        self.pending += buf.len();
+        self.chunks.push_back(buf);
+        Ok(())
+    }
+
+    /// Returns the number of the bytes that are not written yet.
+    pub fn buffered(&self) -> usize {
+        self.pending
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer.
+    ///
+    /// It's inadvisable to directly write to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Flushes the buffered data and returns the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush_buf()?;
+        let mut this = std::mem::ManuallyDrop::new(self);
+        // the other fields are dropped here, the writer is moved out
+        unsafe {
+            std::ptr::drop_in_place(&mut this.chunks);
+            std::ptr::drop_in_place(&mut this.spare);
+            Ok(std::ptr::read(&this.inner))
+        }
+    }
+
+    // write all the pending chunks to the underlying writer
+    fn flush_buf(&mut self) -> io::Result<()> {
+        while self.pending > 0 {
+            let n = {
+                let mut slices = SmallVec::<[IoSlice; MAX_SLICES]>::new();
+                let mut chunks = self.chunks.iter();
+                if let Some(first) = chunks.next() {
+                    slices.push(IoSlice::new(&first[self.written..]));
+                }
+                slices.extend(chunks.take(MAX_SLICES - 1).map(|c| IoSlice::new(c)));
+                match self.inner.write_vectored(&slices) {
+                    Ok(0) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "failed to write the buffered data",
+                        ))
+                    }
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+            self.consume(n);
+        }
+        Ok(())
+    }
+
+    // drop the written chunks
+    fn consume(&mut self, mut n: usize) {
+        self.pending -= n;
+        while n > 0 {
+            let left = self.chunks[0].len() - self.written;
+            if n < left {
+                self.written += n;
+                return;
+            }
+            n -= left;
+            self.written = 0;
+            let mut chunk = self.chunks.pop_front().unwrap();
+            if chunk.capacity() >= self.chunk_size {
+                chunk.clear();
+                self.spare = Some(chunk);
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for CoBufWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending + buf.len() > self.capacity {
+            self.flush_buf()?;
+        }
+        // the big buffer is written directly
+        if buf.len() >= self.capacity {
+            return self.inner.write(buf);
+        }
+
+        self.pending += buf.len();
+        if let Some(last) = self.chunks.back_mut() {
+            // coalesce into the last chunk if it has room, without reallocation
+            if last.len() + buf.len() <= last.capacity() {
+                last.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+        }
+        let mut chunk = match self.spare.take() {
+            Some(c) if buf.len() <= c.capacity() => c,
+            _ => Vec::with_capacity(self.chunk_size.max(buf.len())),
+        };
+        chunk.extend_from_slice(buf);
+        self.chunks.push_back(chunk);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut n = 0;
+        for buf in bufs {
+            n += self.write(buf)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for CoBufWriter<W> {
+    fn drop(&mut self) {
+        // don't do io when unwinding, e.g. the coroutine is cancelled
+        if !std::thread::panicking() {
+            let _ = self.flush_buf();
+        }
+    }
+}
+
+impl<W: Write + fmt::Debug> fmt::Debug for CoBufWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CoBufWriter")
+            .field("inner", &self.inner)
+            .field("buffered", &self.pending)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    // a writer that accepts at most 3 slices and 10 bytes each time
+    struct Limited(Vec<u8>);
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            let mut n = 0;
+            for buf in bufs.iter().take(3) {
+                let len = buf.len().min(10 - n);
+                self.0.extend_from_slice(&buf[..len]);
+                n += len;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buf_writer_partial_write() {
+        let mut w = CoBufWriter::with_capacity(100, Limited(Vec::new()));
+        let mut expected = Vec::new();
+        for i in 0..10u8 {
+            let frame = vec![i; i as usize + 1];
+            expected.extend_from_slice(&frame);
+            if i % 2 == 0 {
+                w.write_all(&frame).unwrap();
+            } else {
+                w.queue(frame).unwrap();
+            }
+        }
+        assert_eq!(w.buffered(), 55);
+        w.flush().unwrap();
+        assert_eq!(w.buffered(), 0);
+        assert_eq!(w.into_inner().unwrap().0, expected);
+    }
+
+    #[test]
+    fn buf_writer_tcp() {
+        use crate::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h = go!(move || {
+            let mut w = CoBufWriter::new(TcpStream::connect(addr).unwrap());
+            for i in 0..1000u32 {
+                w.queue(i.to_be_bytes().to_vec()).unwrap();
+                w.write_all(b";").unwrap();
+            }
+        });
+
+        let (mut s, _) = listener.accept().unwrap();
+        let mut data = Vec::new();
+        s.read_to_end(&mut data).unwrap();
+        h.join().unwrap();
+        assert_eq!(data.len(), 5000);
+        for (i, frame) in data.chunks(5).enumerate() {
+            assert_eq!(frame[..4], (i as u32).to_be_bytes());
+            assert_eq!(frame[4], b';');
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! framed streams with pluggable codecs
+//!
+//! a [`Framed`] turns a byte stream into a stream of frames. the bytes read
+//! from the stream are buffered until the [`Decoder`] finds a whole frame, so
+//! the partial reads are handled in one place, and the frames are encoded by
+//! the [`Encoder`] into a write buffer that is written out on flush.
+//!
+//! the stream is used in the blocking style, for the coroutine io types a
+//! read parks the coroutine until more data arrives and a write parks it
+//! while the socket buffer is full, that's the write backpressure.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const INITIAL_CAPACITY: usize = 8 * 1024;
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Decodes the frames from the buffered bytes.
+pub trait Decoder {
+    /// The type of the decoded frames.
+    type Item;
+
+    /// Decodes a frame from the front of `src`.
+    ///
+    /// Returns the frame and the number of the bytes it consumed, or `None`
+    /// if `src` doesn't hold a whole frame yet. `src` always starts at the
+    /// first byte that is not consumed, and it only grows until a frame is
+    /// returned.
+    fn decode(&mut self, src: &[u8]) -> io::Result<Option<(Self::Item, usize)>>;
+
+    /// Decodes a frame when the stream reaches the end.
+    ///
+    /// By default it's the same as [`decode`], and an error of
+    /// `UnexpectedEof` is returned if the remaining bytes are not a frame.
+    ///
+    /// [`decode`]: Decoder::decode
+    fn decode_eof(&mut self, src: &[u8]) -> io::Result<Option<(Self::Item, usize)>> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "bytes remaining on stream",
+            )),
+        }
+    }
+}
+
+/// Encodes the frames into bytes.
+pub trait Encoder<Item> {
+    /// Appends the encoded frame to `dst`.
+    fn encode(&mut self, item: Item, dst: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// A codec for the frames separated by `\n`.
+///
+/// The decoded lines are `String`s without the trailing `\n` or `\r\n`. Any
+/// `AsRef<str>` can be encoded, a `\n` is appended to it.
+#[derive(Debug, Clone)]
+pub struct LinesCodec {
+    max_length: usize,
+    // the bytes before it are already checked for `\n`
+    next_index: usize,
+}
+
+impl LinesCodec {
+    /// Creates a codec without the line length limit.
+    pub fn new() -> Self {
+        Self::with_max_length(usize::MAX)
+    }
+
+    /// Creates a codec that fails with `InvalidData` on the lines longer
+    /// than `max_length` bytes, not counting the line break.
+    pub fn with_max_length(max_length: usize) -> Self {
+        LinesCodec {
+            max_length,
+            next_index: 0,
+        }
+    }
+
+    /// Returns the max length of a line.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+}
+
+impl Default for LinesCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn to_line(buf: &[u8]) -> io::Result<String> {
+    let buf = buf.strip_suffix(b"\r").unwrap_or(buf);
+    String::from_utf8(buf.to_vec()).map_err(|_| invalid_data("line is not valid utf8"))
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+
+    fn decode(&mut self, src: &[u8]) -> io::Result<Option<(String, usize)>> {
+        let limit = src.len().min(self.max_length.saturating_add(2));
+        match src[self.next_index..limit].iter().position(|b| *b == b'\n') {
+            Some(pos) => {
+                let end = self.next_index + pos;
+                self.next_index = 0;
+                let line = to_line(&src[..end])?;
+                if line.len() > self.max_length {
+                    return Err(invalid_data("line length limit exceeded"));
+                }
+                Ok(Some((line, end + 1)))
+            }
+            None if limit < src.len() => Err(invalid_data("line length limit exceeded")),
+            None => {
+                self.next_index = limit;
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &[u8]) -> io::Result<Option<(String, usize)>> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            // the last line without a line break
+            None => {
+                self.next_index = 0;
+                Ok(Some((to_line(src)?, src.len())))
+            }
+        }
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for LinesCodec {
+    fn encode(&mut self, item: T, dst: &mut Vec<u8>) -> io::Result<()> {
+        let line = item.as_ref();
+        dst.reserve(line.len() + 1);
+        dst.extend_from_slice(line.as_bytes());
+        dst.push(b'\n');
+        Ok(())
+    }
+}
+
+/// A codec for the frames prefixed by their length.
+///
+/// The length is a big endian `u32` that doesn't count itself. The decoded
+/// frames are `Vec<u8>`s, any `AsRef<[u8]>` can be encoded.
+#[derive(Debug, Clone)]
+pub struct LengthDelimitedCodec {
+    max_frame_length: usize,
+}
+
+impl LengthDelimitedCodec {
+    /// Creates a codec with the max frame length of 8MB.
+    pub fn new() -> Self {
+        Self::with_max_frame_length(8 * 1024 * 1024)
+    }
+
+    /// Creates a codec that fails with `InvalidData` on the frames longer
+    /// than `max_frame_length` bytes, in both directions.
+    pub fn with_max_frame_length(max_frame_length: usize) -> Self {
+        LengthDelimitedCodec { max_frame_length }
+    }
+
+    /// Returns the max length of a frame.
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, src: &[u8]) -> io::Result<Option<(Vec<u8>, usize)>> {
+        let head = match src.get(..4) {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as usize;
+        if len > self.max_frame_length {
+            return Err(invalid_data("frame length limit exceeded"));
+        }
+        match src.get(4..4 + len) {
+            Some(frame) => Ok(Some((frame.to_vec(), 4 + len))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LengthDelimitedCodec {
+    fn encode(&mut self, item: T, dst: &mut Vec<u8>) -> io::Result<()> {
+        let frame = item.as_ref();
+        if frame.len() > self.max_frame_length || frame.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame length limit exceeded",
+            ));
+        }
+        dst.reserve(4 + frame.len());
+        dst.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        dst.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+/// A stream of frames over a byte stream.
+///
+/// [`recv`] reads until the codec decodes a whole frame, [`feed`] encodes a
+/// frame into the write buffer and [`flush`] writes the buffer out, [`send`]
+/// does both. It's also an iterator of the received frames.
+///
+/// [`recv`]: Framed::recv
+/// [`feed`]: Framed::feed
+/// [`flush`]: Framed::flush
+/// [`send`]: Framed::send
+///
+/// # Examples
+///
+/// ```rust
+/// use may::io::codec::{Framed, LinesCodec};
+/// use may::net::{TcpListener, TcpStream};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let echo = may::go!(move || {
+///     let (s, _) = listener.accept().unwrap();
+///     let mut framed = Framed::new(s, LinesCodec::new());
+///     while let Some(line) = framed.recv().unwrap() {
+///         framed.send(line.to_uppercase()).unwrap();
+///     }
+/// });
+///
+/// let mut framed = Framed::new(TcpStream::connect(addr).unwrap(), LinesCodec::new());
+/// framed.send("hello").unwrap();
+/// assert_eq!(framed.recv().unwrap().unwrap(), "HELLO");
+/// drop(framed);
+/// echo.join().unwrap();
+/// ```
+pub struct Framed<S, C> {
+    stream: S,
+    codec: C,
+    rd: Vec<u8>,
+    // the start of the bytes that are not consumed in `rd`
+    rd_pos: usize,
+    wr: Vec<u8>,
+    eof: bool,
+}
+
+impl<S, C> Framed<S, C> {
+    /// Creates a framed stream with the codec.
+    pub fn new(stream: S, codec: C) -> Self {
+        Framed {
+            stream,
+            codec,
+            rd: Vec::with_capacity(INITIAL_CAPACITY),
+            rd_pos: 0,
+            wr: Vec::with_capacity(INITIAL_CAPACITY),
+            eof: false,
+        }
+    }
+
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Reading or writing the stream directly may corrupt the frames.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Gets a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Gets a mutable reference to the codec.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Returns the bytes that are read but not decoded yet.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.rd[self.rd_pos..]
+    }
+
+    /// Returns the stream, the codec and the read buffer, the frames that
+    /// are fed but not flushed are discarded.
+    pub fn into_parts(mut self) -> (S, C, Vec<u8>) {
+        self.rd.drain(..self.rd_pos);
+        (self.stream, self.codec, self.rd)
+    }
+}
+
+impl<S: Read, C: Decoder> Framed<S, C> {
+    /// Receives a frame, returns `None` when the stream reaches the end.
+    pub fn recv(&mut self) -> io::Result<Option<C::Item>> {
+        loop {
+            let src = &self.rd[self.rd_pos..];
+            let frame = if self.eof {
+                self.codec.decode_eof(src)?
+            } else {
+                self.codec.decode(src)?
+            };
+            if let Some((item, n)) = frame {
+                assert!(n <= src.len(), "decoder consumed more than the buffer");
+                self.rd_pos += n;
+                return Ok(Some(item));
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            self.fill()?;
+        }
+    }
+
+    // read more bytes into the read buffer
+    fn fill(&mut self) -> io::Result<()> {
+        // move the bytes that are not consumed to the front
+        if self.rd_pos > 0 {
+            self.rd.drain(..self.rd_pos);
+            self.rd_pos = 0;
+        }
+        let len = self.rd.len();
+        self.rd.resize(len + READ_CHUNK, 0);
+        let ret = loop {
+            match self.stream.read(&mut self.rd[len..]) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                ret => break ret,
+            }
+        };
+        let n = *ret.as_ref().unwrap_or(&0);
+        self.rd.truncate(len + n);
+        if ret? == 0 {
+            self.eof = true;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Write, C> Framed<S, C> {
+    /// Encodes a frame into the write buffer without writing it out.
+    pub fn feed<I>(&mut self, item: I) -> io::Result<()>
+    where
+        C: Encoder<I>,
+    {
+        self.codec.encode(item, &mut self.wr)
+    }
+
+    /// Writes out all the buffered frames.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let ret = self.stream.write_all(&self.wr);
+        // the written bytes are unknown on error, the frames are broken anyway
+        self.wr.clear();
+        ret?;
+        self.stream.flush()
+    }
+
+    /// Encodes a frame and writes out all the buffered frames.
+    pub fn send<I>(&mut self, item: I) -> io::Result<()>
+    where
+        C: Encoder<I>,
+    {
+        self.feed(item)?;
+        self.flush()
+    }
+}
+
+impl<S: Read, C: Decoder> Iterator for Framed<S, C> {
+    type Item = io::Result<C::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv().transpose()
+    }
+}
+
+impl<S: fmt::Debug, C: fmt::Debug> fmt::Debug for Framed<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Framed")
+            .field("stream", &self.stream)
+            .field("codec", &self.codec)
+            .field("read_buffered", &self.read_buffer().len())
+            .field("write_buffered", &self.wr.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a reader that returns one byte each time
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((b, rest)) if !buf.is_empty() => {
+                    buf[0] = *b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn lines_partial_reads() {
+        let framed = Framed::new(Trickle(b"one\r\ntwo\n\nlast"), LinesCodec::new());
+        let lines: Vec<_> = framed.map(Result::unwrap).collect();
+        assert_eq!(lines, ["one", "two", "", "last"]);
+
+        let mut framed = Framed::new(Trickle(b"ok\ntoo long\n"), LinesCodec::with_max_length(3));
+        assert_eq!(framed.recv().unwrap().unwrap(), "ok");
+        let err = framed.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn length_delimited_round_trip() {
+        let mut codec = LengthDelimitedCodec::new();
+        let mut buf = Vec::new();
+        codec.encode(b"hello", &mut buf).unwrap();
+        codec.encode(Vec::new(), &mut buf).unwrap();
+        assert_eq!(&buf[..9], b"\0\0\0\x05hello");
+
+        let mut framed = Framed::new(Trickle(&buf), codec);
+        assert_eq!(framed.recv().unwrap().unwrap(), b"hello");
+        assert_eq!(framed.recv().unwrap().unwrap(), b"");
+        assert!(framed.recv().unwrap().is_none());
+
+        // a truncated frame
+        let mut framed = Framed::new(Trickle(&buf[..7]), LengthDelimitedCodec::new());
+        let err = framed.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut framed = Framed::new(Vec::new(), LengthDelimitedCodec::with_max_frame_length(4));
+        framed.feed(b"abcd").unwrap();
+        assert!(framed.feed(b"abcde").is_err());
+        framed.flush().unwrap();
+        assert_eq!(framed.get_ref(), b"\0\0\0\x04abcd");
+    }
+}
@@ -1,9 +1,9 @@
 use std::io;
+use std::time::{Duration, Instant};
 
 use super::sys::{Selector, SysEvent};
-use crate::scheduler::{get_scheduler, WORKER_ID};
-
-const IO_POLLS_MAX: usize = 128;
+use crate::config::config;
+use crate::scheduler::{get_scheduler, Scheduler, WORKER_ID};
 
 /// Single threaded IO event loop.
 pub struct EventLoop {
@@ -11,26 +11,60 @@ pub struct EventLoop {
 }
 
 impl EventLoop {
-    pub fn new(io_workers: usize) -> io::Result<EventLoop> {
-        Selector::new(io_workers).map(|selector| EventLoop { selector })
+    pub fn new(workers: usize, io_threads: usize) -> io::Result<EventLoop> {
+        #[cfg(unix)]
+        let selector = Selector::new(workers, io_threads);
+        // iocp doesn't support dedicated io threads
+        #[cfg(windows)]
+        let selector = {
+            debug_assert_eq!(io_threads, 0);
+            Selector::new(workers)
+        };
+        selector.map(|selector| EventLoop { selector })
     }
 
     /// Keep spinning the event loop indefinitely, and notify the handler whenever
     /// any of the registered handles are ready.
     pub fn run(&self, id: usize) {
-        #[cfg(nightly)]
-        WORKER_ID.set(id);
-        #[cfg(not(nightly))]
-        WORKER_ID.with(|worker_id| worker_id.set(id));
+        let scheduler = get_scheduler();
+        // the dedicated io threads only dispatch the io events to the workers
+        let is_worker = id < scheduler.workers();
+        if is_worker {
+            #[cfg(nightly)]
+            WORKER_ID.set(id);
+            #[cfg(not(nightly))]
+            WORKER_ID.with(|worker_id| worker_id.set(id));
+        }
 
-        let mut events_buf: [SysEvent; IO_POLLS_MAX] = unsafe { std::mem::zeroed() };
+        let batch = config().get_poll_batch();
+        let mut events_buf: Vec<SysEvent> =
+            (0..batch).map(|_| unsafe { std::mem::zeroed() }).collect();
         let mut next_expire = None;
         let selector = &self.selector;
-        let scheduler = get_scheduler();
+        let busy = is_worker && config().get_busy_poll_workers().contains(&id);
+        let mut spinner = Spinner::new(is_worker && !busy);
+        let mut watchdog = Watchdog::new(!busy);
+        if busy {
+            info!("worker {} is busy polling", id);
+        }
 
         loop {
-            next_expire = match selector.select(scheduler, id, &mut events_buf, next_expire) {
-                Ok(t) => t.or(Some(1_000_000_000)),
+            // don't sleep in the poller if new coroutines come while spinning
+            let timeout = if busy || spinner.spin(scheduler, id) {
+                Some(0)
+            } else {
+                next_expire
+            };
+            watchdog.poll(id);
+            let start = spinner.adaptive.then(Instant::now);
+            let ret = selector.select(scheduler, id, &mut events_buf, timeout);
+            if let Some(start) = start {
+                spinner.adjust(start.elapsed().as_nanos() as u64);
+            }
+            // the poll timeout can be adjusted at runtime
+            let poll_timeout = config().get_poll_timeout().as_nanos() as u64;
+            next_expire = match ret {
+                Ok(t) => Some(t.map_or(poll_timeout, |t| t.min(poll_timeout))),
                 Err(e) => {
                     error!("select error = {:?}", e);
                     continue;
@@ -45,3 +79,135 @@ impl EventLoop {
         &self.selector
     }
 }
+
+// spin an idle worker for a while before it's parked in the poller
+struct Spinner {
+    // the configured max spin iterations
+    max: usize,
+    // the spin iterations of the next round
+    limit: usize,
+    adaptive: bool,
+    // the time spent in the last spinning, in ns
+    spin_time: u64,
+}
+
+impl Spinner {
+    fn new(is_worker: bool) -> Self {
+        let config = config();
+        let max = if is_worker {
+            config.get_spin_count()
+        } else {
+            0
+        };
+        let adaptive = config.get_adaptive_spin() && max > 0;
+        Spinner {
+            max,
+            limit: max,
+            adaptive,
+            spin_time: 0,
+        }
+    }
+
+    // return true if there are ready coroutines found during spinning
+    fn spin(&mut self, scheduler: &Scheduler, id: usize) -> bool {
+        if self.limit == 0 {
+            return false;
+        }
+        let start = self.adaptive.then(Instant::now);
+        let mut found = false;
+        for _ in 0..self.limit {
+            if scheduler.has_ready_tasks(id) {
+                found = true;
+                break;
+            }
+            std::hint::spin_loop();
+        }
+        if let Some(start) = start {
+            self.spin_time = start.elapsed().as_nanos() as u64;
+            if found {
+                // spinning pays off, spin longer next time
+                self.limit = (self.limit * 2).min(self.max);
+                self.spin_time = 0;
+            }
+        }
+        found
+    }
+
+    // adjust the spin iterations by the time the worker was parked
+    fn adjust(&mut self, parked: u64) {
+        if self.spin_time == 0 {
+            return;
+        }
+        if parked <= self.spin_time {
+            // woken up soon after giving up spinning
+            self.limit = (self.limit * 2).min(self.max);
+        } else if parked > self.spin_time * 16 {
+            // idle for long, spin less to save the cpu, but never stop
+            // spinning completely so that we can still grow it back
+            self.limit = (self.limit / 2).max(1);
+        }
+        self.spin_time = 0;
+    }
+}
+
+// the polls counted between two checks of the watchdog
+const WATCHDOG_POLLS: u64 = 4096;
+// the min time between two warnings of the same worker
+const WATCHDOG_WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+// detect the workers that poll too often without being set to busy poll
+struct Watchdog {
+    // the polls per second to warn, 0 if disabled
+    threshold: u64,
+    polls: u64,
+    start: Instant,
+    warned: Option<Instant>,
+}
+
+impl Watchdog {
+    fn new(enable: bool) -> Self {
+        let threshold = match enable {
+            true => config().get_busy_poll_watchdog() as u64,
+            false => 0,
+        };
+        Watchdog {
+            threshold,
+            polls: 0,
+            start: Instant::now(),
+            warned: None,
+        }
+    }
+
+    #[inline]
+    #[allow(clippy::manual_is_multiple_of)]
+    fn poll(&mut self, id: usize) {
+        if self.threshold == 0 {
+            return;
+        }
+        self.polls += 1;
+        // only check the time once in a while
+        if self.polls % WATCHDOG_POLLS != 0 {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return;
+        }
+        let rate = (self.polls as f64 / elapsed.as_secs_f64()) as u64;
+        let now = Instant::now();
+        let quiet = self
+            .warned
+            .is_some_and(|t| now - t < WATCHDOG_WARN_INTERVAL);
+        if rate > self.threshold && !quiet {
+            warn!(
+                "worker {} polls {} times per second, a coroutine may spin on \
+                 `yield_now` or an io is always ready, use `set_busy_poll_workers` \
+                 if it's intended",
+                id, rate
+            );
+            self.warned = Some(now);
+        }
+        self.polls = 0;
+        self.start = now;
+    }
+}
@@ -1,4 +1,34 @@
 //! coroutine io utilities
+//!
+//! ## Foreign io objects
+//!
+//! the io types of other crates can be made coroutine aware without access
+//! to the internals of the reactor. on unix, a non-blocking fd is registered
+//! by [`IoData::register`], the io type exposes the io data by implementing
+//! [`AsIoData`], and then [`WaitIo::wait_io`] parks the coroutine until the
+//! fd is ready again. the io operation is retried in a loop:
+//!
+//! 1. call [`WaitIo::reset_io`] to clear the ready flag
+//! 2. do the non-blocking io operation
+//! 3. on `WouldBlock`, call [`WaitIo::wait_io`] and go back to 1
+//!
+//! hold an [`OperationGuard`] over the loop to make it safe to cancel.
+//!
+//! these traits and functions are the supported extension points and follow
+//! the semver of this crate, the event source machinery behind them is an
+//! implementation detail that may change in any release.
+//!
+//! ## Cancellation
+//!
+//! a coroutine blocked in an io operation is unwound when it's cancelled
+//! with the `io_cancel` feature. the io timer and the cancel registration of
+//! the interrupted operation are cleared when the unwinding starts, the
+//! io objects on the stack are dropped by the unwinding, which deregisters
+//! their fds, and only then the stack of the coroutine is reclaimed. so an
+//! io object that survives the cancel, e.g. one that is returned from
+//! [`catch_cancel`], can be used again by another coroutine.
+//!
+//! [`catch_cancel`]: crate::coroutine::catch_cancel
 
 #[cfg(unix)]
 #[path = "sys/unix/mod.rs"]
@@ -11,23 +41,52 @@ pub(crate) mod sys;
 // export the generic IO wrapper
 pub mod co_io_err;
 
+mod buf_pool;
+mod buf_stream;
+mod buf_writer;
+pub mod codec;
 mod event_loop;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod reactor;
 pub(crate) mod split_io;
+#[cfg(unix)]
+mod stdio;
 pub(crate) mod thread;
+#[cfg(feature = "io_timeout")]
+mod timeout;
+#[cfg(unix)]
+pub mod tty;
+#[cfg(unix)]
+mod waker;
 
 use std::ops::Deref;
 
+pub use self::buf_pool::{BufPool, PooledBuf};
+pub use self::buf_stream::BufStream;
+pub use self::buf_writer::{write_all_vectored, CoBufWriter};
 pub(crate) use self::event_loop::EventLoop;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::reactor::{Event, Interest, Reactor};
+#[cfg(unix)]
+pub use self::stdio::{stderr, stdin, stdout, OutputLock, Stderr, Stdin, StdinLock, Stdout};
 #[cfg(feature = "io_cancel")]
 pub(crate) use self::sys::cancel;
 pub use self::sys::co_io::CoIo;
 #[cfg(unix)]
-pub use self::sys::wait_io::{WaitIo, WaitIoWaker};
+pub use self::sys::wait_io::{OperationGuard, WaitIo, WaitIoWaker};
 pub use self::sys::IoData;
-pub(crate) use self::sys::{add_socket, net, Selector};
+pub(crate) use self::sys::{add_listener, add_socket, net, Selector};
+#[cfg(unix)]
+pub use self::waker::{waker, WakeListener, Waker};
 pub use split_io::{SplitIo, SplitReader, SplitWriter};
+#[cfg(feature = "io_timeout")]
+pub use timeout::{Deadline, TimeoutIo};
 
+/// Exposes the io data registered to the reactor.
+///
+/// Implementing this trait makes the type a [`WaitIo`] on unix.
 pub trait AsIoData {
+    /// Gets the io data of the object.
     fn as_io_data(&self) -> &IoData;
 }
 
@@ -0,0 +1,316 @@
+//! a poller for the fds of the foreign event libraries
+//!
+//! the C event libraries in the style of libuv and libevent expect the
+//! embedder to poll their fds and to call back into them with the ready
+//! ones. [`Reactor`] is an epoll instance of its own, the fds are registered
+//! with a token, and [`Reactor::next_events`] returns the ready ones. the
+//! reactor fd is registered to may's poller, so a coroutine waiting for the
+//! events is parked instead of blocking the worker thread.
+//!
+//! the other way around, the reactor fd is readable when any of the
+//! registered fds is ready, so it can be nested into an external event loop,
+//! e.g. when may is embedded in a plugin host. a [`Waker`] fd can be written
+//! by the host to wake a coroutine.
+//!
+//! it's only available on linux and android for now.
+//!
+//! [`Waker`]: super::Waker
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::BitOr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Duration;
+
+use crate::io as io_impl;
+use crate::io::net as net_impl;
+use crate::yield_now::yield_with_io;
+
+/// The readiness that a registered fd is interested in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interest(u32);
+
+impl Interest {
+    /// Interested in the readable readiness.
+    pub const READABLE: Interest = Interest(libc::EPOLLIN as u32);
+    /// Interested in the writable readiness.
+    pub const WRITABLE: Interest = Interest(libc::EPOLLOUT as u32);
+
+    /// Returns true if the readable readiness is included.
+    pub fn is_readable(self) -> bool {
+        self.0 & Interest::READABLE.0 != 0
+    }
+
+    /// Returns true if the writable readiness is included.
+    pub fn is_writable(self) -> bool {
+        self.0 & Interest::WRITABLE.0 != 0
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, other: Interest) -> Interest {
+        Interest(self.0 | other.0)
+    }
+}
+
+/// A ready event returned by [`Reactor::next_events`].
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Event(libc::epoll_event);
+
+impl Event {
+    /// Gets the token that the fd is registered with.
+    pub fn token(&self) -> u64 {
+        self.0.u64
+    }
+
+    /// Returns true if the fd is readable.
+    pub fn is_readable(&self) -> bool {
+        self.flags() & (libc::EPOLLIN | libc::EPOLLPRI) as u32 != 0
+    }
+
+    /// Returns true if the fd is writable.
+    pub fn is_writable(&self) -> bool {
+        self.flags() & libc::EPOLLOUT as u32 != 0
+    }
+
+    /// Returns true if there is an error on the fd.
+    pub fn is_error(&self) -> bool {
+        self.flags() & libc::EPOLLERR as u32 != 0
+    }
+
+    /// Returns true if the peer hung up.
+    pub fn is_hangup(&self) -> bool {
+        self.flags() & (libc::EPOLLHUP | libc::EPOLLRDHUP) as u32 != 0
+    }
+
+    fn flags(&self) -> u32 {
+        self.0.events
+    }
+}
+
+impl Default for Event {
+    fn default() -> Event {
+        Event(libc::epoll_event { events: 0, u64: 0 })
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("token", &self.token())
+            .field("readable", &self.is_readable())
+            .field("writable", &self.is_writable())
+            .field("error", &self.is_error())
+            .field("hangup", &self.is_hangup())
+            .finish()
+    }
+}
+
+/// A poller of the raw fds with the tokens given by the embedder.
+///
+/// The fds are polled in the level triggered mode, an fd is returned again
+/// by the next [`next_events`] until it's not ready any more, as the C event
+/// libraries expect.
+///
+/// [`next_events`]: Reactor::next_events
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Write;
+/// use std::os::unix::io::AsRawFd;
+/// use std::os::unix::net::UnixStream;
+/// use may::io::{Event, Interest, Reactor};
+///
+/// let reactor = Reactor::new().unwrap();
+/// let (mut a, b) = UnixStream::pair().unwrap();
+/// reactor.register_raw(b.as_raw_fd(), Interest::READABLE, 42).unwrap();
+///
+/// let h = may::go!(move || {
+///     let mut events = [Event::default(); 8];
+///     let n = reactor.next_events(&mut events, None).unwrap();
+///     // call back into the C library with the ready ones
+///     events[..n].iter().map(|e| e.token()).collect::<Vec<_>>()
+/// });
+/// a.write_all(b"ping").unwrap();
+/// assert_eq!(h.join().unwrap(), [42]);
+/// ```
+pub struct Reactor {
+    _io: io_impl::IoData,
+    sys: File,
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+impl Reactor {
+    /// Creates a reactor without any fd registered.
+    pub fn new() -> io::Result<Reactor> {
+        let fd = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        let sys = unsafe { File::from_raw_fd(fd) };
+        let io = io_impl::add_socket(&sys)?;
+        Ok(Reactor { _io: io, sys })
+    }
+
+    /// Registers the fd with the interest, the events of it come with the
+    /// token.
+    ///
+    /// The fd is not owned by the reactor, it must be deregistered before
+    /// it's closed.
+    pub fn register_raw(&self, fd: RawFd, interest: Interest, token: u64) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd, interest, token)
+    }
+
+    /// Changes the interest and the token of a registered fd.
+    pub fn reregister_raw(&self, fd: RawFd, interest: Interest, token: u64) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_MOD, fd, interest, token)
+    }
+
+    /// Removes the fd from the reactor.
+    pub fn deregister_raw(&self, fd: RawFd) -> io::Result<()> {
+        let mut ev = libc::epoll_event { events: 0, u64: 0 };
+        let epfd = self.sys.as_raw_fd();
+        cvt(unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_DEL, fd, &mut ev) }).map(drop)
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, interest: Interest, token: u64) -> io::Result<()> {
+        let mut ev = libc::epoll_event {
+            events: interest.0 | libc::EPOLLRDHUP as u32,
+            u64: token,
+        };
+        cvt(unsafe { libc::epoll_ctl(self.sys.as_raw_fd(), op, fd, &mut ev) }).map(drop)
+    }
+
+    // get the ready events without blocking
+    fn poll(&self, events: &mut [Event]) -> io::Result<usize> {
+        let len = events.len().min(libc::c_int::MAX as usize) as libc::c_int;
+        let ptr = events.as_mut_ptr() as *mut libc::epoll_event;
+        let n = loop {
+            match cvt(unsafe { libc::epoll_wait(self.sys.as_raw_fd(), ptr, len, 0) }) {
+                Ok(n) => break n as usize,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        };
+        if n == 0 && len > 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(n)
+    }
+
+    /// Waits for the ready events and fills them into `events`, returns the
+    /// number of them.
+    ///
+    /// It returns `Ok(0)` if nothing is ready when the timeout expires. A
+    /// zero timeout only checks the events that are already ready, the other
+    /// timeouts need the `io_timeout` feature and are ignored without it.
+    /// This works in both coroutine and thread contexts.
+    pub fn next_events(
+        &self,
+        events: &mut [Event],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        self._io.reset();
+        // this is an earlier return try for the ready events
+        match self.poll(events) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            ret => return ret,
+        }
+        if timeout == Some(Duration::ZERO) {
+            return Ok(0);
+        }
+
+        let mut waiter = net_impl::SocketOp::new(
+            self,
+            #[cfg(feature = "io_timeout")]
+            timeout,
+            || self.poll(events),
+        );
+        #[cfg(not(feature = "io_timeout"))]
+        let _ = timeout;
+        yield_with_io(&waiter, waiter.is_coroutine);
+        match waiter.done() {
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            ret => ret,
+        }
+    }
+}
+
+impl io_impl::AsIoData for Reactor {
+    fn as_io_data(&self) -> &io_impl::IoData {
+        &self._io
+    }
+}
+
+impl AsRawFd for Reactor {
+    /// The fd to be nested into an external event loop, it's readable when
+    /// any of the registered fds is ready.
+    fn as_raw_fd(&self) -> RawFd {
+        self.sys.as_raw_fd()
+    }
+}
+
+impl fmt::Debug for Reactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reactor")
+            .field("fd", &self.sys.as_raw_fd())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn reactor_next_events() {
+        let reactor = Reactor::new().unwrap();
+        let (mut a, b) = UnixStream::pair().unwrap();
+        let fd = b.as_raw_fd();
+        reactor.register_raw(fd, Interest::READABLE, 7).unwrap();
+
+        let mut events = [Event::default(); 4];
+        assert_eq!(
+            reactor
+                .next_events(&mut events, Some(Duration::ZERO))
+                .unwrap(),
+            0
+        );
+        #[cfg(feature = "io_timeout")]
+        {
+            let ret = reactor.next_events(&mut events, Some(Duration::from_millis(10)));
+            assert_eq!(ret.unwrap(), 0);
+        }
+
+        let h = go!(move || {
+            let mut events = [Event::default(); 4];
+            let n = reactor.next_events(&mut events, None).unwrap();
+            assert_eq!(n, 1);
+            assert_eq!(events[0].token(), 7);
+            assert!(events[0].is_readable());
+            // level triggered, the fd is still ready
+            reactor
+                .reregister_raw(fd, Interest::READABLE | Interest::WRITABLE, 8)
+                .unwrap();
+            let n = reactor.next_events(&mut events, None).unwrap();
+            assert_eq!(n, 1);
+            assert_eq!(events[0].token(), 8);
+            assert!(events[0].is_readable() && events[0].is_writable());
+            reactor.deregister_raw(fd).unwrap();
+            b
+        });
+        std::thread::sleep(Duration::from_millis(10));
+        a.write_all(b"ping").unwrap();
+        drop(h.join().unwrap());
+    }
+}
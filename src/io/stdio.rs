@@ -0,0 +1,358 @@
+//! coroutine aware standard io
+//!
+//! the reads and writes of [`stdin`], [`stdout`] and [`stderr`] park the
+//! coroutine instead of blocking the worker thread. a pipe, a socket or a
+//! terminal is set to the non-blocking mode and driven by the reactor, the
+//! other fds that can't be polled, e.g. a regular file or `/dev/null`, are
+//! read and written in the blocking pool.
+//!
+//! the non-blocking mode is a flag of the open file, which is shared with
+//! the `std::io` handles of the same fd and with the other processes that
+//! inherit it. the `print!` family of std may fail with `WouldBlock` once the
+//! fd is non-blocking, so the output of the process should all go through
+//! the handles here.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::CoIo;
+use crate::blocking_pool::run_blocking;
+use crate::sync::{Mutex, MutexGuard};
+
+// the raw standard fd, it's never closed
+#[derive(Debug, Clone, Copy)]
+struct StdFd(RawFd);
+
+impl AsRawFd for StdFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Read for StdFd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Write for StdFd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe { libc::write(self.0, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// the fd driven by the reactor, or by the blocking pool if it can't be polled
+enum StdIo {
+    Reactor(CoIo<StdFd>),
+    Blocking(StdFd),
+}
+
+impl StdIo {
+    fn new(fd: RawFd) -> StdIo {
+        match CoIo::new(StdFd(fd)) {
+            Ok(io) => StdIo::Reactor(io),
+            Err(e) => {
+                info!("stdio fd {} is done in the blocking pool, err = {}", fd, e);
+                StdIo::Blocking(StdFd(fd))
+            }
+        }
+    }
+}
+
+impl Read for StdIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            StdIo::Reactor(io) => io.read(buf),
+            StdIo::Blocking(fd) => {
+                let mut fd = *fd;
+                let len = buf.len();
+                let data = run_blocking(move || {
+                    let mut data = vec![0; len];
+                    fd.read(&mut data).map(|n| {
+                        data.truncate(n);
+                        data
+                    })
+                })?;
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+        }
+    }
+}
+
+impl Write for StdIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            StdIo::Reactor(io) => io.write(buf),
+            StdIo::Blocking(fd) => {
+                let mut fd = *fd;
+                let data = buf.to_vec();
+                run_blocking(move || fd.write(&data))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+static STDIN: Mutex<Option<BufReader<StdIo>>> = Mutex::new(None);
+static STDOUT: Mutex<Option<StdIo>> = Mutex::new(None);
+static STDERR: Mutex<Option<StdIo>> = Mutex::new(None);
+
+// lock the handle and open the fd on the first use
+fn lock<T>(
+    handle: &'static Mutex<Option<T>>,
+    open: impl FnOnce() -> T,
+) -> MutexGuard<'static, Option<T>> {
+    // the io never panics with the lock held
+    let mut guard = handle.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_none() {
+        *guard = Some(open());
+    }
+    guard
+}
+
+/// Returns a handle to the standard input of the process.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::io::Write;
+///
+/// let h = may::go!(|| {
+///     let mut line = String::new();
+///     may::io::stdin().read_line(&mut line).unwrap();
+///     may::io::stdout().write_all(line.as_bytes()).unwrap();
+/// });
+/// h.join().unwrap();
+/// ```
+pub fn stdin() -> Stdin {
+    Stdin { _priv: () }
+}
+
+/// Returns a handle to the standard output of the process.
+///
+/// The output is not buffered, wrap it in a [`CoBufWriter`] to write in
+/// larger chunks.
+///
+/// [`CoBufWriter`]: crate::io::CoBufWriter
+pub fn stdout() -> Stdout {
+    Stdout { _priv: () }
+}
+
+/// Returns a handle to the standard error of the process.
+pub fn stderr() -> Stderr {
+    Stderr { _priv: () }
+}
+
+/// A handle to the standard input, see [`stdin`].
+///
+/// The input is buffered, the buffer is shared by all the handles.
+pub struct Stdin {
+    _priv: (),
+}
+
+/// A locked handle to the standard input, see [`Stdin::lock`].
+pub struct StdinLock<'a> {
+    guard: MutexGuard<'a, Option<BufReader<StdIo>>>,
+}
+
+impl Stdin {
+    /// Locks the handle for the reads that must not be interleaved with the
+    /// other coroutines.
+    pub fn lock(&self) -> StdinLock<'static> {
+        let guard = lock(&STDIN, || BufReader::new(StdIo::new(libc::STDIN_FILENO)));
+        StdinLock { guard }
+    }
+
+    /// Reads a line into the buffer, see [`BufRead::read_line`].
+    pub fn read_line(&self, buf: &mut String) -> io::Result<usize> {
+        self.lock().read_line(buf)
+    }
+}
+
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl StdinLock<'_> {
+    fn reader(&mut self) -> &mut BufReader<StdIo> {
+        // it's opened by the lock
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl Read for StdinLock<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader().read(buf)
+    }
+}
+
+impl BufRead for StdinLock<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader().fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader().consume(amt)
+    }
+}
+
+/// A handle to the standard output, see [`stdout`].
+pub struct Stdout {
+    _priv: (),
+}
+
+/// A handle to the standard error, see [`stderr`].
+pub struct Stderr {
+    _priv: (),
+}
+
+/// A locked handle to the standard output or error, see [`Stdout::lock`]
+/// and [`Stderr::lock`].
+pub struct OutputLock<'a> {
+    guard: MutexGuard<'a, Option<StdIo>>,
+}
+
+impl Stdout {
+    /// Locks the handle for the writes that must not be interleaved with the
+    /// other coroutines.
+    pub fn lock(&self) -> OutputLock<'static> {
+        let guard = lock(&STDOUT, || StdIo::new(libc::STDOUT_FILENO));
+        OutputLock { guard }
+    }
+}
+
+impl Stderr {
+    /// Locks the handle for the writes that must not be interleaved with the
+    /// other coroutines.
+    pub fn lock(&self) -> OutputLock<'static> {
+        let guard = lock(&STDERR, || StdIo::new(libc::STDERR_FILENO));
+        OutputLock { guard }
+    }
+}
+
+impl Write for OutputLock<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // it's opened by the lock
+        self.guard.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    // keep the whole buffer together
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    // keep the whole buffer together
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Stdin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Stdin { .. }")
+    }
+}
+
+impl fmt::Debug for Stdout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Stdout { .. }")
+    }
+}
+
+impl fmt::Debug for Stderr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Stderr { .. }")
+    }
+}
+
+impl fmt::Debug for StdinLock<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("StdinLock { .. }")
+    }
+}
+
+impl fmt::Debug for OutputLock<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OutputLock { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn stdio_pipe_and_file() {
+        let h = go!(|| {
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            let mut r = StdIo::new(fds[0]);
+            let mut w = StdIo::new(fds[1]);
+            assert!(matches!(r, StdIo::Reactor(_)));
+            w.write_all(b"hello").unwrap();
+            let mut buf = [0; 5];
+            r.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+            drop((r, w));
+            unsafe { libc::close(fds[0]) };
+            unsafe { libc::close(fds[1]) };
+
+            // a regular file can't be polled
+            let path = std::env::temp_dir().join(format!("may_stdio_{}", std::process::id()));
+            let fd = File::create(&path).unwrap().into_raw_fd();
+            let mut f = StdIo::new(fd);
+            assert!(matches!(f, StdIo::Blocking(_)));
+            f.write_all(b"world").unwrap();
+            drop(f);
+            unsafe { libc::close(fd) };
+            assert_eq!(std::fs::read(&path).unwrap(), b"world");
+            std::fs::remove_file(&path).unwrap();
+        });
+        h.join().unwrap();
+    }
+}
@@ -26,6 +26,9 @@ impl CancelIo for CancelIoImpl {
     unsafe fn cancel(&self) {
         if let Some(e) = self.0.take(Ordering::Acquire) {
             if let Some(co) = e.co.take(Ordering::Acquire) {
+                // the timer would time out the next io on the same fd
+                #[cfg(feature = "io_timeout")]
+                e.clear_timer();
                 get_scheduler().schedule(co);
             }
         }
@@ -152,6 +152,20 @@ impl<T: AsRawFd> CoIo<T> {
 
 impl<T: AsRawFd + Read> Read for CoIo<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_impl(
+            buf,
+            #[cfg(feature = "io_timeout")]
+            self.read_timeout.get(),
+        )
+    }
+}
+
+impl<T: AsRawFd + Read> CoIo<T> {
+    fn read_impl(
+        &mut self,
+        buf: &mut [u8],
+        #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
+    ) -> io::Result<usize> {
         self.io.reset();
         // this is an earlier return try for nonblocking read
         // it's useful for server but not necessary for client
@@ -172,15 +186,19 @@ impl<T: AsRawFd + Read> Read for CoIo<T> {
             self,
             buf,
             #[cfg(feature = "io_timeout")]
-            self.read_timeout.get(),
+            timeout,
         );
         yield_with_io(&reader, reader.is_coroutine);
         reader.done()
     }
 }
 
-impl<T: AsRawFd + Write> Write for CoIo<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+impl<T: AsRawFd + Write> CoIo<T> {
+    fn write_impl(
+        &mut self,
+        buf: &[u8],
+        #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
+    ) -> io::Result<usize> {
         self.io.reset();
         // this is an earlier return try for nonblocking write
         match self.inner.write(buf) {
@@ -200,6 +218,42 @@ impl<T: AsRawFd + Write> Write for CoIo<T> {
             self,
             buf,
             #[cfg(feature = "io_timeout")]
+            timeout,
+        );
+        yield_with_io(&writer, writer.is_coroutine);
+        writer.done()
+    }
+}
+
+impl<T: AsRawFd + Write> Write for CoIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_impl(
+            buf,
+            #[cfg(feature = "io_timeout")]
+            self.write_timeout.get(),
+        )
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.io.reset();
+        // this is an earlier return try for nonblocking write
+        match self.inner.write_vectored(bufs) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut writer = net_impl::SocketWriteVectored::new(
+            self,
+            bufs,
+            #[cfg(feature = "io_timeout")]
             self.write_timeout.get(),
         );
         yield_with_io(&writer, writer.is_coroutine);
@@ -211,6 +265,17 @@ impl<T: AsRawFd + Write> Write for CoIo<T> {
     }
 }
 
+#[cfg(feature = "io_timeout")]
+impl<T: AsRawFd + Read + Write> io_impl::TimeoutIo for CoIo<T> {
+    fn read_timeout_op(&mut self, buf: &mut [u8], dur: Duration) -> io::Result<usize> {
+        self.read_impl(buf, Some(dur))
+    }
+
+    fn write_timeout_op(&mut self, buf: &[u8], dur: Duration) -> io::Result<usize> {
+        self.write_impl(buf, Some(dur))
+    }
+}
+
 // impl<'a, T: AsRawFd + Read> Read for &'a CoIo<T> {
 //     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
 //         let s = unsafe { &mut *(*self as *const _ as *mut _) };
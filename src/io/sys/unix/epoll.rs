@@ -7,33 +7,72 @@ use std::time::Duration;
 
 use super::{from_nix_error, EventData, IoData};
 #[cfg(feature = "io_timeout")]
-use super::{timeout_handler, TimerList};
+use super::{timeout_handler, DetachedTimer, TimerHandle, TimerList};
+use crate::config::config;
+use crate::metrics::{self, Counter};
 use crate::scheduler::Scheduler;
 use crate::sync::queue::mpsc_seg_queue::SegQueue;
 #[cfg(feature = "io_timeout")]
-use crate::timeout_list::{now, ns_to_ms};
+use crate::timeout_list::now;
+use crate::timeout_list::ns_to_ms;
 
-use libc::{eventfd, EFD_NONBLOCK};
+use libc::{eventfd, EFD_CLOEXEC, EFD_NONBLOCK};
+use nix::errno::Errno;
 use nix::sys::epoll::*;
 use nix::unistd::{close, read, write};
+use parking_lot::Mutex;
 use smallvec::SmallVec;
 
 fn create_eventfd() -> io::Result<RawFd> {
-    let fd = unsafe { eventfd(0, EFD_NONBLOCK) };
+    let fd = unsafe { eventfd(0, EFD_NONBLOCK | EFD_CLOEXEC) };
     if fd < 0 {
         return Err(io::Error::last_os_error());
     }
     Ok(fd as RawFd)
 }
 
+fn create_timerfd() -> io::Result<RawFd> {
+    let flags = libc::TFD_NONBLOCK | libc::TFD_CLOEXEC;
+    let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd as RawFd)
+}
+
+// arm the timerfd to expire after `ns`, return false if failed
+fn arm_timerfd(fd: RawFd, ns: u64) -> bool {
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: libc::timespec {
+            tv_sec: (ns / 1_000_000_000) as libc::time_t,
+            tv_nsec: (ns % 1_000_000_000) as libc::c_long,
+        },
+    };
+    unsafe { libc::timerfd_settime(fd, 0, &spec, std::ptr::null_mut()) == 0 }
+}
+
 pub type SysEvent = EpollEvent;
 
+// the event data of the timerfd, the wakeup event data is 0
+const TIMER_TOKEN: u64 = 1;
+
 struct SingleSelector {
     epfd: RawFd,
     evfd: RawFd,
+    // the timerfd for the high resolution timer
+    timerfd: Option<RawFd>,
     #[cfg(feature = "io_timeout")]
     timer_list: TimerList,
     free_ev: SegQueue<Arc<EventData>>,
+    // the io timers detached by the other threads, removed before the timers run
+    #[cfg(feature = "io_timeout")]
+    detached_timers: SegQueue<DetachedTimer>,
+    // the deferred `EpollCtlMod` calls, flushed once before each poll
+    pending_mod: Mutex<Vec<(Arc<EventData>, bool)>>,
 }
 
 impl SingleSelector {
@@ -41,7 +80,8 @@ impl SingleSelector {
         // wakeup data is 0
         let mut info = EpollEvent::new(EpollFlags::EPOLLIN, 0);
 
-        let epfd = epoll_create().map_err(from_nix_error)?;
+        // the reactor fds are never inherited by the exec'ed children
+        let epfd = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).map_err(from_nix_error)?;
         let evfd = match create_eventfd() {
             Ok(fd) => fd,
             Err(err) => {
@@ -57,18 +97,59 @@ impl SingleSelector {
             return Err(from_nix_error(e));
         };
 
+        let timerfd = match config().get_high_res_timer() {
+            true => {
+                let mut info = EpollEvent::new(EpollFlags::EPOLLIN, TIMER_TOKEN);
+                let ret = create_timerfd().and_then(|fd| {
+                    epoll_ctl(epfd, EpollOp::EpollCtlAdd, fd, &mut info)
+                        .map(|_| fd)
+                        .map_err(|e| {
+                            let _ = close(fd);
+                            from_nix_error(e)
+                        })
+                });
+                match ret {
+                    Ok(fd) => Some(fd),
+                    Err(e) => {
+                        let _ = close(evfd);
+                        let _ = close(epfd);
+                        return Err(e);
+                    }
+                }
+            }
+            false => None,
+        };
+
         Ok(SingleSelector {
             epfd,
             evfd,
+            timerfd,
             free_ev: SegQueue::new(),
             #[cfg(feature = "io_timeout")]
+            detached_timers: SegQueue::new(),
+            pending_mod: Mutex::new(Vec::new()),
+            #[cfg(feature = "io_timeout")]
             timer_list: TimerList::new(),
         })
     }
 }
 
+// the epoll event that only waits for the read or the write readiness
+fn mod_event(io_data: &EventData, is_read: bool) -> EpollEvent {
+    let flags = if is_read {
+        EpollFlags::EPOLLIN | EpollFlags::EPOLLRDHUP | EpollFlags::EPOLLET
+    } else {
+        // the blocked writer is also woken up when the peer hangs up
+        EpollFlags::EPOLLOUT | EpollFlags::EPOLLRDHUP | EpollFlags::EPOLLHUP | EpollFlags::EPOLLET
+    };
+    EpollEvent::new(flags, io_data as *const _ as _)
+}
+
 impl Drop for SingleSelector {
     fn drop(&mut self) {
+        if let Some(fd) = self.timerfd {
+            let _ = close(fd);
+        }
         let _ = close(self.evfd);
         let _ = close(self.epfd);
     }
@@ -77,15 +158,20 @@ impl Drop for SingleSelector {
 pub struct Selector {
     // 128 should be fine for max io threads
     vec: SmallVec<[SingleSelector; 128]>,
+    // the io is polled by the selectors starting from this index
+    io_base: usize,
 }
 
 impl Selector {
-    pub fn new(io_workers: usize) -> io::Result<Self> {
+    // the first `workers` selectors belong to the workers, if there are
+    // dedicated io threads the io is only polled by their selectors
+    pub fn new(workers: usize, io_threads: usize) -> io::Result<Self> {
         let mut s = Selector {
             vec: SmallVec::new(),
+            io_base: if io_threads > 0 { workers } else { 0 },
         };
 
-        for _ in 0..io_workers {
+        for _ in 0..workers + io_threads {
             let ss = SingleSelector::new()?;
             s.vec.push(ss);
         }
@@ -93,25 +179,36 @@ impl Selector {
         Ok(s)
     }
 
+    // get the index of the selector that polls the io
+    #[inline]
+    fn io_index(&self, io_id: usize) -> usize {
+        self.io_base + io_id % (self.vec.len() - self.io_base)
+    }
+
     #[inline]
     pub fn select(
         &self,
         scheduler: &Scheduler,
         id: usize,
         events: &mut [SysEvent],
-        _timeout: Option<u64>,
+        timeout: Option<u64>,
     ) -> io::Result<Option<u64>> {
-        #[cfg(feature = "io_timeout")]
-        let timeout_ms = _timeout
-            .map(|to| std::cmp::min(ns_to_ms(to), isize::MAX as u64) as isize)
-            .unwrap_or(-1);
-        #[cfg(not(feature = "io_timeout"))]
-        let timeout_ms = -1;
-        // info!("select; timeout={:?}", timeout_ms);
-
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let epfd = single_selector.epfd;
 
+        let timeout_ms = match (timeout, single_selector.timerfd) {
+            // the timerfd expires at the precise deadline, a stale one that
+            // is armed by the last select only causes a spurious wakeup
+            (Some(to), Some(fd)) if to > 0 && arm_timerfd(fd, to) => -1,
+            _ => timeout
+                .map(|to| std::cmp::min(ns_to_ms(to), isize::MAX as u64) as isize)
+                .unwrap_or(-1),
+        };
+        // info!("select; timeout={:?}", timeout_ms);
+
+        // apply the deferred mods with the poll
+        Self::flush_pending_mod(single_selector);
+
         // Wait for epoll events for at most timeout_ms milliseconds
         let n = epoll_wait(epfd, events, timeout_ms).map_err(from_nix_error)?;
         // println!("epoll_wait = {}", n);
@@ -127,8 +224,21 @@ impl Selector {
                 scheduler.collect_global(id);
                 continue;
             }
+            if event.data() == TIMER_TOKEN {
+                // the deadline is handled by the timer list below
+                let mut buf = [0u8; 8];
+                if let Some(fd) = single_selector.timerfd {
+                    read(fd, &mut buf).ok();
+                }
+                continue;
+            }
             let data = unsafe { &mut *(event.data() as *mut EventData) };
             // info!("select got event, data={:p}", data);
+            // the peer has hung up
+            let hup = EpollFlags::EPOLLRDHUP | EpollFlags::EPOLLHUP | EpollFlags::EPOLLERR;
+            if event.events().intersects(hup) {
+                data.hup.store(true, Ordering::Release);
+            }
             data.io_flag.store(true, Ordering::Release);
 
             // first check the atomic co, this may be grab by the worker first
@@ -139,7 +249,7 @@ impl Selector {
 
             // it's safe to remove the timer since we are running the timer_list in the same thread
             #[cfg(feature = "io_timeout")]
-            data.timer.borrow_mut().take().map(|h| {
+            data.timer.lock().take().map(|h| {
                 unsafe {
                     // tell the timer handler not to cancel the io
                     // it's not always true that you can really remove the timer entry
@@ -148,6 +258,7 @@ impl Selector {
                 h.remove()
             });
 
+            metrics::inc(Counter::IoEvents);
             scheduler.schedule_with_id(co, id);
         }
 
@@ -157,6 +268,13 @@ impl Selector {
         // free the unused event_data
         self.free_unused_event_data(id);
 
+        // remove the detached timers after the event data is freed, so that
+        // none of the freed ones is left in the timer list
+        #[cfg(feature = "io_timeout")]
+        while let Some(DetachedTimer(h)) = single_selector.detached_timers.pop() {
+            h.remove();
+        }
+
         // deal with the timer list
         #[cfg(feature = "io_timeout")]
         let next_expire = single_selector
@@ -187,7 +305,7 @@ impl Selector {
         );
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = self.io_index(io_data.io_id);
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let epfd = single_selector.epfd;
         info!("add fd to epoll select, fd={:?}", fd);
@@ -196,46 +314,99 @@ impl Selector {
             .map(|_| io_data)
     }
 
+    // register the listener to all the io selectors with EPOLLEXCLUSIVE
+    // so that a new connection would only wake up one of the workers
+    pub fn add_listener_fd(&self, io_data: IoData) -> io::Result<IoData> {
+        let flags = EpollFlags::EPOLLIN | EpollFlags::EPOLLEXCLUSIVE | EpollFlags::EPOLLET;
+        let fd = io_data.fd;
+        info!(
+            "add listener fd to all epoll select exclusively, fd={:?}",
+            fd
+        );
+        let io_selectors = &self.vec[self.io_base..];
+        for (i, single_selector) in io_selectors.iter().enumerate() {
+            let mut info = EpollEvent::new(flags, io_data.as_ref() as *const _ as _);
+            if let Err(e) = epoll_ctl(single_selector.epfd, EpollOp::EpollCtlAdd, fd, &mut info) {
+                // roll back the registered ones
+                for s in unsafe { io_selectors.get_unchecked(..i) } {
+                    epoll_ctl(s.epfd, EpollOp::EpollCtlDel, fd, None).ok();
+                    s.free_ev.push((*io_data).clone());
+                }
+                if e == Errno::EINVAL {
+                    // the kernel doesn't support EPOLLEXCLUSIVE
+                    warn!("EPOLLEXCLUSIVE is not supported, fall back to normal mode");
+                    return self.add_fd(io_data);
+                }
+                return Err(from_nix_error(e));
+            }
+        }
+        io_data.exclusive.store(true, Ordering::Relaxed);
+        Ok(io_data)
+    }
+
+    // the mod is deferred to the next poll of the selector, so that the
+    // `epoll_ctl` calls are batched and only the last mod of the same fd is
+    // applied, a stale registration only causes a spurious wakeup
     #[inline]
     pub fn mod_fd(&self, io_data: &IoData, is_read: bool) -> io::Result<()> {
-        let mut info = if is_read {
-            EpollEvent::new(
-                EpollFlags::EPOLLIN | EpollFlags::EPOLLRDHUP | EpollFlags::EPOLLET,
-                io_data.as_ref() as *const _ as _,
-            )
-        } else {
-            EpollEvent::new(
-                EpollFlags::EPOLLOUT | EpollFlags::EPOLLHUP | EpollFlags::EPOLLET,
-                io_data.as_ref() as *const _ as _,
-            )
-        };
-
-        let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = self.io_index(io_data.io_id);
         let single_selector = unsafe { self.vec.get_unchecked(id) };
-        let epfd = single_selector.epfd;
-        info!("mod fd to epoll select, fd={:?}, is_read={}", fd, is_read);
-        epoll_ctl(epfd, EpollOp::EpollCtlMod, fd, &mut info).map_err(from_nix_error)
+        info!(
+            "mod fd to epoll select, fd={:?}, is_read={}",
+            io_data.fd, is_read
+        );
+        let mut pending = single_selector.pending_mod.lock();
+        pending.retain(|(data, _)| !Arc::ptr_eq(data, io_data));
+        pending.push(((*io_data).clone(), is_read));
+        Ok(())
+    }
+
+    // apply the deferred mods, the lock is held so that the fd can't be
+    // deleted and reused during the `epoll_ctl`
+    fn flush_pending_mod(single_selector: &SingleSelector) {
+        let mut pending = single_selector.pending_mod.lock();
+        for (data, is_read) in pending.drain(..) {
+            let mut info = mod_event(&data, is_read);
+            let ret = epoll_ctl(
+                single_selector.epfd,
+                EpollOp::EpollCtlMod,
+                data.fd,
+                &mut info,
+            );
+            if let Err(e) = ret {
+                error!("mod fd failed, fd={:?}, err={:?}", data.fd, e);
+            }
+        }
     }
 
     #[inline]
     pub fn del_fd(&self, io_data: &IoData) {
+        // the timer may be left by a cancelled io
         #[cfg(feature = "io_timeout")]
-        if let Some(h) = io_data.timer.borrow_mut().take() {
-            unsafe {
-                // mark the timer as removed if any, this only happened
-                // when cancel an IO. what if the timer expired at the same time?
-                // because we run this func in the user space, so the timer handler
-                // will not got the coroutine
-                h.with_mut_data(|value| value.data.event_data = std::ptr::null_mut());
+        io_data.clear_timer();
+
+        let fd = io_data.fd;
+        if io_data.exclusive.load(Ordering::Relaxed) {
+            info!("del listener fd from all epoll select, fd={:?}", fd);
+            for single_selector in self.vec[self.io_base..].iter() {
+                epoll_ctl(single_selector.epfd, EpollOp::EpollCtlDel, fd, None).ok();
+                // the event data may still be used by any of the selectors
+                single_selector.free_ev.push((*io_data).clone());
             }
+            return;
         }
 
-        let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = self.io_index(io_data.io_id);
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let epfd = single_selector.epfd;
         info!("del fd from epoll select, fd={:?}", fd);
+        // drop the deferred mod, the fd may be reused after it's closed
+        {
+            let mut pending = single_selector.pending_mod.lock();
+            if !pending.is_empty() {
+                pending.retain(|(data, _)| !Arc::ptr_eq(data, io_data));
+            }
+        }
         epoll_ctl(epfd, EpollOp::EpollCtlDel, fd, None).ok();
 
         // after EpollCtlDel push the unused event data
@@ -250,11 +421,20 @@ impl Selector {
         while free_ev.pop_bulk().is_some() {}
     }
 
+    // hand the detached io timer to the selector thread that runs its timer list
+    #[inline]
+    #[cfg(feature = "io_timeout")]
+    pub fn detach_timer(&self, io: &EventData, h: TimerHandle) {
+        let id = self.io_index(io.io_id);
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        single_selector.detached_timers.push(DetachedTimer(h));
+    }
+
     // register the io request to the timeout list
     #[inline]
     #[cfg(feature = "io_timeout")]
     pub fn add_io_timer(&self, io: &IoData, timeout: Duration) {
-        let id = io.fd as usize % self.vec.len();
+        let id = self.io_index(io.io_id);
         // info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
@@ -263,6 +443,61 @@ impl Selector {
             // wake up the event loop thread to recall the next wait timeout
             self.wakeup(id);
         }
-        io.timer.borrow_mut().replace(h);
+        io.timer.lock().replace(h);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::Builder;
+    use crate::io::sys::add_socket;
+    use crate::scheduler::{current_worker_id, get_scheduler};
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn per_worker_poller() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let worker = config().get_workers() - 1;
+
+        config().set_per_worker_poller(true);
+        let h = unsafe {
+            Builder::new().pin_to_worker(worker).spawn(move || {
+                // accept and register the socket on the worker like the `accept`
+                let (stream, _) = listener.accept().unwrap();
+                stream.set_nonblocking(true).unwrap();
+                let io = add_socket(&stream).unwrap();
+                (stream, io, current_worker_id())
+            })
+        }
+        .unwrap();
+        let (stream, io, id) = h.join().unwrap();
+        config().set_per_worker_poller(false);
+        assert_eq!(id, Some(worker));
+        assert_eq!(io.io_id, worker);
+
+        // the readiness of the socket is only reported by the selector of the
+        // accepting worker, use a selector of the same layout that is not
+        // polled by the workers to check it
+        let io_threads = config().get_io_threads();
+        let workers = get_scheduler().get_selector().vec.len() - io_threads;
+        let selector = Selector::new(workers, io_threads).unwrap();
+        let local_io = selector
+            .add_fd(IoData::new_with_id(&stream, io.io_id))
+            .unwrap();
+        client.write_all(b"ping").unwrap();
+
+        let index = selector.io_index(io.io_id);
+        let mut events = vec![EpollEvent::empty(); 4];
+        for (i, ss) in selector.vec.iter().enumerate() {
+            let n = epoll_wait(ss.epfd, &mut events, if i == index { 1000 } else { 0 }).unwrap();
+            let ready = events[..n]
+                .iter()
+                .any(|e| e.data() == local_io.as_ref() as *const EventData as u64);
+            assert_eq!(ready, i == index);
+        }
+        selector.del_fd(&local_io);
     }
 }
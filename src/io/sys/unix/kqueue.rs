@@ -4,17 +4,23 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::{io, ptr};
 
-use super::{timeout_handler, EventData, IoData, TimerList};
+use super::{timeout_handler, DetachedTimer, EventData, IoData, TimerHandle, TimerList};
+use crate::config::config;
+use crate::metrics::{self, Counter};
 use crate::scheduler::Scheduler;
 use crate::sync::queue::mpsc_seg_queue::SegQueue;
 use crate::timeout_list::{now, ns_to_dur};
 
+use parking_lot::Mutex;
 use smallvec::SmallVec;
 
 pub type SysEvent = libc::kevent;
 
 // used for notify wakeup
 const NOTIFY_IDENT: usize = 42;
+// used for the high resolution timer
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+const TIMER_IDENT: usize = 43;
 
 macro_rules! kevent {
     ($id:expr, $filter:expr, $flags:expr, $data:expr) => {
@@ -31,8 +37,14 @@ macro_rules! kevent {
 
 struct SingleSelector {
     kqfd: RawFd,
+    // use the `EVFILT_TIMER` for the nearest deadline
+    high_res_timer: bool,
     timer_list: TimerList,
     free_ev: SegQueue<Arc<EventData>>,
+    // the io timers detached by the other threads, removed before the timers run
+    detached_timers: SegQueue<DetachedTimer>,
+    // the deferred filter deletions, flushed once before each poll
+    pending_mod: Mutex<Vec<(Arc<EventData>, bool)>>,
 }
 
 impl SingleSelector {
@@ -41,6 +53,12 @@ impl SingleSelector {
         if kqfd < 0 {
             return Err(io::Error::last_os_error());
         }
+        // the reactor fds are never inherited by the exec'ed children
+        if unsafe { libc::fcntl(kqfd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(kqfd) };
+            return Err(err);
+        }
 
         let kev = libc::kevent {
             ident: NOTIFY_IDENT,
@@ -59,7 +77,10 @@ impl SingleSelector {
 
         Ok(SingleSelector {
             kqfd,
+            high_res_timer: config().get_high_res_timer(),
             free_ev: SegQueue::new(),
+            detached_timers: SegQueue::new(),
+            pending_mod: Mutex::new(Vec::new()),
             timer_list: TimerList::new(),
         })
     }
@@ -71,18 +92,42 @@ impl Drop for SingleSelector {
     }
 }
 
+// the oneshot timer event that expires after `ns`
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+fn high_res_timer(ns: u64) -> Option<libc::kevent> {
+    Some(libc::kevent {
+        ident: TIMER_IDENT,
+        filter: libc::EVFILT_TIMER,
+        flags: libc::EV_ADD | libc::EV_ONESHOT,
+        fflags: libc::NOTE_NSECONDS,
+        data: std::cmp::min(ns, isize::MAX as u64) as _,
+        udata: ptr::null_mut(),
+    })
+}
+
+// the timer of nanoseconds is not supported, use the kevent timeout
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+fn high_res_timer(_ns: u64) -> Option<libc::kevent> {
+    None
+}
+
 pub struct Selector {
     // 128 should be fine for max io threads
     vec: SmallVec<[SingleSelector; 128]>,
+    // the io is polled by the selectors starting from this index
+    io_base: usize,
 }
 
 impl Selector {
-    pub fn new(io_workers: usize) -> io::Result<Self> {
+    // the first `workers` selectors belong to the workers, if there are
+    // dedicated io threads the io is only polled by their selectors
+    pub fn new(workers: usize, io_threads: usize) -> io::Result<Self> {
         let mut s = Selector {
             vec: SmallVec::new(),
+            io_base: if io_threads > 0 { workers } else { 0 },
         };
 
-        for _ in 0..io_workers {
+        for _ in 0..workers + io_threads {
             let ss = SingleSelector::new()?;
             s.vec.push(ss);
         }
@@ -90,6 +135,12 @@ impl Selector {
         Ok(s)
     }
 
+    // get the index of the selector that polls the io
+    #[inline]
+    fn io_index(&self, io_id: usize) -> usize {
+        self.io_base + io_id % (self.vec.len() - self.io_base)
+    }
+
     #[inline]
     pub fn select(
         &self,
@@ -98,6 +149,19 @@ impl Selector {
         events: &mut [SysEvent],
         timeout: Option<u64>,
     ) -> io::Result<Option<u64>> {
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+
+        // arm a oneshot kernel timer for the deadline in the same syscall,
+        // a stale one that is armed by the last select only causes a
+        // spurious wakeup
+        let timer = match timeout {
+            Some(to) if to > 0 && single_selector.high_res_timer => high_res_timer(to),
+            _ => None,
+        };
+        let timeout = if timer.is_some() { None } else { timeout };
+        let nchanges = timer.is_some() as libc::c_int;
+        let changes = timer.as_ref().map_or(ptr::null(), |kev| kev as *const _);
+
         let timeout = timeout.map(|to| {
             let dur = ns_to_dur(to);
             libc::timespec {
@@ -112,15 +176,16 @@ impl Selector {
             .unwrap_or(ptr::null_mut());
         // info!("select; timeout={:?}", timeout_ms);
 
-        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        // apply the deferred mods before the poll
+        Self::flush_pending_mod(single_selector);
 
         // Wait for kqueue events for at most timeout_ms milliseconds
         let kqfd = single_selector.kqfd;
         let n = unsafe {
             libc::kevent(
                 kqfd,
-                ptr::null(),
-                0,
+                changes,
+                nchanges,
                 events.as_mut_ptr(),
                 events.len() as libc::c_int,
                 timeout,
@@ -134,6 +199,10 @@ impl Selector {
         let n = n as usize;
 
         for event in unsafe { events.get_unchecked(..n) } {
+            if event.filter == libc::EVFILT_TIMER {
+                // the deadline is handled by the timer list below
+                continue;
+            }
             if event.udata.is_null() {
                 // this is just a wakeup event, ignore it
                 // let mut buf = [0u8; 8];
@@ -145,6 +214,10 @@ impl Selector {
             }
             let data = unsafe { &mut *(event.udata as *mut EventData) };
             // info!("select got event, data={:p}", data);
+            if event.flags & libc::EV_EOF != 0 {
+                // the peer has hung up
+                data.hup.store(true, Ordering::Release);
+            }
             data.io_flag.store(true, Ordering::Release);
 
             // first check the atomic co, this may be grab by the worker first
@@ -154,7 +227,7 @@ impl Selector {
             };
 
             // it's safe to remove the timer since we are running the timer_list in the same thread
-            data.timer.borrow_mut().take().map(|h| {
+            data.timer.lock().take().map(|h| {
                 unsafe {
                     // tell the timer handler not to cancel the io
                     // it's not always true that you can really remove the timer entry
@@ -163,6 +236,7 @@ impl Selector {
                 h.remove()
             });
 
+            metrics::inc(Counter::IoEvents);
             scheduler.schedule_with_id(co, id);
         }
 
@@ -172,6 +246,12 @@ impl Selector {
         // free the unused event_data
         self.free_unused_event_data(id);
 
+        // remove the detached timers after the event data is freed, so that
+        // none of the freed ones is left in the timer list
+        while let Some(DetachedTimer(h)) = single_selector.detached_timers.pop() {
+            h.remove();
+        }
+
         // deal with the timer list
         let next_expire = single_selector
             .timer_list
@@ -201,7 +281,7 @@ impl Selector {
     #[inline]
     pub fn add_fd(&self, io_data: IoData) -> io::Result<IoData> {
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = self.io_index(io_data.io_id);
         let kqfd = unsafe { self.vec.get_unchecked(id) }.kqfd;
         info!("add fd to kqueue select, fd={:?}", fd);
 
@@ -229,24 +309,48 @@ impl Selector {
         Ok(io_data)
     }
 
+    // kqueue has no exclusive mode, just register it in the normal way
+    #[inline]
+    pub fn add_listener_fd(&self, io_data: IoData) -> io::Result<IoData> {
+        self.add_fd(io_data)
+    }
+
+    // the mod is deferred to the next poll of the selector, so that the
+    // `kevent` calls are batched, a stale filter only causes a spurious wakeup
     #[inline]
     pub fn mod_fd(&self, io_data: &IoData, is_read: bool) -> io::Result<()> {
-        let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
-        let kqfd = unsafe { self.vec.get_unchecked(id) }.kqfd;
-        info!("add fd to kqueue select, fd={:?}", fd);
+        let id = self.io_index(io_data.io_id);
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        info!("mod fd to kqueue select, fd={:?}", io_data.fd);
+        let mut pending = single_selector.pending_mod.lock();
+        pending.retain(|(data, _)| !Arc::ptr_eq(data, io_data));
+        pending.push(((*io_data).clone(), is_read));
+        Ok(())
+    }
 
+    // delete the unused filters of the deferred mods in one `kevent` call,
+    // the lock is held so that the fd can't be deleted and reused meanwhile
+    fn flush_pending_mod(single_selector: &SingleSelector) {
+        let mut pending = single_selector.pending_mod.lock();
+        if pending.is_empty() {
+            return;
+        }
         let flags = libc::EV_DELETE;
-        let udata = io_data.as_ref() as *const _;
-        let changes = if is_read {
-            [kevent!(fd, libc::EVFILT_WRITE, flags, udata)]
-        } else {
-            [kevent!(fd, libc::EVFILT_READ, flags, udata)]
-        };
+        let changes: Vec<libc::kevent> = pending
+            .drain(..)
+            .map(|(data, is_read)| {
+                let udata = Arc::as_ptr(&data);
+                if is_read {
+                    kevent!(data.fd, libc::EVFILT_WRITE, flags, udata)
+                } else {
+                    kevent!(data.fd, libc::EVFILT_READ, flags, udata)
+                }
+            })
+            .collect();
 
         let n = unsafe {
             libc::kevent(
-                kqfd,
+                single_selector.kqfd,
                 changes.as_ptr(),
                 changes.len() as libc::c_int,
                 ptr::null_mut(),
@@ -255,29 +359,28 @@ impl Selector {
             )
         };
         if n < 0 {
-            return Err(io::Error::last_os_error());
+            error!("mod fd failed, err={:?}", io::Error::last_os_error());
         }
-
-        Ok(())
     }
 
     #[inline]
     pub fn del_fd(&self, io_data: &IoData) {
-        io_data.timer.borrow_mut().take().map(|h| {
-            unsafe {
-                // mark the timer as removed if any, this only happened
-                // when cancel an IO. what if the timer expired at the same time?
-                // because we run this func in the user space, so the timer handler
-                // will not got the coroutine
-                h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
-            }
-        });
+        // the timer may be left by a cancelled io
+        #[cfg(feature = "io_timeout")]
+        io_data.clear_timer();
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = self.io_index(io_data.io_id);
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let kqfd = single_selector.kqfd;
         info!("del fd from kqueue select, fd={:?}", fd);
+        // drop the deferred mod, the fd may be reused after it's closed
+        {
+            let mut pending = single_selector.pending_mod.lock();
+            if !pending.is_empty() {
+                pending.retain(|(data, _)| !Arc::ptr_eq(data, io_data));
+            }
+        }
 
         let filter = libc::EV_DELETE;
         let changes = [
@@ -308,10 +411,18 @@ impl Selector {
         while free_ev.pop_bulk().is_some() {}
     }
 
+    // hand the detached io timer to the selector thread that runs its timer list
+    #[inline]
+    pub fn detach_timer(&self, io: &EventData, h: TimerHandle) {
+        let id = self.io_index(io.io_id);
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        single_selector.detached_timers.push(DetachedTimer(h));
+    }
+
     // register the io request to the timeout list
     #[inline]
     pub fn add_io_timer(&self, io: &IoData, timeout: Duration) {
-        let id = io.fd as usize % self.vec.len();
+        let id = self.io_index(io.io_id);
         // info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
@@ -320,6 +431,6 @@ impl Selector {
             // wakeup the event loop thread to recall the next wait timeout
             self.wakeup(id);
         }
-        io.timer.borrow_mut().replace(h);
+        io.timer.lock().replace(h);
     }
 }
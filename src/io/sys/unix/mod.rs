@@ -3,7 +3,6 @@
 mod select;
 
 #[cfg(any(
-    target_os = "bitrig",
     target_os = "dragonfly",
     target_os = "freebsd",
     target_os = "ios",
@@ -20,30 +19,61 @@ pub mod co_io;
 pub mod net;
 pub mod wait_io;
 
-#[cfg(feature = "io_timeout")]
-use std::cell::RefCell;
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(feature = "io_timeout")]
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{fmt, io};
 
+use crate::config::config;
 use crate::coroutine_impl::{run_coroutine, CoroutineImpl};
 use crate::io::thread::ASSOCIATED_IO_RET;
 use crate::likely::likely;
-use crate::scheduler::get_scheduler;
+#[cfg(feature = "io_timeout")]
+use crate::metrics::{self, Counter};
+use crate::scheduler::{current_worker_id, get_scheduler};
 use crate::sync::AtomicOption;
 #[cfg(feature = "io_timeout")]
 use crate::timeout_list::{TimeOutList, TimeoutHandle};
 use crate::yield_now::get_co_para;
 #[cfg(feature = "io_timeout")]
 use crate::yield_now::set_co_para;
+#[cfg(feature = "io_timeout")]
+use parking_lot::Mutex;
 
 pub use self::select::{Selector, SysEvent};
 
+// register the socket to the selector of the current worker if the per
+// worker poller is configured, or else pick the selector by the fd
 #[inline]
 pub fn add_socket<T: AsRawFd + ?Sized>(t: &T) -> io::Result<IoData> {
-    get_scheduler().get_selector().add_fd(IoData::new(t))
+    let io_data = match current_worker_id() {
+        Some(id) if config().get_per_worker_poller() => IoData::new_with_id(t, id),
+        _ => IoData::new(t),
+    };
+    get_scheduler().get_selector().add_fd(io_data)
+}
+
+// register the socket to the selector of the specified io worker
+#[inline]
+pub fn add_socket_with_id<T: AsRawFd + ?Sized>(t: &T, id: usize) -> io::Result<IoData> {
+    get_scheduler()
+        .get_selector()
+        .add_fd(IoData::new_with_id(t, id))
+}
+
+// register the listener to the selector, the listener would be polled by
+// all the io workers exclusively if `accept_exclusive` is configured
+#[inline]
+pub fn add_listener<T: AsRawFd + ?Sized>(t: &T) -> io::Result<IoData> {
+    let selector = get_scheduler().get_selector();
+    if config().get_accept_exclusive() {
+        selector.add_listener_fd(IoData::new(t))
+    } else {
+        selector.add_fd(IoData::new(t))
+    }
 }
 
 #[inline]
@@ -86,8 +116,15 @@ fn timeout_handler(data: TimerData) {
     }
 
     let event_data = unsafe { &mut *data.event_data };
-    // remove the event timer
-    event_data.timer.borrow_mut().take();
+    // remove the event timer, a stale one is already detached from the io
+    // and the coroutine may wait for the next io
+    {
+        let mut timer = event_data.timer.lock();
+        if event_data.timer_seq.load(Ordering::Acquire) != data.seq {
+            return;
+        }
+        timer.take();
+    }
 
     // get and check the coroutine
     let mut co = match event_data.co.take(Ordering::Relaxed) {
@@ -96,15 +133,22 @@ fn timeout_handler(data: TimerData) {
     };
 
     set_co_para(&mut co, io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+    metrics::inc(Counter::IoTimeouts);
 
     // resume the coroutine with timeout error
-    run_coroutine(co);
+    match current_worker_id() {
+        Some(_) => run_coroutine(co),
+        // the dedicated io threads don't run coroutines
+        None => get_scheduler().schedule_global(co),
+    }
 }
 
 // the timeout data
 #[cfg(feature = "io_timeout")]
 pub struct TimerData {
     event_data: *mut EventData,
+    // the timer is stale if it's not the latest one of the event data
+    seq: usize,
 }
 
 #[cfg(feature = "io_timeout")]
@@ -112,13 +156,30 @@ pub type TimerList = TimeOutList<TimerData>;
 #[cfg(feature = "io_timeout")]
 pub type TimerHandle = TimeoutHandle<TimerData>;
 
+// an io timer taken out of its event data by another thread, it's sent to
+// the selector thread that owns the timer list to be removed there
+#[cfg(feature = "io_timeout")]
+pub struct DetachedTimer(pub TimerHandle);
+
+#[cfg(feature = "io_timeout")]
+unsafe impl Send for DetachedTimer {}
+
 // event associated io data, must be construct in
 // each file handle, the epoll event.data would point to it
 pub struct EventData {
     pub fd: RawFd,
+    // used to pick the selector, default is the fd
+    pub io_id: usize,
+    // the fd is registered to all the selectors exclusively
+    pub exclusive: AtomicBool,
     pub io_flag: AtomicBool,
+    // the peer has closed or reset the connection, set by the selector
+    pub hup: AtomicBool,
+    // the timer of the io, taken by the thread that owns the io completion
+    #[cfg(feature = "io_timeout")]
+    pub timer: Mutex<Option<TimerHandle>>,
     #[cfg(feature = "io_timeout")]
-    pub timer: RefCell<Option<TimerHandle>>,
+    timer_seq: AtomicUsize,
     pub co: AtomicOption<CoroutineImpl>,
 }
 
@@ -126,12 +187,17 @@ unsafe impl Send for EventData {}
 unsafe impl Sync for EventData {}
 
 impl EventData {
-    pub fn new(fd: RawFd) -> EventData {
+    pub fn new(fd: RawFd, io_id: usize) -> EventData {
         EventData {
             fd,
+            io_id,
+            exclusive: AtomicBool::new(false),
             io_flag: AtomicBool::new(false),
+            hup: AtomicBool::new(false),
+            #[cfg(feature = "io_timeout")]
+            timer: Mutex::new(None),
             #[cfg(feature = "io_timeout")]
-            timer: RefCell::new(None),
+            timer_seq: AtomicUsize::new(0),
             co: AtomicOption::none(),
         }
     }
@@ -140,6 +206,21 @@ impl EventData {
     pub fn timer_data(&self) -> TimerData {
         TimerData {
             event_data: self as *const _ as *mut _,
+            seq: self.timer_seq.fetch_add(1, Ordering::AcqRel) + 1,
+        }
+    }
+
+    // detach the io timer, the selector thread removes it from the timer list
+    // later and the timer handler ignores it if it expires before that. this
+    // is used out of the selector thread, e.g. when the io is cancelled or the
+    // fd is deregistered
+    #[cfg(feature = "io_timeout")]
+    pub fn clear_timer(&self) {
+        let mut timer = self.timer.lock();
+        // the timer that expires before it's removed is stale
+        self.timer_seq.fetch_add(1, Ordering::AcqRel);
+        if let Some(h) = timer.take() {
+            get_scheduler().get_selector().detach_timer(self, h);
         }
     }
 
@@ -153,7 +234,7 @@ impl EventData {
 
         // it's safe to remove the timer since we are running the timer_list in the same thread
         #[cfg(feature = "io_timeout")]
-        self.timer.borrow_mut().take().map(|h| {
+        self.timer.lock().take().map(|h| {
             unsafe {
                 // tell the timer function not to cancel the io
                 // it's not always true that you can really remove the timer entry
@@ -173,15 +254,101 @@ pub struct IoData(Arc<EventData>);
 impl IoData {
     pub fn new<T: AsRawFd + ?Sized>(t: &T) -> Self {
         let fd = t.as_raw_fd();
-        let event_data = Arc::new(EventData::new(fd));
+        Self::new_with_id(t, fd as usize)
+    }
+
+    // the io events would be polled by the selector of `io_id % workers`
+    pub fn new_with_id<T: AsRawFd + ?Sized>(t: &T, io_id: usize) -> Self {
+        let fd = t.as_raw_fd();
+        let event_data = Arc::new(EventData::new(fd, io_id));
         IoData(event_data)
     }
 
+    /// Registers a non-blocking fd to the reactor, the returned io data is
+    /// used to wait for the io events of the fd by [`WaitIo`].
+    ///
+    /// This is the way to make the foreign io objects, e.g. the socket of a
+    /// database driver, work with coroutines. The fd must be set to the
+    /// non-blocking mode, and it's deregistered when the io data is dropped,
+    /// so the io data must be dropped before the fd is closed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Read, Write};
+    /// use std::os::unix::net::UnixStream;
+    /// use may::io::{AsIoData, IoData, WaitIo};
+    ///
+    /// // a foreign io type that parks the coroutine when it would block
+    /// struct Conn {
+    ///     io: IoData,
+    ///     stream: UnixStream,
+    /// }
+    ///
+    /// impl AsIoData for Conn {
+    ///     fn as_io_data(&self) -> &IoData {
+    ///         &self.io
+    ///     }
+    /// }
+    ///
+    /// impl Conn {
+    ///     fn new(stream: UnixStream) -> io::Result<Conn> {
+    ///         stream.set_nonblocking(true)?;
+    ///         let io = IoData::register(&stream)?;
+    ///         Ok(Conn { io, stream })
+    ///     }
+    ///
+    ///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    ///         loop {
+    ///             self.reset_io();
+    ///             match self.stream.read(buf) {
+    ///                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.wait_io(),
+    ///                 ret => return ret,
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let (a, mut b) = UnixStream::pair().unwrap();
+    /// let h = may::go!(move || {
+    ///     let mut conn = Conn::new(a).unwrap();
+    ///     let mut buf = [0; 5];
+    ///     let n = conn.read(&mut buf).unwrap();
+    ///     buf[..n].to_vec()
+    /// });
+    /// b.write_all(b"hello").unwrap();
+    /// assert_eq!(h.join().unwrap(), b"hello");
+    /// ```
+    ///
+    /// [`WaitIo`]: crate::io::WaitIo
+    pub fn register<T: AsRawFd + ?Sized>(t: &T) -> io::Result<IoData> {
+        add_socket(t)
+    }
+
     // clear the io flag
     #[inline]
     pub fn reset(&self) {
         self.io_flag.store(false, Ordering::Relaxed);
     }
+
+    /// Returns true if the reactor has seen the peer hang up.
+    ///
+    /// The peer has shut down its write half, closed or reset the
+    /// connection. The blocked reader and writer are woken up as soon as it
+    /// happens, so they don't have to wait for the next io or a timeout to
+    /// find out.
+    #[inline]
+    pub fn peer_hung_up(&self) -> bool {
+        self.hup.load(Ordering::Acquire)
+    }
+
+    // wake up the blocked coroutine to retry the io, e.g. after shutdown
+    pub fn wake(&self) {
+        self.io_flag.store(true, Ordering::Release);
+        if let Some(co) = self.co.take(Ordering::Acquire) {
+            get_scheduler().schedule(co);
+        }
+    }
 }
 
 impl Deref for IoData {
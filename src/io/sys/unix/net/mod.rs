@@ -1,23 +1,33 @@
+mod socket_op;
 mod socket_read;
 mod socket_write;
 mod socket_write_vectored;
 mod tcp_listener_accept;
 mod tcp_stream_connect;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod udp_mmsg;
 mod udp_recv_from;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod udp_recv_gro;
 mod udp_send_to;
 mod unix_listener_accept;
 mod unix_recv_from;
 mod unix_send_to;
 mod unix_stream_connect;
 
+pub use self::socket_op::SocketOp;
 pub use self::socket_read::SocketRead;
 pub use self::socket_write::SocketWrite;
 pub use self::socket_write_vectored::SocketWriteVectored;
 pub use self::tcp_listener_accept::TcpListenerAccept;
 pub use self::tcp_stream_connect::TcpStreamConnect;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::udp_mmsg::{recv_gro, recv_mmsg, send_mmsg, set_udp_opt, udp_opt};
 pub use self::udp_recv_from::UdpRecvFrom;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::udp_recv_gro::UdpRecvGro;
 pub use self::udp_send_to::UdpSendTo;
 pub use self::unix_listener_accept::UnixListenerAccept;
 pub use self::unix_recv_from::UnixRecvFrom;
-pub use self::unix_send_to::UnixSendTo;
+pub use self::unix_send_to::{SendTarget, UnixSendTo};
 pub use self::unix_stream_connect::UnixStreamConnect;
@@ -3,15 +3,17 @@ use std::sync::atomic::Ordering;
 #[cfg(feature = "io_timeout")]
 use std::time::Duration;
 
-use super::super::{co_io_result, IoData};
+use super::super::{co_io_result, from_nix_error, IoData};
 use crate::coroutine_impl::{is_coroutine, CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::yield_now::yield_with_io;
 
+use nix::sys::uio::writev;
+
+// write all the slices with a single `writev` for each readiness event
 pub struct SocketWriteVectored<'a> {
     io_data: &'a IoData,
     bufs: &'a [IoSlice<'a>],
-    socket: &'a std::net::TcpStream,
     #[cfg(feature = "io_timeout")]
     timeout: Option<Duration>,
     pub(crate) is_coroutine: bool,
@@ -20,14 +22,12 @@ pub struct SocketWriteVectored<'a> {
 impl<'a> SocketWriteVectored<'a> {
     pub fn new<T: AsIoData>(
         s: &'a T,
-        socket: &'a std::net::TcpStream,
         bufs: &'a [IoSlice<'a>],
         #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
     ) -> Self {
         SocketWriteVectored {
             io_data: s.as_io_data(),
             bufs,
-            socket,
             #[cfg(feature = "io_timeout")]
             timeout,
             is_coroutine: is_coroutine(),
@@ -35,22 +35,19 @@ impl<'a> SocketWriteVectored<'a> {
     }
 
     pub fn done(&mut self) -> io::Result<usize> {
-        use std::io::Write;
-
         loop {
             co_io_result(self.is_coroutine)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
 
-            match self.socket.write_vectored(self.bufs) {
+            match writev(self.io_data.fd, self.bufs) {
                 Ok(n) => return Ok(n),
                 Err(e) => {
-                    let raw_err = e.raw_os_error();
-                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
-                        // do nothing here
+                    if e == nix::errno::Errno::EAGAIN {
+                        // do nothing
                     } else {
-                        return Err(e);
+                        return Err(from_nix_error(e));
                     }
                 }
             }
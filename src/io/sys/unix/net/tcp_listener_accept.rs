@@ -1,5 +1,7 @@
 use std::net::SocketAddr;
 use std::sync::atomic::Ordering;
+#[cfg(feature = "io_timeout")]
+use std::time::Duration;
 use std::{self, io};
 
 use super::super::{add_socket, co_io_result, IoData};
@@ -13,14 +15,21 @@ use crate::yield_now::yield_with_io;
 pub struct TcpListenerAccept<'a> {
     io_data: &'a IoData,
     socket: &'a std::net::TcpListener,
+    #[cfg(feature = "io_timeout")]
+    timeout: Option<Duration>,
     pub(crate) is_coroutine: bool,
 }
 
 impl<'a> TcpListenerAccept<'a> {
-    pub fn new(socket: &'a TcpListener) -> io::Result<Self> {
+    pub fn new(
+        socket: &'a TcpListener,
+        #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
+    ) -> io::Result<Self> {
         Ok(TcpListenerAccept {
             io_data: socket.as_io_data(),
             socket: socket.inner(),
+            #[cfg(feature = "io_timeout")]
+            timeout,
             is_coroutine: is_coroutine(),
         })
     }
@@ -63,7 +72,14 @@ impl<'a> EventSource for TcpListenerAccept<'a> {
         #[cfg(feature = "io_cancel")]
         let cancel = co_cancel_data(&co);
         let io_data = self.io_data;
-        // if there is no timer we don't need to call add_io_timer
+
+        #[cfg(feature = "io_timeout")]
+        if let Some(dur) = self.timeout {
+            crate::scheduler::get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, dur);
+        }
+
         io_data.co.swap(co, Ordering::Release);
 
         // there is event happened
@@ -24,13 +24,14 @@ pub struct TcpStreamConnect {
 }
 
 impl TcpStreamConnect {
+    #[allow(clippy::manual_try_fold)]
     pub fn new<A: ToSocketAddrs>(
         addr: A,
         #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
     ) -> io::Result<Self> {
         use socket2::{Domain, Type};
 
-        let err = io::Error::new(io::ErrorKind::Other, "no socket addresses resolved");
+        let err = io::Error::other("no socket addresses resolved");
         addr.to_socket_addrs()?
             .fold(Err(err), |prev, addr| {
                 prev.or_else(|_| {
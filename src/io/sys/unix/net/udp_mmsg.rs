@@ -0,0 +1,178 @@
+//! batched udp io with `recvmmsg`/`sendmmsg` and the segmentation offload
+
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::{io, mem, ptr};
+
+use crate::net::MsgBuf;
+use socket2::SockAddr;
+
+// messages more than this are handled in the next call
+const MAX_BATCH: usize = 64;
+
+/// receive the datagrams into the buffers with a single `recvmmsg` call
+///
+/// return the number of the filled buffers, the socket must be nonblocking
+/// the headers are allocated on heap since the coroutine stack is small
+pub fn recv_mmsg<S: AsRawFd>(socket: &S, msgs: &mut [MsgBuf]) -> io::Result<usize> {
+    let n = msgs.len().min(MAX_BATCH);
+    let msgs = &mut msgs[..n];
+    let mut addrs: Vec<libc::sockaddr_storage> = (0..n).map(|_| unsafe { mem::zeroed() }).collect();
+    let mut iovs: Vec<libc::iovec> = msgs
+        .iter_mut()
+        .map(|m| libc::iovec {
+            iov_base: m.buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: m.buf.len(),
+        })
+        .collect();
+    let mut hdrs: Vec<libc::mmsghdr> = addrs
+        .iter_mut()
+        .zip(iovs.iter_mut())
+        .map(|(addr, iov)| {
+            let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+            hdr.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+            hdr.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            hdr.msg_hdr.msg_iov = iov;
+            hdr.msg_hdr.msg_iovlen = 1;
+            hdr
+        })
+        .collect();
+
+    let ret = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            hdrs.as_mut_ptr(),
+            n as _,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ret = ret as usize;
+    let filled = msgs.iter_mut().zip(hdrs.iter()).zip(addrs.iter()).take(ret);
+    for ((m, hdr), addr) in filled {
+        m.len = hdr.msg_len as usize;
+        m.addr = unsafe { SockAddr::new(*addr, hdr.msg_hdr.msg_namelen) }.as_socket();
+    }
+    Ok(ret)
+}
+
+/// send the buffers with a single `sendmmsg` call
+///
+/// return the number of the sent buffers, the socket must be nonblocking
+pub fn send_mmsg<S: AsRawFd>(socket: &S, msgs: &[MsgBuf]) -> io::Result<usize> {
+    let n = msgs.len().min(MAX_BATCH);
+    let msgs = &msgs[..n];
+    let addrs: Vec<Option<SockAddr>> = msgs.iter().map(|m| m.addr.map(SockAddr::from)).collect();
+    let mut iovs: Vec<libc::iovec> = msgs
+        .iter()
+        .map(|m| libc::iovec {
+            iov_base: m.data().as_ptr() as *mut libc::c_void,
+            iov_len: m.len,
+        })
+        .collect();
+    let mut hdrs: Vec<libc::mmsghdr> = addrs
+        .iter()
+        .zip(iovs.iter_mut())
+        .map(|(addr, iov)| {
+            let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+            if let Some(addr) = addr {
+                hdr.msg_hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+                hdr.msg_hdr.msg_namelen = addr.len();
+            }
+            hdr.msg_hdr.msg_iov = iov;
+            hdr.msg_hdr.msg_iovlen = 1;
+            hdr
+        })
+        .collect();
+
+    let ret = unsafe { libc::sendmmsg(socket.as_raw_fd(), hdrs.as_mut_ptr(), n as _, 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+/// set an integer udp level socket option, e.g. `UDP_SEGMENT` and `UDP_GRO`
+pub fn set_udp_opt<S: AsRawFd>(socket: &S, opt: libc::c_int, val: libc::c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            opt,
+            &val as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as _,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// get an integer udp level socket option
+pub fn udp_opt<S: AsRawFd>(socket: &S, opt: libc::c_int) -> io::Result<libc::c_int> {
+    let mut val: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            opt,
+            &mut val as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(val)
+}
+
+/// receive the coalesced datagrams of a `UDP_GRO` socket with `recvmsg`
+///
+/// return the received bytes, the peer address and the segment size, the
+/// segment size is the received bytes if the datagram is not coalesced
+pub fn recv_gro<S: AsRawFd>(socket: &S, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, usize)> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // the control message of a c_int, u64 for the alignment of cmsghdr
+    const CMSG_LEN: usize = 4;
+    let mut control = [0u64; CMSG_LEN];
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = mem::size_of_val(&control) as _;
+
+    let ret = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut hdr, 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let n = ret as usize;
+
+    let mut segment = n;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                segment = size as usize;
+            }
+            cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+        }
+    }
+
+    let addr = unsafe { SockAddr::new(addr, hdr.msg_namelen) }
+        .as_socket()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid peer address"))?;
+    Ok((n, addr, segment))
+}
@@ -1,3 +1,4 @@
+use std::os::unix::net::SocketAddr;
 use std::path::Path;
 use std::sync::atomic::Ordering;
 #[cfg(feature = "io_timeout")]
@@ -10,23 +11,33 @@ use crate::io::AsIoData;
 use crate::os::unix::net::UnixDatagram;
 use crate::yield_now::yield_with_io;
 
+// the destination of the datagram
+pub enum SendTarget<'a> {
+    Path(&'a Path),
+    Addr(&'a SocketAddr),
+}
+
 pub struct UnixSendTo<'a> {
     io_data: &'a IoData,
     buf: &'a [u8],
     socket: &'a std::os::unix::net::UnixDatagram,
-    path: &'a Path,
+    target: SendTarget<'a>,
     #[cfg(feature = "io_timeout")]
     timeout: Option<Duration>,
     pub(crate) is_coroutine: bool,
 }
 
 impl<'a> UnixSendTo<'a> {
-    pub fn new(socket: &'a UnixDatagram, buf: &'a [u8], path: &'a Path) -> io::Result<Self> {
+    pub fn new(
+        socket: &'a UnixDatagram,
+        buf: &'a [u8],
+        target: SendTarget<'a>,
+    ) -> io::Result<Self> {
         Ok(UnixSendTo {
             io_data: socket.0.as_io_data(),
             buf,
             socket: socket.0.inner(),
-            path,
+            target,
             #[cfg(feature = "io_timeout")]
             timeout: socket.write_timeout().unwrap(),
             is_coroutine: is_coroutine(),
@@ -40,7 +51,11 @@ impl<'a> UnixSendTo<'a> {
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
 
-            match self.socket.send_to(self.buf, self.path) {
+            let ret = match self.target {
+                SendTarget::Path(path) => self.socket.send_to(self.buf, path),
+                SendTarget::Addr(addr) => self.socket.send_to_addr(self.buf, addr),
+            };
+            match ret {
                 Ok(n) => return Ok(n),
                 Err(e) => {
                     // raw_os_error is faster than kind
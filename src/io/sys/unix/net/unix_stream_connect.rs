@@ -1,4 +1,5 @@
 use std::io;
+use std::os::unix::net::SocketAddr;
 use std::path::Path;
 use std::sync::atomic::Ordering;
 #[cfg(feature = "io_timeout")]
@@ -23,7 +24,14 @@ pub struct UnixStreamConnect {
 
 impl UnixStreamConnect {
     pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let path = SockAddr::unix(path)?;
+        Self::with_sock_addr(SockAddr::unix(path)?)
+    }
+
+    pub fn with_addr(addr: &SocketAddr) -> io::Result<Self> {
+        Self::with_sock_addr(to_sock_addr(addr)?)
+    }
+
+    fn with_sock_addr(path: SockAddr) -> io::Result<Self> {
         let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
         // before yield we must set the socket to nonblocking mode and register to selector
         socket.set_nonblocking(true)?;
@@ -88,6 +96,31 @@ impl UnixStreamConnect {
     }
 }
 
+// convert the std address, the abstract names are only supported on linux
+fn to_sock_addr(addr: &SocketAddr) -> io::Result<SockAddr> {
+    if let Some(path) = addr.as_pathname() {
+        return SockAddr::unix(path);
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use std::ffi::OsStr;
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+
+        if let Some(name) = addr.as_abstract_name() {
+            // the abstract name starts with a nul byte
+            let mut path = Vec::with_capacity(name.len() + 1);
+            path.push(0);
+            path.extend_from_slice(name);
+            return SockAddr::unix(OsStr::from_bytes(&path));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "can't connect to an unnamed address",
+    ))
+}
+
 impl EventSource for UnixStreamConnect {
     fn subscribe(&mut self, co: CoroutineImpl) {
         #[cfg(feature = "io_cancel")]
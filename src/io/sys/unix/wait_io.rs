@@ -7,7 +7,7 @@ use std::sync::Arc;
 
 use crate::cancel::Cancel;
 #[cfg(feature = "io_cancel")]
-use crate::coroutine_impl::co_get_handle;
+use crate::coroutine_impl::{co_get_handle, current_cancel_data, is_coroutine};
 use crate::coroutine_impl::{CoroutineImpl, EventSource};
 use crate::io as io_impl;
 use crate::yield_now::yield_with_io;
@@ -98,3 +98,66 @@ impl<T: io_impl::AsIoData> WaitIo for T {
         }
     }
 }
+
+/// Clears the pending state of an interrupted io operation when dropped.
+///
+/// A coroutine blocked in [`WaitIo::wait_io`] is unwound from the wait when
+/// it's cancelled. Hold the guard over the io loop of a foreign io type, so
+/// that the io timer and the cancel registration left by the interrupted
+/// wait are cleared before the io data is dropped or used by the next
+/// operation, no matter the loop returns or is unwound.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Read};
+/// use std::os::unix::net::UnixStream;
+/// use may::io::{AsIoData, IoData, OperationGuard, WaitIo};
+///
+/// struct Conn {
+///     io: IoData,
+///     stream: UnixStream,
+/// }
+///
+/// impl AsIoData for Conn {
+///     fn as_io_data(&self) -> &IoData {
+///         &self.io
+///     }
+/// }
+///
+/// impl Conn {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         let _guard = OperationGuard::new(&self.io);
+///         loop {
+///             self.reset_io();
+///             match self.stream.read(buf) {
+///                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.wait_io(),
+///                 ret => return ret,
+///             }
+///         }
+///     }
+/// }
+/// ```
+pub struct OperationGuard<'a> {
+    io_data: &'a io_impl::IoData,
+}
+
+impl<'a> OperationGuard<'a> {
+    /// Creates a guard for the operations on the io data.
+    pub fn new(io_data: &'a io_impl::IoData) -> Self {
+        OperationGuard { io_data }
+    }
+}
+
+impl<'a> Drop for OperationGuard<'a> {
+    fn drop(&mut self) {
+        #[cfg(feature = "io_timeout")]
+        self.io_data.clear_timer();
+        #[cfg(feature = "io_cancel")]
+        if is_coroutine() {
+            current_cancel_data().clear();
+        }
+        #[cfg(not(any(feature = "io_timeout", feature = "io_cancel")))]
+        let _ = self.io_data;
+    }
+}
@@ -4,6 +4,7 @@ use std::time::Duration;
 use std::{io, ptr};
 
 use crate::coroutine_impl::CoroutineImpl;
+use crate::metrics::{self, Counter};
 use crate::scheduler::Scheduler;
 use crate::timeout_list::{now, ns_to_dur, TimeOutList, TimeoutHandle};
 use crate::yield_now::set_co_para;
@@ -154,6 +155,7 @@ impl Selector {
                 ERROR_OPERATION_ABORTED | STATUS_CANCELLED_U32 => {
                     warn!("coroutine timeout, stat=0x{:x}", overlapped.Internal);
                     set_co_para(&mut co, io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                    metrics::inc(Counter::IoTimeouts);
                     // timer data is popped already
                 }
                 NO_ERROR => {
@@ -174,6 +176,7 @@ impl Selector {
                 }
             }
 
+            metrics::inc(Counter::IoEvents);
             scheduler.schedule_with_id(co, id);
         }
 
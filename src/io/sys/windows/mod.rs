@@ -58,6 +58,12 @@ pub fn add_socket<T: AsRawSocket + ?Sized>(t: &T) -> io::Result<IoData> {
     get_scheduler().get_selector().add_socket(t).map(|_| IoData)
 }
 
+// iocp only wakes up one thread for each completion, no special handling
+#[inline]
+pub fn add_listener<T: AsRawSocket + ?Sized>(t: &T) -> io::Result<IoData> {
+    add_socket(t)
+}
+
 // deal with the io result
 #[inline]
 fn co_io_result(io: &EventData, is_coroutine: bool) -> io::Result<usize> {
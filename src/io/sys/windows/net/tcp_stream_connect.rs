@@ -26,10 +26,11 @@ pub struct TcpStreamConnect {
 }
 
 impl TcpStreamConnect {
+    #[allow(clippy::manual_try_fold)]
     pub fn new<A: ToSocketAddrs>(addr: A, timeout: Option<Duration>) -> io::Result<Self> {
         use socket2::{Domain, Socket, Type};
 
-        let err = io::Error::new(io::ErrorKind::Other, "no socket addresses resolved");
+        let err = io::Error::other("no socket addresses resolved");
         // TODO:
         // resolve addr is a blocking operation!
         // here we should use a thread to finish the resolve?
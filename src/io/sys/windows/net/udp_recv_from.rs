@@ -40,9 +40,10 @@ impl<'a> UdpRecvFrom<'a> {
 
     pub fn done(&mut self) -> io::Result<(usize, SocketAddr)> {
         let size = co_io_result(&self.io_data, self.is_coroutine)?;
-        let addr = self.addr.to_socket_addr().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::Other, "could not obtain remote address")
-        })?;
+        let addr = self
+            .addr
+            .to_socket_addr()
+            .ok_or_else(|| io::Error::other("could not obtain remote address"))?;
         Ok((size, addr))
     }
 }
@@ -25,7 +25,7 @@ impl<'a> UdpSendTo<'a> {
         buf: &'a [u8],
         addr: A,
     ) -> io::Result<Self> {
-        let err = io::Error::new(io::ErrorKind::Other, "no socket addresses resolved");
+        let err = io::Error::other("no socket addresses resolved");
         addr.to_socket_addrs()?
             .fold(Err(err), |prev, addr| prev.or(Ok(addr)))
             .map(|addr| UdpSendTo {
@@ -0,0 +1,93 @@
+//! per operation timeouts for the io objects
+//!
+//! `set_read_timeout` and `set_write_timeout` apply to every operation of
+//! the socket. the operations of [`TimeoutIo`] carry their own timeout to
+//! the event source instead, the timer is registered only when the
+//! operation has to wait, and it's removed when the operation completes.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// The io objects that support a timeout for a single operation.
+///
+/// The socket-wide timeout is not used by these operations. A timed out
+/// operation returns an error of [`io::ErrorKind::TimedOut`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::io::Read;
+/// use std::time::Duration;
+/// use may::io::TimeoutIo;
+/// use may::net::TcpStream;
+///
+/// let mut stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+/// let mut header = [0u8; 16];
+/// // the whole header must arrive within one second
+/// stream
+///     .timeout(Duration::from_secs(1))
+///     .read_exact(&mut header)
+///     .unwrap();
+/// ```
+pub trait TimeoutIo {
+    /// Reads into the buffer, fails if nothing is read within `dur`.
+    fn read_timeout_op(&mut self, buf: &mut [u8], dur: Duration) -> io::Result<usize>;
+
+    /// Writes the buffer, fails if nothing is written within `dur`.
+    fn write_timeout_op(&mut self, buf: &[u8], dur: Duration) -> io::Result<usize>;
+
+    /// Returns a [`Deadline`] whose operations fail after `dur` from now.
+    fn timeout(&mut self, dur: Duration) -> Deadline<'_, Self> {
+        self.deadline(crate::time::now() + dur)
+    }
+
+    /// Returns a [`Deadline`] whose operations fail after the `deadline`.
+    fn deadline(&mut self, deadline: Instant) -> Deadline<'_, Self> {
+        Deadline { io: self, deadline }
+    }
+}
+
+/// An io object with a deadline shared by all the operations on it.
+///
+/// It's created by [`TimeoutIo::timeout`] or [`TimeoutIo::deadline`]. Each
+/// operation waits for the time left, so a `read_exact` or `write_all` on it
+/// completes before the deadline or fails.
+#[derive(Debug)]
+pub struct Deadline<'a, T: ?Sized> {
+    io: &'a mut T,
+    deadline: Instant,
+}
+
+impl<'a, T: ?Sized> Deadline<'a, T> {
+    /// Returns the deadline of the operations.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // the time left, fail if the deadline is passed
+    fn remaining(&self) -> io::Result<Duration> {
+        let left = self.deadline.saturating_duration_since(crate::time::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+        }
+        Ok(left)
+    }
+}
+
+impl<'a, T: TimeoutIo + ?Sized> Read for Deadline<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let dur = self.remaining()?;
+        self.io.read_timeout_op(buf, dur)
+    }
+}
+
+impl<'a, T: TimeoutIo + ?Sized> Write for Deadline<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let dur = self.remaining()?;
+        self.io.write_timeout_op(buf, dur)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+//! serial ports and ttys for coroutines
+//!
+//! a serial device is opened in non-blocking mode and registered to the
+//! reactor like a socket, so reading from a slow device parks the coroutine
+//! instead of tying up a worker thread. the line settings are configured by
+//! `termios`, so this module is only available on unix.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+#[cfg(feature = "io_timeout")]
+use std::time::Duration;
+
+use super::CoIo;
+
+/// The parity checking mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit.
+    None,
+    /// The parity bit is set to make the number of ones odd.
+    Odd,
+    /// The parity bit is set to make the number of ones even.
+    Even,
+}
+
+/// The queue that is discarded by [`Serial::discard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queue {
+    /// The received data that is not read yet.
+    Input,
+    /// The written data that is not transmitted yet.
+    Output,
+    /// Both of them.
+    Both,
+}
+
+// the baud rates and the termios speed constants
+#[rustfmt::skip]
+const BAUD_RATES: &[(u32, libc::speed_t)] = &[
+    (1200, libc::B1200), (2400, libc::B2400), (4800, libc::B4800),
+    (9600, libc::B9600), (19200, libc::B19200), (38400, libc::B38400),
+    (57600, libc::B57600), (115200, libc::B115200), (230400, libc::B230400),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (460800, libc::B460800),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (921600, libc::B921600),
+];
+
+fn cvt(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A serial port or tty device that can be used in coroutine context.
+///
+/// The port is set to the raw mode by [`open`], the data is passed through
+/// without any line discipline processing. Use [`set_canonical`] to have the
+/// input assembled into lines by the kernel.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::io::{BufRead, BufReader, Write};
+/// use may::io::tty::Serial;
+///
+/// let h = may::go!(|| {
+///     let mut port = Serial::open("/dev/ttyUSB0", 115200).unwrap();
+///     port.write_all(b"AT\r\n").unwrap();
+///     let mut line = String::new();
+///     BufReader::new(port).read_line(&mut line).unwrap();
+///     line
+/// });
+/// println!("{}", h.join().unwrap());
+/// ```
+///
+/// [`open`]: #method.open
+/// [`set_canonical`]: #method.set_canonical
+#[derive(Debug)]
+pub struct Serial {
+    io: CoIo<File>,
+}
+
+impl Serial {
+    /// Opens the device in the raw mode with the baud rate, 8 data bits, no
+    /// parity and 1 stop bit.
+    pub fn open<P: AsRef<Path>>(path: P, baud_rate: u32) -> io::Result<Serial> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+        let serial = Serial::new(file)?;
+        serial.set_raw()?;
+        serial.set_baud_rate(baud_rate)?;
+        Ok(serial)
+    }
+
+    /// Creates a serial port from an opened tty, the settings are unchanged.
+    pub fn new(file: File) -> io::Result<Serial> {
+        // make sure it's a tty
+        let _ = get_attr(file.as_raw_fd())?;
+        let io = CoIo::new(file)?;
+        Ok(Serial { io })
+    }
+
+    /// Sets the baud rate for both the input and the output.
+    ///
+    /// Returns an `InvalidInput` error if the baud rate is not supported.
+    pub fn set_baud_rate(&self, baud_rate: u32) -> io::Result<()> {
+        let speed = BAUD_RATES
+            .iter()
+            .find(|(rate, _)| *rate == baud_rate)
+            .map(|(_, speed)| *speed)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported baud rate"))?;
+        self.update(|t| unsafe {
+            cvt(libc::cfsetispeed(t, speed))?;
+            cvt(libc::cfsetospeed(t, speed))
+        })
+    }
+
+    /// Gets the output baud rate.
+    pub fn baud_rate(&self) -> io::Result<u32> {
+        let t = get_attr(self.as_raw_fd())?;
+        let speed = unsafe { libc::cfgetospeed(&t) };
+        BAUD_RATES
+            .iter()
+            .find(|(_, s)| *s == speed)
+            .map(|(rate, _)| *rate)
+            .ok_or_else(|| io::Error::other("unknown baud rate"))
+    }
+
+    /// Sets the number of the data bits, from 5 to 8.
+    pub fn set_data_bits(&self, bits: u8) -> io::Result<()> {
+        let size = match bits {
+            5 => libc::CS5,
+            6 => libc::CS6,
+            7 => libc::CS7,
+            8 => libc::CS8,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid data bits",
+                ))
+            }
+        };
+        self.update(|t| {
+            t.c_cflag = (t.c_cflag & !libc::CSIZE) | size;
+            Ok(())
+        })
+    }
+
+    /// Sets the parity checking mode.
+    pub fn set_parity(&self, parity: Parity) -> io::Result<()> {
+        self.update(|t| {
+            match parity {
+                Parity::None => {
+                    t.c_cflag &= !(libc::PARENB | libc::PARODD);
+                    t.c_iflag &= !libc::INPCK;
+                }
+                Parity::Odd => {
+                    t.c_cflag |= libc::PARENB | libc::PARODD;
+                    t.c_iflag |= libc::INPCK;
+                }
+                Parity::Even => {
+                    t.c_cflag = (t.c_cflag | libc::PARENB) & !libc::PARODD;
+                    t.c_iflag |= libc::INPCK;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets the number of the stop bits, 1 or 2.
+    pub fn set_stop_bits(&self, bits: u8) -> io::Result<()> {
+        let two = match bits {
+            1 => false,
+            2 => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid stop bits",
+                ))
+            }
+        };
+        self.update(|t| {
+            if two {
+                t.c_cflag |= libc::CSTOPB;
+            } else {
+                t.c_cflag &= !libc::CSTOPB;
+            }
+            Ok(())
+        })
+    }
+
+    /// Enables or disables the RTS/CTS hardware flow control.
+    pub fn set_flow_control(&self, on: bool) -> io::Result<()> {
+        self.update(|t| {
+            if on {
+                t.c_cflag |= libc::CRTSCTS;
+            } else {
+                t.c_cflag &= !libc::CRTSCTS;
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets the raw mode, the data is passed through byte by byte without
+    /// echo, special characters or newline translation.
+    pub fn set_raw(&self) -> io::Result<()> {
+        self.update(|t| {
+            t.c_iflag &= !(libc::IGNBRK
+                | libc::BRKINT
+                | libc::PARMRK
+                | libc::ISTRIP
+                | libc::INLCR
+                | libc::IGNCR
+                | libc::ICRNL
+                | libc::IXON);
+            t.c_oflag &= !libc::OPOST;
+            t.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+            t.c_cflag = (t.c_cflag & !(libc::CSIZE | libc::PARENB)) | libc::CS8;
+            t.c_cflag |= libc::CLOCAL | libc::CREAD;
+            t.c_cc[libc::VMIN] = 1;
+            t.c_cc[libc::VTIME] = 0;
+            Ok(())
+        })
+    }
+
+    /// Sets the canonical mode, the input is available line by line and can
+    /// be edited by the erase and kill characters, the received CR is
+    /// translated to NL.
+    pub fn set_canonical(&self, echo: bool) -> io::Result<()> {
+        self.update(|t| {
+            t.c_lflag |= libc::ICANON;
+            t.c_iflag |= libc::ICRNL;
+            if echo {
+                t.c_lflag |= libc::ECHO;
+            } else {
+                t.c_lflag &= !libc::ECHO;
+            }
+            Ok(())
+        })
+    }
+
+    /// Discards the data in the queue.
+    pub fn discard(&self, queue: Queue) -> io::Result<()> {
+        let queue = match queue {
+            Queue::Input => libc::TCIFLUSH,
+            Queue::Output => libc::TCOFLUSH,
+            Queue::Both => libc::TCIOFLUSH,
+        };
+        cvt(unsafe { libc::tcflush(self.as_raw_fd(), queue) })
+    }
+
+    /// Transmits a break, a stream of zero bits for at least 0.25 seconds.
+    ///
+    /// This blocks the worker thread for the duration.
+    pub fn send_break(&self) -> io::Result<()> {
+        cvt(unsafe { libc::tcsendbreak(self.as_raw_fd(), 0) })
+    }
+
+    /// Gets the underlying file.
+    pub fn inner(&self) -> &File {
+        self.io.inner()
+    }
+
+    /// Sets the read timeout.
+    #[cfg(feature = "io_timeout")]
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.io.set_read_timeout(dur)
+    }
+
+    /// Sets the write timeout.
+    #[cfg(feature = "io_timeout")]
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.io.set_write_timeout(dur)
+    }
+
+    // modify the termios settings, applied immediately
+    fn update<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut libc::termios) -> io::Result<()>,
+    {
+        let fd = self.as_raw_fd();
+        let mut t = get_attr(fd)?;
+        f(&mut t)?;
+        cvt(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &t) })
+    }
+}
+
+fn get_attr(fd: RawFd) -> io::Result<libc::termios> {
+    let mut t = std::mem::MaybeUninit::<libc::termios>::uninit();
+    cvt(unsafe { libc::tcgetattr(fd, t.as_mut_ptr()) })?;
+    Ok(unsafe { t.assume_init() })
+}
+
+impl Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsRawFd for Serial {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::io::FromRawFd;
+
+    // the master and slave ends of a pseudo terminal
+    fn pty() -> (File, File) {
+        let mut master = 0;
+        let mut slave = 0;
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(ret, 0);
+        unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) }
+    }
+
+    #[test]
+    fn serial_raw_settings() {
+        let (mut master, slave) = pty();
+        let mut port = Serial::new(slave).unwrap();
+        port.set_raw().unwrap();
+        port.set_baud_rate(115200).unwrap();
+        assert_eq!(port.baud_rate().unwrap(), 115200);
+        assert!(port.set_baud_rate(12345).is_err());
+        port.set_parity(Parity::Even).unwrap();
+        port.set_data_bits(8).unwrap();
+        port.set_stop_bits(2).unwrap();
+        assert!(port.set_data_bits(9).is_err());
+
+        let h = go!(move || {
+            let mut buf = [0; 4];
+            port.read_exact(&mut buf).unwrap();
+            port.write_all(&buf).unwrap();
+            buf
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        // the CR is not translated in the raw mode
+        master.write_all(b"ab\rc").unwrap();
+        assert_eq!(&h.join().unwrap(), b"ab\rc");
+        let mut buf = [0; 4];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ab\rc");
+    }
+
+    #[test]
+    fn serial_canonical_lines() {
+        let (mut master, slave) = pty();
+        let port = Serial::new(slave).unwrap();
+        port.set_canonical(false).unwrap();
+
+        let h = go!(move || {
+            let mut lines = Vec::new();
+            for line in BufReader::new(port).lines().take(2) {
+                lines.push(line.unwrap());
+            }
+            lines
+        });
+        master.write_all(b"hello\rworld\n").unwrap();
+        assert_eq!(h.join().unwrap(), ["hello", "world"]);
+    }
+}
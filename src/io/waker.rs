@@ -0,0 +1,239 @@
+//! wake a coroutine from any thread or signal handler
+//!
+//! the callbacks of a C library run on threads that are not managed by may,
+//! or even in signal handlers, where neither the channels nor the blockers
+//! can be used. [`waker`] creates a pair of a [`Waker`] and a [`WakeListener`]
+//! backed by an eventfd on linux and a pipe on the other unix systems. the
+//! waker only writes to the fd, which is async-signal-safe, and the listener
+//! is registered to the reactor, so the coroutine waiting on it is scheduled
+//! by the reactor when an event is pushed.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::Arc;
+
+use super::CoIo;
+
+/// A cloneable handle that pushes events to a [`WakeListener`].
+///
+/// [`wake`] and [`notify`] only call `write(2)` on the fd, they don't take
+/// any lock or allocate, so they can be called from any OS thread, including
+/// the threads not managed by may, and from signal handlers.
+///
+/// [`wake`]: Waker::wake
+/// [`notify`]: Waker::notify
+#[derive(Debug, Clone)]
+pub struct Waker {
+    fd: Arc<File>,
+}
+
+/// The receiving end of the events pushed by the [`Waker`]s.
+///
+/// The events that are pushed before [`wait`] is called are coalesced, the
+/// sum of their values is returned by the next [`wait`].
+///
+/// [`wait`]: WakeListener::wait
+#[derive(Debug)]
+pub struct WakeListener {
+    io: CoIo<File>,
+}
+
+/// Creates a new pair of [`Waker`] and [`WakeListener`].
+///
+/// # Examples
+///
+/// ```rust
+/// let (waker, mut listener) = may::io::waker().unwrap();
+/// let h = may::go!(move || listener.wait().unwrap());
+///
+/// // a thread that is not managed by may, e.g. the callback of a C library
+/// std::thread::spawn(move || waker.wake()).join().unwrap();
+/// assert_eq!(h.join().unwrap(), 1);
+/// ```
+pub fn waker() -> io::Result<(Waker, WakeListener)> {
+    let (rx, tx) = new_fds()?;
+    let io = CoIo::new(rx).map_err(io::Error::from)?;
+    Ok((Waker { fd: Arc::new(tx) }, WakeListener { io }))
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+// the read end and the write end, they are the same eventfd on linux
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn new_fds() -> io::Result<(File, File)> {
+    let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) })?;
+    let rx = unsafe { File::from_raw_fd(fd) };
+    let tx = rx.try_clone()?;
+    Ok((rx, tx))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn new_fds() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    cvt(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    let rx = unsafe { File::from_raw_fd(fds[0]) };
+    let tx = unsafe { File::from_raw_fd(fds[1]) };
+    for fd in fds {
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+        let flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+    }
+    Ok((rx, tx))
+}
+
+impl Waker {
+    /// Wakes the coroutine or thread waiting on the listener.
+    ///
+    /// This is async-signal-safe.
+    #[inline]
+    pub fn wake(&self) {
+        self.notify(1)
+    }
+
+    /// Pushes an event with the value to the listener, the value is added
+    /// to the result of the next [`WakeListener::wait`].
+    ///
+    /// A zero value is ignored. This is async-signal-safe.
+    pub fn notify(&self, value: u64) {
+        if value == 0 {
+            return;
+        }
+        let buf = value.to_ne_bytes();
+        // the write only fails when the counter or the pipe is full, and then
+        // the listener is already woken up. the errno is not touched, so it's
+        // safe for the interrupted code in a signal handler
+        unsafe {
+            let errno = *errno_location();
+            libc::write(self.fd.as_raw_fd(), buf.as_ptr() as *const _, buf.len());
+            *errno_location() = errno;
+        }
+    }
+}
+
+impl AsRawFd for Waker {
+    /// The fd to be written by the foreign code, each write must be a native
+    /// endian `u64` value.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    #[cfg(target_os = "linux")]
+    return libc::__errno_location();
+    #[cfg(target_os = "android")]
+    return libc::__errno();
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+impl WakeListener {
+    /// Parks the caller until an event is pushed, returns the sum of the
+    /// values of the events since the last call.
+    ///
+    /// This works in both coroutine and thread contexts.
+    pub fn wait(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 64];
+        let n = self.io.read(&mut buf)?;
+        Ok(sum_events(&buf[..n]))
+    }
+
+    /// Returns the sum of the values of the pushed events without blocking,
+    /// zero means no event is pushed.
+    pub fn try_wait(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 64];
+        match self.io.inner_mut().read(&mut buf) {
+            Ok(n) => Ok(sum_events(&buf[..n])),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl AsRawFd for WakeListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+// the writes are 8 bytes, they are atomic for both eventfd and pipe
+fn sum_events(buf: &[u8]) -> u64 {
+    buf.chunks_exact(8)
+        .map(|c| u64::from_ne_bytes(c.try_into().unwrap()))
+        .fold(0, u64::saturating_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    #[test]
+    fn waker_notify_coalesce() {
+        let (waker, mut listener) = waker().unwrap();
+        assert_eq!(listener.try_wait().unwrap(), 0);
+        waker.wake();
+        waker.clone().notify(5);
+        waker.notify(0);
+        assert_eq!(listener.try_wait().unwrap(), 6);
+        assert_eq!(listener.try_wait().unwrap(), 0);
+
+        let h = go!(move || {
+            let mut total = 0;
+            while total < 10 {
+                total += listener.wait().unwrap();
+            }
+            total
+        });
+        std::thread::sleep(Duration::from_millis(10));
+        for _ in 0..10 {
+            waker.wake();
+        }
+        assert_eq!(h.join().unwrap(), 10);
+    }
+
+    #[test]
+    fn waker_from_signal_handler() {
+        static WAKER: OnceLock<Waker> = OnceLock::new();
+        extern "C" fn on_signal(_: libc::c_int) {
+            if let Some(w) = WAKER.get() {
+                w.wake();
+            }
+        }
+
+        let (waker, mut listener) = waker().unwrap();
+        WAKER.set(waker).unwrap();
+        unsafe {
+            libc::signal(
+                libc::SIGUSR2,
+                on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+        let h = go!(move || listener.wait().unwrap());
+        std::thread::sleep(Duration::from_millis(10));
+        unsafe { libc::raise(libc::SIGUSR2) };
+        assert_eq!(h.join().unwrap(), 1);
+    }
+}
@@ -1,17 +1,24 @@
 use std::any::Any;
 use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::Result;
+use std::time::Duration;
 
 use crate::coroutine_impl::Coroutine;
 use crate::sync::{AtomicOption, Blocker};
 use crossbeam::atomic::AtomicCell;
 use generator::Error;
 
+// the completion callback that is registered by `JoinHandle::on_complete`
+type Callback = Box<dyn FnOnce() + Send>;
+
 pub struct Join {
     // the coroutine that waiting for this join handler
     to_wake: AtomicOption<Arc<Blocker>>,
+    // run once when the coroutine is done
+    on_done: AtomicOption<Box<Callback>>,
     // the flag indicate if the host coroutine is not finished
     // when set to false, the coroutine is done
     state: AtomicBool,
@@ -28,6 +35,7 @@ impl Join {
     pub fn new(panic: Arc<AtomicCell<Option<Box<dyn Any + Send>>>>) -> Self {
         Join {
             to_wake: AtomicOption::none(),
+            on_done: AtomicOption::none(),
             state: AtomicBool::new(true),
             panic,
         }
@@ -39,10 +47,24 @@ impl Join {
     }
 
     pub fn trigger(&self) {
-        self.state.store(false, Ordering::Release);
+        // pairs with the check in `set_callback`, so one of them runs it
+        self.state.store(false, Ordering::SeqCst);
         if let Some(w) = self.to_wake.take(Ordering::Acquire) {
             w.unpark();
         }
+        if let Some(f) = self.on_done.take(Ordering::SeqCst) {
+            run_callback(*f);
+        }
+    }
+
+    // register the callback, it's run right away if the coroutine is done
+    fn set_callback(&self, f: Callback) {
+        self.on_done.swap(Box::new(f), Ordering::SeqCst);
+        if !self.state.load(Ordering::SeqCst) {
+            if let Some(f) = self.on_done.take(Ordering::SeqCst) {
+                run_callback(*f);
+            }
+        }
     }
 
     fn wait(&self) {
@@ -59,6 +81,51 @@ impl Join {
             }
         }
     }
+
+    // return true if the coroutine is done before timeout
+    fn wait_timeout(&self, dur: Duration) -> bool {
+        if self.state.load(Ordering::Acquire) {
+            let cur = Blocker::current();
+            // register the blocker first
+            self.to_wake.swap(cur.clone(), Ordering::Release);
+            // re-check the state
+            if self.state.load(Ordering::Acquire) {
+                cur.park(Some(dur)).ok();
+            }
+            // the blocker is not needed any more
+            self.to_wake.take(Ordering::Acquire);
+        }
+        !self.state.load(Ordering::Acquire)
+    }
+}
+
+// a panic of the callback must not take down the worker that runs it
+fn run_callback(f: Callback) {
+    if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+        error!("the on_complete callback of the coroutine panicked");
+    }
+}
+
+/// The result of [`JoinHandle::join_timeout`]
+///
+/// [`JoinHandle::join_timeout`]: struct.JoinHandle.html#method.join_timeout
+pub enum JoinStatus<T> {
+    /// the coroutine is finished with the value
+    Finished(T),
+    /// the coroutine is not finished yet, the handle is returned back
+    TimedOut(JoinHandle<T>),
+    /// the coroutine is panicked or cancelled
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl<T> fmt::Debug for JoinStatus<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinStatus::Finished(_) => f.pad("Finished(..)"),
+            JoinStatus::TimedOut(_) => f.pad("TimedOut(..)"),
+            JoinStatus::Panicked(_) => f.pad("Panicked(..)"),
+        }
+    }
 }
 
 /// A join handle to a coroutine
@@ -93,11 +160,63 @@ impl<T> JoinHandle<T> {
         &self.co
     }
 
+    /// Requests the coroutine to stop gracefully.
+    ///
+    /// It's the safe alternative of [`Coroutine::cancel`], the coroutine is
+    /// only interrupted within [`cancel_safe`], see
+    /// [`Coroutine::cancel_graceful`] for the details.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use may::coroutine::cancel_safe;
+    /// use may::sync::{mpsc::channel, Cancelled};
+    ///
+    /// let (tx, rx) = channel::<u32>();
+    /// let h = may::go!(move || {
+    ///     let mut sum = 0;
+    ///     loop {
+    ///         match cancel_safe(|| rx.recv()) {
+    ///             Ok(Ok(n)) => sum += n,
+    ///             Ok(Err(_)) => return Ok(sum),
+    ///             Err(Cancelled) => return Err(sum),
+    ///         }
+    ///     }
+    /// });
+    ///
+    /// tx.send(1).unwrap();
+    /// tx.send(2).unwrap();
+    /// std::thread::sleep(std::time::Duration::from_millis(10));
+    /// h.cancel_graceful();
+    /// assert_eq!(h.join().unwrap(), Err(3));
+    /// ```
+    ///
+    /// [`Coroutine::cancel`]: crate::coroutine::Coroutine::cancel
+    /// [`Coroutine::cancel_graceful`]: crate::coroutine::Coroutine::cancel_graceful
+    /// [`cancel_safe`]: crate::coroutine::cancel_safe
+    pub fn cancel_graceful(&self) {
+        self.co.cancel_graceful();
+    }
+
     /// return true if the coroutine is finished
     pub fn is_done(&self) -> bool {
         !self.join.state.load(Ordering::Acquire)
     }
 
+    /// return true if the coroutine is finished, this never blocks
+    ///
+    /// same as `is_done`, the name follows `std::thread::JoinHandle`
+    pub fn is_finished(&self) -> bool {
+        self.is_done()
+    }
+
+    /// block until the coroutine is done or the timeout expires
+    ///
+    /// return true if the coroutine is done
+    pub fn wait_timeout(&self, dur: Duration) -> bool {
+        self.join.wait_timeout(dur)
+    }
+
     /// block until the coroutine is done
     pub fn wait(&self) {
         self.join.wait();
@@ -112,6 +231,114 @@ impl<T> JoinHandle<T> {
             .take()
             .ok_or_else(|| self.panic.take().unwrap_or_else(|| Box::new(Error::Cancel)))
     }
+
+    /// Detaches the coroutine, the callback is called with its result when
+    /// it finishes.
+    ///
+    /// This is the fire-and-forget spawn that still gets the errors or the
+    /// panics, without a watcher coroutine for each of them. The result is
+    /// the same as [`join`] would return.
+    ///
+    /// The callback runs in the context that finishes the coroutine, which
+    /// is the coroutine itself or the worker after it panics or is cancelled,
+    /// and right away in the caller if it's already done. So it should be
+    /// short and must not block.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let failed = Arc::new(AtomicUsize::new(0));
+    /// for i in 0..10 {
+    ///     let failed = failed.clone();
+    ///     may::go!(move || if i % 3 == 0 { Err(i) } else { Ok(i) }).on_complete(move |ret| {
+    ///         if !matches!(ret, Ok(Ok(_))) {
+    ///             failed.fetch_add(1, Ordering::Relaxed);
+    ///         }
+    ///     });
+    /// }
+    /// # while failed.load(Ordering::Relaxed) < 4 {
+    /// #     std::thread::sleep(std::time::Duration::from_millis(1));
+    /// # }
+    /// ```
+    ///
+    /// [`join`]: JoinHandle::join
+    pub fn on_complete<F>(self, f: F)
+    where
+        F: FnOnce(Result<T>) + Send + 'static,
+        T: Send + 'static,
+    {
+        let packet = self.packet;
+        let panic = self.panic;
+        self.join.set_callback(Box::new(move || {
+            let ret = packet
+                .take()
+                .ok_or_else(|| panic.take().unwrap_or_else(|| Box::new(Error::Cancel)));
+            f(ret)
+        }));
+    }
+
+    /// Join the coroutine with a timeout.
+    ///
+    /// If the coroutine is not finished in time, the handle is returned back
+    /// in [`JoinStatus::TimedOut`] so that it can be joined again later.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use may::coroutine::JoinStatus;
+    ///
+    /// let h = may::go!(|| {
+    ///     may::coroutine::sleep(Duration::from_millis(100));
+    ///     42
+    /// });
+    /// let h = match h.join_timeout(Duration::from_millis(10)) {
+    ///     JoinStatus::TimedOut(h) => h,
+    ///     s => panic!("unexpected join status {:?}", s),
+    /// };
+    /// match h.join_timeout(Duration::from_secs(10)) {
+    ///     JoinStatus::Finished(v) => assert_eq!(v, 42),
+    ///     s => panic!("unexpected join status {:?}", s),
+    /// }
+    /// ```
+    ///
+    /// [`JoinStatus::TimedOut`]: enum.JoinStatus.html#variant.TimedOut
+    pub fn join_timeout(self, dur: Duration) -> JoinStatus<T> {
+        if !self.join.wait_timeout(dur) {
+            return JoinStatus::TimedOut(self);
+        }
+        match self.join() {
+            Ok(v) => JoinStatus::Finished(v),
+            Err(e) => JoinStatus::Panicked(e),
+        }
+    }
+}
+
+/// Wait for all the coroutines to finish within the timeout.
+///
+/// Return true if all of them are done, the handles are not consumed so the
+/// results can be joined afterwards.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// let handles: Vec<_> = (0..10u64)
+///     .map(|i| may::go!(move || may::coroutine::sleep(Duration::from_millis(i))))
+///     .collect();
+/// assert!(may::coroutine::wait_all(&handles, Duration::from_secs(10)));
+/// assert!(handles.iter().all(|h| h.is_finished()));
+/// ```
+pub fn wait_all<T>(handles: &[JoinHandle<T>], timeout: Duration) -> bool {
+    let deadline = crate::time::now() + timeout;
+    handles.iter().all(|h| {
+        let left = deadline.saturating_duration_since(crate::time::now());
+        h.is_done() || (!left.is_zero() && h.wait_timeout(left))
+    })
 }
 
 impl<T> fmt::Debug for JoinHandle<T> {
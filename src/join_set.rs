@@ -0,0 +1,193 @@
+//! a set of coroutines that are joined in the completion order
+//!
+//! joining a list of handles one by one waits for the slowest coroutine
+//! before the results of the others can be processed. each coroutine in the
+//! set sends its id to a channel when it exits, so the set can join the
+//! finished ones first.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::thread::Result;
+
+use crate::coroutine_impl::Builder;
+use crate::join::JoinHandle;
+use crate::sync::mpsc::{channel, Receiver, Sender};
+
+// send the id of the coroutine when it exits, even if it's panicked or
+// cancelled, or it's dropped without being run
+struct ExitNotify {
+    id: usize,
+    tx: Sender<usize>,
+}
+
+impl Drop for ExitNotify {
+    fn drop(&mut self) {
+        // the set may be already dropped
+        let _ = self.tx.send(self.id);
+    }
+}
+
+/// A collection of coroutines that are joined in the completion order.
+///
+/// The coroutines are detached when the set is dropped, call [`abort_all`]
+/// first to cancel them.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::coroutine::JoinSet;
+///
+/// let mut set = JoinSet::new();
+/// for i in (0..3u64).rev() {
+///     unsafe {
+///         set.spawn(move || {
+///             may::coroutine::sleep(Duration::from_millis(i * 50));
+///             i
+///         })
+///     };
+/// }
+///
+/// let mut results = Vec::new();
+/// while let Some(ret) = set.join_next() {
+///     results.push(ret.unwrap());
+/// }
+/// assert_eq!(results, [0, 1, 2]);
+/// ```
+///
+/// [`abort_all`]: #method.abort_all
+pub struct JoinSet<T> {
+    handles: HashMap<usize, JoinHandle<T>>,
+    next_id: usize,
+    tx: Sender<usize>,
+    rx: Receiver<usize>,
+}
+
+impl<T> JoinSet<T> {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        JoinSet {
+            handles: HashMap::new(),
+            next_id: 0,
+            tx,
+            rx,
+        }
+    }
+
+    /// Returns the number of the coroutines in the set.
+    ///
+    /// The finished coroutines are counted until they are joined.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Returns true if there is no coroutine in the set.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Cancels all the coroutines in the set.
+    ///
+    /// The coroutines stay in the set, the cancelled ones are returned by
+    /// [`join_next`] with an error.
+    ///
+    /// [`join_next`]: #method.join_next
+    pub fn abort_all(&self) {
+        for h in self.handles.values() {
+            if !h.is_done() {
+                unsafe { h.coroutine().cancel() };
+            }
+        }
+    }
+
+    /// Waits for the next finished coroutine and returns its result.
+    ///
+    /// Returns `None` if the set is empty. The caller is parked until one of
+    /// the coroutines is finished.
+    pub fn join_next(&mut self) -> Option<Result<T>> {
+        while !self.handles.is_empty() {
+            // the sender is held by the set, the channel is never disconnected
+            let id = self.rx.recv().expect("join set channel disconnected");
+            if let Some(ret) = self.join_id(id) {
+                return Some(ret);
+            }
+        }
+        None
+    }
+
+    /// Returns the result of a finished coroutine if there is any, this
+    /// never blocks.
+    pub fn try_join_next(&mut self) -> Option<Result<T>> {
+        while let Ok(id) = self.rx.try_recv() {
+            if let Some(ret) = self.join_id(id) {
+                return Some(ret);
+            }
+        }
+        None
+    }
+
+    // the id of a coroutine that failed to spawn is not in the set
+    fn join_id(&mut self, id: usize) -> Option<Result<T>> {
+        // the id is sent right before the coroutine exits
+        self.handles.remove(&id).map(|h| h.join())
+    }
+}
+
+impl<T: Send + 'static> JoinSet<T> {
+    /// Spawns a coroutine into the set.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`coroutine::spawn`], the closure must not overflow the
+    /// coroutine stack.
+    ///
+    /// [`coroutine::spawn`]: ../coroutine/fn.spawn.html
+    #[track_caller]
+    pub unsafe fn spawn<F>(&mut self, f: F)
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.spawn_with(Builder::new(), f).unwrap()
+    }
+
+    /// Spawns a coroutine with the builder into the set.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Builder::spawn`].
+    ///
+    /// [`Builder::spawn`]: ../coroutine/struct.Builder.html#method.spawn
+    #[track_caller]
+    pub unsafe fn spawn_with<F>(&mut self, builder: Builder, f: F) -> io::Result<()>
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        // the id is not reused even if the spawn failed
+        let id = self.next_id;
+        self.next_id += 1;
+        let notify = ExitNotify {
+            id,
+            tx: self.tx.clone(),
+        };
+        let h = builder.spawn(move || {
+            let _notify = notify;
+            f()
+        })?;
+        self.handles.insert(id, h);
+        Ok(())
+    }
+}
+
+impl<T> Default for JoinSet<T> {
+    fn default() -> Self {
+        JoinSet::new()
+    }
+}
+
+impl<T> fmt::Debug for JoinSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JoinSet").field("len", &self.len()).finish()
+    }
+}
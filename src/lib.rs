@@ -9,11 +9,13 @@
 //! * Support schedule on a configurable number of threads for multi-core systems;
 //! * Support coroutine's version of a local storage ([CLS][cls]);
 //! * Support efficient asynchronous network I/O;
+//! * Support file I/O that is offloaded to a blocking thread pool;
 //! * Support efficient timer management;
 //! * Support standard synchronization primitives, a semaphore, an MPMC channel, etc;
 //! * Support cancellation of coroutines;
 //! * Support graceful panic handling that will not affect other coroutines;
 //! * Support scoped coroutine creation;
+//! * Support generators that yield values from a coroutine with backpressure;
 //! * Support general selection for all the coroutine's API;
 //! * All the coroutine's API are compatible with the standard library semantics;
 //! * All the coroutine's API can be safely called in multi-threaded context;
@@ -27,13 +29,20 @@
 #[macro_use]
 extern crate log;
 
+mod autoscale;
+mod blocking_pool;
 mod cancel;
 mod config;
+mod group;
 mod join;
+mod join_set;
 mod likely;
 mod local;
+#[cfg(not(feature = "metrics"))]
+mod metrics;
 mod park;
 mod pool;
+mod runtime;
 mod sleep;
 #[macro_use]
 mod macros;
@@ -41,13 +50,33 @@ mod coroutine_impl;
 mod scheduler;
 mod scoped;
 mod timeout_list;
+#[cfg(feature = "tracing")]
+mod trace;
 mod yield_now;
 
+#[cfg(feature = "compat")]
+pub mod compat;
 pub mod coroutine;
 pub mod cqueue;
+#[cfg(all(feature = "core_dump", unix))]
+pub mod debug;
+pub mod fs;
+pub mod generator;
+#[cfg(feature = "http2")]
+pub mod http2;
 pub mod io;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod net;
 pub mod os;
+pub mod retry;
+pub mod steal;
 pub mod sync;
-pub use crate::config::{config, Config};
+pub mod time;
+#[cfg(feature = "ws")]
+pub mod ws;
+pub use crate::autoscale::AutoscaleConfig;
+pub use crate::config::{config, Config, PanicPolicy, WakeOrder};
 pub use crate::local::LocalKey;
+pub use crate::runtime::{Runtime, RuntimeConfig};
+pub use may_macros::cooperative;
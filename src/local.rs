@@ -20,15 +20,25 @@ pub struct CoroutineLocal {
     join: Arc<Join>,
     // real local data hash map
     local_data: LocalMap,
+    // the tracing spans entered by the coroutine
+    #[cfg(feature = "tracing")]
+    spans: crate::trace::SpanStack,
+    // the record in the live coroutine list for the debuggers
+    #[cfg(all(feature = "core_dump", unix))]
+    registration: Option<crate::debug::Registration>,
 }
 
 impl CoroutineLocal {
     /// create coroutine local storage
     pub fn new(co: Coroutine, join: Arc<Join>) -> Box<Self> {
         Box::new(CoroutineLocal {
+            #[cfg(feature = "tracing")]
+            spans: crate::trace::SpanStack::new(crate::trace::coroutine_span(co.id(), co.name())),
             co,
             join,
             local_data: RefCell::new(HashMap::default()),
+            #[cfg(all(feature = "core_dump", unix))]
+            registration: None,
         })
     }
 
@@ -41,6 +51,24 @@ impl CoroutineLocal {
     pub fn get_join(&self) -> Arc<Join> {
         self.join.clone()
     }
+
+    // set the record in the live coroutine list
+    #[cfg(all(feature = "core_dump", unix))]
+    pub fn set_registration(&mut self, registration: crate::debug::Registration) {
+        self.registration = Some(registration);
+    }
+
+    // get the record in the live coroutine list
+    #[cfg(all(feature = "core_dump", unix))]
+    pub fn registration(&self) -> Option<&crate::debug::Registration> {
+        self.registration.as_ref()
+    }
+
+    // get the saved tracing spans
+    #[cfg(feature = "tracing")]
+    pub fn get_spans(&self) -> &crate::trace::SpanStack {
+        &self.spans
+    }
 }
 
 #[inline]
@@ -34,15 +34,17 @@ macro_rules! go {
 
     // for cqueue add spawn
     ($cqueue:expr, $token:expr, $func:expr) => {{
-        fn _go_check<F, T>(f: F) -> F
+        fn _go_check<K, F, T>(_cqueue: &$crate::cqueue::Cqueue<K>, f: F) -> F
         where
-            F: FnOnce($crate::cqueue::EventSender) -> T + Send,
+            K: Clone,
+            F: FnOnce($crate::cqueue::EventSender<K>) -> T + Send,
             T: Send,
         {
             f
         }
-        let f = _go_check($func);
-        unsafe { $cqueue.add($token, f) }
+        let cqueue = &$cqueue;
+        let f = _go_check(cqueue, $func);
+        unsafe { cqueue.add($token, f) }
     }};
 }
 
@@ -93,7 +95,7 @@ macro_rules! cqueue_add {
     ($cqueue:ident, $token:expr, $name:pat = $top:expr => $bottom:expr) => {{
         go!($cqueue, $token, |es| loop {
             let $name = $top;
-            es.send(es.get_token());
+            es.send(0);
             $bottom
         })
     }};
@@ -106,7 +108,7 @@ macro_rules! cqueue_add_oneshot {
     ($cqueue:ident, $token:expr, $name:pat = $top:expr => $bottom:expr) => {{
         go!($cqueue, $token, |es| {
             let $name = $top;
-            es.send(es.get_token());
+            es.send(0);
             $bottom
         })
     }};
@@ -174,3 +176,37 @@ macro_rules! coroutine_local {
         };
     };
 }
+
+/// Yields the coroutine if it has run too long without suspension.
+///
+/// It's a cheap check to put into the cpu bound loops, the coroutine yields
+/// once it passed the budget of the checks set by
+/// `config().set_yield_budget`, so the other coroutines on the same worker
+/// get the chance to run. See also the [`cooperative`] attribute.
+///
+/// [`cooperative`]: crate::cooperative
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate may;
+///
+/// fn main() {
+///     let h = go!(|| {
+///         let mut sum = 0u64;
+///         for i in 0..1_000_000 {
+///             sum += i;
+///             maybe_yield!();
+///         }
+///         sum
+///     });
+///     assert_eq!(h.join().unwrap(), 499_999_500_000);
+/// }
+/// ```
+#[macro_export]
+macro_rules! maybe_yield {
+    () => {
+        $crate::coroutine::maybe_yield()
+    };
+}
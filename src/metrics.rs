@@ -0,0 +1,330 @@
+//! runtime metrics and the prometheus exporter
+//!
+//! with the `metrics` feature enabled, the scheduler, the io selectors, the
+//! timers and the channels count their events in global counters. the
+//! counters can be read by [`snapshot`], or served in the prometheus text
+//! format by [`prometheus_exporter`], which only uses the may networking, so
+//! no other runtime is needed.
+//!
+//! without the feature all the recording is a no-op.
+
+#[cfg(feature = "metrics")]
+use std::fmt::Write as _;
+#[cfg(feature = "metrics")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "metrics")]
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(feature = "metrics")]
+use std::panic;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "metrics")]
+use crossbeam::utils::CachePadded;
+
+#[cfg(feature = "metrics")]
+use crate::coroutine::{Builder, JoinHandle};
+#[cfg(feature = "metrics")]
+use crate::net::{Server, TcpListener, TcpStream};
+#[cfg(feature = "metrics")]
+use crate::sync::CancellationToken;
+
+/// the events counted by the runtime
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Counter {
+    Spawned,
+    Finished,
+    Resumed,
+    IoEvents,
+    #[cfg_attr(not(feature = "io_timeout"), allow(dead_code))]
+    IoTimeouts,
+    Timers,
+    ChannelSends,
+    ChannelParks,
+}
+
+#[cfg(feature = "metrics")]
+const COUNTERS: usize = Counter::ChannelParks as usize + 1;
+
+#[cfg(feature = "metrics")]
+static VALUES: [CachePadded<AtomicU64>; COUNTERS] =
+    [const { CachePadded::new(AtomicU64::new(0)) }; COUNTERS];
+
+/// count an event
+#[inline]
+pub(crate) fn inc(counter: Counter) {
+    #[cfg(feature = "metrics")]
+    VALUES[counter as usize].fetch_add(1, Ordering::Relaxed);
+    #[cfg(not(feature = "metrics"))]
+    let _ = counter;
+}
+
+#[cfg(feature = "metrics")]
+fn get(counter: Counter) -> u64 {
+    VALUES[counter as usize].load(Ordering::Relaxed)
+}
+
+/// A snapshot of the runtime metrics of the global scheduler.
+///
+/// The counters are shared by all the runtimes. Only available with the
+/// `metrics` feature.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// The number of the spawned coroutines.
+    pub spawned: u64,
+    /// The number of the finished coroutines.
+    pub finished: u64,
+    /// The number of times the coroutines are resumed by the workers.
+    pub resumed: u64,
+    /// The number of the io events that woke up a coroutine.
+    pub io_events: u64,
+    /// The number of the io operations that timed out.
+    pub io_timeouts: u64,
+    /// The number of the timers registered by the coroutines.
+    pub timers: u64,
+    /// The number of the messages sent to the mpsc and mpmc channels.
+    pub channel_sends: u64,
+    /// The number of times a receiver is parked on an empty channel.
+    pub channel_parks: u64,
+    /// The number of the worker threads.
+    pub workers: usize,
+    /// The number of the coroutines waiting in the run queues.
+    pub queued: usize,
+}
+
+/// Returns a snapshot of the runtime metrics.
+///
+/// Only available with the `metrics` feature.
+#[cfg(feature = "metrics")]
+pub fn snapshot() -> Metrics {
+    let sched = crate::scheduler::get_scheduler();
+    Metrics {
+        spawned: get(Counter::Spawned),
+        finished: get(Counter::Finished),
+        resumed: get(Counter::Resumed),
+        io_events: get(Counter::IoEvents),
+        io_timeouts: get(Counter::IoTimeouts),
+        timers: get(Counter::Timers),
+        channel_sends: get(Counter::ChannelSends),
+        channel_parks: get(Counter::ChannelParks),
+        workers: sched.active_workers(),
+        queued: sched.queued(),
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP may_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE may_{} {}", name, kind);
+    let _ = writeln!(out, "may_{} {}", name, value);
+}
+
+/// Renders the runtime metrics in the prometheus text format.
+///
+/// The contention metrics of the sync primitives are included with the
+/// `sync_metrics` feature. Only available with the `metrics` feature.
+#[cfg(feature = "metrics")]
+pub fn render() -> String {
+    let m = snapshot();
+    let mut out = String::new();
+    #[rustfmt::skip]
+    let counters = [
+        ("coroutines_spawned_total", "The number of the spawned coroutines.", m.spawned),
+        ("coroutines_finished_total", "The number of the finished coroutines.", m.finished),
+        ("coroutine_resumes_total", "The number of times the coroutines are resumed.", m.resumed),
+        ("io_events_total", "The number of the io events that woke up a coroutine.", m.io_events),
+        ("io_timeouts_total", "The number of the io operations that timed out.", m.io_timeouts),
+        ("timers_total", "The number of the registered timers.", m.timers),
+        ("channel_sends_total", "The number of the messages sent to the channels.", m.channel_sends),
+        ("channel_parks_total", "The number of times a receiver is parked.", m.channel_parks),
+    ];
+    for (name, help, value) in counters {
+        metric(&mut out, name, "counter", help, value);
+    }
+    let alive = m.spawned.saturating_sub(m.finished);
+    metric(
+        &mut out,
+        "coroutines_alive",
+        "gauge",
+        "The number of the live coroutines.",
+        alive,
+    );
+    metric(
+        &mut out,
+        "workers",
+        "gauge",
+        "The number of the worker threads.",
+        m.workers,
+    );
+    metric(
+        &mut out,
+        "run_queue_length",
+        "gauge",
+        "The number of the queued coroutines.",
+        m.queued,
+    );
+
+    #[cfg(feature = "sync_metrics")]
+    {
+        type Value = fn(&crate::sync::LockMetrics) -> u64;
+        let locks = crate::sync::metrics();
+        #[rustfmt::skip]
+        let series: [(_, _, Value); 2] = [
+            ("lock_acquisitions_total", "The number of the lock acquisitions.", |l| l.acquisitions),
+            ("lock_contentions_total", "The number of times a caller is parked.", |l| l.contentions),
+        ];
+        for (name, help, value) in series {
+            let _ = writeln!(out, "# HELP may_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE may_{} counter", name);
+            for l in &locks {
+                let (kind, location) = (l.kind, l.location);
+                let labels = format!("kind=\"{}\",location=\"{}\"", kind, location);
+                let _ = writeln!(out, "may_{}{{{}}} {}", name, labels, value(l));
+            }
+        }
+    }
+    out
+}
+
+/// A running prometheus exporter, created by [`prometheus_exporter`].
+///
+/// Dropping it leaves the exporter running, call [`shutdown`] to stop it.
+///
+/// [`shutdown`]: Exporter::shutdown
+#[cfg(feature = "metrics")]
+#[derive(Debug)]
+pub struct Exporter {
+    addr: SocketAddr,
+    token: CancellationToken,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+#[cfg(feature = "metrics")]
+impl Exporter {
+    /// Returns the address that the exporter listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the exporter and waits for the connections to finish.
+    pub fn shutdown(self) -> io::Result<()> {
+        self.token.cancel();
+        self.handle
+            .join()
+            .unwrap_or_else(|e| panic::resume_unwind(e))
+    }
+}
+
+/// Serves the runtime metrics over http in the prometheus text format.
+///
+/// The metrics are served on `/metrics`, the exporter runs in a coroutine of
+/// the global scheduler. Only available with the `metrics` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Read, Write};
+/// use may::net::TcpStream;
+///
+/// let exporter = may::metrics::prometheus_exporter("127.0.0.1:0").unwrap();
+///
+/// let mut s = TcpStream::connect(exporter.local_addr()).unwrap();
+/// s.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+/// let mut rsp = String::new();
+/// s.read_to_string(&mut rsp).unwrap();
+/// assert!(rsp.starts_with("HTTP/1.1 200 OK"));
+/// assert!(rsp.contains("may_coroutines_spawned_total"));
+///
+/// exporter.shutdown().unwrap();
+/// ```
+#[cfg(feature = "metrics")]
+pub fn prometheus_exporter<A: ToSocketAddrs>(addr: A) -> io::Result<Exporter> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    let token = CancellationToken::new();
+    let server = Server::new().max_conns(16).shutdown(token.clone());
+    let builder = Builder::new().name("prometheus_exporter".to_owned());
+    let handle = unsafe {
+        builder.spawn(move || {
+            server.serve(listener, |s| {
+                if let Err(e) = handle_conn(s) {
+                    debug!("metrics connection error: {}", e);
+                }
+            })
+        })
+    }?;
+    Ok(Exporter {
+        addr,
+        token,
+        handle,
+    })
+}
+
+// serve one request and close the connection
+#[cfg(feature = "metrics")]
+fn handle_conn(mut s: TcpStream) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = s.read(&mut chunk)?;
+        if n == 0 || buf.len() + n > 8192 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let line = buf.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = line.split(|b| *b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        s,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    s.flush()
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut s = TcpStream::connect(addr).unwrap();
+        write!(s, "GET {} HTTP/1.1\r\n\r\n", path).unwrap();
+        let mut rsp = String::new();
+        s.read_to_string(&mut rsp).unwrap();
+        rsp
+    }
+
+    #[test]
+    fn prometheus_exporter_serve() {
+        let (tx, rx) = crate::sync::mpsc::channel();
+        let h = go!(move || {
+            rx.recv().unwrap();
+            crate::coroutine::sleep(std::time::Duration::from_millis(1));
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        tx.send(1).unwrap();
+        h.join().unwrap();
+
+        let m = snapshot();
+        assert!(m.spawned >= 1 && m.finished >= 1);
+        assert!(m.channel_sends >= 1 && m.channel_parks >= 1);
+        assert!(m.timers >= 1);
+
+        let exporter = prometheus_exporter("127.0.0.1:0").unwrap();
+        let rsp = get(exporter.local_addr(), "/metrics");
+        assert!(rsp.starts_with("HTTP/1.1 200 OK"));
+        assert!(rsp.contains("# TYPE may_coroutines_spawned_total counter"));
+        assert!(rsp.contains("\nmay_workers "));
+        assert!(get(exporter.local_addr(), "/").starts_with("HTTP/1.1 404"));
+        exporter.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+//! socket options applied before the bind
+//!
+//! an ipv6 socket bound to the unspecified address `[::]` accepts the ipv4
+//! connections too on most systems, but the default is decided by the os
+//! (`net.ipv6.bindv6only` on linux, always ipv6 only on windows and the
+//! bsds). [`TcpListenerBuilder::only_v6`] and [`UdpSocketBuilder::only_v6`]
+//! set `IPV6_V6ONLY` explicitly, so the dual-stack behavior doesn't depend
+//! on the host.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+
+use socket2::{Domain, Socket, Type};
+
+use super::{TcpListener, TcpStream, UdpSocket};
+use crate::cqueue;
+
+// create the socket for the address and set `IPV6_V6ONLY` if it's ipv6
+fn new_socket(addr: &SocketAddr, ty: Type, only_v6: Option<bool>) -> io::Result<Socket> {
+    let socket = match addr {
+        SocketAddr::V4(_) => Socket::new(Domain::IPV4, ty, None)?,
+        SocketAddr::V6(_) => {
+            let socket = Socket::new(Domain::IPV6, ty, None)?;
+            if let Some(only_v6) = only_v6 {
+                socket.set_only_v6(only_v6)?;
+            }
+            socket
+        }
+    };
+    Ok(socket)
+}
+
+fn no_addr() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "no socket address")
+}
+
+/// A builder of [`TcpListener`] with the socket options applied before the
+/// bind.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use may::net::TcpListenerBuilder;
+///
+/// // accept both the ipv4 and ipv6 connections on port 8080
+/// let listener = TcpListenerBuilder::new()
+///     .only_v6(false)
+///     .bind("[::]:8080")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TcpListenerBuilder {
+    only_v6: Option<bool>,
+    reuse_address: bool,
+    backlog: i32,
+}
+
+impl Default for TcpListenerBuilder {
+    fn default() -> Self {
+        TcpListenerBuilder {
+            only_v6: None,
+            reuse_address: true,
+            backlog: 1024,
+        }
+    }
+}
+
+impl TcpListenerBuilder {
+    /// Creates a builder with the same options as [`TcpListener::bind`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `IPV6_V6ONLY` of the ipv6 sockets, it's ignored for the ipv4
+    /// addresses. By default it's left to the os.
+    pub fn only_v6(mut self, only_v6: bool) -> Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Sets `SO_REUSEADDR`, the default is true.
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Sets the backlog of the listen queue, the default is 1024.
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Binds to the first resolved address.
+    pub fn bind<A: ToSocketAddrs>(&self, addr: A) -> io::Result<TcpListener> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(no_addr)?;
+        self.bind_one(&addr)
+    }
+
+    /// Binds to all the resolved addresses, the connections of all of them
+    /// are accepted by the returned [`MultiListener`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::net::SocketAddr;
+    /// use may::net::TcpListenerBuilder;
+    ///
+    /// let addrs: [SocketAddr; 2] = ["0.0.0.0:8080".parse().unwrap(), "[::]:8080".parse().unwrap()];
+    /// let listener = TcpListenerBuilder::new()
+    ///     .only_v6(true)
+    ///     .bind_all(&addrs[..])
+    ///     .unwrap();
+    /// for stream in listener.incoming() {
+    ///     // handle the stream
+    ///     drop(stream);
+    /// }
+    /// ```
+    pub fn bind_all<A: ToSocketAddrs>(&self, addr: A) -> io::Result<MultiListener> {
+        let listeners = addr
+            .to_socket_addrs()?
+            .map(|addr| self.bind_one(&addr))
+            .collect::<io::Result<Vec<_>>>()?;
+        if listeners.is_empty() {
+            return Err(no_addr());
+        }
+        Ok(MultiListener {
+            listeners,
+            pending: parking_lot::Mutex::new(VecDeque::new()),
+        })
+    }
+
+    fn bind_one(&self, addr: &SocketAddr) -> io::Result<TcpListener> {
+        let socket = new_socket(addr, Type::STREAM, self.only_v6)?;
+        // windows not have reuse port but reuse address is not safe
+        socket.set_reuse_address(self.reuse_address)?;
+        socket.bind(&(*addr).into())?;
+        socket.listen(self.backlog)?;
+        let s: net::TcpListener = socket.into();
+        TcpListener::new(s)
+    }
+}
+
+/// A builder of [`UdpSocket`] with the socket options applied before the
+/// bind.
+#[derive(Debug, Clone, Default)]
+pub struct UdpSocketBuilder {
+    only_v6: Option<bool>,
+    reuse_address: bool,
+}
+
+impl UdpSocketBuilder {
+    /// Creates a builder with the same options as [`UdpSocket::bind`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `IPV6_V6ONLY` of the ipv6 sockets, it's ignored for the ipv4
+    /// addresses. By default it's left to the os.
+    pub fn only_v6(mut self, only_v6: bool) -> Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Sets `SO_REUSEADDR`, the default is false.
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Binds to the first resolved address.
+    pub fn bind<A: ToSocketAddrs>(&self, addr: A) -> io::Result<UdpSocket> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(no_addr)?;
+        let socket = new_socket(&addr, Type::DGRAM, self.only_v6)?;
+        if self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        socket.bind(&addr.into())?;
+        let s: net::UdpSocket = socket.into();
+        UdpSocket::new(s)
+    }
+}
+
+/// A listener bound to several addresses, created by
+/// [`TcpListenerBuilder::bind_all`].
+///
+/// [`accept`] waits on all the listeners at once with a cqueue, the
+/// connections accepted at the same time are queued for the next calls.
+///
+/// [`accept`]: MultiListener::accept
+#[derive(Debug)]
+pub struct MultiListener {
+    listeners: Vec<TcpListener>,
+    // the connections accepted by the losers of the select
+    pending: parking_lot::Mutex<VecDeque<io::Result<(TcpStream, SocketAddr)>>>,
+}
+
+impl MultiListener {
+    /// Accepts a new connection from any of the listeners.
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        if let Some(ret) = self.pending.lock().pop_front() {
+            return ret;
+        }
+
+        cqueue::scope(|cqueue| {
+            for (i, listener) in self.listeners.iter().enumerate() {
+                let pending = &self.pending;
+                cqueue.add(i, move |es| {
+                    let ret = listener.accept();
+                    // queue it before any cancel point, so that it's never
+                    // lost when the select is done by another listener
+                    pending.lock().push_back(ret);
+                    es.send(0);
+                });
+            }
+            cqueue.poll(None).ok();
+        });
+
+        self.pending
+            .lock()
+            .pop_front()
+            .expect("no connection accepted")
+    }
+
+    /// Returns an iterator over the connections being accepted.
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<TcpStream>> + '_ {
+        std::iter::repeat_with(move || self.accept().map(|(s, _)| s))
+    }
+
+    /// Returns the listeners.
+    pub fn listeners(&self) -> &[TcpListener] {
+        &self.listeners
+    }
+
+    /// Returns the local addresses of the listeners.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(|l| l.local_addr()).collect()
+    }
+}
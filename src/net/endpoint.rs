@@ -0,0 +1,445 @@
+//! listeners bound to the urls chosen at runtime
+//!
+//! the server code is written once against [`Listener`] and [`Stream`], and
+//! the transport is picked by the url in the config:
+//!
+//! - `tcp://127.0.0.1:8080`
+//! - `unix:///run/app.sock`, unix only
+//! - `tls://0.0.0.0:8443`, a tcp listener whose connections are wrapped by
+//!   a [`TlsAcceptor`] given by the application, see the [tls] section
+//!
+//! [tls]: super#tls
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::{TcpListener, TcpStream};
+#[cfg(unix)]
+use crate::os::unix::net::{UnixListener, UnixStream};
+
+/// A tls connection built on a [`TcpStream`] by the tls library.
+pub trait TlsStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> TlsStream for T {}
+
+/// Wraps the accepted tcp connections of a `tls://` listener.
+///
+/// It's not called by [`Listener::accept`] but on the first read or write of
+/// the accepted [`Stream`], so the handshake is done by the coroutine that
+/// serves the connection, and a slow client doesn't hold up the accept loop.
+pub type TlsAcceptor = Arc<dyn Fn(TcpStream) -> io::Result<Box<dyn TlsStream>> + Send + Sync>;
+
+// the accepted tls connection, the acceptor is run on the first read or write
+struct LazyTls {
+    acceptor: TlsAcceptor,
+    tcp: Option<TcpStream>,
+    tls: Option<Box<dyn TlsStream>>,
+}
+
+impl LazyTls {
+    fn stream(&mut self) -> io::Result<&mut Box<dyn TlsStream>> {
+        if let Some(tcp) = self.tcp.take() {
+            self.tls = Some((self.acceptor)(tcp)?);
+        }
+        self.tls
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "tls handshake failed"))
+    }
+}
+
+impl Read for LazyTls {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream()?.read(buf)
+    }
+}
+
+impl Write for LazyTls {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream()?.flush()
+    }
+}
+
+/// An address parsed from a `tcp://`, `unix://` or `tls://` url.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// The `host:port` of a tcp listener.
+    Tcp(String),
+    /// The path of a unix socket.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// The `host:port` of a tls listener.
+    Tls(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = io::Error;
+
+    fn from_str(url: &str) -> io::Result<Endpoint> {
+        let invalid =
+            |msg| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", msg, url));
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| invalid("no scheme"))?;
+        if rest.is_empty() {
+            return Err(invalid("no address"));
+        }
+        match scheme {
+            "tcp" => Ok(Endpoint::Tcp(rest.to_owned())),
+            "tls" => Ok(Endpoint::Tls(rest.to_owned())),
+            #[cfg(unix)]
+            "unix" => Ok(Endpoint::Unix(PathBuf::from(rest))),
+            _ => Err(invalid("unsupported scheme")),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            Endpoint::Tls(addr) => write!(f, "tls://{}", addr),
+        }
+    }
+}
+
+/// A listener of any transport.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Read, Write};
+/// use may::net::{Listener, TcpStream};
+///
+/// // from the config
+/// let url = "tcp://127.0.0.1:0";
+/// let listener = Listener::bind_url(url).unwrap();
+/// let local = listener.local_endpoint().unwrap();
+///
+/// let h = may::go!(move || {
+///     let mut stream = listener.accept().unwrap();
+///     let mut buf = [0; 4];
+///     stream.read_exact(&mut buf).unwrap();
+///     stream.write_all(&buf).unwrap();
+/// });
+///
+/// let addr = local.to_string().replace("tcp://", "");
+/// let mut client = TcpStream::connect(addr).unwrap();
+/// client.write_all(b"ping").unwrap();
+/// let mut buf = [0; 4];
+/// client.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"ping");
+/// h.join().unwrap();
+/// ```
+pub enum Listener {
+    /// A tcp listener.
+    Tcp(TcpListener),
+    /// A unix socket listener.
+    #[cfg(unix)]
+    Unix(UnixListener),
+    /// A tcp listener whose connections are wrapped by the acceptor.
+    Tls(TcpListener, TlsAcceptor),
+}
+
+impl Listener {
+    /// Binds to a `tcp://` or `unix://` url.
+    ///
+    /// A `tls://` url fails with `InvalidInput`, use [`bind_url_with_tls`]
+    /// for it.
+    ///
+    /// [`bind_url_with_tls`]: Listener::bind_url_with_tls
+    pub fn bind_url(url: &str) -> io::Result<Listener> {
+        Listener::bind(&url.parse()?, None)
+    }
+
+    /// Binds to the url, the acceptor is used if it's a `tls://` one.
+    pub fn bind_url_with_tls(url: &str, acceptor: TlsAcceptor) -> io::Result<Listener> {
+        Listener::bind(&url.parse()?, Some(acceptor))
+    }
+
+    /// Binds to the endpoint, a tls endpoint needs the acceptor.
+    pub fn bind(endpoint: &Endpoint, acceptor: Option<TlsAcceptor>) -> io::Result<Listener> {
+        match endpoint {
+            Endpoint::Tcp(addr) => TcpListener::bind(addr.as_str()).map(Listener::Tcp),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => UnixListener::bind(path).map(Listener::Unix),
+            Endpoint::Tls(addr) => {
+                let acceptor = acceptor.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "no tls acceptor for tls://")
+                })?;
+                let listener = TcpListener::bind(addr.as_str())?;
+                Ok(Listener::Tls(listener, acceptor))
+            }
+        }
+    }
+
+    /// Accepts a new connection.
+    ///
+    /// The tls handshake of a `tls://` connection is not done here, it's run
+    /// by the first read or write of the returned stream.
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(l) => l.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(l) => l.accept().map(|(s, _)| Stream::Unix(s)),
+            Listener::Tls(l, acceptor) => {
+                let (s, _) = l.accept()?;
+                Ok(Stream::Tls(Box::new(LazyTls {
+                    acceptor: acceptor.clone(),
+                    tcp: Some(s),
+                    tls: None,
+                })))
+            }
+        }
+    }
+
+    /// Returns an iterator over the connections being accepted.
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+
+    /// Gets the endpoint that the listener is bound to, e.g. to get the port
+    /// of `tcp://127.0.0.1:0`.
+    pub fn local_endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Listener::Tcp(l) => Ok(Endpoint::Tcp(l.local_addr()?.to_string())),
+            #[cfg(unix)]
+            Listener::Unix(l) => {
+                let addr = l.local_addr()?;
+                let path = addr.as_pathname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Unsupported, "unnamed unix socket")
+                })?;
+                Ok(Endpoint::Unix(path.to_owned()))
+            }
+            Listener::Tls(l, _) => Ok(Endpoint::Tls(l.local_addr()?.to_string())),
+        }
+    }
+}
+
+impl fmt::Debug for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(l) => f.debug_tuple("Tcp").field(l).finish(),
+            #[cfg(unix)]
+            Listener::Unix(l) => f.debug_tuple("Unix").field(l).finish(),
+            Listener::Tls(l, _) => f.debug_tuple("Tls").field(l).finish(),
+        }
+    }
+}
+
+/// An iterator over the connections of a [`Listener`].
+#[derive(Debug)]
+pub struct Incoming<'a> {
+    listener: &'a Listener,
+}
+
+impl<'a> Iterator for Incoming<'a> {
+    type Item = io::Result<Stream>;
+
+    fn next(&mut self) -> Option<io::Result<Stream>> {
+        Some(self.listener.accept())
+    }
+}
+
+/// A connection accepted by a [`Listener`].
+pub enum Stream {
+    /// A tcp connection.
+    Tcp(TcpStream),
+    /// A unix socket connection.
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// A tls connection.
+    Tls(Box<dyn TlsStream>),
+}
+
+impl Stream {
+    /// Shuts down the read, write, or both halves of the connection.
+    ///
+    /// It's a no-op for a tls connection, whose shutdown belongs to the tls
+    /// library, drop the stream to close it.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.shutdown(how),
+            Stream::Tls(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stream::Tcp(s) => f.debug_tuple("Tcp").field(s).finish(),
+            #[cfg(unix)]
+            Stream::Unix(s) => f.debug_tuple("Unix").field(s).finish(),
+            Stream::Tls(_) => f.debug_tuple("Tls").finish_non_exhaustive(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_endpoint() {
+        let ep: Endpoint = "tcp://127.0.0.1:80".parse().unwrap();
+        assert_eq!(ep, Endpoint::Tcp("127.0.0.1:80".into()));
+        assert_eq!(ep.to_string(), "tcp://127.0.0.1:80");
+        let ep: Endpoint = "tls://[::1]:443".parse().unwrap();
+        assert_eq!(ep, Endpoint::Tls("[::1]:443".into()));
+        #[cfg(unix)]
+        {
+            let ep: Endpoint = "unix:///run/app.sock".parse().unwrap();
+            assert_eq!(ep, Endpoint::Unix("/run/app.sock".into()));
+            assert_eq!(ep.to_string(), "unix:///run/app.sock");
+        }
+        assert!("127.0.0.1:80".parse::<Endpoint>().is_err());
+        assert!("udp://127.0.0.1:80".parse::<Endpoint>().is_err());
+        assert!("tcp://".parse::<Endpoint>().is_err());
+        let err = Listener::bind_url("tls://127.0.0.1:0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn tls_listener() {
+        // an identity "tls" that only prepends a greeting
+        struct Greet(TcpStream, bool);
+        impl Read for Greet {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Write for Greet {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if !self.1 {
+                    self.0.write_all(b"hi ")?;
+                    self.1 = true;
+                }
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.0.flush()
+            }
+        }
+
+        let acceptor: TlsAcceptor = Arc::new(|s: TcpStream| -> io::Result<Box<dyn TlsStream>> {
+            Ok(Box::new(Greet(s, false)))
+        });
+        let listener = Listener::bind_url_with_tls("tls://127.0.0.1:0", acceptor).unwrap();
+        let addr = match listener.local_endpoint().unwrap() {
+            Endpoint::Tls(addr) => addr,
+            ep => panic!("unexpected endpoint {}", ep),
+        };
+        let h = go!(move || {
+            let mut s = listener.incoming().next().unwrap().unwrap();
+            assert!(matches!(s, Stream::Tls(_)));
+            s.write_all(b"there").unwrap();
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut buf = String::new();
+        client.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hi there");
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn tls_handshake_per_connection() {
+        // the "handshake" waits for a byte from the client
+        let acceptor: TlsAcceptor =
+            Arc::new(|mut s: TcpStream| -> io::Result<Box<dyn TlsStream>> {
+                let mut b = [0; 1];
+                s.read_exact(&mut b)?;
+                Ok(Box::new(s))
+            });
+        let listener = Listener::bind_url_with_tls("tls://127.0.0.1:0", acceptor).unwrap();
+        let addr = listener.local_endpoint().unwrap().to_string();
+        let addr = addr.trim_start_matches("tls://").to_owned();
+        let h = go!(move || {
+            let mut conns = Vec::new();
+            for s in listener.incoming().take(2) {
+                let mut s = s.unwrap();
+                conns.push(go!(move || s.write_all(b"ok")));
+            }
+            conns
+        });
+
+        // the first client never finishes the handshake
+        let slow = TcpStream::connect(&addr).unwrap();
+        let mut fast = TcpStream::connect(&addr).unwrap();
+        fast.write_all(b"x").unwrap();
+        let mut buf = [0; 2];
+        fast.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ok");
+
+        drop(slow);
+        let rets: Vec<_> = h
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.join().unwrap())
+            .collect();
+        assert!(rets[0].is_err());
+        assert!(rets[1].is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_listener() {
+        let dir = std::env::temp_dir().join(format!("may_endpoint_{}", std::process::id()));
+        let _ = std::fs::remove_file(&dir);
+        let url = format!("unix://{}", dir.display());
+        let listener = Listener::bind_url(&url).unwrap();
+        assert_eq!(listener.local_endpoint().unwrap().to_string(), url);
+        let h = go!(move || {
+            let mut s = listener.accept().unwrap();
+            s.write_all(b"pong").unwrap();
+        });
+        let mut client = UnixStream::connect(&dir).unwrap();
+        let mut buf = String::new();
+        client.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "pong");
+        h.join().unwrap();
+        std::fs::remove_file(&dir).unwrap();
+    }
+}
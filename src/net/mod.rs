@@ -1,8 +1,32 @@
 //! Networking primitives
 //!
+//! # TLS
+//!
+//! There is no tls module in may. The tls libraries that work on a blocking
+//! `Read + Write` stream, e.g. `native-tls` or `rustls::StreamOwned`, can be
+//! used on top of [`TcpStream`] directly, the handshake and the records io
+//! park the coroutine instead of blocking the worker thread. The server side
+//! features like the SNI certificate selection, the session resumption and
+//! the ALPN negotiation are configured in the tls library. See
+//! `examples/https.rs` for a server built with `native-tls`.
+//!
+//! A [`Listener`] bound to a `tls://` url takes the tls library as a
+//! [`TlsAcceptor`], so the same server code can also run on `tcp://` and
+//! `unix://` urls.
 
+mod bind;
+mod endpoint;
+pub mod proxy;
+#[cfg(unix)]
+mod raw;
+mod serve;
 mod tcp;
 mod udp;
 
-pub use self::tcp::{TcpListener, TcpStream};
-pub use self::udp::UdpSocket;
+pub use self::bind::{MultiListener, TcpListenerBuilder, UdpSocketBuilder};
+pub use self::endpoint::{Endpoint, Incoming, Listener, Stream, TlsAcceptor, TlsStream};
+#[cfg(unix)]
+pub use self::raw::RawSocket;
+pub use self::serve::{serve, Server};
+pub use self::tcp::{OwnedReadHalf, OwnedWriteHalf, ReadHalf, TcpListener, TcpStream, WriteHalf};
+pub use self::udp::{MsgBuf, UdpSocket};
@@ -0,0 +1,280 @@
+//! raw ip and packet sockets
+//!
+//! a raw socket sends and receives the datagrams below the transport layer,
+//! e.g. the icmp packets of ping and traceroute, or the whole link layer
+//! frames with an `AF_PACKET` socket on linux. the socket is registered to
+//! the reactor, so [`RawSocket::recv_from`] parks the coroutine instead of
+//! the worker thread.
+//!
+//! creating a raw socket usually needs the root privilege or the
+//! `CAP_NET_RAW` capability.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+#[cfg(feature = "io_timeout")]
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::io as io_impl;
+use crate::io::net as net_impl;
+#[cfg(feature = "io_timeout")]
+use crate::sync::atomic_dur::AtomicDuration;
+use crate::yield_now::yield_with_io;
+
+/// A raw socket driven by the coroutine reactor.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::net::{Ipv4Addr, SocketAddrV4};
+/// use may::net::RawSocket;
+/// use socket2::{Domain, Protocol};
+///
+/// let socket = RawSocket::new(Domain::IPV4, Protocol::ICMPV4).unwrap();
+/// // an icmp echo request with the checksum of the zero id and sequence
+/// let echo = [8, 0, 0xf7, 0xff, 0, 0, 0, 0];
+/// let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+/// socket.send_to(&echo, &dst.into()).unwrap();
+///
+/// // the reply comes with the ip header
+/// let mut buf = [0u8; 1500];
+/// let (n, from) = socket.recv_from(&mut buf).unwrap();
+/// println!("{} bytes from {:?}", n, from.as_socket());
+/// ```
+#[derive(Debug)]
+pub struct RawSocket {
+    _io: io_impl::IoData,
+    sys: Socket,
+    #[cfg(feature = "io_timeout")]
+    read_timeout: AtomicDuration,
+    #[cfg(feature = "io_timeout")]
+    write_timeout: AtomicDuration,
+}
+
+impl RawSocket {
+    /// Creates a raw socket of the domain for the ip protocol, e.g.
+    /// `Domain::IPV4` and `Protocol::ICMPV4`.
+    pub fn new(domain: Domain, protocol: Protocol) -> io::Result<RawSocket> {
+        let s = Socket::new(domain, Type::RAW, Some(protocol))?;
+        RawSocket::from_socket(s)
+    }
+
+    /// Creates an `AF_PACKET` socket that receives the link layer frames of
+    /// the ethernet protocol, e.g. `libc::ETH_P_ALL` for all of them.
+    ///
+    /// Bind it to an interface by [`bind`] with a `sockaddr_ll` address to
+    /// only get the frames of that interface.
+    ///
+    /// [`bind`]: RawSocket::bind
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn packet(protocol: u16) -> io::Result<RawSocket> {
+        // the protocol is in the network byte order
+        let protocol = Protocol::from(protocol.to_be() as libc::c_int);
+        let s = Socket::new(Domain::from(libc::AF_PACKET), Type::RAW, Some(protocol))?;
+        RawSocket::from_socket(s)
+    }
+
+    /// Registers a raw socket created by `socket2` to the reactor.
+    pub fn from_socket(s: Socket) -> io::Result<RawSocket> {
+        s.set_nonblocking(true)?;
+        io_impl::add_socket(&s).map(|io| RawSocket {
+            _io: io,
+            sys: s,
+            #[cfg(feature = "io_timeout")]
+            read_timeout: AtomicDuration::new(None),
+            #[cfg(feature = "io_timeout")]
+            write_timeout: AtomicDuration::new(None),
+        })
+    }
+
+    /// Gets the underlying socket, e.g. to set the socket options.
+    pub fn inner(&self) -> &Socket {
+        &self.sys
+    }
+
+    /// Binds the socket to the address.
+    pub fn bind(&self, addr: &SockAddr) -> io::Result<()> {
+        self.sys.bind(addr)
+    }
+
+    /// Connects the socket to the address, so [`send`] and [`recv`] only
+    /// talk to it.
+    ///
+    /// [`send`]: RawSocket::send
+    /// [`recv`]: RawSocket::recv
+    pub fn connect(&self, addr: &SockAddr) -> io::Result<()> {
+        self.sys.connect(addr)
+    }
+
+    /// Gets the local address of the socket.
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.sys.local_addr()
+    }
+
+    /// Sends the datagram to the address, returns the number of bytes sent.
+    pub fn send_to(&self, buf: &[u8], addr: &SockAddr) -> io::Result<usize> {
+        self.write_op(|| self.sys.send_to(buf, addr))
+    }
+
+    /// Receives a datagram, returns the number of bytes read and the address
+    /// it comes from.
+    ///
+    /// The datagram of an ipv4 raw socket includes the ip header.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SockAddr)> {
+        let buf = as_uninit(buf);
+        self.read_op(|| self.sys.recv_from(buf))
+    }
+
+    /// Sends the datagram to the connected address.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.write_op(|| self.sys.send(buf))
+    }
+
+    /// Receives a datagram from the connected address.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = as_uninit(buf);
+        self.read_op(|| self.sys.recv(buf))
+    }
+
+    fn read_op<R, F>(&self, mut op: F) -> io::Result<R>
+    where
+        F: FnMut() -> io::Result<R>,
+    {
+        self._io.reset();
+        // this is an earlier return try for nonblocking read
+        match op() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            ret => return ret,
+        }
+
+        let mut reader = net_impl::SocketOp::new(
+            self,
+            #[cfg(feature = "io_timeout")]
+            self.read_timeout.get(),
+            op,
+        );
+        yield_with_io(&reader, reader.is_coroutine);
+        reader.done()
+    }
+
+    fn write_op<R, F>(&self, mut op: F) -> io::Result<R>
+    where
+        F: FnMut() -> io::Result<R>,
+    {
+        self._io.reset();
+        // this is an earlier return try for nonblocking write
+        match op() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            ret => return ret,
+        }
+
+        let mut writer = net_impl::SocketOp::new(
+            self,
+            #[cfg(feature = "io_timeout")]
+            self.write_timeout.get(),
+            op,
+        );
+        yield_with_io(&writer, writer.is_coroutine);
+        writer.done()
+    }
+
+    #[cfg(feature = "io_timeout")]
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.sys.set_read_timeout(dur)?;
+        self.read_timeout.swap(dur);
+        Ok(())
+    }
+
+    #[cfg(feature = "io_timeout")]
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.sys.set_write_timeout(dur)?;
+        self.write_timeout.swap(dur);
+        Ok(())
+    }
+
+    #[cfg(feature = "io_timeout")]
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.read_timeout.get())
+    }
+
+    #[cfg(feature = "io_timeout")]
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.write_timeout.get())
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.sys.ttl()
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.sys.set_ttl(ttl)
+    }
+
+    /// Sets `IP_HDRINCL`, the datagrams to send include the ip header if
+    /// it's true.
+    pub fn set_header_included(&self, included: bool) -> io::Result<()> {
+        self.sys.set_header_included(included)
+    }
+
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.sys.take_error()
+    }
+}
+
+// the socket only writes the initialized bytes into the buffer
+fn as_uninit(buf: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) }
+}
+
+impl io_impl::AsIoData for RawSocket {
+    fn as_io_data(&self) -> &io_impl::IoData {
+        &self._io
+    }
+}
+
+impl AsRawFd for RawSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sys.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for RawSocket {
+    fn into_raw_fd(self) -> RawFd {
+        self.sys.into_raw_fd()
+        // drop self will dereg from the selector
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    #[test]
+    fn raw_socket_ping() {
+        let socket = match RawSocket::new(Domain::IPV4, Protocol::ICMPV4) {
+            Ok(s) => s,
+            // not privileged
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("failed to create the raw socket: {}", e),
+        };
+        let h = go!(move || {
+            let echo = [8, 0, 0xf7, 0xff, 0, 0, 0, 0];
+            let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+            socket.send_to(&echo, &dst.into()).unwrap();
+            let mut buf = [0u8; 1500];
+            // the request is received too
+            loop {
+                let (n, from) = socket.recv_from(&mut buf).unwrap();
+                let ihl = (buf[0] & 0x0f) as usize * 4;
+                assert!(n >= ihl + 8);
+                if buf[ihl] == 0 {
+                    break from.as_socket_ipv4().unwrap();
+                }
+            }
+        });
+        assert_eq!(h.join().unwrap().ip(), &Ipv4Addr::LOCALHOST);
+    }
+}
@@ -0,0 +1,210 @@
+//! accept loop helper for tcp servers
+//!
+//! the accept loop spawns one coroutine for each connection, limits the number
+//! of the live connections and stops gracefully with a cancellation token.
+//!
+
+use std::io;
+use std::panic;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{TcpListener, TcpStream};
+use crate::coroutine::Builder;
+use crate::sync::{CancellationToken, Semphore};
+
+// release the connection permit even if the handler panics
+struct Permit(Arc<Semphore>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.post();
+    }
+}
+
+/// A tcp server that runs the accept loop.
+///
+/// The configurations available are:
+///
+/// - `max_conns`: the max number of the connections handled at the same
+///   time, the accept loop waits for a free slot when it's reached
+/// - `stack_size`: the stack size of the connection coroutines
+/// - `shutdown`: a token that stops the server when cancelled
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Read, Write};
+/// use may::net::{Server, TcpListener, TcpStream};
+/// use may::sync::CancellationToken;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let token = CancellationToken::new();
+///
+/// let server = Server::new().max_conns(100).shutdown(token.clone());
+/// let h = may::go!(move || {
+///     server.serve(listener, |mut s| {
+///         let mut buf = [0; 5];
+///         s.read_exact(&mut buf).unwrap();
+///         s.write_all(&buf).unwrap();
+///     })
+/// });
+///
+/// let mut s = TcpStream::connect(addr).unwrap();
+/// s.write_all(b"hello").unwrap();
+/// let mut buf = [0; 5];
+/// s.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"hello");
+///
+/// token.cancel();
+/// h.join().unwrap().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Server {
+    max_conns: usize,
+    stack_size: Option<usize>,
+    shutdown: Option<CancellationToken>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    /// Creates a new server with the default configurations.
+    pub fn new() -> Server {
+        Server {
+            max_conns: 1024,
+            stack_size: None,
+            shutdown: None,
+        }
+    }
+
+    /// Sets the max number of the connections handled at the same time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_conns` is 0.
+    pub fn max_conns(mut self, max_conns: usize) -> Server {
+        assert!(max_conns > 0, "max_conns must be greater than 0");
+        self.max_conns = max_conns;
+        self
+    }
+
+    /// Sets the stack size of the connection coroutines.
+    pub fn stack_size(mut self, size: usize) -> Server {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Sets the token that shuts down the server.
+    ///
+    /// When the token is cancelled, the server stops accepting new
+    /// connections and waits for the live connections to finish.
+    pub fn shutdown(mut self, token: CancellationToken) -> Server {
+        self.shutdown = Some(token);
+        self
+    }
+
+    /// Runs the accept loop, `handler` is called in a new coroutine for each
+    /// connection.
+    ///
+    /// This returns after the server is shut down and all the connections are
+    /// done. The transient accept errors are ignored, other errors stop the
+    /// server and are returned after the connections are drained.
+    pub fn serve<F>(self, listener: TcpListener, handler: F) -> io::Result<()>
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        // run in a coroutine so that the accept loop can always be cancelled
+        let h = go!(move || self.run(listener, handler));
+        h.join().unwrap_or_else(|e| panic::resume_unwind(e))
+    }
+
+    fn run<F>(self, listener: TcpListener, handler: F) -> io::Result<()>
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let permits = Arc::new(Semphore::new(self.max_conns));
+        let token = self.shutdown.clone().unwrap_or_default();
+
+        let ret = token.run(|| loop {
+            permits.wait();
+            let permit = Permit(permits.clone());
+            let stream = match listener.accept() {
+                Ok((s, _)) => s,
+                Err(e) if is_transient(&e) => {
+                    error!("accept error = {:?}", e);
+                    // wait a while for the resources to be released
+                    crate::coroutine::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Err(e) => break Err(e),
+            };
+
+            let handler = handler.clone();
+            let mut builder = Builder::new();
+            if let Some(size) = self.stack_size {
+                builder = builder.stack_size(size);
+            }
+            // the closure is 'static, it's safe to spawn
+            let ret = unsafe {
+                builder.spawn(move || {
+                    let _permit = permit;
+                    handler(stream)
+                })
+            };
+            if let Err(e) = ret {
+                error!("failed to spawn connection coroutine, err = {:?}", e);
+            }
+        });
+
+        // drain the live connections
+        for _ in 0..self.max_conns {
+            permits.wait();
+        }
+
+        match ret {
+            Ok(ret) => ret,
+            // shut down by the token
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+// the errors that only affect the current connection or are temporary
+fn is_transient(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => true,
+        #[cfg(unix)]
+        _ => matches!(
+            e.raw_os_error(),
+            Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+        ),
+        #[cfg(not(unix))]
+        _ => false,
+    }
+}
+
+/// Runs the accept loop on the listener, `handler` is called in a new
+/// coroutine for each connection.
+///
+/// At most `max_conns` connections are handled at the same time. This is a
+/// shortcut of [`Server`] with the other configurations as default, the
+/// server runs until an accept error occurs.
+///
+/// [`Server`]: struct.Server.html
+pub fn serve<F>(listener: TcpListener, max_conns: usize, handler: F) -> io::Result<()>
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    Server::new().max_conns(max_conns).serve(listener, handler)
+}
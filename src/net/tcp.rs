@@ -1,5 +1,8 @@
 use std::io::{self, Read, Write};
+use std::marker::PhantomData;
 use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 #[cfg(feature = "io_timeout")]
 use std::time::Duration;
 
@@ -7,7 +10,7 @@ use crate::io as io_impl;
 use crate::io::net as net_impl;
 use crate::io::split_io::{SplitIo, SplitReader, SplitWriter};
 #[cfg(unix)]
-use crate::io::sys::mod_socket;
+use crate::io::sys::{add_socket_with_id, mod_socket};
 #[cfg(unix)]
 use crate::io::AsIoData;
 #[cfg(feature = "io_timeout")]
@@ -18,10 +21,16 @@ use crate::yield_now::yield_with_io;
 //
 //
 
+// the bits of the closed halves
+const READ_CLOSED: u8 = 1;
+const WRITE_CLOSED: u8 = 2;
+
 #[derive(Debug)]
 pub struct TcpStream {
     _io: io_impl::IoData,
     sys: net::TcpStream,
+    // the closed halves, shared by the cloned streams
+    closed: Arc<AtomicU8>,
     #[cfg(feature = "io_timeout")]
     read_timeout: AtomicDuration,
     #[cfg(feature = "io_timeout")]
@@ -38,6 +47,7 @@ impl TcpStream {
         io_impl::add_socket(&s).map(|io| TcpStream {
             _io: io,
             sys: s,
+            closed: Arc::new(AtomicU8::new(0)),
             #[cfg(feature = "io_timeout")]
             read_timeout: AtomicDuration::new(None),
             #[cfg(feature = "io_timeout")]
@@ -98,7 +108,8 @@ impl TcpStream {
 
     #[cfg(not(windows))]
     pub fn try_clone(&self) -> io::Result<TcpStream> {
-        let s = self.sys.try_clone().and_then(TcpStream::new)?;
+        let mut s = self.sys.try_clone().and_then(TcpStream::new)?;
+        s.closed = self.closed.clone();
         #[cfg(feature = "io_timeout")]
         s.set_read_timeout(self.read_timeout.get()).unwrap();
         #[cfg(feature = "io_timeout")]
@@ -117,13 +128,72 @@ impl TcpStream {
         Ok(TcpStream {
             _io: io_impl::IoData::new(0),
             sys: s,
+            closed: self.closed.clone(),
             read_timeout: AtomicDuration::new(self.read_timeout.get()),
             write_timeout: AtomicDuration::new(self.write_timeout.get()),
         })
     }
 
+    /// Shuts down the read, write, or both halves of this connection.
+    ///
+    /// After the write half is shut down, the peer sees EOF while this side
+    /// can still read the data until the peer closes. The coroutine blocked
+    /// on this stream is woken up to see the EOF or error.
     pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
-        self.sys.shutdown(how)
+        self.sys.shutdown(how)?;
+        let bits = match how {
+            Shutdown::Read => READ_CLOSED,
+            Shutdown::Write => WRITE_CLOSED,
+            Shutdown::Both => READ_CLOSED | WRITE_CLOSED,
+        };
+        self.closed.fetch_or(bits, Ordering::Release);
+        #[cfg(unix)]
+        self._io.wake();
+        Ok(())
+    }
+
+    /// Returns true if the read half is closed.
+    ///
+    /// That is the read half is shut down, or a read has returned EOF.
+    pub fn read_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire) & READ_CLOSED != 0
+    }
+
+    /// Returns true if the write half is closed.
+    ///
+    /// That is the write half is shut down, or a write has failed because
+    /// the peer is gone.
+    pub fn write_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire) & WRITE_CLOSED != 0
+    }
+
+    /// Returns true if the peer has hung up.
+    ///
+    /// It's set by the reactor as soon as the peer shuts down its write
+    /// half, closes or resets the connection, so the idle connections can be
+    /// dropped without waiting for the next io or a timeout.
+    #[cfg(unix)]
+    pub fn peer_hung_up(&self) -> bool {
+        self._io.peer_hung_up()
+    }
+
+    // record the closed read half
+    fn check_read(&self, ret: &io::Result<usize>, len: usize) {
+        if matches!(ret, Ok(0)) && len > 0 {
+            self.closed.fetch_or(READ_CLOSED, Ordering::Release);
+        }
+    }
+
+    // record the closed write half
+    fn check_write<T>(&self, ret: &io::Result<T>) {
+        if let Err(e) = ret {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+            ) {
+                self.closed.fetch_or(WRITE_CLOSED, Ordering::Release);
+            }
+        }
     }
 
     pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
@@ -166,11 +236,62 @@ impl TcpStream {
         self.sys.ttl()
     }
 
+    /// Splits the stream into the owned read and write halves.
+    ///
+    /// Each half has its own registration in the reactor, so one coroutine
+    /// can read while another writes without sharing the stream behind a
+    /// mutex. The write half is shut down when it's dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::io::{Read, Write};
+    /// use may::net::TcpStream;
+    ///
+    /// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+    /// let (mut r, mut w) = stream.into_split().unwrap();
+    /// let h = may::go!(move || w.write_all(b"hello").unwrap());
+    /// let mut buf = [0; 5];
+    /// r.read_exact(&mut buf).unwrap();
+    /// h.join().unwrap();
+    /// ```
+    pub fn into_split(self) -> io::Result<(OwnedReadHalf, OwnedWriteHalf)> {
+        let writer = self.split_writer()?;
+        #[cfg(unix)]
+        mod_socket(self.as_io_data(), true)?;
+        Ok((
+            OwnedReadHalf { stream: self },
+            OwnedWriteHalf { stream: writer },
+        ))
+    }
+
+    /// Splits the stream into the borrowed read and write halves.
+    ///
+    /// The halves can be used by two coroutines in a scope, the stream is
+    /// intact after they are dropped.
+    pub fn split(&mut self) -> io::Result<(ReadHalf<'_>, WriteHalf<'_>)> {
+        let writer = self.split_writer()?;
+        let w = WriteHalf {
+            stream: writer,
+            _marker: PhantomData,
+        };
+        Ok((ReadHalf { stream: self }, w))
+    }
+
+    // a new handle that only waits for the write readiness
+    fn split_writer(&self) -> io::Result<TcpStream> {
+        let writer = self.try_clone()?;
+        #[cfg(unix)]
+        mod_socket(writer.as_io_data(), false)?;
+        Ok(writer)
+    }
+
     // convert std::net::TcpStream to Self without add_socket
     pub(crate) fn from_stream(s: net::TcpStream, io: io_impl::IoData) -> Self {
         TcpStream {
             _io: io,
             sys: s,
+            closed: Arc::new(AtomicU8::new(0)),
             #[cfg(feature = "io_timeout")]
             read_timeout: AtomicDuration::new(None),
             #[cfg(feature = "io_timeout")]
@@ -181,6 +302,50 @@ impl TcpStream {
 
 impl Read for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = self.read_impl(
+            buf,
+            #[cfg(feature = "io_timeout")]
+            self.read_timeout.get(),
+        );
+        self.check_read(&ret, buf.len());
+        ret
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let ret = self.write_impl(
+            buf,
+            #[cfg(feature = "io_timeout")]
+            self.write_timeout.get(),
+        );
+        self.check_write(&ret);
+        ret
+    }
+
+    #[cfg(unix)]
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let ret = self.write_vectored_impl(
+            bufs,
+            #[cfg(feature = "io_timeout")]
+            self.write_timeout.get(),
+        );
+        self.check_write(&ret);
+        ret
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // TcpStream just return Ok(()), no need to yield
+        self.sys.flush()
+    }
+}
+
+impl TcpStream {
+    fn read_impl(
+        &mut self,
+        buf: &mut [u8],
+        #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
+    ) -> io::Result<usize> {
         #[cfg(unix)]
         {
             self._io.reset();
@@ -204,15 +369,17 @@ impl Read for TcpStream {
             self,
             buf,
             #[cfg(feature = "io_timeout")]
-            self.read_timeout.get(),
+            timeout,
         );
         yield_with_io(&reader, reader.is_coroutine);
         reader.done()
     }
-}
 
-impl Write for TcpStream {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    fn write_impl(
+        &mut self,
+        buf: &[u8],
+        #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
+    ) -> io::Result<usize> {
         #[cfg(unix)]
         {
             self._io.reset();
@@ -235,14 +402,18 @@ impl Write for TcpStream {
             self,
             buf,
             #[cfg(feature = "io_timeout")]
-            self.write_timeout.get(),
+            timeout,
         );
         yield_with_io(&writer, writer.is_coroutine);
         writer.done()
     }
 
     #[cfg(unix)]
-    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+    fn write_vectored_impl(
+        &mut self,
+        bufs: &[io::IoSlice<'_>],
+        #[cfg(feature = "io_timeout")] timeout: Option<Duration>,
+    ) -> io::Result<usize> {
         #[cfg(unix)]
         {
             self._io.reset();
@@ -263,18 +434,27 @@ impl Write for TcpStream {
 
         let mut writer = net_impl::SocketWriteVectored::new(
             self,
-            &self.sys,
             bufs,
             #[cfg(feature = "io_timeout")]
-            self.write_timeout.get(),
+            timeout,
         );
         yield_with_io(&writer, writer.is_coroutine);
         writer.done()
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> {
-        // TcpStream just return Ok(()), no need to yield
-        self.sys.flush()
+#[cfg(feature = "io_timeout")]
+impl io_impl::TimeoutIo for TcpStream {
+    fn read_timeout_op(&mut self, buf: &mut [u8], dur: Duration) -> io::Result<usize> {
+        let ret = self.read_impl(buf, Some(dur));
+        self.check_read(&ret, buf.len());
+        ret
+    }
+
+    fn write_timeout_op(&mut self, buf: &[u8], dur: Duration) -> io::Result<usize> {
+        let ret = self.write_impl(buf, Some(dur));
+        self.check_write(&ret);
+        ret
     }
 }
 
@@ -315,13 +495,13 @@ pub struct TcpListener {
 }
 
 impl TcpListener {
-    fn new(s: net::TcpListener) -> io::Result<TcpListener> {
+    pub(super) fn new(s: net::TcpListener) -> io::Result<TcpListener> {
         // only set non blocking in coroutine context
         // we would first call nonblocking io in the coroutine
         // to avoid unnecessary context switch
         s.set_nonblocking(true)?;
 
-        io_impl::add_socket(&s).map(|io| TcpListener { _io: io, sys: s })
+        io_impl::add_listener(&s).map(|io| TcpListener { _io: io, sys: s })
     }
 
     #[inline]
@@ -330,31 +510,100 @@ impl TcpListener {
     }
 
     pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+        super::TcpListenerBuilder::new().bind(addr)
+    }
+
+    /// Binds to all the resolved addresses, see
+    /// [`TcpListenerBuilder::bind_all`].
+    ///
+    /// [`TcpListenerBuilder::bind_all`]: super::TcpListenerBuilder::bind_all
+    pub fn bind_all<A: ToSocketAddrs>(addr: A) -> io::Result<super::MultiListener> {
+        super::TcpListenerBuilder::new().bind_all(addr)
+    }
+
+    /// Creates one listener per shard, all bound to the same address with
+    /// `SO_REUSEPORT` so that the kernel balances new connections among them.
+    ///
+    /// If `shards` is 0, one listener per worker thread is created. The io
+    /// events of the `n`th listener are polled by worker `n % workers`, so a
+    /// coroutine blocking on `accept` of that listener is resumed on its own
+    /// worker instead of contending with others on a single socket.
+    ///
+    /// If the port of `addr` is 0, the port picked for the first listener is
+    /// shared by the rest of them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may::net::TcpListener;
+    ///
+    /// let listeners = TcpListener::bind_reuseport("127.0.0.1:8080", 0).unwrap();
+    /// for listener in listeners {
+    ///     may::go!(move || {
+    ///         for stream in listener.incoming() {
+    ///             // handle the stream
+    ///             drop(stream);
+    ///         }
+    ///     });
+    /// }
+    /// ```
+    #[cfg(unix)]
+    pub fn bind_reuseport<A: ToSocketAddrs>(
+        addr: A,
+        shards: usize,
+    ) -> io::Result<Vec<TcpListener>> {
         use socket2::{Domain, Socket, Type};
-        let mut addrs = addr.to_socket_addrs()?;
-        let addr = addrs.next().unwrap();
-        let listener = match &addr {
-            SocketAddr::V4(_) => Socket::new(Domain::IPV4, Type::STREAM, None)?,
-            SocketAddr::V6(_) => Socket::new(Domain::IPV6, Type::STREAM, None)?,
+        let mut addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no socket address"))?;
+        let shards = if shards == 0 {
+            crate::config::config().get_workers()
+        } else {
+            shards
         };
 
-        // windows not have reuse port but reuse address is not safe
-        listener.set_reuse_address(true)?;
-
-        // #[cfg(unix)]
-        // listener.set_reuse_port(true)?;
+        let mut listeners = Vec::with_capacity(shards);
+        for id in 0..shards {
+            let listener = match &addr {
+                SocketAddr::V4(_) => Socket::new(Domain::IPV4, Type::STREAM, None)?,
+                SocketAddr::V6(_) => Socket::new(Domain::IPV6, Type::STREAM, None)?,
+            };
+            listener.set_reuse_address(true)?;
+            listener.set_reuse_port(true)?;
+            listener.bind(&addr.into())?;
+            listener.listen(1024)?;
+
+            let s: net::TcpListener = listener.into();
+            if addr.port() == 0 {
+                addr = s.local_addr()?;
+            }
+            s.set_nonblocking(true)?;
+            let io = add_socket_with_id(&s, id)?;
+            listeners.push(TcpListener { _io: io, sys: s });
+        }
+        Ok(listeners)
+    }
 
-        listener.bind(&addr.into())?;
-        // for addr in addrs {
-        //     listener.bind(&addr.into())?;
-        // }
-        listener.listen(1024)?;
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.accept_impl(
+            #[cfg(all(unix, feature = "io_timeout"))]
+            None,
+        )
+    }
 
-        let s = listener.into();
-        TcpListener::new(s)
+    /// Accepts a new connection, fails if there is none within `dur`.
+    ///
+    /// The timeout only applies to this call.
+    #[cfg(all(unix, feature = "io_timeout"))]
+    pub fn accept_timeout_op(&self, dur: Duration) -> io::Result<(TcpStream, SocketAddr)> {
+        self.accept_impl(Some(dur))
     }
 
-    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+    fn accept_impl(
+        &self,
+        #[cfg(all(unix, feature = "io_timeout"))] timeout: Option<Duration>,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
         #[cfg(unix)]
         {
             self._io.reset();
@@ -372,12 +621,16 @@ impl TcpListener {
             }
         }
 
-        let mut a = net_impl::TcpListenerAccept::new(self)?;
+        let mut a = net_impl::TcpListenerAccept::new(
+            self,
+            #[cfg(all(unix, feature = "io_timeout"))]
+            timeout,
+        )?;
         yield_with_io(&a, a.is_coroutine);
         a.done()
     }
 
-    pub fn incoming(&self) -> Incoming {
+    pub fn incoming(&self) -> Incoming<'_> {
         Incoming { listener: self }
     }
 
@@ -487,15 +740,139 @@ impl FromRawFd for TcpListener {
 
 impl SplitIo for TcpStream {
     fn split(self) -> io::Result<(SplitReader<Self>, SplitWriter<Self>)> {
-        let writer = self.try_clone()?;
-        #[cfg(unix)]
-        mod_socket(writer.as_io_data(), false)?;
+        let writer = self.split_writer()?;
         #[cfg(unix)]
         mod_socket(self.as_io_data(), true)?;
         Ok((SplitReader::new(self), SplitWriter::new(writer)))
     }
 }
 
+// ===== Split halves =====
+//
+//
+
+/// The owned read half of a [`TcpStream`], created by
+/// [`TcpStream::into_split`].
+#[derive(Debug)]
+pub struct OwnedReadHalf {
+    stream: TcpStream,
+}
+
+/// The owned write half of a [`TcpStream`], created by
+/// [`TcpStream::into_split`].
+///
+/// The write direction of the stream is shut down when it's dropped.
+#[derive(Debug)]
+pub struct OwnedWriteHalf {
+    stream: TcpStream,
+}
+
+/// The borrowed read half of a [`TcpStream`], created by
+/// [`TcpStream::split`].
+#[derive(Debug)]
+pub struct ReadHalf<'a> {
+    stream: &'a mut TcpStream,
+}
+
+/// The borrowed write half of a [`TcpStream`], created by
+/// [`TcpStream::split`].
+#[derive(Debug)]
+pub struct WriteHalf<'a> {
+    stream: TcpStream,
+    _marker: PhantomData<&'a TcpStream>,
+}
+
+impl OwnedReadHalf {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+}
+
+impl OwnedWriteHalf {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+}
+
+impl AsRef<TcpStream> for OwnedReadHalf {
+    fn as_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl AsRef<TcpStream> for OwnedWriteHalf {
+    fn as_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl AsRef<TcpStream> for ReadHalf<'_> {
+    fn as_ref(&self) -> &TcpStream {
+        self.stream
+    }
+}
+
+impl AsRef<TcpStream> for WriteHalf<'_> {
+    fn as_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl Read for OwnedReadHalf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Read for ReadHalf<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for OwnedWriteHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.stream.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Write for WriteHalf<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.stream.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for OwnedWriteHalf {
+    fn drop(&mut self) {
+        // the peer sees EOF, the read half still works
+        self.stream.shutdown(Shutdown::Write).ok();
+    }
+}
+
 // ===== Windows ext =====
 //
 //
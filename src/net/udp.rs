@@ -9,6 +9,64 @@ use crate::io::net as net_impl;
 use crate::sync::atomic_dur::AtomicDuration;
 use crate::yield_now::yield_with_io;
 
+/// A datagram buffer used by the batched io of `UdpSocket`.
+///
+/// For [`UdpSocket::recv_multiple`] the buffer receives a datagram and the
+/// address it comes from. For [`UdpSocket::send_multiple`] the data of the
+/// buffer is sent to its address, or to the connected peer if the address
+/// is `None`.
+///
+/// [`UdpSocket::recv_multiple`]: struct.UdpSocket.html#method.recv_multiple
+/// [`UdpSocket::send_multiple`]: struct.UdpSocket.html#method.send_multiple
+#[derive(Debug, Clone, Default)]
+pub struct MsgBuf {
+    pub(crate) buf: Vec<u8>,
+    pub(crate) len: usize,
+    pub(crate) addr: Option<SocketAddr>,
+}
+
+impl MsgBuf {
+    /// create an empty buffer that can receive a datagram of `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        MsgBuf {
+            buf: vec![0; capacity],
+            len: 0,
+            addr: None,
+        }
+    }
+
+    /// create a buffer that holds the data to be sent to the address
+    pub fn with_data(data: &[u8], addr: Option<SocketAddr>) -> Self {
+        MsgBuf {
+            buf: data.to_vec(),
+            len: data.len(),
+            addr,
+        }
+    }
+
+    /// the received data, or the data to be sent
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// replace the data to be sent
+    pub fn set_data(&mut self, data: &[u8]) {
+        self.buf.clear();
+        self.buf.extend_from_slice(data);
+        self.len = data.len();
+    }
+
+    /// the peer address of the datagram
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// set the peer address that the data is sent to
+    pub fn set_addr(&mut self, addr: Option<SocketAddr>) {
+        self.addr = addr;
+    }
+}
+
 #[derive(Debug)]
 pub struct UdpSocket {
     _io: io_impl::IoData,
@@ -20,7 +78,7 @@ pub struct UdpSocket {
 }
 
 impl UdpSocket {
-    fn new(s: net::UdpSocket) -> io::Result<UdpSocket> {
+    pub(super) fn new(s: net::UdpSocket) -> io::Result<UdpSocket> {
         // only set non blocking in coroutine context
         // we would first call nonblocking io in the coroutine
         // to avoid unnecessary context switch
@@ -126,6 +184,203 @@ impl UdpSocket {
         reader.done()
     }
 
+    /// Receives multiple datagrams, with a single `recvmmsg` call on linux.
+    ///
+    /// Blocks until at least one datagram is received, then fills the rest
+    /// of the buffers with the datagrams that are already arrived. Returns
+    /// the number of the filled buffers, which are at the front of `msgs`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::net::{MsgBuf, UdpSocket};
+    ///
+    /// let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// let addr = socket.local_addr().unwrap();
+    /// let msgs: Vec<_> = (0..4u8).map(|i| MsgBuf::with_data(&[i], Some(addr))).collect();
+    /// assert_eq!(socket.send_multiple(&msgs).unwrap(), 4);
+    ///
+    /// let mut bufs = vec![MsgBuf::new(16); 8];
+    /// let mut cnt = 0;
+    /// while cnt < 4 {
+    ///     cnt += socket.recv_multiple(&mut bufs[cnt..]).unwrap();
+    /// }
+    /// assert_eq!(bufs[3].data(), &[3]);
+    /// assert_eq!(bufs[3].addr(), Some(addr));
+    /// ```
+    pub fn recv_multiple(&self, msgs: &mut [MsgBuf]) -> io::Result<usize> {
+        if msgs.is_empty() {
+            return Ok(0);
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            self._io.reset();
+            // this is an earlier return try for nonblocking read
+            match net_impl::recv_mmsg(&self.sys, msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        // wait for the first datagram
+        let (first, rest) = msgs.split_first_mut().unwrap();
+        let (n, addr) = self.recv_from(&mut first.buf)?;
+        first.len = n;
+        first.addr = Some(addr);
+
+        // the errors of the rest would be reported by the next call
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let cnt = net_impl::recv_mmsg(&self.sys, rest).unwrap_or(0);
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let cnt = rest
+            .iter_mut()
+            .map_while(|m| {
+                let (n, addr) = self.sys.recv_from(&mut m.buf).ok()?;
+                m.len = n;
+                m.addr = Some(addr);
+                Some(())
+            })
+            .count();
+        Ok(cnt + 1)
+    }
+
+    /// Sends multiple datagrams, with a single `sendmmsg` call on linux.
+    ///
+    /// Blocks until at least one datagram is sent, then sends the rest of
+    /// the buffers as long as it would not block. Returns the number of the
+    /// sent buffers, which are at the front of `msgs`.
+    pub fn send_multiple(&self, msgs: &[MsgBuf]) -> io::Result<usize> {
+        if msgs.is_empty() {
+            return Ok(0);
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            self._io.reset();
+            // this is an earlier return try for nonblocking write
+            match net_impl::send_mmsg(&self.sys, msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        // wait for the first datagram to be sent
+        let (first, rest) = msgs.split_first().unwrap();
+        match first.addr {
+            Some(addr) => self.send_to(first.data(), addr)?,
+            None => self.send(first.data())?,
+        };
+
+        // the errors of the rest would be reported by the next call
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let cnt = net_impl::send_mmsg(&self.sys, rest).unwrap_or(0);
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let cnt = rest
+            .iter()
+            .map_while(|m| match m.addr {
+                Some(addr) => self.sys.send_to(m.data(), addr).ok(),
+                None => self.sys.send(m.data()).ok(),
+            })
+            .count();
+        Ok(cnt + 1)
+    }
+
+    /// Sets the `UDP_SEGMENT` option for the generic segmentation offload.
+    ///
+    /// Once set, each buffer passed to the send methods is split by the
+    /// kernel into datagrams of `size` bytes, the last one can be shorter.
+    /// A size of `0` disables the segmentation.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_segment_size(&self, size: u16) -> io::Result<()> {
+        net_impl::set_udp_opt(&self.sys, libc::UDP_SEGMENT, size as libc::c_int)
+    }
+
+    /// Gets the value of the `UDP_SEGMENT` option.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn segment_size(&self) -> io::Result<u16> {
+        net_impl::udp_opt(&self.sys, libc::UDP_SEGMENT).map(|v| v as u16)
+    }
+
+    /// Sets the `UDP_GRO` option for the generic receive offload.
+    ///
+    /// Once set, the kernel may coalesce the datagrams of the same flow into
+    /// one buffer, use [`recv_gro`] to get the segment size of them.
+    ///
+    /// [`recv_gro`]: #method.recv_gro
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_gro(&self, on: bool) -> io::Result<()> {
+        net_impl::set_udp_opt(&self.sys, libc::UDP_GRO, on as libc::c_int)
+    }
+
+    /// Gets the value of the `UDP_GRO` option.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn gro(&self) -> io::Result<bool> {
+        net_impl::udp_opt(&self.sys, libc::UDP_GRO).map(|v| v != 0)
+    }
+
+    /// Receives the coalesced datagrams of a socket with `UDP_GRO` enabled.
+    ///
+    /// Returns the number of bytes read, the source address and the segment
+    /// size. The buffer holds `n / segment` datagrams of `segment` bytes and
+    /// a shorter one if there is a remainder. The segment size is `n` if the
+    /// datagram is not coalesced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::net::UdpSocket;
+    ///
+    /// let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// rx.set_gro(true).unwrap();
+    /// let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// tx.connect(rx.local_addr().unwrap()).unwrap();
+    /// tx.send(b"hello").unwrap();
+    ///
+    /// let mut buf = [0; 64];
+    /// let (n, addr, segment) = rx.recv_gro(&mut buf).unwrap();
+    /// assert_eq!(&buf[..n], b"hello");
+    /// assert_eq!(addr, tx.local_addr().unwrap());
+    /// assert_eq!(segment, 5);
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn recv_gro(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, usize)> {
+        self._io.reset();
+        // this is an earlier return try for nonblocking read
+        match net_impl::recv_gro(&self.sys, buf) {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::UdpRecvGro::new(self, buf);
+        yield_with_io(&reader, reader.is_coroutine);
+        reader.done()
+    }
+
     pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
         #[cfg(unix)]
         {
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use super::cvt;
+use crate::io::CoIo;
+
+/// An eventfd registered to the reactor.
+///
+/// [`read`] parks the coroutine until the counter is non-zero, so the
+/// notifications of other event systems, e.g. the irqfd of a userspace
+/// driver, can be waited in a coroutine directly.
+///
+/// [`read`]: EventFd::read
+///
+/// # Examples
+///
+/// ```rust
+/// use may::os::linux::EventFd;
+///
+/// let mut efd = EventFd::new(0).unwrap();
+/// let notifier = efd.try_clone().unwrap();
+/// let h = may::go!(move || efd.read().unwrap());
+///
+/// std::thread::spawn(move || notifier.write(3).unwrap());
+/// assert_eq!(h.join().unwrap(), 3);
+/// ```
+pub struct EventFd {
+    io: CoIo<File>,
+}
+
+impl EventFd {
+    /// Creates an eventfd with the initial value of the counter.
+    pub fn new(init: u32) -> io::Result<EventFd> {
+        Self::with_flags(init, 0)
+    }
+
+    /// Creates an eventfd in the semaphore mode, each [`read`] returns 1
+    /// and decrements the counter by 1.
+    ///
+    /// [`read`]: EventFd::read
+    pub fn semaphore(init: u32) -> io::Result<EventFd> {
+        Self::with_flags(init, libc::EFD_SEMAPHORE)
+    }
+
+    fn with_flags(init: u32, flags: libc::c_int) -> io::Result<EventFd> {
+        let flags = flags | libc::EFD_NONBLOCK | libc::EFD_CLOEXEC;
+        let fd = cvt(unsafe { libc::eventfd(init, flags) })?;
+        Self::from_file(unsafe { File::from_raw_fd(fd) })
+    }
+
+    fn from_file(file: File) -> io::Result<EventFd> {
+        let io = CoIo::new(file).map_err(io::Error::from)?;
+        Ok(EventFd { io })
+    }
+
+    /// Creates a new handle of the same eventfd, e.g. to write it from
+    /// another thread.
+    pub fn try_clone(&self) -> io::Result<EventFd> {
+        Self::from_file(self.io.inner().try_clone()?)
+    }
+
+    /// Reads the counter and resets it to zero, or decrements it by 1 in the
+    /// semaphore mode, parking the caller until the counter is non-zero.
+    ///
+    /// This works in both coroutine and thread contexts.
+    pub fn read(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        self.io.read_exact(&mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    /// Same as [`read`] but never blocks, returns `None` if the counter is
+    /// zero.
+    ///
+    /// [`read`]: EventFd::read
+    pub fn try_read(&mut self) -> io::Result<Option<u64>> {
+        let mut buf = [0u8; 8];
+        match self.io.inner_mut().read(&mut buf) {
+            Ok(_) => Ok(Some(u64::from_ne_bytes(buf))),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds the value to the counter, it never blocks.
+    ///
+    /// An error of [`io::ErrorKind::WouldBlock`] is returned if the counter
+    /// would overflow.
+    pub fn write(&self, value: u64) -> io::Result<()> {
+        let mut file = self.io.inner();
+        file.write_all(&value.to_ne_bytes())
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for EventFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.io.into_raw_fd()
+    }
+}
+
+impl FromRawFd for EventFd {
+    /// The fd must be an eventfd in the nonblocking mode.
+    unsafe fn from_raw_fd(fd: RawFd) -> EventFd {
+        Self::from_file(File::from_raw_fd(fd)).expect("can't register the eventfd")
+    }
+}
+
+impl fmt::Debug for EventFd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventFd")
+            .field("fd", &self.as_raw_fd())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn eventfd_semaphore() {
+        let mut efd = EventFd::semaphore(2).unwrap();
+        assert_eq!(efd.try_read().unwrap(), Some(1));
+        assert_eq!(efd.read().unwrap(), 1);
+        assert_eq!(efd.try_read().unwrap(), None);
+
+        let tx = efd.try_clone().unwrap();
+        let h = go!(move || efd.read().unwrap());
+        std::thread::sleep(Duration::from_millis(10));
+        tx.write(1).unwrap();
+        assert_eq!(h.join().unwrap(), 1);
+    }
+}
@@ -0,0 +1,17 @@
+//! Linux-specific functionality
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+mod eventfd;
+mod timerfd;
+
+pub use self::eventfd::EventFd;
+pub use self::timerfd::TimerFd;
+
+use std::io;
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
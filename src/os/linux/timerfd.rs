@@ -0,0 +1,163 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Duration;
+
+use super::cvt;
+use crate::io::CoIo;
+
+/// A timerfd on the monotonic clock registered to the reactor.
+///
+/// Unlike `coroutine::sleep`, the expirations of a periodic timer are
+/// counted by the kernel, and the fd can be shared with other event systems.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::os::linux::TimerFd;
+///
+/// let mut timer = TimerFd::new().unwrap();
+/// timer.set_interval(Duration::from_millis(10)).unwrap();
+/// let h = may::go!(move || {
+///     let mut ticks = 0;
+///     while ticks < 3 {
+///         ticks += timer.wait().unwrap();
+///     }
+///     ticks
+/// });
+/// assert!(h.join().unwrap() >= 3);
+/// ```
+pub struct TimerFd {
+    io: CoIo<File>,
+}
+
+fn to_timespec(dur: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: dur.as_secs() as libc::time_t,
+        tv_nsec: dur.subsec_nanos() as libc::c_long,
+    }
+}
+
+fn from_timespec(ts: libc::timespec) -> Duration {
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+impl TimerFd {
+    /// Creates a disarmed timer.
+    pub fn new() -> io::Result<TimerFd> {
+        let flags = libc::TFD_NONBLOCK | libc::TFD_CLOEXEC;
+        let fd = cvt(unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, flags) })?;
+        let io = CoIo::new(unsafe { File::from_raw_fd(fd) }).map_err(io::Error::from)?;
+        Ok(TimerFd { io })
+    }
+
+    /// Arms the timer to expire after `value`, and then every `interval` if
+    /// it's not zero.
+    ///
+    /// A zero `value` disarms the timer. The expirations that are not waited
+    /// yet are discarded.
+    pub fn set(&self, value: Duration, interval: Duration) -> io::Result<()> {
+        let spec = libc::itimerspec {
+            it_interval: to_timespec(interval),
+            it_value: to_timespec(value),
+        };
+        let fd = self.io.as_raw_fd();
+        cvt(unsafe { libc::timerfd_settime(fd, 0, &spec, std::ptr::null_mut()) })?;
+        Ok(())
+    }
+
+    /// Arms the timer to expire once after `dur`.
+    pub fn set_oneshot(&self, dur: Duration) -> io::Result<()> {
+        self.set(dur, Duration::ZERO)
+    }
+
+    /// Arms the timer to expire every `dur`.
+    pub fn set_interval(&self, dur: Duration) -> io::Result<()> {
+        self.set(dur, dur)
+    }
+
+    /// Disarms the timer.
+    pub fn disarm(&self) -> io::Result<()> {
+        self.set(Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Returns the time until the next expiration and the interval, the
+    /// time is zero if the timer is disarmed.
+    pub fn get(&self) -> io::Result<(Duration, Duration)> {
+        let mut spec = std::mem::MaybeUninit::<libc::itimerspec>::uninit();
+        cvt(unsafe { libc::timerfd_gettime(self.io.as_raw_fd(), spec.as_mut_ptr()) })?;
+        let spec = unsafe { spec.assume_init() };
+        Ok((
+            from_timespec(spec.it_value),
+            from_timespec(spec.it_interval),
+        ))
+    }
+
+    /// Parks the caller until the timer expires, returns the number of the
+    /// expirations since the last wait.
+    ///
+    /// This works in both coroutine and thread contexts. It waits forever
+    /// if the timer is disarmed.
+    pub fn wait(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        self.io.read_exact(&mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    /// Same as [`wait`] but never blocks, returns zero if the timer is not
+    /// expired yet.
+    ///
+    /// [`wait`]: TimerFd::wait
+    pub fn try_wait(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        match self.io.inner_mut().read(&mut buf) {
+            Ok(_) => Ok(u64::from_ne_bytes(buf)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for TimerFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.io.into_raw_fd()
+    }
+}
+
+impl fmt::Debug for TimerFd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimerFd")
+            .field("fd", &self.as_raw_fd())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timerfd_oneshot() {
+        let mut timer = TimerFd::new().unwrap();
+        assert_eq!(timer.get().unwrap(), (Duration::ZERO, Duration::ZERO));
+        assert_eq!(timer.try_wait().unwrap(), 0);
+
+        timer.set_oneshot(Duration::from_millis(20)).unwrap();
+        let (left, interval) = timer.get().unwrap();
+        assert!(left > Duration::ZERO && interval.is_zero());
+        let h = go!(move || {
+            let start = std::time::Instant::now();
+            assert_eq!(timer.wait().unwrap(), 1);
+            start.elapsed()
+        });
+        assert!(h.join().unwrap() >= Duration::from_millis(15));
+    }
+}
@@ -1 +1,2 @@
+pub mod linux;
 pub mod unix;
@@ -1,3 +1,6 @@
 #![cfg(unix)]
 
 pub mod net;
+mod process;
+
+pub use self::process::{fork_exec, Child};
@@ -2,6 +2,7 @@
 
 use std::fmt;
 use std::io;
+use std::marker::PhantomData;
 use std::net::Shutdown;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::{self, SocketAddr};
@@ -14,6 +15,8 @@ use crate::io::split_io::{SplitIo, SplitReader, SplitWriter};
 use crate::io::sys::mod_socket;
 use crate::io::sys::net as net_impl;
 use crate::io::CoIo;
+#[cfg(feature = "io_timeout")]
+use crate::io::TimeoutIo;
 use crate::io::{self as io_impl, AsIoData};
 use crate::yield_now::yield_with_io;
 
@@ -84,6 +87,43 @@ impl UnixStream {
         c.done()
     }
 
+    /// Connects to the socket specified by the address.
+    ///
+    /// Unlike [`connect`], this can connect to a linux abstract namespace
+    /// address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may::os::unix::net::UnixStream;
+    /// # #[cfg(target_os = "linux")]
+    /// use std::os::linux::net::SocketAddrExt;
+    /// use std::os::unix::net::SocketAddr;
+    ///
+    /// # #[cfg(target_os = "linux")]
+    /// # {
+    /// let addr = SocketAddr::from_abstract_name(b"may").unwrap();
+    /// let stream = UnixStream::connect_addr(&addr).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// [`connect`]: #method.connect
+    pub fn connect_addr(addr: &SocketAddr) -> io::Result<UnixStream> {
+        if !is_coroutine() {
+            let stream = net::UnixStream::connect_addr(addr)?;
+            return Ok(UnixStream(CoIo::new(stream)?));
+        }
+
+        let mut c = net_impl::UnixStreamConnect::with_addr(addr)?;
+
+        if c.check_connected()? {
+            return c.done();
+        }
+
+        yield_with_io(&c, c.is_coroutine);
+        c.done()
+    }
+
     /// Creates an unnamed pair of connected sockets.
     ///
     /// Returns two `UnixStream`s which are connected to each other.
@@ -267,6 +307,14 @@ impl UnixStream {
         self.0.inner().shutdown(how)
     }
 
+    /// Returns true if the peer has hung up.
+    ///
+    /// It's set by the reactor as soon as the peer shuts down its write
+    /// half or closes the connection.
+    pub fn peer_hung_up(&self) -> bool {
+        self.0.as_io_data().peer_hung_up()
+    }
+
     #[inline]
     pub fn inner(&self) -> &net::UnixStream {
         self.0.inner()
@@ -276,6 +324,64 @@ impl UnixStream {
     pub fn inner_mut(&mut self) -> &mut net::UnixStream {
         self.0.inner_mut()
     }
+
+    /// Splits the stream into the owned read and write halves.
+    ///
+    /// Each half has its own registration in the reactor, so one coroutine
+    /// can read while another writes without sharing the stream behind a
+    /// mutex. The write half is shut down when it's dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    /// use may::os::unix::net::UnixStream;
+    ///
+    /// let (a, mut b) = UnixStream::pair().unwrap();
+    /// let (mut r, mut w) = a.into_split().unwrap();
+    /// let h = may::go!(move || {
+    ///     let mut buf = [0; 5];
+    ///     r.read_exact(&mut buf).unwrap();
+    ///     buf
+    /// });
+    /// w.write_all(b"hello").unwrap();
+    /// // the peer sees EOF after the write half is dropped
+    /// drop(w);
+    /// let mut rsp = Vec::new();
+    /// b.read_to_end(&mut rsp).unwrap();
+    /// assert_eq!(rsp, b"hello");
+    /// // the read half still works
+    /// b.write_all(b"world").unwrap();
+    /// assert_eq!(&h.join().unwrap(), b"world");
+    /// ```
+    pub fn into_split(self) -> io::Result<(OwnedReadHalf, OwnedWriteHalf)> {
+        let writer = self.split_writer()?;
+        mod_socket(self.as_io_data(), true)?;
+        Ok((
+            OwnedReadHalf { stream: self },
+            OwnedWriteHalf { stream: writer },
+        ))
+    }
+
+    /// Splits the stream into the borrowed read and write halves.
+    ///
+    /// The halves can be used by two coroutines in a scope, the stream is
+    /// intact after they are dropped.
+    pub fn split(&mut self) -> io::Result<(ReadHalf<'_>, WriteHalf<'_>)> {
+        let writer = self.split_writer()?;
+        let w = WriteHalf {
+            stream: writer,
+            _marker: PhantomData,
+        };
+        Ok((ReadHalf { stream: self }, w))
+    }
+
+    // a new handle that only waits for the write readiness
+    fn split_writer(&self) -> io::Result<UnixStream> {
+        let writer = self.try_clone()?;
+        mod_socket(writer.as_io_data(), false)?;
+        Ok(writer)
+    }
 }
 
 impl io::Read for UnixStream {
@@ -284,6 +390,17 @@ impl io::Read for UnixStream {
     }
 }
 
+#[cfg(feature = "io_timeout")]
+impl TimeoutIo for UnixStream {
+    fn read_timeout_op(&mut self, buf: &mut [u8], dur: Duration) -> io::Result<usize> {
+        self.0.read_timeout_op(buf, dur)
+    }
+
+    fn write_timeout_op(&mut self, buf: &[u8], dur: Duration) -> io::Result<usize> {
+        self.0.write_timeout_op(buf, dur)
+    }
+}
+
 // impl<'a> io::Read for &'a UnixStream {
 //     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
 //         (&self.0).read(buf)
@@ -295,6 +412,10 @@ impl io::Write for UnixStream {
         self.0.write(buf)
     }
 
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.0.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.0.flush()
     }
@@ -337,13 +458,114 @@ impl io_impl::AsIoData for UnixStream {
 
 impl SplitIo for UnixStream {
     fn split(self) -> io::Result<(SplitReader<Self>, SplitWriter<Self>)> {
-        let writer = self.try_clone()?;
-        mod_socket(writer.as_io_data(), false)?;
+        let writer = self.split_writer()?;
         mod_socket(self.as_io_data(), true)?;
         Ok((SplitReader::new(self), SplitWriter::new(writer)))
     }
 }
 
+/// The owned read half of a [`UnixStream`], created by
+/// [`UnixStream::into_split`].
+#[derive(Debug)]
+pub struct OwnedReadHalf {
+    stream: UnixStream,
+}
+
+/// The owned write half of a [`UnixStream`], created by
+/// [`UnixStream::into_split`].
+///
+/// The write direction of the stream is shut down when it's dropped.
+#[derive(Debug)]
+pub struct OwnedWriteHalf {
+    stream: UnixStream,
+}
+
+/// The borrowed read half of a [`UnixStream`], created by
+/// [`UnixStream::split`].
+#[derive(Debug)]
+pub struct ReadHalf<'a> {
+    stream: &'a mut UnixStream,
+}
+
+/// The borrowed write half of a [`UnixStream`], created by
+/// [`UnixStream::split`].
+#[derive(Debug)]
+pub struct WriteHalf<'a> {
+    stream: UnixStream,
+    _marker: PhantomData<&'a UnixStream>,
+}
+
+impl AsRef<UnixStream> for OwnedReadHalf {
+    fn as_ref(&self) -> &UnixStream {
+        &self.stream
+    }
+}
+
+impl AsRef<UnixStream> for OwnedWriteHalf {
+    fn as_ref(&self) -> &UnixStream {
+        &self.stream
+    }
+}
+
+impl AsRef<UnixStream> for ReadHalf<'_> {
+    fn as_ref(&self) -> &UnixStream {
+        self.stream
+    }
+}
+
+impl AsRef<UnixStream> for WriteHalf<'_> {
+    fn as_ref(&self) -> &UnixStream {
+        &self.stream
+    }
+}
+
+impl io::Read for OwnedReadHalf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(&mut self.stream, buf)
+    }
+}
+
+impl io::Read for ReadHalf<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(&mut *self.stream, buf)
+    }
+}
+
+impl io::Write for OwnedWriteHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut self.stream, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        io::Write::write_vectored(&mut self.stream, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(&mut self.stream)
+    }
+}
+
+impl io::Write for WriteHalf<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut self.stream, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        io::Write::write_vectored(&mut self.stream, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(&mut self.stream)
+    }
+}
+
+impl Drop for OwnedWriteHalf {
+    fn drop(&mut self) {
+        // the peer sees EOF, the read half still works
+        self.stream.shutdown(Shutdown::Write).ok();
+    }
+}
+
 /// A structure representing a Unix domain socket server.
 ///
 /// # Examples
@@ -406,6 +628,32 @@ impl UnixListener {
         Ok(UnixListener(CoIo::new(listener)?))
     }
 
+    /// Creates a new `UnixListener` bound to the specified address.
+    ///
+    /// Unlike [`bind`], this can bind to a linux abstract namespace address,
+    /// which doesn't leave a socket file on the filesystem.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may::os::unix::net::UnixListener;
+    /// # #[cfg(target_os = "linux")]
+    /// use std::os::linux::net::SocketAddrExt;
+    /// use std::os::unix::net::SocketAddr;
+    ///
+    /// # #[cfg(target_os = "linux")]
+    /// # {
+    /// let addr = SocketAddr::from_abstract_name(b"may").unwrap();
+    /// let listener = UnixListener::bind_addr(&addr).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// [`bind`]: #method.bind
+    pub fn bind_addr(addr: &SocketAddr) -> io::Result<UnixListener> {
+        let listener = net::UnixListener::bind_addr(addr)?;
+        Ok(UnixListener(CoIo::new(listener)?))
+    }
+
     /// Accepts a new incoming connection to this listener.
     ///
     /// This function will block the calling thread until a new Unix connection
@@ -524,7 +772,7 @@ impl UnixListener {
     ///     }
     /// }
     /// ```
-    pub fn incoming(&self) -> Incoming {
+    pub fn incoming(&self) -> Incoming<'_> {
         Incoming { listener: self }
     }
 }
@@ -597,7 +845,7 @@ impl<'a> Iterator for Incoming<'a> {
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (usize::max_value(), None)
+        (usize::MAX, None)
     }
 }
 
@@ -651,6 +899,31 @@ impl UnixDatagram {
         Ok(UnixDatagram(CoIo::new(datagram)?))
     }
 
+    /// Creates a Unix datagram socket bound to the given address.
+    ///
+    /// Unlike [`bind`], this can bind to a linux abstract namespace address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may::os::unix::net::UnixDatagram;
+    /// # #[cfg(target_os = "linux")]
+    /// use std::os::linux::net::SocketAddrExt;
+    /// use std::os::unix::net::SocketAddr;
+    ///
+    /// # #[cfg(target_os = "linux")]
+    /// # {
+    /// let addr = SocketAddr::from_abstract_name(b"may").unwrap();
+    /// let sock = UnixDatagram::bind_addr(&addr).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// [`bind`]: #method.bind
+    pub fn bind_addr(addr: &SocketAddr) -> io::Result<UnixDatagram> {
+        let datagram = net::UnixDatagram::bind_addr(addr)?;
+        Ok(UnixDatagram(CoIo::new(datagram)?))
+    }
+
     /// Creates a Unix Datagram socket which is not bound to any address.
     ///
     /// # Examples
@@ -724,6 +997,24 @@ impl UnixDatagram {
         self.0.inner().connect(path)
     }
 
+    /// Connects the socket to the specified address, which can be a linux
+    /// abstract namespace address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may::os::unix::net::UnixDatagram;
+    ///
+    /// let bound = UnixDatagram::bind("/path/to/the/socket").unwrap();
+    /// let addr = bound.local_addr().unwrap();
+    ///
+    /// let sock = UnixDatagram::unbound().unwrap();
+    /// sock.connect_addr(&addr).expect("Couldn't connect");
+    /// ```
+    pub fn connect_addr(&self, addr: &SocketAddr) -> io::Result<()> {
+        self.0.inner().connect_addr(addr)
+    }
+
     /// Creates a new independently owned handle to the underlying socket.
     ///
     /// The returned `UnixDatagram` is a reference to the same socket that this
@@ -884,7 +1175,46 @@ impl UnixDatagram {
             }
         }
 
-        let mut writer = net_impl::UnixSendTo::new(self, buf, path.as_ref())?;
+        let target = net_impl::SendTarget::Path(path.as_ref());
+        let mut writer = net_impl::UnixSendTo::new(self, buf, target)?;
+        yield_with_io(&writer, writer.is_coroutine);
+        writer.done()
+    }
+
+    /// Sends data on the socket to the specified address, which can be a
+    /// linux abstract namespace address.
+    ///
+    /// On success, returns the number of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use may::os::unix::net::UnixDatagram;
+    ///
+    /// let bound = UnixDatagram::bind("/path/to/the/socket").unwrap();
+    /// let addr = bound.local_addr().unwrap();
+    ///
+    /// let sock = UnixDatagram::unbound().unwrap();
+    /// sock.send_to_addr(b"omelette au fromage", &addr).expect("send_to_addr function failed");
+    /// ```
+    pub fn send_to_addr(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        self.0.io_reset();
+        // this is an earlier return try for nonblocking write
+        match self.0.inner().send_to_addr(buf, addr) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let target = net_impl::SendTarget::Addr(addr);
+        let mut writer = net_impl::UnixSendTo::new(self, buf, target)?;
         yield_with_io(&writer, writer.is_coroutine);
         writer.done()
     }
@@ -1394,4 +1724,38 @@ mod test {
     fn abstract_namespace_not_allowed() {
         assert!(UnixStream::connect("\0asdf").is_err());
     }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn abstract_namespace() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("may-test-{}", std::process::id());
+        let addr = or_panic!(SocketAddr::from_abstract_name(name.as_bytes()));
+        let listener = or_panic!(UnixListener::bind_addr(&addr));
+        let local = or_panic!(listener.local_addr());
+        assert_eq!(local.as_abstract_name(), Some(name.as_bytes()));
+
+        let h = go!(move || {
+            let mut stream = or_panic!(UnixStream::connect_addr(&addr));
+            or_panic!(stream.write_all(b"hello"));
+        });
+        let mut stream = or_panic!(listener.accept()).0;
+        let mut buf = vec![];
+        or_panic!(stream.read_to_end(&mut buf));
+        assert_eq!(buf, b"hello");
+        h.join().unwrap();
+
+        let dgram_name = format!("may-dgram-{}", std::process::id());
+        let dgram_addr = or_panic!(SocketAddr::from_abstract_name(dgram_name.as_bytes()));
+        let server = or_panic!(UnixDatagram::bind_addr(&dgram_addr));
+        let h = go!(move || {
+            let client = or_panic!(UnixDatagram::unbound());
+            or_panic!(client.send_to_addr(b"ping", &dgram_addr));
+        });
+        let mut buf = [0; 4];
+        assert_eq!(or_panic!(server.recv(&mut buf)), 4);
+        assert_eq!(&buf, b"ping");
+        h.join().unwrap();
+    }
 }
@@ -0,0 +1,147 @@
+//! spawning the child processes from coroutines
+//!
+//! a plain `fork` in a coroutine duplicates the reactor of the scheduler, the
+//! epoll or kqueue fd and the wakeup fds are shared with the child, and a
+//! child that keeps using them steals the events of the parent. the
+//! reactor fds are all created with `CLOEXEC`, so [`fork_exec`] spawns the
+//! child by `fork` + `exec` of the std `Command`, the child never runs any
+//! coroutine code and doesn't inherit the reactor.
+//!
+//! no `SIGCHLD` handler is installed, [`Child::wait`] waits for its own pid
+//! in the blocking pool, so the other code that reaps its children is not
+//! affected.
+
+use std::fmt;
+use std::io;
+use std::process::{self, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus};
+
+use crate::blocking_pool::spawn_blocking;
+use crate::io::CoIo;
+
+/// A child process spawned by [`fork_exec`].
+///
+/// The piped stdio of the child are coroutine io, reading or writing them
+/// parks the coroutine instead of the worker thread.
+pub struct Child {
+    inner: process::Child,
+    /// The handle for writing to the stdin of the child, if it's piped.
+    pub stdin: Option<CoIo<ChildStdin>>,
+    /// The handle for reading from the stdout of the child, if it's piped.
+    pub stdout: Option<CoIo<ChildStdout>>,
+    /// The handle for reading from the stderr of the child, if it's piped.
+    pub stderr: Option<CoIo<ChildStderr>>,
+}
+
+/// Spawns the command as a child process.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+/// use std::process::{Command, Stdio};
+/// use may::os::unix::fork_exec;
+///
+/// let h = may::go!(|| {
+///     let mut cmd = Command::new("echo");
+///     cmd.arg("hello").stdout(Stdio::piped());
+///     let mut child = fork_exec(&mut cmd).unwrap();
+///     let mut out = String::new();
+///     child.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+///     assert!(child.wait().unwrap().success());
+///     out
+/// });
+/// assert_eq!(h.join().unwrap(), "hello\n");
+/// ```
+pub fn fork_exec(cmd: &mut Command) -> io::Result<Child> {
+    let mut inner = cmd.spawn()?;
+    let stdin = inner.stdin.take().map(CoIo::new).transpose()?;
+    let stdout = inner.stdout.take().map(CoIo::new).transpose()?;
+    let stderr = inner.stderr.take().map(CoIo::new).transpose()?;
+    Ok(Child {
+        inner,
+        stdin,
+        stdout,
+        stderr,
+    })
+}
+
+// wait for the child to exit without reaping it
+fn wait_exited(pid: u32) -> io::Result<()> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    loop {
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        let ret = unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+impl Child {
+    /// Returns the os assigned process id of the child.
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    /// Kills the child with `SIGKILL`.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.inner.kill()
+    }
+
+    /// Returns the exit status if the child has exited, this never blocks.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.inner.try_wait()
+    }
+
+    /// Waits for the child to exit and returns its exit status.
+    ///
+    /// The stdin of the child is closed before waiting. The wait is done in
+    /// the blocking pool, so only the calling coroutine is parked.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        if let Some(status) = self.inner.try_wait()? {
+            return Ok(status);
+        }
+        let pid = self.inner.id();
+        spawn_blocking(move || wait_exited(pid))?;
+        // it's exited, the reap never blocks
+        self.inner.wait()
+    }
+}
+
+impl fmt::Debug for Child {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Child").field("id", &self.id()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+
+    #[test]
+    fn fork_exec_pipes() {
+        let h = go!(|| {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "read x; echo \"got $x\"; exit 3"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped());
+            let mut child = fork_exec(&mut cmd).unwrap();
+            child.stdin.as_mut().unwrap().write_all(b"hi\n").unwrap();
+            let mut out = String::new();
+            let mut stdout = child.stdout.take().unwrap();
+            stdout.read_to_string(&mut out).unwrap();
+            let status = child.wait().unwrap();
+            (out, status.code())
+        });
+        let (out, code) = h.join().unwrap();
+        assert_eq!(out, "got hi\n");
+        assert_eq!(code, Some(3));
+    }
+}
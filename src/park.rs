@@ -6,7 +6,9 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::cancel::Cancel;
-use crate::coroutine_impl::{co_cancel_data, run_coroutine, CoroutineImpl, EventSource};
+use crate::coroutine_impl::{
+    co_cancel_data, home_scheduler, run_coroutine, CoroutineImpl, EventSource,
+};
 use crate::scheduler::get_scheduler;
 use crate::sync::atomic_dur::AtomicDuration;
 use crate::sync::AtomicOption;
@@ -157,7 +159,11 @@ impl Park {
             if b_sync {
                 run_coroutine(co);
             } else {
-                get_scheduler().schedule(co);
+                // the unpark may come from a thread that is not managed by
+                // any runtime, don't start the global one for it
+                home_scheduler(&co)
+                    .unwrap_or_else(get_scheduler)
+                    .schedule_woken(co);
             }
         }
     }
@@ -187,7 +193,7 @@ impl Park {
         // the subscribe would re-check it
         yield_with(self);
         // clear the trigger state
-        self.check_park();
+        let unparked = !self.check_park();
         // remove timer handle
         self.remove_timeout_handle();
 
@@ -196,6 +202,8 @@ impl Park {
 
         if let Some(err) = get_co_para() {
             match err.kind() {
+                // the unpark comes along with the timeout, don't lose it
+                ErrorKind::TimedOut if unparked => return Ok(()),
                 ErrorKind::TimedOut => return Err(ParkError::Timeout),
                 ErrorKind::Other => return Err(ParkError::Canceled),
                 _ => unreachable!("unexpected return error kind"),
@@ -205,7 +213,7 @@ impl Park {
         Ok(())
     }
 
-    fn delay_drop(&self) -> DropGuard {
+    fn delay_drop(&self) -> DropGuard<'_> {
         self.wait_kernel.store(true, Ordering::Release);
         DropGuard(self)
     }
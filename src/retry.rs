@@ -0,0 +1,293 @@
+//! retry with exponential backoff
+//!
+//! [`retry`] runs the operation until it succeeds or the [`RetryPolicy`]
+//! gives up. the delays between the attempts grow exponentially with a
+//! random jitter, so the clients that fail at the same time don't retry at
+//! the same time. the delays are slept by the coroutine timer, and in a
+//! thread context by the thread itself.
+//!
+//! with a [`CancellationToken`] the retry stops as soon as the token is
+//! cancelled, including in the middle of a delay.
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::sleep::sleep;
+use crate::sync::CancellationToken;
+
+/// The policy of [`retry`].
+///
+/// The defaults are 5 attempts, 100ms for the first delay, doubled each
+/// time up to 30s, a 20% jitter and no deadline.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::retry::{retry, RetryPolicy};
+/// use may::sync::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let policy = RetryPolicy::new()
+///     .initial_delay(Duration::from_millis(1))
+///     .max_attempts(3)
+///     .deadline(Duration::from_secs(1))
+///     .cancel_token(token.clone());
+///
+/// let mut attempts = 0;
+/// let ret: Result<_, _> = retry(&policy, || {
+///     attempts += 1;
+///     if attempts < 3 {
+///         Err("unavailable")
+///     } else {
+///         Ok(attempts)
+///     }
+/// });
+/// assert_eq!(ret.unwrap(), 3);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_attempts: usize,
+    deadline: Option<Duration>,
+    token: Option<CancellationToken>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default policy.
+    pub fn new() -> Self {
+        RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: 5,
+            deadline: None,
+            token: None,
+        }
+    }
+
+    /// Sets the delay after the first failed attempt.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Sets the max delay between two attempts.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Sets the factor that the delay grows by after each attempt, at least
+    /// 1.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Sets the part of the delay that is random, in the range of `[0, 1]`.
+    ///
+    /// With a jitter of 0.2 the delays are between 80% and 100% of the
+    /// exponential ones.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the max number of the attempts, 0 means no limit.
+    pub fn max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Sets the total time for all the attempts.
+    ///
+    /// It gives up instead of sleeping past the deadline, an attempt that is
+    /// running when the deadline expires is not interrupted.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stops the retry when the token is cancelled.
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    // the delay after the failed attempt, counted from 0
+    fn delay(&self, attempt: usize) -> Duration {
+        let exp = self.multiplier.powi(attempt.min(i32::MAX as usize) as i32);
+        let ns = self.initial_delay.as_nanos() as f64 * exp;
+        let ns = ns.min(self.max_delay.as_nanos() as f64);
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        Duration::from_nanos((ns * (1.0 - self.jitter * random)) as u64)
+    }
+}
+
+/// The error of [`retry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryError<E> {
+    /// The attempts or the deadline are used up, with the error of the last
+    /// attempt.
+    Exhausted(E),
+    /// The error is not retryable, see [`retry_if`].
+    Permanent(E),
+    /// The token is cancelled, with the error of the last attempt if any.
+    Cancelled(Option<E>),
+}
+
+impl<E> RetryError<E> {
+    /// Returns the error of the last attempt if any.
+    pub fn into_inner(self) -> Option<E> {
+        match self {
+            RetryError::Exhausted(e) | RetryError::Permanent(e) => Some(e),
+            RetryError::Cancelled(e) => e,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetryError::Exhausted(e) => write!(f, "retry exhausted, last error: {}", e),
+            RetryError::Permanent(e) => write!(f, "permanent error: {}", e),
+            RetryError::Cancelled(Some(e)) => write!(f, "retry cancelled, last error: {}", e),
+            RetryError::Cancelled(None) => write!(f, "retry cancelled"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RetryError::Exhausted(e) | RetryError::Permanent(e) => Some(e),
+            RetryError::Cancelled(e) => e.as_ref().map(|e| e as &(dyn Error + 'static)),
+        }
+    }
+}
+
+/// Runs the operation until it succeeds or the policy gives up.
+pub fn retry<T, E, F>(policy: &RetryPolicy, op: F) -> Result<T, RetryError<E>>
+where
+    F: FnMut() -> Result<T, E>,
+{
+    retry_if(policy, op, |_| true)
+}
+
+/// Same as [`retry`], but only the errors that `retryable` returns true for
+/// are retried, the others are returned as [`RetryError::Permanent`].
+pub fn retry_if<T, E, F, P>(
+    policy: &RetryPolicy,
+    mut op: F,
+    mut retryable: P,
+) -> Result<T, RetryError<E>>
+where
+    F: FnMut() -> Result<T, E>,
+    P: FnMut(&E) -> bool,
+{
+    let start = Instant::now();
+    let cancelled = || policy.token.as_ref().is_some_and(|t| t.is_cancelled());
+    let mut attempt = 0;
+    loop {
+        if cancelled() {
+            return Err(RetryError::Cancelled(None));
+        }
+        let err = match op() {
+            Ok(t) => return Ok(t),
+            Err(e) if !retryable(&e) => return Err(RetryError::Permanent(e)),
+            Err(e) => e,
+        };
+        attempt += 1;
+        if policy.max_attempts != 0 && attempt >= policy.max_attempts {
+            return Err(RetryError::Exhausted(err));
+        }
+
+        let delay = policy.delay(attempt - 1);
+        if let Some(deadline) = policy.deadline {
+            if start.elapsed() + delay >= deadline {
+                return Err(RetryError::Exhausted(err));
+            }
+        }
+        match policy.token {
+            Some(ref token) => {
+                if token.sleep(delay).is_err() {
+                    return Err(RetryError::Cancelled(Some(err)));
+                }
+            }
+            None => sleep(delay),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_backoff() {
+        let policy = RetryPolicy::new()
+            .initial_delay(Duration::from_millis(10))
+            .jitter(0.0)
+            .max_attempts(3);
+        assert_eq!(policy.delay(0), Duration::from_millis(10));
+        assert_eq!(policy.delay(2), Duration::from_millis(40));
+        let capped = policy.clone().max_delay(Duration::from_millis(15));
+        assert_eq!(capped.delay(5), Duration::from_millis(15));
+
+        let h = go!(move || {
+            let start = Instant::now();
+            let mut n = 0;
+            let ret = retry(&policy, || {
+                n += 1;
+                Err::<(), _>(n)
+            });
+            assert_eq!(ret, Err(RetryError::Exhausted(3)));
+            // two delays of 10ms and 20ms
+            assert!(start.elapsed() >= Duration::from_millis(30));
+
+            let ret = retry_if(
+                &policy,
+                || Err::<(), _>("bad request"),
+                |e| *e != "bad request",
+            );
+            assert_eq!(ret, Err(RetryError::Permanent("bad request")));
+        });
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn retry_deadline_and_cancel() {
+        let policy = RetryPolicy::new()
+            .initial_delay(Duration::from_millis(20))
+            .jitter(0.0)
+            .max_attempts(0)
+            .deadline(Duration::from_millis(50));
+        let start = Instant::now();
+        let ret = retry(&policy, || Err::<(), _>(()));
+        assert_eq!(ret, Err(RetryError::Exhausted(())));
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        let token = CancellationToken::new();
+        let policy = RetryPolicy::new()
+            .initial_delay(Duration::from_secs(10))
+            .cancel_token(token.clone());
+        let h = go!(move || retry(&policy, || Err::<(), _>("down")));
+        crate::sleep::sleep(Duration::from_millis(20));
+        token.cancel();
+        assert_eq!(h.join().unwrap(), Err(RetryError::Cancelled(Some("down"))));
+    }
+}
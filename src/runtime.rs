@@ -0,0 +1,195 @@
+//! independent scheduler instances
+//!
+//! the coroutines spawned by `go!` run on the global scheduler, which is
+//! configured once for the whole process by [`config`]. a [`Runtime`] has
+//! its own worker threads, timer thread and io selectors, so a library can
+//! run its coroutines without touching the global configuration.
+//!
+//! the coroutines spawned inside a runtime stay in it, including the ones
+//! spawned by `go!`, and they are always resumed by the threads of their own
+//! runtime, even if they are woken up by a coroutine of another one.
+//!
+//! [`config`]: crate::config
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::autoscale::{self, AutoscaleConfig};
+use crate::coroutine::Builder;
+use crate::join::JoinHandle;
+use crate::scheduler::{self, Scheduler};
+use crate::steal::StealPolicy;
+
+/// The configuration of a [`Runtime`].
+///
+/// The stack size, pool capacity and the other settings of [`Config`] are
+/// still shared with the global scheduler.
+///
+/// [`Config`]: crate::Config
+#[derive(Clone)]
+pub struct RuntimeConfig {
+    workers: usize,
+    max_workers: usize,
+    io_threads: usize,
+    steal_policy: Option<Arc<dyn StealPolicy>>,
+    autoscale: Option<AutoscaleConfig>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuntimeConfig {
+    /// Creates a config with a worker per cpu core and no io threads.
+    pub fn new() -> Self {
+        RuntimeConfig {
+            workers: num_cpus::get().min(64),
+            max_workers: 0,
+            io_threads: 0,
+            steal_policy: None,
+            autoscale: None,
+        }
+    }
+
+    /// Sets the number of the worker threads, at least one.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets the max number of the worker threads that [`Runtime::set_workers`]
+    /// can adjust to, the default is the number of the workers.
+    pub fn max_workers(mut self, max_workers: usize) -> Self {
+        self.max_workers = max_workers;
+        self
+    }
+
+    /// Sets the number of the dedicated io threads.
+    pub fn io_threads(mut self, io_threads: usize) -> Self {
+        self.io_threads = io_threads;
+        self
+    }
+
+    /// Sets the policy of choosing the worker to steal the coroutines from,
+    /// the default is the one of the global [`Config`].
+    ///
+    /// [`Config`]: crate::Config
+    pub fn steal_policy<P: StealPolicy>(mut self, policy: P) -> Self {
+        self.steal_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Adjusts the number of the workers by the run queue latency, up to the
+    /// max workers, see [`AutoscaleConfig`].
+    pub fn autoscale(mut self, autoscale: AutoscaleConfig) -> Self {
+        self.autoscale = Some(autoscale);
+        self
+    }
+}
+
+impl fmt::Debug for RuntimeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RuntimeConfig")
+            .field("workers", &self.workers)
+            .field("max_workers", &self.max_workers)
+            .field("io_threads", &self.io_threads)
+            .field("autoscale", &self.autoscale)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A handle of an independent scheduler.
+///
+/// The threads of a runtime are never stopped, they live until the process
+/// exits, so create the runtimes at startup instead of per request.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::{Runtime, RuntimeConfig};
+///
+/// let rt = Runtime::new(RuntimeConfig::new().workers(2));
+/// let h = unsafe {
+///     rt.spawn(|| {
+///         // `go!` spawns into the same runtime
+///         may::go!(|| 21 * 2).join().unwrap()
+///     })
+/// };
+/// assert_eq!(h.join().unwrap(), 42);
+/// ```
+#[derive(Clone, Copy)]
+pub struct Runtime {
+    sched: &'static Scheduler,
+}
+
+impl Runtime {
+    /// Creates a runtime and starts its threads.
+    pub fn new(config: RuntimeConfig) -> Runtime {
+        let max_workers = config.max_workers.max(config.workers);
+        let steal = config
+            .steal_policy
+            .unwrap_or_else(|| crate::config().get_steal_policy());
+        let sched = Scheduler::new(max_workers, config.io_threads, steal);
+        let sched: &'static Scheduler = Box::leak(sched);
+        sched.set_active_workers(config.workers);
+        scheduler::start_threads(sched);
+        if let Some(config) = config.autoscale {
+            autoscale::start(sched, config);
+        }
+        Runtime { sched }
+    }
+
+    /// Spawns a coroutine into the runtime.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`coroutine::spawn`].
+    ///
+    /// [`coroutine::spawn`]: crate::coroutine::spawn
+    #[track_caller]
+    pub unsafe fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with(Builder::new(), f).unwrap()
+    }
+
+    /// Spawns a coroutine with the builder into the runtime.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Builder::spawn`].
+    #[track_caller]
+    pub unsafe fn spawn_with<F, T>(&self, builder: Builder, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        builder.scheduler(self.sched).spawn(f)
+    }
+
+    /// Returns the number of the worker threads.
+    pub fn workers(&self) -> usize {
+        self.sched.active_workers()
+    }
+
+    /// Adjusts the number of the worker threads, up to the max workers.
+    ///
+    /// The same as `config().set_workers()` for the global scheduler. The
+    /// autoscaler, if any, keeps adjusting it from the new number.
+    pub fn set_workers(&self, workers: usize) {
+        self.sched.set_active_workers(workers)
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Runtime")
+            .field("workers", &self.workers())
+            .finish()
+    }
+}
@@ -5,11 +5,13 @@ use std::sync::{Arc, Once};
 use std::thread;
 use std::time::Duration;
 
-use crate::config::config;
-use crate::coroutine_impl::{run_coroutine, CoroutineImpl};
+use crate::config::{config, WakeOrder};
+use crate::coroutine_impl::{mark_ready, route, run_coroutine, CoroutineImpl};
 use crate::io::{EventLoop, Selector};
 use crate::likely::likely;
+use crate::metrics::{self, Counter};
 use crate::pool::CoroutinePool;
+use crate::steal::StealPolicy;
 use crate::sync::queue::mpsc_seg_queue::SegQueue;
 use crate::sync::queue::tokio_queue::{Local, Steal};
 use crate::sync::AtomicOption;
@@ -23,7 +25,7 @@ use crate::yield_now::set_co_para;
 pub static WORKER_ID: Cell<usize> = Cell::new(!1);
 
 #[cfg(not(nightly))]
-thread_local! { pub static WORKER_ID: Cell<usize> = Cell::new(!1); }
+thread_local! { pub static WORKER_ID: Cell<usize> = const { Cell::new(!1) }; }
 
 // here we use Arc<AtomicOption<>> for that in the select implementation
 // other event may try to consume the coroutine while timer thread consume it
@@ -32,14 +34,53 @@ type TimerThread = timeout_list::TimerThread<TimerData>;
 
 static mut SCHED: *const Scheduler = std::ptr::null();
 
+// the scheduler of the threads started by a runtime, null for the others
+#[cfg(nightly)]
+#[thread_local]
+static CURRENT_SCHED: Cell<*const Scheduler> = Cell::new(std::ptr::null());
+
+#[cfg(not(nightly))]
+thread_local! {
+    static CURRENT_SCHED: Cell<*const Scheduler> = const { Cell::new(std::ptr::null()) };
+}
+
+#[inline]
+fn current_sched() -> *const Scheduler {
+    #[cfg(nightly)]
+    return CURRENT_SCHED.get();
+    #[cfg(not(nightly))]
+    CURRENT_SCHED.with(|s| s.get())
+}
+
+pub(crate) fn set_current_sched(s: &'static Scheduler) {
+    #[cfg(nightly)]
+    CURRENT_SCHED.set(s);
+    #[cfg(not(nightly))]
+    CURRENT_SCHED.with(|c| c.set(s));
+}
+
 #[inline(never)]
 fn init_scheduler() {
-    let workers = config().get_workers();
-    let b: Box<Scheduler> = Scheduler::new(workers);
+    let workers = config().get_max_workers();
+    let io_threads = config().get_io_threads();
+    let steal = config().get_steal_policy();
+    let b: Box<Scheduler> = Scheduler::new(workers, io_threads, steal);
+    b.set_active_workers(config().get_workers());
     unsafe { SCHED = Box::into_raw(b) };
+    start_threads(unsafe { &*SCHED });
+    if let Some(autoscale) = config().get_autoscale() {
+        crate::autoscale::start(unsafe { &*SCHED }, autoscale);
+    }
+}
+
+// start the timer thread, the workers and the dedicated io threads
+pub(crate) fn start_threads(s: &'static Scheduler) {
+    let workers = s.workers();
+    let io_threads = s.io_threads;
 
     // timer thread
     thread::spawn(move || {
+        set_current_sched(s);
         // timer function
         let timer_event_handler = |c: Arc<AtomicOption<CoroutineImpl>>| {
             // just re-push the co to the visit list
@@ -51,7 +92,6 @@ fn init_scheduler() {
             }
         };
 
-        let s = unsafe { &*SCHED };
         s.timer_thread.run(&timer_event_handler);
     });
 
@@ -60,14 +100,28 @@ fn init_scheduler() {
     for (id, core) in (0..workers).zip(core_ids.into_iter().cycle()) {
         thread::spawn(move || {
             core_affinity::set_for_current(core);
-            let s = unsafe { &*SCHED };
+            set_current_sched(s);
+            s.event_loop.run(id);
+        });
+    }
+
+    // dedicated io threads, their selectors follow the workers ones
+    for id in workers..workers + io_threads {
+        thread::spawn(move || {
+            set_current_sched(s);
             s.event_loop.run(id);
         });
     }
 }
 
+// the scheduler of the current thread, the global one is initialized if
+// the thread is not started by a runtime
 #[inline]
 pub fn get_scheduler() -> &'static Scheduler {
+    let cur = current_sched();
+    if !cur.is_null() {
+        return unsafe { &*cur };
+    }
     unsafe {
         if likely(!SCHED.is_null()) {
             return &*SCHED;
@@ -78,56 +132,181 @@ pub fn get_scheduler() -> &'static Scheduler {
     unsafe { &*SCHED }
 }
 
+// the global scheduler if it's started
+pub(crate) fn started_scheduler() -> Option<&'static Scheduler> {
+    unsafe { SCHED.as_ref() }
+}
+
+// return true if the coroutines of the scheduler can run on this thread
 #[inline]
-fn steal_local<T>(stealer: &Steal<T>, local: &Local<T>) -> Option<T> {
-    match stealer.steal_into(local) {
-        Ok(t) => Some(t),
+pub(crate) fn is_current_scheduler(s: *const Scheduler) -> bool {
+    let cur = current_sched();
+    if !cur.is_null() {
+        return std::ptr::eq(cur, s);
+    }
+    // the threads not started by a runtime belong to the global one
+    std::ptr::eq(unsafe { SCHED }, s)
+}
+
+// get the worker id of the current thread, none for non worker threads
+#[inline]
+pub fn current_worker_id() -> Option<usize> {
+    #[cfg(nightly)]
+    let id = WORKER_ID.get();
+    #[cfg(not(nightly))]
+    let id = WORKER_ID.with(|id| id.get());
+
+    if id != !1 {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+// every this many runs the worker takes a coroutine from its global queue
+// first, so the coroutines sent by other threads are not starved by the ones
+// that keep rescheduling into the local queue
+const FAIRNESS_TICK: usize = 61;
+
+// the max coroutines in a row that the worker runs from its lifo slot, so
+// the ones that keep waking each other can't starve the run queue
+const MAX_LIFO_RUNS: usize = 3;
+
+// the scheduler that the coroutine belongs to if it's not this one
+#[inline]
+fn foreign_home(co: &CoroutineImpl, s: &Scheduler) -> Option<&'static Scheduler> {
+    match route(co) {
+        Some(route) if !std::ptr::eq(route.sched, s) => Some(route.sched),
         _ => None,
     }
 }
 
+#[inline]
+fn steal_local<T>(stealer: &Steal<T>, local: &Local<T>) -> Option<T> {
+    stealer.steal_into(local).ok()
+}
+
 #[repr(align(128))]
 pub struct Scheduler {
+    // fixed size, the overflowed coroutines go to the global queues
     local_queues: Vec<Local<CoroutineImpl>>,
     stealers: Vec<Steal<CoroutineImpl>>,
+    // the woken coroutine that the worker runs next, see `WakeOrder::Lifo`
+    lifo_slots: Vec<AtomicOption<CoroutineImpl>>,
+    // the injectors of the workers, pushed by other threads and overflows
     global_queues: Vec<SegQueue<CoroutineImpl>>,
+    // the pinned coroutines are never put into the local queues
+    // so that they can't be stolen by other workers
+    pinned_queues: Vec<SegQueue<CoroutineImpl>>,
+    // the workers with the id below it take the coroutines
+    active: AtomicUsize,
+    io_threads: usize,
     event_loop: EventLoop,
     timer_thread: TimerThread,
+    steal: Arc<dyn StealPolicy>,
     pub pool: CoroutinePool,
 }
 
+// the scheduler is shared by all the threads that it starts, the queues
+// and the timer list are thread safe
+unsafe impl Send for Scheduler {}
+unsafe impl Sync for Scheduler {}
+
 impl Scheduler {
-    pub fn new(workers: usize) -> Box<Self> {
+    pub fn new(workers: usize, io_threads: usize, steal: Arc<dyn StealPolicy>) -> Box<Self> {
         let local_queues = Vec::from_iter((0..workers).map(|_| Local::new()));
         let stealers = Vec::from_iter(local_queues.iter().map(|l| l.stealer()));
+        let lifo_slots = Vec::from_iter((0..workers).map(|_| AtomicOption::none()));
         let global_queues = Vec::from_iter((0..workers).map(|_| SegQueue::new()));
+        let pinned_queues = Vec::from_iter((0..workers).map(|_| SegQueue::new()));
 
         Box::new(Scheduler {
             pool: CoroutinePool::new(),
-            event_loop: EventLoop::new(workers).expect("can't create event_loop"),
+            event_loop: EventLoop::new(workers, io_threads).expect("can't create event_loop"),
             local_queues,
             stealers,
+            lifo_slots,
             global_queues,
+            pinned_queues,
+            active: AtomicUsize::new(workers),
+            io_threads,
             timer_thread: TimerThread::new(),
+            steal,
         })
     }
 
     #[inline]
     pub fn run_queued_tasks(&self, id: usize) {
+        if id >= self.local_queues.len() {
+            return;
+        }
         let local = unsafe { self.local_queues.get_unchecked(id) };
+        let lifo = unsafe { self.lifo_slots.get_unchecked(id) };
+        let global = unsafe { self.global_queues.get_unchecked(id) };
+        let pinned = unsafe { self.pinned_queues.get_unchecked(id) };
 
-        let mut next_id = id;
+        // a retired worker hands over the queued coroutines to the active
+        // ones, and only runs the coroutines pinned to it
+        if id >= self.active_workers() {
+            while let Some(co) = lifo
+                .take(Ordering::Acquire)
+                .or_else(|| local.pop())
+                .or_else(|| global.pop())
+            {
+                self.schedule_global(co);
+            }
+            while let Some(co) = pinned.pop() {
+                run_coroutine(co);
+            }
+            return;
+        }
+
+        let mut attempt = 0;
+        let mut tick = 0;
+        let mut lifo_runs = 0;
 
         let mut get_co = || {
-            local
+            tick += 1;
+            // the fairness tick, pull from the global queue first
+            #[allow(clippy::manual_is_multiple_of)]
+            let injected = if tick % FAIRNESS_TICK == 0 {
+                global.pop()
+            } else {
+                None
+            };
+            injected
+                .or_else(|| {
+                    // skip the swap when the slot is empty, it's the usual case
+                    if lifo.is_none() {
+                        lifo_runs = 0;
+                        return None;
+                    }
+                    match lifo.take(Ordering::Acquire) {
+                        Some(co) if lifo_runs >= MAX_LIFO_RUNS => {
+                            lifo_runs = 0;
+                            self.push_local(co, id);
+                            None
+                        }
+                        Some(co) => {
+                            lifo_runs += 1;
+                            Some(co)
+                        }
+                        None => {
+                            lifo_runs = 0;
+                            None
+                        }
+                    }
+                })
+                .or_else(|| pinned.pop())
                 // Try get a task from the local queue.
-                .pop()
+                .or_else(|| local.pop())
                 // Try stealing a of task from other local queues.
                 .or_else(|| {
-                    next_id = (next_id + 1).rem_euclid(self.local_queues.len());
-                    let stealer = unsafe { self.stealers.get_unchecked(next_id) };
-                    steal_local(stealer, local)
+                    attempt += 1;
+                    self.steal_from_victim(id, attempt - 1, local)
                 })
+                // the overflowed ones and the ones not collected yet
+                .or_else(|| global.pop())
         };
 
         // Pop a task from the local queue
@@ -136,9 +315,8 @@ impl Scheduler {
         if let Some(co) = &cur_co {
             co.prefetch();
         } else {
-            let steal_id = (id + 4).rem_euclid(self.local_queues.len());
-            let stealer = unsafe { self.stealers.get_unchecked(steal_id) };
-            cur_co = match steal_local(stealer, local) {
+            // the first attempt is already tried by `get_co`
+            cur_co = match self.steal_from_victim(id, 1, local) {
                 Some(co) => {
                     co.prefetch();
                     Some(co)
@@ -166,70 +344,206 @@ impl Scheduler {
         }
     }
 
+    // steal from the worker chosen by the policy, the invalid ones are ignored
+    #[inline]
+    fn steal_from_victim(
+        &self,
+        id: usize,
+        attempt: usize,
+        local: &Local<CoroutineImpl>,
+    ) -> Option<CoroutineImpl> {
+        let victim = self.steal.victim(id, attempt, self.stealers.len())?;
+        if victim == id {
+            return None;
+        }
+        steal_local(self.stealers.get(victim)?, local)
+    }
+
+    // queue the coroutine to its home scheduler or its pinned worker, it's
+    // given back if this scheduler can run it on any worker
+    #[inline]
+    fn route_away(&self, co: CoroutineImpl) -> Option<CoroutineImpl> {
+        if let Some(route) = route(&co) {
+            if !std::ptr::eq(route.sched, self) {
+                route.sched.schedule_global(co);
+                return None;
+            }
+            if let Some(worker) = route.worker {
+                self.schedule_pinned(co, worker);
+                return None;
+            }
+        }
+        Some(co)
+    }
+
     /// put the coroutine to correct queue so that next time it can be scheduled
     #[inline]
     pub fn schedule(&self, co: CoroutineImpl) {
-        #[cfg(nightly)]
-        let id = WORKER_ID.get();
-        #[cfg(not(nightly))]
-        let id = WORKER_ID.with(|id| id.get());
-
-        if id != !1 {
-            self.schedule_with_id(co, id);
-        } else {
-            self.schedule_global(co);
+        match current_worker_id() {
+            Some(id) if is_current_scheduler(self) => self.schedule_with_id(co, id),
+            _ => self.schedule_global(co),
         }
     }
 
     /// called by selector with known id
     #[inline]
     pub fn schedule_with_id(&self, co: CoroutineImpl, id: usize) {
+        let co = match self.route_away(co) {
+            Some(co) => co,
+            None => return,
+        };
+        // the dedicated io threads and the retired workers take no coroutine
+        if id >= self.active_workers() {
+            return self.schedule_global(co);
+        }
+        mark_ready(&co);
+        self.push_local(co, id);
+    }
+
+    /// put the woken coroutine to the queue chosen by its wake order
+    #[inline]
+    pub fn schedule_woken(&self, co: CoroutineImpl) {
+        match current_worker_id() {
+            Some(id)
+                if is_current_scheduler(self)
+                    && route(&co).map(|r| r.wake_order) == Some(WakeOrder::Lifo) =>
+            {
+                self.schedule_lifo(co, id)
+            }
+            _ => self.schedule(co),
+        }
+    }
+
+    // put the coroutine to the lifo slot of the current worker
+    #[inline]
+    fn schedule_lifo(&self, co: CoroutineImpl, id: usize) {
+        let co = match self.route_away(co) {
+            Some(co) => co,
+            None => return,
+        };
+        if id >= self.active_workers() {
+            return self.schedule_global(co);
+        }
+        mark_ready(&co);
+        let slot = unsafe { self.lifo_slots.get_unchecked(id) };
+        // the replaced one is queued at the back
+        if let Some(prev) = slot.swap(co, Ordering::AcqRel) {
+            self.push_local(prev, id);
+        }
+    }
+
+    #[inline]
+    fn push_local(&self, co: CoroutineImpl, id: usize) {
         let queue = unsafe { self.local_queues.get_unchecked(id) };
-        match queue.push_back(co) {
-            Ok(()) => {}
-            // Err(co) => self.schedule_global(co),
-            Err(co) => run_coroutine(co),
+        if let Err(co) = queue.push_back(co) {
+            // overflow to the global queue of the same worker, it's picked
+            // up by the fairness tick or before the worker goes to sleep
+            let global = unsafe { self.global_queues.get_unchecked(id) };
+            global.push(co);
         }
     }
 
     /// put the coroutine to global queue so that next time it can be scheduled
     #[inline]
     pub fn schedule_global(&self, co: CoroutineImpl) {
+        let co = match self.route_away(co) {
+            Some(co) => co,
+            None => return,
+        };
         // let thread_id = self.workers.get_idle_thread();
         static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
         let thread_id = NEXT_THREAD_ID
             .fetch_add(1, Ordering::AcqRel)
-            .rem_euclid(self.global_queues.len());
+            .rem_euclid(self.active_workers());
         let global = unsafe { self.global_queues.get_unchecked(thread_id) };
+        mark_ready(&co);
         global.push(co);
         // signal one waiting thread if any
         self.get_selector().wakeup(thread_id);
     }
 
+    /// put the pinned coroutine to the queue of its worker
+    #[inline]
+    pub fn schedule_pinned(&self, co: CoroutineImpl, id: usize) {
+        if let Some(home) = foreign_home(&co, self) {
+            return home.schedule_global(co);
+        }
+        let pinned = unsafe { self.pinned_queues.get_unchecked(id) };
+        mark_ready(&co);
+        pinned.push(co);
+        // the worker would check the pinned queue before going to sleep
+        if current_worker_id() != Some(id) || !is_current_scheduler(self) {
+            self.get_selector().wakeup(id);
+        }
+    }
+
+    /// check if other threads sent coroutines to the worker
+    /// the local queue is only pushed by the worker itself, no need to check
+    #[inline]
+    pub fn has_ready_tasks(&self, id: usize) -> bool {
+        let global = unsafe { self.global_queues.get_unchecked(id) };
+        let pinned = unsafe { self.pinned_queues.get_unchecked(id) };
+        !global.is_empty() || !pinned.is_empty()
+    }
+
+    /// get the number of the workers
+    #[inline]
+    pub fn workers(&self) -> usize {
+        self.local_queues.len()
+    }
+
+    /// get the number of the workers that take the coroutines
+    #[inline]
+    pub fn active_workers(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// activate or retire the workers, the number is clamped to 1 and the
+    /// number of the workers
+    pub fn set_active_workers(&self, active: usize) {
+        let active = active.clamp(1, self.workers());
+        let old = self.active.swap(active, Ordering::AcqRel);
+        // let the retired workers hand over their queued coroutines
+        for id in active..old {
+            self.get_selector().wakeup(id);
+        }
+    }
+
     #[inline]
     pub fn collect_global(&self, id: usize) {
+        if id >= self.local_queues.len() {
+            return;
+        }
         let local = unsafe { self.local_queues.get_unchecked(id) };
         let global = unsafe { self.global_queues.get_unchecked(id) };
         while let Some(co) = global.pop() {
-            match local.push_back(co) {
-                Ok(()) => {}
-                Err(co) => {
-                    run_coroutine(co);
-                    // self.schedule_global(co);
-                    // wake up self again in future
-                    self.get_selector().wakeup(id);
-                    break;
-                }
+            if let Err(co) = local.push_back(co) {
+                // the local queue is full, leave the rest in the global queue
+                global.push(co);
+                // wake up self again in future
+                self.get_selector().wakeup(id);
+                break;
             }
         }
     }
 
+    /// the number of the coroutines waiting in the run queues
+    #[cfg(feature = "metrics")]
+    pub fn queued(&self) -> usize {
+        let local: usize = self.stealers.iter().map(|s| s.len()).sum();
+        let lifo = self.lifo_slots.iter().filter(|s| !s.is_none()).count();
+        let global: usize = self.global_queues.iter().map(|q| q.len()).sum();
+        let pinned: usize = self.pinned_queues.iter().map(|q| q.len()).sum();
+        local + lifo + global + pinned
+    }
+
     #[inline]
     pub fn add_timer(
         &self,
         dur: Duration,
         co: Arc<AtomicOption<CoroutineImpl>>,
     ) -> timeout_list::TimeoutHandle<TimerData> {
+        metrics::inc(Counter::Timers);
         self.timer_thread.add_timer(dur, co)
     }
 
@@ -238,6 +552,17 @@ impl Scheduler {
         self.timer_thread.del_timer(handle);
     }
 
+    // check the timers of the timer thread and all the selectors, the clock
+    // may jump forward
+    #[cfg(feature = "mock_clock")]
+    pub fn wake_timers(&self) {
+        self.timer_thread.wakeup();
+        let selector = self.get_selector();
+        for id in 0..self.workers() + self.io_threads {
+            selector.wakeup(id);
+        }
+    }
+
     #[inline]
     pub fn get_selector(&self) -> &Selector {
         self.event_loop.get_selector()
@@ -13,6 +13,7 @@ use crate::join::JoinHandle;
 use crossbeam::atomic::AtomicCell;
 
 /// Like `coroutine::spawn`, but without the closure bounds.
+#[track_caller]
 pub unsafe fn spawn_unsafe<'a, F>(f: F) -> JoinHandle<()>
 where
     F: FnOnce() + Send + 'a,
@@ -131,6 +132,7 @@ impl<'a> Scope<'a> {
     /// before the current stack frame goes away, allowing you to reference the parent stack frame
     /// directly. This is ensured by having the parent join on the child coroutine before the
     /// scope exits.
+    #[track_caller]
     fn spawn_impl<F, T>(&self, f: F) -> ScopedJoinHandle<T>
     where
         F: FnOnce() -> T + Send + 'a,
@@ -168,6 +170,7 @@ impl<'a> Scope<'a> {
     /// before the current stack frame goes away, allowing you to reference the parent stack frame
     /// directly. This is ensured by having the parent join on the child coroutine before the
     /// scope exits.
+    #[track_caller]
     pub unsafe fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<T>
     where
         F: FnOnce() -> T + Send + 'a,
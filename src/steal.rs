@@ -0,0 +1,183 @@
+//! work stealing policies of the scheduler
+//!
+//! an idle worker steals the coroutines from the local queues of the other
+//! workers. [`StealPolicy`] decides which worker is tried next, it's set by
+//! [`Config::set_steal_policy`] for the global scheduler and by
+//! [`RuntimeConfig::steal_policy`] for a runtime.
+//!
+//! the pinned coroutines are never put into the local queues, so they are
+//! never stolen whatever the policy is.
+//!
+//! [`Config::set_steal_policy`]: crate::Config::set_steal_policy
+//! [`RuntimeConfig::steal_policy`]: crate::RuntimeConfig::steal_policy
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+/// Decides which worker an idle worker steals from.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::steal::StealPolicy;
+///
+/// // only steal from the neighbor worker
+/// struct Neighbor;
+///
+/// impl StealPolicy for Neighbor {
+///     fn victim(&self, thief: usize, attempt: usize, workers: usize) -> Option<usize> {
+///         if attempt == 0 {
+///             Some((thief + 1) % workers)
+///         } else {
+///             None
+///         }
+///     }
+/// }
+///
+/// may::config().set_steal_policy(Neighbor);
+/// ```
+pub trait StealPolicy: Send + Sync + 'static {
+    /// Returns the worker to steal from, or `None` to not steal this time.
+    ///
+    /// `thief` is the id of the idle worker, `attempt` counts the steals it
+    /// tried since it started to run the queued coroutines, and `workers` is
+    /// the number of the workers. A victim that is the thief itself or not
+    /// below `workers` is ignored.
+    fn victim(&self, thief: usize, attempt: usize, workers: usize) -> Option<usize>;
+}
+
+/// Tries the workers one by one after the thief. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundRobin;
+
+impl StealPolicy for RoundRobin {
+    fn victim(&self, thief: usize, attempt: usize, workers: usize) -> Option<usize> {
+        Some((thief + attempt + 1) % workers)
+    }
+}
+
+/// Tries a random worker each time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomVictim;
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+// xorshift64, it's enough to spread the victims
+fn next_random() -> u64 {
+    RNG.with(|rng| {
+        let mut x = rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        rng.set(x);
+        x
+    })
+}
+
+impl StealPolicy for RandomVictim {
+    fn victim(&self, _thief: usize, _attempt: usize, workers: usize) -> Option<usize> {
+        Some((next_random() % workers as u64) as usize)
+    }
+}
+
+/// Tries the workers in the same group as the thief first, then the others.
+///
+/// The workers are grouped by their ids, every `group_size` consecutive
+/// workers are a group. The workers are bound to the cpu cores in order, so
+/// with the group size set to the number of cores per numa node, the stolen
+/// coroutines stay on the same node as long as it has work.
+#[derive(Clone, Copy)]
+pub struct NumaFirst {
+    group_size: usize,
+}
+
+impl NumaFirst {
+    /// Creates the policy with the number of the workers in a group.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `group_size` is zero.
+    pub fn new(group_size: usize) -> Self {
+        assert!(group_size > 0, "group size must be non-zero");
+        NumaFirst { group_size }
+    }
+}
+
+impl fmt::Debug for NumaFirst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NumaFirst({})", self.group_size)
+    }
+}
+
+impl StealPolicy for NumaFirst {
+    fn victim(&self, thief: usize, attempt: usize, workers: usize) -> Option<usize> {
+        let base = thief / self.group_size * self.group_size;
+        let group_len = self.group_size.min(workers.saturating_sub(base));
+        let pos = attempt % workers;
+        // the other members of the group
+        if pos + 1 < group_len {
+            return Some(base + (thief - base + pos + 1) % group_len);
+        }
+        // then the workers out of the group, the last slot is the thief
+        let j = pos + 1 - group_len;
+        if j < workers - group_len {
+            Some((base + group_len + j) % workers)
+        } else {
+            None
+        }
+    }
+}
+
+/// Never steals, each worker only runs the coroutines scheduled to it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoSteal;
+
+impl StealPolicy for NoSteal {
+    fn victim(&self, _thief: usize, _attempt: usize, _workers: usize) -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn victims<P: StealPolicy>(p: &P, thief: usize, workers: usize) -> Vec<Option<usize>> {
+        (0..workers).map(|i| p.victim(thief, i, workers)).collect()
+    }
+
+    #[test]
+    fn steal_numa_first_order() {
+        let p = NumaFirst::new(4);
+        let v = victims(&p, 5, 10);
+        assert_eq!(
+            v,
+            [6, 7, 4, 8, 9, 0, 1, 2, 3]
+                .into_iter()
+                .map(Some)
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+        // the last group is not full
+        let v = victims(&p, 9, 10);
+        assert_eq!(v[0], Some(8));
+        assert!(v[1..9].iter().all(|w| w.is_some_and(|w| w < 8)));
+        assert_eq!(v[9], None);
+    }
+
+    #[test]
+    fn steal_round_robin_and_random() {
+        assert_eq!(
+            victims(&RoundRobin, 2, 4),
+            [Some(3), Some(0), Some(1), Some(2)]
+        );
+        for i in 0..100 {
+            assert!(RandomVictim.victim(0, i, 3).is_some_and(|w| w < 3));
+        }
+        assert_eq!(victims(&NoSteal, 0, 2), [None, None]);
+    }
+}
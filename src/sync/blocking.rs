@@ -4,6 +4,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use super::AtomicOption;
 use crate::coroutine_impl::is_coroutine;
 use crate::park::{Park, ParkError};
 
@@ -31,7 +32,8 @@ impl ThreadPark {
                 None => self.cvar.wait(&mut guard),
                 Some(t) => {
                     let t = self.cvar.wait_for(&mut guard, t);
-                    if t.timed_out() {
+                    // the unpark wins if it comes along with the timeout
+                    if t.timed_out() && !*guard {
                         result = Err(ParkError::Timeout);
                     }
                 }
@@ -52,14 +54,14 @@ impl ThreadPark {
 }
 
 #[derive(Debug)]
-pub enum Parker {
+pub enum ParkKind {
     Coroutine(Park),
     Thread(ThreadPark),
 }
 
 #[derive(Debug)]
 pub struct Blocker {
-    parker: Parker,
+    parker: ParkKind,
 }
 
 impl Blocker {
@@ -68,10 +70,10 @@ impl Blocker {
         let parker = if is_coroutine() {
             let park = Park::new();
             park.ignore_cancel(ignore_cancel);
-            Parker::Coroutine(park)
+            ParkKind::Coroutine(park)
         } else {
             let park = ThreadPark::new();
-            Parker::Thread(park)
+            ParkKind::Thread(park)
         };
 
         Blocker { parker }
@@ -82,21 +84,123 @@ impl Blocker {
         Arc::new(Self::new(false))
     }
 
+    /// park the current coroutine or thread until unparked or timeout
+    ///
+    /// there is no spurious wakeup, `Ok` means it's unparked, and an unpark
+    /// that races with the timeout is reported as `Ok` instead of being lost
     #[inline]
     pub fn park(&self, timeout: Option<Duration>) -> Result<(), ParkError> {
         match self.parker {
-            Parker::Coroutine(ref co) => co.park_timeout(timeout),
-            Parker::Thread(ref t) => t.park_timeout(timeout),
+            ParkKind::Coroutine(ref co) => co.park_timeout(timeout),
+            ParkKind::Thread(ref t) => t.park_timeout(timeout),
         }
     }
 
     #[inline]
     pub fn unpark(&self) {
         match self.parker {
-            Parker::Coroutine(ref co) => co.unpark(),
-            Parker::Thread(ref t) => t.unpark(),
+            ParkKind::Coroutine(ref co) => co.unpark(),
+            ParkKind::Thread(ref t) => t.unpark(),
         }
     }
+
+    /// check if the blocker is created in a coroutine context
+    #[inline]
+    pub(crate) fn is_coroutine(&self) -> bool {
+        matches!(self.parker, ParkKind::Coroutine(_))
+    }
+}
+
+// get the cached blocker, a new one is only created when the context changed
+pub(crate) fn cached_blocker(cache: &AtomicOption<Arc<Blocker>>) -> Arc<Blocker> {
+    let blocker = match cache.take(Ordering::Acquire) {
+        Some(b) if b.is_coroutine() == is_coroutine() => b,
+        _ => Blocker::current(),
+    };
+    cache.swap(blocker.clone(), Ordering::Release);
+    blocker
+}
+
+/// A parker that blocks the current coroutine or thread until unparked.
+///
+/// It's created in the context that would be parked, the clones of it can
+/// unpark it from any coroutine or thread. An unpark before the park is not
+/// lost, the next park returns immediately.
+///
+/// There is no spurious wakeup, so a timed park tells whether it's unparked
+/// or timed out without re-checking any other condition. A cancelled
+/// coroutine would panic in the park as usual.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::sync::Parker;
+///
+/// let h = may::go!(|| {
+///     let parker = Parker::new();
+///     let p = parker.clone();
+///     may::go!(move || p.unpark());
+///     assert!(parker.park_timeout(Duration::from_secs(10)));
+///     assert!(!parker.park_timeout(Duration::from_millis(10)));
+/// });
+/// h.join().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Parker {
+    blocker: Arc<Blocker>,
+}
+
+impl Parker {
+    /// Creates a parker for the current coroutine or thread.
+    pub fn new() -> Self {
+        Parker {
+            blocker: Blocker::current(),
+        }
+    }
+
+    /// Blocks until unparked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it's not called in the context that created the parker.
+    pub fn park(&self) {
+        self.check_context();
+        // the cancel panics in the park, so it's never an error
+        self.blocker.park(None).ok();
+    }
+
+    /// Blocks until unparked or the timeout expires.
+    ///
+    /// Returns true if unparked, false if timed out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it's not called in the context that created the parker.
+    pub fn park_timeout(&self, dur: Duration) -> bool {
+        self.check_context();
+        self.blocker.park(Some(dur)).is_ok()
+    }
+
+    /// Unparks the parked coroutine or thread, or makes the next park return
+    /// immediately.
+    pub fn unpark(&self) {
+        self.blocker.unpark();
+    }
+
+    fn check_context(&self) {
+        assert_eq!(
+            self.blocker.is_coroutine(),
+            is_coroutine(),
+            "the parker is parked in a different context"
+        );
+    }
+}
+
+impl Default for Parker {
+    fn default() -> Self {
+        Parker::new()
+    }
 }
 
 // only used for coroutine that would schedule immediately
@@ -177,3 +281,44 @@ impl SyncBlocker {
         self.unparked.store(true, Ordering::Release);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn parker_unpark_before_park() {
+        let parker = Parker::new();
+        parker.unpark();
+        parker.unpark();
+        let now = Instant::now();
+        // the unparks are merged into one
+        assert!(parker.park_timeout(Duration::from_secs(10)));
+        assert!(!parker.park_timeout(Duration::from_millis(20)));
+        assert!(now.elapsed() >= Duration::from_millis(20));
+    }
+
+    // the unpark that races with the timeout is never lost
+    fn timeout_race() {
+        for _ in 0..100 {
+            let parker = Parker::new();
+            let p = parker.clone();
+            let h = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(1));
+                p.unpark();
+            });
+            let unparked = parker.park_timeout(Duration::from_millis(1));
+            h.join().unwrap();
+            if !unparked {
+                assert!(parker.park_timeout(Duration::from_secs(10)));
+            }
+        }
+    }
+
+    #[test]
+    fn parker_timeout_race() {
+        timeout_race();
+        go!(timeout_race).join().unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+//! a channel for byte payloads with pooled buffers
+//!
+//! a streaming pipeline usually reads the payload into a buffer, copies it
+//! into a new `Vec` to send it, and the receiver copies it out again. the
+//! buffers of this channel are recycled through a pool shared by both ends:
+//! [`BytesSender::send_vectored`] gathers the slices into a pooled buffer
+//! with a single copy, and [`BytesReceiver::recv_into`] swaps the received
+//! buffer with the caller's one, which goes back to the pool.
+
+use std::fmt;
+use std::io::IoSlice;
+use std::sync::mpsc::{RecvError, SendError, TryRecvError};
+use std::sync::Arc;
+
+use crossbeam::queue::ArrayQueue;
+
+use super::mpsc::{channel, Receiver, Sender};
+
+// the number of the idle buffers kept by the pool
+const POOL_SIZE: usize = 64;
+
+/// Creates a new byte channel, returning the sender and receiver halves.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::IoSlice;
+/// use may::sync::bytes_channel::bytes_channel;
+///
+/// let (tx, rx) = bytes_channel();
+/// let h = may::go!(move || {
+///     let header = [0u8, 5];
+///     tx.send_vectored(&[IoSlice::new(&header), IoSlice::new(b"hello")])
+///         .unwrap();
+/// });
+///
+/// let mut buf = Vec::new();
+/// rx.recv_into(&mut buf).unwrap();
+/// assert_eq!(buf, b"\0\x05hello");
+/// h.join().unwrap();
+/// ```
+pub fn bytes_channel() -> (BytesSender, BytesReceiver) {
+    let (tx, rx) = channel();
+    let pool = Arc::new(ArrayQueue::new(POOL_SIZE));
+    (
+        BytesSender {
+            tx,
+            pool: pool.clone(),
+        },
+        BytesReceiver { rx, pool },
+    )
+}
+
+/// The sending half of a [`bytes_channel`], it can be cloned.
+#[derive(Clone)]
+pub struct BytesSender {
+    tx: Sender<Vec<u8>>,
+    pool: Arc<ArrayQueue<Vec<u8>>>,
+}
+
+/// The receiving half of a [`bytes_channel`].
+pub struct BytesReceiver {
+    rx: Receiver<Vec<u8>>,
+    pool: Arc<ArrayQueue<Vec<u8>>>,
+}
+
+impl BytesSender {
+    /// Sends the buffer without copying it.
+    ///
+    /// The buffer is returned back in the error if the receiver is dropped.
+    pub fn send(&self, buf: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        self.tx.send(buf)
+    }
+
+    /// Gathers the slices into a pooled buffer and sends it as one message.
+    pub fn send_vectored(&self, bufs: &[IoSlice]) -> Result<(), SendError<()>> {
+        let len = bufs.iter().map(|b| b.len()).sum();
+        let mut buf = self.take_buf(len);
+        for b in bufs {
+            buf.extend_from_slice(b);
+        }
+        self.tx.send(buf).map_err(|SendError(buf)| {
+            recycle(&self.pool, buf);
+            SendError(())
+        })
+    }
+
+    /// Copies the slice into a pooled buffer and sends it.
+    pub fn send_slice(&self, data: &[u8]) -> Result<(), SendError<()>> {
+        self.send_vectored(&[IoSlice::new(data)])
+    }
+
+    /// Takes an empty buffer from the pool with at least `capacity`, it can
+    /// be filled and passed to [`send`] to avoid the allocation.
+    ///
+    /// [`send`]: BytesSender::send
+    pub fn take_buf(&self, capacity: usize) -> Vec<u8> {
+        let mut buf = self.pool.pop().unwrap_or_default();
+        buf.reserve(capacity);
+        buf
+    }
+
+    /// Returns true if the receiver is dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl BytesReceiver {
+    /// Receives a message, parking the caller until there is one.
+    ///
+    /// Pass the buffer to [`recycle`] when done with it.
+    ///
+    /// [`recycle`]: BytesReceiver::recycle
+    pub fn recv(&self) -> Result<Vec<u8>, RecvError> {
+        self.rx.recv()
+    }
+
+    /// Attempts to receive a message without blocking.
+    pub fn try_recv(&self) -> Result<Vec<u8>, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Receives a message into `buf`, parking the caller until there is one.
+    ///
+    /// The message replaces the content of `buf` without copying, the old
+    /// buffer goes back to the pool.
+    pub fn recv_into(&self, buf: &mut Vec<u8>) -> Result<(), RecvError> {
+        let msg = self.rx.recv()?;
+        self.recycle(std::mem::replace(buf, msg));
+        Ok(())
+    }
+
+    /// Same as [`recv_into`] but never blocks.
+    ///
+    /// [`recv_into`]: BytesReceiver::recv_into
+    pub fn try_recv_into(&self, buf: &mut Vec<u8>) -> Result<(), TryRecvError> {
+        let msg = self.rx.try_recv()?;
+        self.recycle(std::mem::replace(buf, msg));
+        Ok(())
+    }
+
+    /// Returns a buffer to the pool so it's reused by the senders.
+    pub fn recycle(&self, buf: Vec<u8>) {
+        recycle(&self.pool, buf)
+    }
+
+    /// Closes the channel without dropping the receiver, the messages that
+    /// are already sent can still be received.
+    pub fn close(&self) {
+        self.rx.close()
+    }
+}
+
+// keep the buffer if it's allocated and the pool is not full
+fn recycle(pool: &ArrayQueue<Vec<u8>>, mut buf: Vec<u8>) {
+    if buf.capacity() > 0 {
+        buf.clear();
+        let _ = pool.push(buf);
+    }
+}
+
+impl fmt::Debug for BytesSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BytesSender")
+            .field("pooled", &self.pool.len())
+            .finish()
+    }
+}
+
+impl fmt::Debug for BytesReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BytesReceiver")
+            .field("pooled", &self.pool.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_channel_reuse_buf() {
+        let (tx, rx) = bytes_channel();
+        let mut buf = Vec::with_capacity(128);
+        let ptr = buf.as_ptr();
+
+        tx.send_slice(b"first").unwrap();
+        rx.recv_into(&mut buf).unwrap();
+        assert_eq!(buf, b"first");
+
+        // the buffer passed to recv_into is reused by the next send
+        tx.send_vectored(&[IoSlice::new(b"sec"), IoSlice::new(b"ond")])
+            .unwrap();
+        let msg = rx.recv().unwrap();
+        assert_eq!(msg, b"second");
+        assert_eq!(msg.as_ptr(), ptr);
+
+        rx.recycle(msg);
+        let mut b = tx.take_buf(4);
+        assert_eq!(b.as_ptr(), ptr);
+        b.extend_from_slice(b"data");
+        tx.send(b).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), b"data");
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send_slice(b"lost"), Err(SendError(())));
+    }
+}
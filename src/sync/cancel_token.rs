@@ -0,0 +1,316 @@
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use super::SyncFlag;
+use crate::coroutine_impl::{current, current_cancel_data, is_coroutine, Coroutine};
+use crate::yield_now::yield_now;
+use parking_lot::Mutex;
+
+/// An error returned when an operation is interrupted by a [`CancellationToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        "operation cancelled".fmt(f)
+    }
+}
+
+impl Error for Cancelled {}
+
+const FIRING: usize = 1;
+const FIRED: usize = 2;
+
+// a coroutine that is running a closure bound to the token
+struct Bound {
+    id: usize,
+    co: Coroutine,
+    // FIRING when the token starts to cancel the coroutine
+    // FIRED after the cancel request is sent to the coroutine
+    state: AtomicUsize,
+}
+
+#[derive(Default)]
+struct State {
+    children: Vec<Weak<Inner>>,
+    bound: Vec<Arc<Bound>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    flag: SyncFlag,
+    state: Mutex<State>,
+}
+
+impl Inner {
+    fn cancel(&self) {
+        let (children, bound) = {
+            let mut state = self.state.lock();
+            if self.flag.is_fired() {
+                return;
+            }
+            self.flag.fire();
+            for b in state.bound.iter() {
+                b.state.store(FIRING, Ordering::Release);
+            }
+            (std::mem::take(&mut state.children), state.bound.clone())
+        };
+
+        // cancel the coroutine out of the lock, it may be run in place
+        for b in bound {
+            unsafe { b.co.cancel() };
+            b.state.store(FIRED, Ordering::Release);
+        }
+
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// A token for cooperative cancellation.
+///
+/// Unlike [`Coroutine::cancel`] which tears down the whole coroutine, a token
+/// is cancelled independently from any coroutine. Code can poll it with
+/// [`is_cancelled`], wait for it with [`cancelled`], or bind a scope of
+/// blocking operations to it with [`run`], so that the operations are
+/// interrupted when the token is cancelled.
+///
+/// Cancelling a token also cancels all the tokens created by [`child_token`],
+/// while cancelling a child has no effect on its parent.
+///
+/// Clones of a token share the same state.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::sync::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let child = token.child_token();
+///
+/// let h = may::go!(move || {
+///     // the sleep would be interrupted once the token is cancelled
+///     child.run(|| may::coroutine::sleep(Duration::from_secs(1000)))
+/// });
+///
+/// token.cancel();
+/// assert!(h.join().unwrap().is_err());
+/// ```
+///
+/// [`Coroutine::cancel`]: ../coroutine/struct.Coroutine.html#method.cancel
+/// [`is_cancelled`]: #method.is_cancelled
+/// [`cancelled`]: #method.cancelled
+/// [`run`]: #method.run
+/// [`child_token`]: #method.child_token
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    /// create a new token that is not cancelled
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// create a child token that would be cancelled when this one is cancelled
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        {
+            let mut state = self.inner.state.lock();
+            if !self.inner.flag.is_fired() {
+                // drop the children that are already gone
+                state.children.retain(|c| c.strong_count() > 0);
+                state.children.push(Arc::downgrade(&child.inner));
+                return child;
+            }
+        }
+        child.cancel();
+        child
+    }
+
+    /// cancel the token and all its child tokens
+    ///
+    /// all the waiters of the token are woken up, and the blocking operations
+    /// executed within [`run`](#method.run) are interrupted
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// return true if the token is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.flag.is_fired()
+    }
+
+    /// block the current coroutine or thread until the token is cancelled
+    pub fn cancelled(&self) {
+        self.inner.flag.wait();
+    }
+
+    /// same as `cancelled` except that with an extra timeout value
+    /// return false if timeout happened
+    pub fn cancelled_timeout(&self, dur: Duration) -> bool {
+        self.inner.flag.wait_timeout(dur)
+    }
+
+    /// sleep for the duration unless the token is cancelled before that
+    pub fn sleep(&self, dur: Duration) -> Result<(), Cancelled> {
+        if self.cancelled_timeout(dur) {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// run the closure with its blocking operations bound to the token
+    ///
+    /// when the token is cancelled, the blocking API (io, sleep, channels and
+    /// other sync primitives) that the closure is waiting on is interrupted,
+    /// the closure is unwound just like a panic and `Err(Cancelled)` is
+    /// returned. So the closure should not leave shared state inconsistent
+    /// across its blocking points.
+    ///
+    /// In a thread context the closure can't be interrupted, it's only
+    /// checked whether the token is cancelled before running it.
+    pub fn run<F, R>(&self, f: F) -> Result<R, Cancelled>
+    where
+        F: FnOnce() -> R,
+    {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+
+        if !is_coroutine() {
+            return Ok(f());
+        }
+
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut state = self.inner.state.lock();
+            if self.inner.flag.is_fired() {
+                return Err(Cancelled);
+            }
+            state.bound.push(Arc::new(Bound {
+                id,
+                co: current(),
+                state: AtomicUsize::new(0),
+            }));
+        }
+
+        let ret = panic::catch_unwind(AssertUnwindSafe(f));
+
+        let bound = {
+            let mut state = self.inner.state.lock();
+            let i = state.bound.iter().position(|b| b.id == id).unwrap();
+            state.bound.swap_remove(i)
+        };
+
+        let fired = bound.state.load(Ordering::Acquire) != 0;
+        if fired {
+            // wait the cancel request is sent and then consume it
+            // the cancel bit may be set already, disable it so that the
+            // yield doesn't unwind the coroutine again
+            let cancel = current_cancel_data();
+            cancel.disable_cancel();
+            while bound.state.load(Ordering::Acquire) != FIRED {
+                yield_now();
+            }
+            cancel.enable_cancel();
+            cancel.clear_cancel_bit();
+        }
+
+        match ret {
+            Ok(r) => Ok(r),
+            Err(e) => match e.downcast_ref::<generator::Error>() {
+                Some(generator::Error::Cancel) if fired => Err(Cancelled),
+                _ => panic::resume_unwind(e),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "CancellationToken {{ is_cancelled: {} }}",
+            self.is_cancelled()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn cancel_child_token() {
+        let token = CancellationToken::new();
+        let child = token.child_token();
+        let grand_child = child.child_token();
+        let other = token.child_token();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grand_child.is_cancelled());
+        assert!(!token.is_cancelled());
+        assert!(!other.is_cancelled());
+
+        token.cancel();
+        assert!(other.is_cancelled());
+        assert!(token.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn wait_cancelled() {
+        let token = CancellationToken::new();
+        assert!(!token.cancelled_timeout(Duration::from_millis(10)));
+
+        let t = token.clone();
+        let h = go!(move || t.cancelled());
+        let t = token.child_token();
+        let h1 = thread::spawn(move || t.cancelled());
+
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+        h.join().unwrap();
+        h1.join().unwrap();
+        assert_eq!(token.sleep(Duration::from_secs(100)), Err(Cancelled));
+    }
+
+    #[test]
+    fn run_cancelled() {
+        let token = CancellationToken::new();
+
+        let t = token.clone();
+        let h = go!(move || {
+            let ret = t.run(|| crate::sleep::sleep(Duration::from_secs(100)));
+            assert_eq!(ret, Err(Cancelled));
+            // the coroutine is still usable after the cancellation
+            crate::sleep::sleep(Duration::from_millis(1));
+            10
+        });
+
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+        assert_eq!(h.join().unwrap(), 10);
+        assert_eq!(token.run(|| 0), Err(Cancelled));
+    }
+
+    #[test]
+    fn run_finished() {
+        let token = CancellationToken::new();
+        let t = token.clone();
+        let h = go!(move || t.run(|| 1));
+        assert_eq!(h.join().unwrap(), Ok(1));
+        token.cancel();
+    }
+}
@@ -6,6 +6,7 @@ use std::sync::{LockResult, PoisonError};
 use std::time::Duration;
 
 use super::blocking::SyncBlocker;
+use super::metrics::Recorder;
 use super::mutex::{self, Mutex, MutexGuard};
 use crate::cancel::trigger_cancel_panic;
 use crate::park::ParkError;
@@ -29,13 +30,19 @@ pub struct Condvar {
     to_wake: Mutex<SegQueue<Arc<SyncBlocker>>>,
     // used to verify the same mutex instance
     mutex: AtomicUsize,
+    metrics: Recorder,
 }
 
 impl Condvar {
-    pub fn new() -> Condvar {
+    /// Creates a new condition variable.
+    ///
+    /// It's a const fn, so the condvar can be put in a `static` directly.
+    #[track_caller]
+    pub const fn new() -> Condvar {
         Condvar {
             to_wake: Mutex::new(SegQueue::new()),
             mutex: AtomicUsize::new(0),
+            metrics: Recorder::new("Condvar", std::panic::Location::caller()),
         }
     }
 
@@ -65,7 +72,10 @@ impl Condvar {
         }
 
         // wait until coming back
+        self.metrics.acquired();
+        let timer = self.metrics.contended();
         let ret = cur.park(dur);
+        self.metrics.waited(timer);
         // disable cancel panic
         if let Some(c) = cancel.as_ref() {
             c.disable_cancel();
@@ -197,6 +207,7 @@ impl Condvar {
 }
 
 impl Default for Condvar {
+    #[track_caller]
     fn default() -> Condvar {
         Condvar::new()
     }
@@ -209,7 +220,6 @@ mod tests {
     use std::sync::Arc;
     use std::thread;
     use std::time::Duration;
-    use std::u32;
 
     #[test]
     fn smoke() {
@@ -244,7 +254,7 @@ mod tests {
             let data = data.clone();
             let tx = tx.clone();
             thread::spawn(move || {
-                let &(ref lock, ref cond) = &*data;
+                let (lock, cond) = &*data;
                 let mut cnt = lock.lock().unwrap();
                 *cnt += 1;
                 if *cnt == N {
@@ -258,7 +268,7 @@ mod tests {
         }
         drop(tx);
 
-        let &(ref lock, ref cond) = &*data;
+        let (lock, cond) = &*data;
         rx.recv().unwrap();
         let mut cnt = lock.lock().unwrap();
         assert_eq!(*cnt, N);
@@ -324,7 +334,7 @@ mod tests {
             let data = data.clone();
             let tx = tx.clone();
             let h = go!(move || {
-                let &(ref lock, ref cond) = &*data;
+                let (lock, cond) = &*data;
                 let mut cnt = lock.lock().unwrap();
                 *cnt += 1;
                 if *cnt == N {
@@ -339,7 +349,7 @@ mod tests {
         }
         drop(tx);
 
-        let &(ref lock, ref cond) = &*data;
+        let (lock, cond) = &*data;
         rx.recv().unwrap();
         let mut cnt = lock.lock().unwrap();
         assert_eq!(*cnt, N);
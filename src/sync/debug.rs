@@ -0,0 +1,252 @@
+//! detect yielding while holding a std lock guard
+//!
+//! a coroutine that blocks on io, a channel or a sleep while holding a guard
+//! of `std::sync::Mutex` or `std::sync::RwLock` keeps the lock across the
+//! yield. another coroutine on the same worker that tries to take the lock
+//! blocks the whole worker thread, and the holder may never be resumed.
+//!
+//! the std guards can't be seen by the runtime, so they are registered by
+//! wrapping them with [`guard`]. in debug builds every yield of a coroutine
+//! checks the registered guards of it, and reports the first one to the hook
+//! installed by [`set_hook`], the default hook panics. in release builds the
+//! wrapper is a plain new type and nothing is tracked.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+
+#[cfg(debug_assertions)]
+use super::reentrant_mutex::current_owner;
+
+type Hook = Box<dyn Fn(&HeldGuard) + Send + Sync>;
+
+/// The information of a registered guard that is held across a yield.
+#[derive(Debug, Clone)]
+pub struct HeldGuard {
+    location: &'static Location<'static>,
+    coroutine: Option<String>,
+}
+
+impl HeldGuard {
+    /// Returns where the guard is registered.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the name of the yielding coroutine, if it has one.
+    pub fn coroutine(&self) -> Option<&str> {
+        self.coroutine.as_deref()
+    }
+}
+
+impl fmt::Display for HeldGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.coroutine.as_deref().unwrap_or("<unnamed>");
+        write!(
+            f,
+            "coroutine '{}' yields while holding the std lock guard registered at {}",
+            name, self.location
+        )
+    }
+}
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct Registry {
+    next_id: usize,
+    // the registered guards of each coroutine or thread
+    held: HashMap<u64, Vec<(usize, &'static Location<'static>)>>,
+}
+
+// the number of the registered guards, the yields skip the registry if zero
+#[cfg(debug_assertions)]
+static HELD: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(debug_assertions)]
+fn registry() -> &'static parking_lot::Mutex<Registry> {
+    lazy_static::lazy_static! {
+        static ref REGISTRY: parking_lot::Mutex<Registry> = Default::default();
+    }
+    &REGISTRY
+}
+
+fn hook() -> &'static parking_lot::RwLock<Option<Hook>> {
+    lazy_static::lazy_static! {
+        static ref HOOK: parking_lot::RwLock<Option<Hook>> = Default::default();
+    }
+    &HOOK
+}
+
+/// Registers a std lock guard, returns a wrapper that derefs to it.
+///
+/// The guard is tracked until the wrapper is dropped, a yield of the
+/// coroutine before that is reported to the hook. Only debug builds do the
+/// tracking.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use may::sync::debug;
+///
+/// let m = Arc::new(Mutex::new(0));
+/// let m1 = m.clone();
+/// let h = may::go!(move || {
+///     let mut v = debug::guard(m1.lock().unwrap());
+///     *v += 1;
+///     // the mutex is still locked, the default hook panics
+///     may::coroutine::sleep(Duration::from_millis(1));
+/// });
+/// assert_eq!(h.join().is_err(), cfg!(debug_assertions));
+/// ```
+#[track_caller]
+pub fn guard<G>(guard: G) -> Tracked<G> {
+    Tracked {
+        guard,
+        #[cfg(debug_assertions)]
+        id: register(Location::caller()),
+    }
+}
+
+/// Installs the hook that is called when a coroutine yields while holding a
+/// registered guard, it replaces the previous one.
+///
+/// The hook is called in the yielding coroutine before it's suspended. The
+/// default hook panics with the [`HeldGuard`] message.
+pub fn set_hook(f: impl Fn(&HeldGuard) + Send + Sync + 'static) {
+    *hook().write() = Some(Box::new(f));
+}
+
+/// Restores the default hook.
+pub fn reset_hook() {
+    hook().write().take();
+}
+
+/// A std lock guard registered by [`guard`].
+#[must_use = "if unused the guard will immediately unlock"]
+pub struct Tracked<G> {
+    guard: G,
+    #[cfg(debug_assertions)]
+    id: usize,
+}
+
+impl<G: Deref> Deref for Tracked<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Tracked<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+impl<G: fmt::Debug> fmt::Debug for Tracked<G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.guard.fmt(f)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<G> Drop for Tracked<G> {
+    fn drop(&mut self) {
+        unregister(self.id);
+    }
+}
+
+#[cfg(debug_assertions)]
+fn register(location: &'static Location<'static>) -> usize {
+    let mut registry = registry().lock();
+    registry.next_id += 1;
+    let id = registry.next_id;
+    let owner = current_owner();
+    registry.held.entry(owner).or_default().push((id, location));
+    HELD.fetch_add(1, Ordering::Relaxed);
+    id
+}
+
+#[cfg(debug_assertions)]
+fn unregister(id: usize) {
+    let owner = current_owner();
+    let mut registry = registry().lock();
+    if let Some(held) = registry.held.get_mut(&owner) {
+        if let Some(pos) = held.iter().rposition(|(h, _)| *h == id) {
+            held.remove(pos);
+            HELD.fetch_sub(1, Ordering::Relaxed);
+        }
+        if held.is_empty() {
+            registry.held.remove(&owner);
+        }
+    }
+}
+
+// called by the coroutine before it yields
+#[cfg(debug_assertions)]
+#[inline]
+pub(crate) fn check_yield() {
+    if HELD.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let location = {
+        let registry = registry().lock();
+        match registry.held.get(&current_owner()).and_then(|h| h.first()) {
+            Some((_, location)) => *location,
+            None => return,
+        }
+    };
+    let held = HeldGuard {
+        location,
+        coroutine: crate::coroutine::current().name().map(str::to_owned),
+    };
+    match hook().read().as_ref() {
+        Some(f) => f(&held),
+        None => panic!("{}", held),
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn held_guard_hook() {
+        let m = Arc::new(Mutex::new(0));
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let r = reported.clone();
+        set_hook(move |held| r.lock().unwrap().push(held.to_string()));
+
+        let m1 = m.clone();
+        let builder = crate::coroutine::Builder::new().name("holder".to_owned());
+        let h = unsafe {
+            builder.spawn(move || {
+                let line = line!() + 1;
+                let mut v = guard(m1.lock().unwrap());
+                *v += 1;
+                crate::coroutine::sleep(Duration::from_millis(1));
+                drop(v);
+                // the guard is released, no more reports
+                crate::coroutine::yield_now();
+                line
+            })
+        };
+        let line = h.unwrap().join().unwrap();
+        reset_hook();
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 1);
+        assert!(reported[0].starts_with("coroutine 'holder' yields"));
+        assert!(reported[0].contains(&format!("debug.rs:{}:", line)));
+        assert_eq!(*m.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+//! lock order checker for the mutexes
+//!
+//! with the `lock_order` feature enabled, each `Mutex` gets an id and the
+//! mutexes held by each coroutine or thread are tracked. locking a mutex
+//! while holding another one records the order in a global graph. when a new
+//! order closes a cycle in the graph, the contexts could lock the mutexes in
+//! the opposite orders and deadlock, so it panics with the backtraces of both
+//! the acquisitions, even if the deadlock doesn't happen this time.
+//!
+//! it's a debug tool, the ids are never reused and every lock takes a global
+//! lock, so don't enable it in production.
+
+use std::backtrace::Backtrace;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::reentrant_mutex::current_owner;
+
+#[derive(Default)]
+struct Graph {
+    // the mutexes held by each coroutine or thread
+    held: HashMap<u64, Vec<usize>>,
+    // a -> b means b is locked while holding a, with the backtrace
+    edges: HashMap<usize, HashMap<usize, Arc<Backtrace>>>,
+}
+
+impl Graph {
+    // find a path from `from` to `to`, return the backtrace of its first edge
+    fn find_path(&self, from: usize, to: usize) -> Option<Arc<Backtrace>> {
+        let mut visited = HashSet::new();
+        // the node and the backtrace of the first edge to reach it
+        let mut stack: Vec<(usize, Option<&Arc<Backtrace>>)> = vec![(from, None)];
+        while let Some((node, first)) = stack.pop() {
+            if node == to {
+                return first.cloned();
+            }
+            if !visited.insert(node) {
+                continue;
+            }
+            if let Some(edges) = self.edges.get(&node) {
+                for (next, bt) in edges {
+                    stack.push((*next, first.or(Some(bt))));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn graph() -> &'static parking_lot::Mutex<Graph> {
+    lazy_static::lazy_static! {
+        static ref GRAPH: parking_lot::Mutex<Graph> = Default::default();
+    }
+    &GRAPH
+}
+
+// allocate an id for a new mutex
+pub(crate) fn new_lock_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// called before locking the mutex, panic if it could deadlock
+pub(crate) fn check(id: usize) {
+    let mut graph = graph().lock();
+    let held = match graph.held.get(&current_owner()) {
+        Some(held) => held.clone(),
+        None => return,
+    };
+    let mut current: Option<Arc<Backtrace>> = None;
+    for a in held {
+        if a == id {
+            drop(graph);
+            panic!(
+                "mutex #{} is locked again by the coroutine or thread holding it",
+                id
+            );
+        }
+        if graph.edges.get(&a).is_some_and(|e| e.contains_key(&id)) {
+            continue;
+        }
+        // the new edge a -> id closes a cycle
+        if let Some(previous) = graph.find_path(id, a) {
+            drop(graph);
+            panic!(
+                "potential deadlock: mutex #{} is locked while holding mutex #{}, \
+                 but they were locked in the opposite order before\n\n\
+                 current acquisition:\n{}\n\nprevious acquisition:\n{}",
+                id,
+                a,
+                Backtrace::force_capture(),
+                previous
+            );
+        }
+        let bt = current.get_or_insert_with(|| Arc::new(Backtrace::force_capture()));
+        let bt = bt.clone();
+        graph.edges.entry(a).or_default().insert(id, bt);
+    }
+}
+
+// called after the mutex is locked
+pub(crate) fn acquired(id: usize) {
+    let mut graph = graph().lock();
+    graph.held.entry(current_owner()).or_default().push(id);
+}
+
+// called after the mutex is unlocked
+pub(crate) fn released(id: usize) {
+    let owner = current_owner();
+    let mut graph = graph().lock();
+    if let Some(held) = graph.held.get_mut(&owner) {
+        // the mutexes are not always unlocked in the reverse order
+        if let Some(pos) = held.iter().rposition(|h| *h == id) {
+            held.remove(pos);
+        }
+        if held.is_empty() {
+            graph.held.remove(&owner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::{Mutex, ReentrantMutex};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::TryLockError;
+
+    #[test]
+    fn lock_order_cycle() {
+        let a = Mutex::new(0);
+        let b = Mutex::new(0);
+        {
+            let _a = a.lock().unwrap();
+            let _b = b.lock().unwrap();
+        }
+        // the same order is fine
+        drop((a.lock().unwrap(), b.lock().unwrap()));
+
+        let ret = catch_unwind(AssertUnwindSafe(|| {
+            let _b = b.lock().unwrap();
+            let _a = a.lock().unwrap();
+        }));
+        let err = ret.unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.starts_with("potential deadlock"));
+        assert!(msg.contains("previous acquisition"));
+        // the held mutex is released by the unwinding
+        assert!(a.try_lock().is_ok());
+        assert!(matches!(b.try_lock(), Err(TryLockError::Poisoned(_))));
+    }
+
+    #[test]
+    fn lock_order_reentrant() {
+        let a = ReentrantMutex::new(());
+        let b = Mutex::new(());
+        let h = go!(move || {
+            let _a1 = a.lock();
+            let _b = b.lock().unwrap();
+            // locking again doesn't count as a new order
+            let _a2 = a.lock();
+        });
+        h.join().unwrap();
+    }
+}
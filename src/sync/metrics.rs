@@ -0,0 +1,207 @@
+//! contention metrics of the sync primitives
+//!
+//! with the `sync_metrics` feature enabled, each `Mutex` and `Condvar` records
+//! how often it's acquired, how often the caller has to wait and the longest
+//! wait time. the records are grouped by the source location where the
+//! primitive is created and can be read by [`metrics`].
+//!
+//! without the feature all the recording is a no-op.
+
+use std::panic::Location;
+#[cfg(feature = "sync_metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "sync_metrics")]
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "sync_metrics")]
+use std::time::{Duration, Instant};
+
+/// A snapshot of the contention metrics for the primitives created at the
+/// same source location.
+#[cfg(feature = "sync_metrics")]
+#[derive(Debug, Clone)]
+pub struct LockMetrics {
+    /// The kind of the primitive, `"Mutex"` or `"Condvar"`.
+    pub kind: &'static str,
+    /// Where the primitives are created.
+    pub location: &'static Location<'static>,
+    /// The number of the successful lock acquisitions, or waits for a condvar.
+    pub acquisitions: u64,
+    /// The number of times the caller had to be parked.
+    pub contentions: u64,
+    /// The longest time a caller was parked.
+    pub max_wait: Duration,
+}
+
+#[cfg(feature = "sync_metrics")]
+struct Stats {
+    kind: &'static str,
+    location: &'static Location<'static>,
+    acquisitions: AtomicU64,
+    contentions: AtomicU64,
+    // in ns
+    max_wait: AtomicU64,
+}
+
+#[cfg(feature = "sync_metrics")]
+fn registry() -> &'static parking_lot::Mutex<Vec<Arc<Stats>>> {
+    lazy_static::lazy_static! {
+        static ref REGISTRY: parking_lot::Mutex<Vec<Arc<Stats>>> = Default::default();
+    }
+    &REGISTRY
+}
+
+/// the metrics recorder embedded in a sync primitive
+///
+/// the record is registered on the first use, so that the primitives can be
+/// created in a const context
+#[cfg(feature = "sync_metrics")]
+pub(crate) struct Recorder {
+    kind: &'static str,
+    location: &'static Location<'static>,
+    stats: OnceLock<Arc<Stats>>,
+}
+
+#[cfg(feature = "sync_metrics")]
+impl Recorder {
+    pub const fn new(kind: &'static str, location: &'static Location<'static>) -> Self {
+        Recorder {
+            kind,
+            location,
+            stats: OnceLock::new(),
+        }
+    }
+
+    fn stats(&self) -> &Stats {
+        self.stats.get_or_init(|| {
+            let (kind, location) = (self.kind, self.location);
+            let mut registry = registry().lock();
+            let found = registry
+                .iter()
+                .find(|s| s.kind == kind && s.location == location);
+            match found {
+                Some(s) => s.clone(),
+                None => {
+                    let s = Arc::new(Stats {
+                        kind,
+                        location,
+                        acquisitions: AtomicU64::new(0),
+                        contentions: AtomicU64::new(0),
+                        max_wait: AtomicU64::new(0),
+                    });
+                    registry.push(s.clone());
+                    s
+                }
+            }
+        })
+    }
+
+    #[inline]
+    pub fn acquired(&self) {
+        self.stats().acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // called before the caller is parked
+    #[inline]
+    pub fn contended(&self) -> WaitTimer {
+        self.stats().contentions.fetch_add(1, Ordering::Relaxed);
+        WaitTimer(Instant::now())
+    }
+
+    // called after the parked caller comes back
+    #[inline]
+    pub fn waited(&self, timer: WaitTimer) {
+        let ns = timer.0.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+        self.stats().max_wait.fetch_max(ns, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "sync_metrics")]
+pub(crate) struct WaitTimer(Instant);
+
+#[cfg(not(feature = "sync_metrics"))]
+pub(crate) struct Recorder;
+
+#[cfg(not(feature = "sync_metrics"))]
+impl Recorder {
+    #[inline]
+    pub const fn new(_kind: &'static str, _location: &'static Location<'static>) -> Self {
+        Recorder
+    }
+
+    #[inline]
+    pub fn acquired(&self) {}
+
+    #[inline]
+    pub fn contended(&self) -> WaitTimer {
+        WaitTimer
+    }
+
+    #[inline]
+    pub fn waited(&self, _timer: WaitTimer) {}
+}
+
+#[cfg(not(feature = "sync_metrics"))]
+pub(crate) struct WaitTimer;
+
+/// Returns the contention metrics of all the `Mutex` and `Condvar` used so
+/// far, the busiest ones first.
+///
+/// The primitives created at the same source location share one record.
+/// Only available with the `sync_metrics` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::{metrics, Mutex};
+///
+/// let m = Mutex::new(0);
+/// *m.lock().unwrap() += 1;
+///
+/// for m in metrics() {
+///     println!(
+///         "{} at {}: acquisitions={}, contentions={}, max_wait={:?}",
+///         m.kind, m.location, m.acquisitions, m.contentions, m.max_wait
+///     );
+/// }
+/// ```
+#[cfg(feature = "sync_metrics")]
+pub fn metrics() -> Vec<LockMetrics> {
+    let mut ret: Vec<_> = registry()
+        .lock()
+        .iter()
+        .map(|s| LockMetrics {
+            kind: s.kind,
+            location: s.location,
+            acquisitions: s.acquisitions.load(Ordering::Relaxed),
+            contentions: s.contentions.load(Ordering::Relaxed),
+            max_wait: Duration::from_nanos(s.max_wait.load(Ordering::Relaxed)),
+        })
+        .collect();
+    ret.sort_by_key(|m| std::cmp::Reverse(m.contentions));
+    ret
+}
+
+#[cfg(all(test, feature = "sync_metrics"))]
+mod tests {
+    use super::*;
+    use crate::sync::Mutex;
+
+    #[test]
+    fn mutex_metrics() {
+        let m = Arc::new(Mutex::new(0));
+        let m1 = m.clone();
+        let g = m.lock().unwrap();
+        let h = go!(move || *m1.lock().unwrap() += 1);
+        crate::coroutine::sleep(Duration::from_millis(10));
+        drop(g);
+        h.join().unwrap();
+
+        let stat = metrics()
+            .into_iter()
+            .find(|s| s.kind == "Mutex" && s.location.file() == file!())
+            .unwrap();
+        assert_eq!(stat.acquisitions, 2);
+        assert_eq!(stat.contentions, 1);
+        assert!(stat.max_wait >= Duration::from_millis(5));
+    }
+}
@@ -1,23 +1,40 @@
 mod atomic_option;
 mod blocking;
+mod cancel_token;
 mod condvar;
+#[cfg(feature = "lock_order")]
+mod lock_order;
+mod metrics;
 mod mutex;
+mod once_cell;
 mod poison;
+mod reentrant_mutex;
 mod rwlock;
 mod semphore;
+mod spsc_ring;
 mod sync_flag;
 
 pub(crate) mod atomic_dur;
+pub mod bytes_channel;
+pub mod debug;
 #[cfg(not(unix))]
 pub(crate) mod delay_drop;
 pub mod mpmc;
 pub mod mpsc;
 pub mod queue;
+pub mod rpc;
+pub mod sharded_map;
 pub mod spsc;
 pub use self::atomic_option::{AtomicOption, PointerType};
-pub use self::blocking::{Blocker, FastBlocker};
+pub use self::blocking::{Blocker, FastBlocker, Parker};
+pub use self::cancel_token::{CancellationToken, Cancelled};
 pub use self::condvar::{Condvar, WaitTimeoutResult};
+#[cfg(feature = "sync_metrics")]
+pub use self::metrics::{metrics, LockMetrics};
 pub use self::mutex::{Mutex, MutexGuard};
+pub use self::once_cell::{Lazy, OnceCell};
+pub use self::reentrant_mutex::{ReentrantMutex, ReentrantMutexGuard};
 pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 pub use self::semphore::Semphore;
+pub use self::sharded_map::ShardedMap;
 pub use self::sync_flag::SyncFlag;
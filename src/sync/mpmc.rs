@@ -4,14 +4,21 @@
 //! it's almost the same as `mpsc` except that we support multi receivers
 //! each receiver would consume one data each time so that other receivers
 //! would not see that the same data any more
+//!
+//! the parked receivers are woken up in the order they are parked, and the
+//! data is handed over to the woken receiver directly, so a busy receiver
+//! can't take the data away from the idle ones. it can be used as the work
+//! queue of a worker pool without a dispatcher coroutine
 
 use std::fmt;
+use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
 use std::sync::Arc;
 use std::time::Duration;
 
 use super::Semphore;
+use crate::metrics::{self, Counter};
 use crossbeam::queue::SegQueue;
 
 /// /////////////////////////////////////////////////////////////////////////////
@@ -43,6 +50,7 @@ impl<T> InnerQueue<T> {
         }
 
         self.queue.push(t);
+        metrics::inc(Counter::ChannelSends);
         self.sem.post();
         Ok(())
     }
@@ -54,6 +62,7 @@ impl<T> InnerQueue<T> {
             Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
         }
 
+        metrics::inc(Counter::ChannelParks);
         match dur {
             None => self.sem.wait(),
             Some(t) => {
@@ -136,6 +145,9 @@ pub struct Receiver<T> {
 
 unsafe impl<T: Send> Send for Receiver<T> {}
 // impl<T> !Sync for Receiver<T> {}
+// an interrupted recv never loses the data
+impl<T: Send> UnwindSafe for Receiver<T> {}
+impl<T: Send> RefUnwindSafe for Receiver<T> {}
 
 pub struct Iter<'a, T: 'a> {
     rx: &'a Receiver<T>,
@@ -155,15 +167,42 @@ pub struct Sender<T> {
 
 unsafe impl<T: Send> Send for Sender<T> {}
 // impl<T> !Sync for Sender<T> {}
-
+impl<T: Send> UnwindSafe for Sender<T> {}
+impl<T: Send> RefUnwindSafe for Sender<T> {}
+
+/// Creates a new channel that the receivers can be cloned, each message is
+/// received by exactly one of them.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::mpmc;
+///
+/// let (tx, rx) = mpmc::channel();
+/// let workers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let rx = rx.clone();
+///         may::go!(move || rx.iter().map(|n: u32| n * 2).sum::<u32>())
+///     })
+///     .collect();
+/// drop(rx);
+///
+/// for n in 1..=100 {
+///     tx.send(n).unwrap();
+/// }
+/// drop(tx);
+///
+/// let total: u32 = workers.into_iter().map(|h| h.join().unwrap()).sum();
+/// assert_eq!(total, 10100);
+/// ```
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let a = Arc::new(InnerQueue::new());
     (Sender::new(a.clone()), Receiver::new(a))
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Sender
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Sender
+// /////////////////////////////////////////////////////////////////////////////
 
 impl<T> Sender<T> {
     fn new(inner: Arc<InnerQueue<T>>) -> Sender<T> {
@@ -178,6 +217,16 @@ impl<T> Sender<T> {
     pub fn pressure(&self) -> usize {
         self.inner.sem.get_value()
     }
+
+    /// return the number of the messages waiting in the channel
+    pub fn len(&self) -> usize {
+        self.inner.queue.len()
+    }
+
+    /// return true if there is no message waiting in the channel
+    pub fn is_empty(&self) -> bool {
+        self.inner.queue.is_empty()
+    }
 }
 
 impl<T> Clone for Sender<T> {
@@ -199,9 +248,9 @@ impl<T> fmt::Debug for Sender<T> {
     }
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Receiver
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Receiver
+// /////////////////////////////////////////////////////////////////////////////
 
 impl<T> Receiver<T> {
     fn new(inner: Arc<InnerQueue<T>>) -> Receiver<T> {
@@ -223,13 +272,23 @@ impl<T> Receiver<T> {
         self.inner.recv(Some(timeout))
     }
 
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
         Iter { rx: self }
     }
 
-    pub fn try_iter(&self) -> TryIter<T> {
+    pub fn try_iter(&self) -> TryIter<'_, T> {
         TryIter { rx: self }
     }
+
+    /// return the number of the messages waiting in the channel
+    pub fn len(&self) -> usize {
+        self.inner.queue.len()
+    }
+
+    /// return true if there is no message waiting in the channel
+    pub fn is_empty(&self) -> bool {
+        self.inner.queue.is_empty()
+    }
 }
 
 impl<'a, T> Iterator for Iter<'a, T> {
@@ -1036,4 +1095,43 @@ mod tests {
         }
         assert!(rx1.try_recv().is_err());
     }
+
+    #[test]
+    fn len_and_fair_recv() {
+        let (tx, rx) = channel::<usize>();
+        assert!(rx.is_empty());
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!((tx.len(), rx.len()), (2, 2));
+        assert_eq!(rx.try_iter().count(), 2);
+        assert!(tx.is_empty());
+
+        // the parked receivers take the messages in turn
+        let (ack_tx, ack_rx) = crate::sync::mpsc::channel();
+        let workers: Vec<_> = (0..4)
+            .map(|id| {
+                let rx = rx.clone();
+                let ack_tx = ack_tx.clone();
+                go!(move || {
+                    let mut got = 0;
+                    for _ in rx.iter() {
+                        got += 1;
+                        ack_tx.send(id).unwrap();
+                    }
+                    got
+                })
+            })
+            .collect();
+        drop(rx);
+        thread::sleep(Duration::from_millis(50));
+
+        for i in 0..40 {
+            tx.send(i).unwrap();
+            ack_rx.recv().unwrap();
+        }
+        drop(tx);
+        let counts: Vec<usize> = workers.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(counts.iter().sum::<usize>(), 40);
+        assert!(counts.iter().all(|n| *n >= 5), "unfair {:?}", counts);
+    }
 }
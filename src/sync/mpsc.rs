@@ -1,63 +1,221 @@
 //! compatible with std::sync::mpsc except for both thread and coroutine
 //! please ref the doc from std::sync::mpsc
+//!
+//! the bounded [`sync_channel`] doesn't support a zero bound, and its
+//! senders can [`reserve`] a slot before the value is ready.
+//!
+//! ## Sending from threads
+//!
+//! the sends of the unbounded [`channel`] never block, and they work the
+//! same in any thread, including the ones that are not managed by may, e.g.
+//! the callback threads of a C library. the value is pushed into a lock
+//! free queue, and a parked receiver coroutine is put to the ready queue of
+//! its own scheduler, whose worker is woken up by an eventfd write if it's
+//! sleeping. a parked receiver thread is unparked directly. use
+//! [`SenderFromThread`] to share one sender among such threads.
+//!
+//! the receiver keeps the blocker that it parks on and registers it again
+//! for the next blocking recv, so only the first one allocates the blocker.
+//!
+//! the [`priority_channel`] has a lane for each priority level, so the
+//! control messages can overtake the bulk data that is already queued.
+//!
+//! [`reserve`]: SyncSender::reserve
+use std::cell::Cell;
 use std::fmt;
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
+use super::blocking::cached_blocker;
 use super::queue::mpsc_seg_queue::SegQueue;
+use super::queue::seg_queue::SegQueue as WaiterQueue;
 use super::{AtomicOption, Blocker};
+use crate::cancel::trigger_cancel_panic;
 use crate::likely::{likely, unlikely};
+use crate::metrics::{self, Counter};
+use crate::park::ParkError;
 
-// TODO: SyncSender
-/// /////////////////////////////////////////////////////////////////////////////
-/// InnerQueue
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// InnerQueue
+// /////////////////////////////////////////////////////////////////////////////
 struct InnerQueue<T> {
     queue: SegQueue<T>,
     // thread/coroutine for wake up
     to_wake: AtomicOption<Arc<Blocker>>,
     // The number of tx channels which are currently using this queue.
     channels: AtomicUsize,
-    // if rx is dropped
-    port_dropped: AtomicBool,
+    // if rx is dropped or the channel is closed
+    closed: AtomicBool,
+    // the senders that wait for the channel to be closed
+    close_waiters: WaiterQueue<Arc<Blocker>>,
+    // if there is a receiver using this queue
+    rx_alive: AtomicBool,
+    // the number of the weak receivers
+    weak_rx: AtomicUsize,
+    // if the sends are limited by the free slots
+    bounded: bool,
+    // the free slots of a bounded channel
+    slots: AtomicUsize,
+    // the senders that wait for a free slot
+    send_waiters: WaiterQueue<Arc<Blocker>>,
 }
 
 impl<T> InnerQueue<T> {
     pub fn new() -> InnerQueue<T> {
+        InnerQueue::with_slots(false, 0)
+    }
+
+    pub fn bounded(bound: usize) -> InnerQueue<T> {
+        InnerQueue::with_slots(true, bound)
+    }
+
+    fn with_slots(bounded: bool, slots: usize) -> InnerQueue<T> {
         InnerQueue {
             queue: SegQueue::new(),
             to_wake: AtomicOption::none(),
             channels: AtomicUsize::new(1),
-            port_dropped: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            close_waiters: WaiterQueue::new(),
+            rx_alive: AtomicBool::new(true),
+            weak_rx: AtomicUsize::new(0),
+            bounded,
+            slots: AtomicUsize::new(slots),
+            send_waiters: WaiterQueue::new(),
+        }
+    }
+
+    // take a free slot of the bounded channel, wait for it if `block`
+    pub fn acquire(&self, block: bool) -> Result<(), TrySendError<()>> {
+        loop {
+            if unlikely(self.is_closed()) {
+                return Err(TrySendError::Disconnected(()));
+            }
+            if self.try_take_slot() {
+                return Ok(());
+            }
+            if !block {
+                return Err(TrySendError::Full(()));
+            }
+            let cur = Blocker::current();
+            // the waiters queue is unbounded, never fails
+            let _ = self.send_waiters.push(cur.clone());
+            // re-check in case a slot is just released or the channel closed
+            if self.is_closed() || self.slots.load(Ordering::SeqCst) > 0 {
+                continue;
+            }
+            if let Err(ParkError::Canceled) = cur.park(None) {
+                // the wakeup may be for us, let the others retry
+                self.wake_senders();
+                trigger_cancel_panic();
+            }
+        }
+    }
+
+    fn try_take_slot(&self) -> bool {
+        let mut n = self.slots.load(Ordering::SeqCst);
+        while n > 0 {
+            match self
+                .slots
+                .compare_exchange_weak(n, n - 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return true,
+                Err(cur) => n = cur,
+            }
+        }
+        false
+    }
+
+    // give back a slot when the data is received or a permit is unused
+    pub fn release(&self) {
+        if self.bounded {
+            self.slots.fetch_add(1, Ordering::SeqCst);
+            self.wake_senders();
+        }
+    }
+
+    // a waiter may have left the queue without being woken, so all of them
+    // are woken to retry instead of only one
+    fn wake_senders(&self) {
+        while let Some(w) = self.send_waiters.pop() {
+            w.unpark();
         }
     }
 
+    // push the data with a slot that is already taken
+    pub fn send_reserved(&self, t: T) {
+        if unlikely(self.closed.load(Ordering::Acquire)) {
+            // no one would receive it, the data is dropped
+            self.release();
+            return;
+        }
+        self.queue.push(t);
+        metrics::inc(Counter::ChannelSends);
+        self.wake_receiver();
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let data = self.queue.pop();
+        if data.is_some() {
+            self.release();
+        }
+        data
+    }
+
     pub fn send(&self, t: T) -> Result<(), T> {
-        if unlikely(self.port_dropped.load(Ordering::Acquire)) {
+        if unlikely(self.closed.load(Ordering::Acquire)) {
             return Err(t);
         }
         self.queue.push(t);
+        metrics::inc(Counter::ChannelSends);
+        self.wake_receiver();
+        Ok(())
+    }
+
+    // push all the data and wake up the receiver only once
+    pub fn send_all<I: Iterator<Item = T>>(&self, iter: I) -> Result<usize, I> {
+        if unlikely(self.closed.load(Ordering::Acquire)) {
+            return Err(iter);
+        }
+        let mut n = 0;
+        for t in iter {
+            self.queue.push(t);
+            metrics::inc(Counter::ChannelSends);
+            n += 1;
+        }
+        if n > 0 {
+            self.wake_receiver();
+        }
+        Ok(n)
+    }
+
+    // wake up the parked receiver if any
+    fn wake_receiver(&self) {
         if let Some(w) = self.to_wake.take(Ordering::Acquire) {
             w.unpark();
         }
-        Ok(())
     }
 
-    pub fn recv(&self, dur: Option<Duration>) -> Result<T, TryRecvError> {
+    // `blocker` is the cached blocker of the receiver
+    pub fn recv(
+        &self,
+        dur: Option<Duration>,
+        blocker: &AtomicOption<Arc<Blocker>>,
+    ) -> Result<T, TryRecvError> {
         match self.try_recv() {
             Err(TryRecvError::Empty) => {}
             data => return data,
         }
 
-        let cur = Blocker::current();
+        let cur = cached_blocker(blocker);
         // register the waiter
         self.to_wake.swap(cur.clone(), Ordering::Release);
         // re-check the queue
         match self.try_recv() {
             Err(TryRecvError::Empty) => {
+                metrics::inc(Counter::ChannelParks);
                 cur.park(dur).ok();
             }
             data => {
@@ -72,14 +230,14 @@ impl<T> InnerQueue<T> {
     }
 
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
-        match self.queue.pop() {
+        match self.pop() {
             Some(data) => Ok(data),
             None => {
-                if likely(self.channels.load(Ordering::Acquire) > 0) {
+                if likely(self.channels.load(Ordering::Acquire) > 0 && !self.is_closed()) {
                     Err(TryRecvError::Empty)
                 } else {
-                    // there is no sender any more, should re-check
-                    self.queue.pop().ok_or(TryRecvError::Disconnected)
+                    // there is no sender any more or closed, should re-check
+                    self.pop().ok_or(TryRecvError::Disconnected)
                 }
             }
         }
@@ -89,22 +247,66 @@ impl<T> InnerQueue<T> {
         self.channels.fetch_add(1, Ordering::AcqRel);
     }
 
+    // add a tx channel only if there is still one
+    pub fn upgrade_chan(&self) -> bool {
+        let mut n = self.channels.load(Ordering::Acquire);
+        while n > 0 {
+            match self
+                .channels
+                .compare_exchange_weak(n, n + 1, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(cur) => n = cur,
+            }
+        }
+        false
+    }
+
     pub fn drop_chan(&self) {
         match self.channels.fetch_sub(1, Ordering::AcqRel) {
-            1 => self
-                .to_wake
-                .take(Ordering::Relaxed)
-                .map(|w| w.unpark())
-                .unwrap_or(()),
+            1 => self.wake_receiver(),
             n if n > 1 => {}
             n => panic!("bad number of channels left {}", n),
         }
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    // the sent data is still received
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.wake_receiver();
+        while let Some(w) = self.close_waiters.pop() {
+            w.unpark();
+        }
+        self.wake_senders();
+    }
+
+    pub fn wait_closed(&self) {
+        while !self.is_closed() {
+            let cur = Blocker::current();
+            // the waiters queue is unbounded, never fails
+            let _ = self.close_waiters.push(cur.clone());
+            // re-check the state in case it's just closed
+            if self.is_closed() {
+                break;
+            }
+            if let Err(ParkError::Canceled) = cur.park(None) {
+                trigger_cancel_panic();
+            }
+        }
+    }
+
     pub fn drop_port(&self) {
-        self.port_dropped.store(true, Ordering::Release);
-        // clear all the data
-        while self.queue.pop().is_some() {}
+        self.close();
+        // the weak receivers can still receive the data left
+        if self.weak_rx.load(Ordering::Acquire) == 0 {
+            // clear all the data
+            while self.queue.pop().is_some() {}
+        }
+        self.rx_alive.store(false, Ordering::Release);
     }
 }
 
@@ -117,10 +319,15 @@ impl<T> Drop for InnerQueue<T> {
 
 pub struct Receiver<T> {
     inner: Arc<InnerQueue<T>>,
+    // the blocker that is parked on, reused by the next blocking recv
+    blocker: AtomicOption<Arc<Blocker>>,
 }
 
 unsafe impl<T: Send> Send for Receiver<T> {}
 // impl<T> !Sync for Receiver<T> {}
+// an interrupted recv never loses the data
+impl<T: Send> UnwindSafe for Receiver<T> {}
+impl<T: Send> RefUnwindSafe for Receiver<T> {}
 
 pub struct Iter<'a, T: 'a> {
     rx: &'a Receiver<T>,
@@ -148,9 +355,9 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     (Sender::new(a.clone()), Receiver::new(a))
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Sender
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Sender
+// /////////////////////////////////////////////////////////////////////////////
 
 impl<T> Sender<T> {
     fn new(inner: Arc<InnerQueue<T>>) -> Sender<T> {
@@ -160,6 +367,76 @@ impl<T> Sender<T> {
     pub fn send(&self, t: T) -> Result<(), SendError<T>> {
         self.inner.send(t).map_err(SendError)
     }
+
+    /// Sends all the values of the iterator, returns the number of them.
+    ///
+    /// The receiver is woken up once for the whole batch instead of once
+    /// per value. If the channel is closed, the iterator is returned back in
+    /// the error without being consumed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use may::sync::mpsc::channel;
+    ///
+    /// let (tx, rx) = channel();
+    /// assert_eq!(tx.send_all(0..3).unwrap(), 3);
+    /// let mut buf = Vec::new();
+    /// assert_eq!(rx.recv_many(&mut buf, 10).unwrap(), 3);
+    /// assert_eq!(buf, [0, 1, 2]);
+    /// ```
+    pub fn send_all<I: IntoIterator<Item = T>>(
+        &self,
+        iter: I,
+    ) -> Result<usize, SendError<I::IntoIter>> {
+        self.inner.send_all(iter.into_iter()).map_err(SendError)
+    }
+
+    /// Closes the channel for all the senders.
+    ///
+    /// The following sends fail with the value returned back in the error,
+    /// the receiver gets the data that is already sent, then a disconnected
+    /// error.
+    pub fn close(&self) {
+        self.inner.close();
+    }
+
+    /// Returns true if the channel is closed or the receiver is dropped.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Blocks until the channel is closed or the receiver is dropped.
+    ///
+    /// This can be used by a producer to stop early when the consumer goes
+    /// away.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use may::sync::mpsc::channel;
+    ///
+    /// let (tx, rx) = channel::<u32>();
+    /// let h = may::go!(move || {
+    ///     tx.closed();
+    ///     tx.send(1).unwrap_err().0
+    /// });
+    /// drop(rx);
+    /// assert_eq!(h.join().unwrap(), 1);
+    /// ```
+    pub fn closed(&self) {
+        self.inner.wait_closed();
+    }
+
+    /// Creates a [`WeakSender`] that doesn't count as a sender.
+    ///
+    /// The receiver gets the disconnected error after all the senders are
+    /// dropped, no matter how many weak senders are left.
+    pub fn downgrade(&self) -> WeakSender<T> {
+        WeakSender {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
 }
 
 impl<T> Clone for Sender<T> {
@@ -181,28 +458,318 @@ impl<T> fmt::Debug for Sender<T> {
     }
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Receiver
-/// /////////////////////////////////////////////////////////////////////////////
+/// A [`Sender`] for the threads that are not managed by may.
+///
+/// It's `Sync`, so one sender can be shared by the callbacks of a C library
+/// that run on any thread. The send never blocks the calling thread and
+/// doesn't need a coroutine context, see the [module docs] for the wakeup
+/// path.
+///
+/// [module docs]: self#sending-from-threads
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use may::sync::mpsc::{channel, SenderFromThread};
+///
+/// let (tx, rx) = channel();
+/// let tx = Arc::new(SenderFromThread::new(tx));
+/// let h = may::go!(move || rx.iter().sum::<u32>());
+///
+/// let threads: Vec<_> = (0..4)
+///     .map(|i| {
+///         let tx = tx.clone();
+///         std::thread::spawn(move || tx.send(i).unwrap())
+///     })
+///     .collect();
+/// for t in threads {
+///     t.join().unwrap();
+/// }
+/// drop(tx);
+/// assert_eq!(h.join().unwrap(), 6);
+/// ```
+pub struct SenderFromThread<T> {
+    tx: Sender<T>,
+}
+
+// all the send path is lock free and thread safe
+unsafe impl<T: Send> Sync for SenderFromThread<T> {}
+
+impl<T> SenderFromThread<T> {
+    /// Wraps the sender.
+    pub fn new(tx: Sender<T>) -> Self {
+        SenderFromThread { tx }
+    }
+
+    /// Sends the value without blocking, see [`Sender::send`].
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.tx.send(t)
+    }
+
+    /// Returns true if the channel is closed or the receiver is dropped.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Gets the sender back.
+    pub fn into_inner(self) -> Sender<T> {
+        self.tx
+    }
+}
+
+impl<T> From<Sender<T>> for SenderFromThread<T> {
+    fn from(tx: Sender<T>) -> Self {
+        SenderFromThread::new(tx)
+    }
+}
+
+impl<T> Clone for SenderFromThread<T> {
+    fn clone(&self) -> Self {
+        SenderFromThread::new(self.tx.clone())
+    }
+}
+
+impl<T> fmt::Debug for SenderFromThread<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SenderFromThread {{ .. }}")
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// SyncSender
+// /////////////////////////////////////////////////////////////////////////////
+
+/// The sending half of a bounded channel created by [`sync_channel`].
+pub struct SyncSender<T> {
+    inner: Arc<InnerQueue<T>>,
+}
+
+unsafe impl<T: Send> Send for SyncSender<T> {}
+impl<T: Send> UnwindSafe for SyncSender<T> {}
+impl<T: Send> RefUnwindSafe for SyncSender<T> {}
+
+/// Creates a bounded channel that holds at most `bound` values.
+///
+/// The sends block when the channel is full, until the receiver takes a
+/// value out.
+///
+/// # Panics
+///
+/// Panics if `bound` is zero, the rendezvous channel is not supported.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::mpsc::sync_channel;
+/// use std::sync::mpsc::TrySendError;
+///
+/// let (tx, rx) = sync_channel(1);
+/// tx.send(1).unwrap();
+/// assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+/// assert_eq!(rx.recv().unwrap(), 1);
+/// tx.try_send(2).unwrap();
+/// ```
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    assert!(
+        bound > 0,
+        "the bound of sync_channel must be greater than 0"
+    );
+    let a = Arc::new(InnerQueue::bounded(bound));
+    (SyncSender::new(a.clone()), Receiver::new(a))
+}
+
+impl<T> SyncSender<T> {
+    fn new(inner: Arc<InnerQueue<T>>) -> SyncSender<T> {
+        SyncSender { inner }
+    }
+
+    /// Sends the value, blocks until there is a free slot.
+    ///
+    /// The value is returned back in the error if the channel is closed.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        match self.inner.acquire(true) {
+            Ok(()) => {
+                self.inner.send_reserved(t);
+                Ok(())
+            }
+            Err(_) => Err(SendError(t)),
+        }
+    }
+
+    /// Sends the value if there is a free slot without blocking.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        match self.inner.acquire(false) {
+            Ok(()) => {
+                self.inner.send_reserved(t);
+                Ok(())
+            }
+            Err(TrySendError::Full(())) => Err(TrySendError::Full(t)),
+            Err(TrySendError::Disconnected(())) => Err(TrySendError::Disconnected(t)),
+        }
+    }
+
+    /// Waits for a free slot and reserves it, so the send by the returned
+    /// [`Permit`] never blocks.
+    ///
+    /// It's useful to make sure that the channel has the room before
+    /// building an expensive value. The slot is given back if the permit is
+    /// dropped without sending.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use may::sync::mpsc::sync_channel;
+    ///
+    /// let (tx, rx) = sync_channel(1);
+    /// let permit = tx.reserve().unwrap();
+    /// // the reserved slot is not available to the others
+    /// assert!(tx.try_reserve().is_err());
+    /// permit.send(String::from("expensive"));
+    /// assert_eq!(rx.recv().unwrap(), "expensive");
+    /// ```
+    pub fn reserve(&self) -> Result<Permit<'_, T>, SendError<()>> {
+        match self.inner.acquire(true) {
+            Ok(()) => Ok(Permit { inner: &self.inner }),
+            Err(_) => Err(SendError(())),
+        }
+    }
+
+    /// Reserves a free slot without blocking, see [`reserve`].
+    ///
+    /// [`reserve`]: SyncSender::reserve
+    pub fn try_reserve(&self) -> Result<Permit<'_, T>, TrySendError<()>> {
+        self.inner
+            .acquire(false)
+            .map(|_| Permit { inner: &self.inner })
+    }
+
+    /// Closes the channel for all the senders, see [`Sender::close`].
+    pub fn close(&self) {
+        self.inner.close();
+    }
+
+    /// Returns true if the channel is closed or the receiver is dropped.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Blocks until the channel is closed or the receiver is dropped.
+    pub fn closed(&self) {
+        self.inner.wait_closed();
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> SyncSender<T> {
+        self.inner.clone_chan();
+        SyncSender::new(self.inner.clone())
+    }
+}
+
+impl<T> Drop for SyncSender<T> {
+    fn drop(&mut self) {
+        self.inner.drop_chan();
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SyncSender {{ .. }}")
+    }
+}
+
+/// A slot of a bounded channel reserved by [`SyncSender::reserve`].
+///
+/// The slot is given back if it's dropped without sending.
+pub struct Permit<'a, T> {
+    inner: &'a InnerQueue<T>,
+}
+
+impl<'a, T> Permit<'a, T> {
+    /// Sends the value with the reserved slot, it never blocks.
+    ///
+    /// The value is dropped if the channel is closed after the reservation.
+    pub fn send(self, t: T) {
+        let inner = self.inner;
+        // the slot is taken by the value now
+        std::mem::forget(self);
+        inner.send_reserved(t);
+    }
+}
+
+impl<'a, T> Drop for Permit<'a, T> {
+    fn drop(&mut self) {
+        self.inner.release();
+    }
+}
+
+impl<'a, T> fmt::Debug for Permit<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Permit {{ .. }}")
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Receiver
+// /////////////////////////////////////////////////////////////////////////////
 
 impl<T> Receiver<T> {
     fn new(inner: Arc<InnerQueue<T>>) -> Receiver<T> {
-        Receiver { inner }
+        Receiver {
+            inner,
+            blocker: AtomicOption::none(),
+        }
     }
 
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         self.inner.try_recv()
     }
 
+    /// Closes the channel without dropping the receiver.
+    ///
+    /// The senders get the value back in the error promptly, the data that
+    /// is already sent can still be received.
+    pub fn close(&self) {
+        self.inner.close();
+    }
+
+    /// Returns true if the channel is closed or all the senders are dropped.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed() || self.inner.channels.load(Ordering::Acquire) == 0
+    }
+
     pub fn recv(&self) -> Result<T, RecvError> {
         loop {
-            match self.inner.recv(None) {
+            match self.inner.recv(None, &self.blocker) {
                 Err(TryRecvError::Empty) => {}
                 data => return data.map_err(|_| RecvError),
             }
         }
     }
 
+    /// Receives up to `limit` values into `buf`, returns the number of them.
+    ///
+    /// It blocks until at least one value is available, then takes the other
+    /// ones that are already sent without blocking again. It returns
+    /// `Ok(0)` if `limit` is zero, and an error if the channel is empty and
+    /// disconnected.
+    pub fn recv_many(&self, buf: &mut Vec<T>, limit: usize) -> Result<usize, RecvError> {
+        if limit == 0 {
+            return Ok(0);
+        }
+        buf.push(self.recv()?);
+        let mut n = 1;
+        while n < limit {
+            match self.inner.pop() {
+                Some(t) => buf.push(t),
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
     pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
         // Do an optimistic try_recv to avoid the performance impact of
         // Instant::now() in the full-channel case.
@@ -214,9 +781,9 @@ impl<T> Receiver<T> {
     }
 
     fn recv_max_until(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
-        let deadline = Instant::now() + timeout;
+        let deadline = crate::time::now() + timeout;
         loop {
-            match self.inner.recv(Some(timeout)) {
+            match self.inner.recv(Some(timeout), &self.blocker) {
                 Ok(t) => return Ok(t),
                 Err(TryRecvError::Empty) => {}
                 Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
@@ -224,19 +791,31 @@ impl<T> Receiver<T> {
 
             // If we're already passed the deadline, and we're here without
             // data, return a timeout, else try again.
-            if Instant::now() >= deadline {
+            if crate::time::now() >= deadline {
                 return Err(RecvTimeoutError::Timeout);
             }
         }
     }
 
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
         Iter { rx: self }
     }
 
-    pub fn try_iter(&self) -> TryIter<T> {
+    pub fn try_iter(&self) -> TryIter<'_, T> {
         TryIter { rx: self }
     }
+
+    /// Creates a [`WeakReceiver`] that doesn't keep the channel open.
+    ///
+    /// The channel is still closed when the receiver is dropped, but the data
+    /// that is already sent is kept for the weak receivers instead of being
+    /// dropped.
+    pub fn downgrade(&self) -> WeakReceiver<T> {
+        self.inner.weak_rx.fetch_add(1, Ordering::AcqRel);
+        WeakReceiver {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
 }
 
 impl<'a, T> Iterator for Iter<'a, T> {
@@ -292,12 +871,380 @@ impl<T> fmt::Debug for Receiver<T> {
     }
 }
 
+// /////////////////////////////////////////////////////////////////////////////
+// WeakSender
+// /////////////////////////////////////////////////////////////////////////////
+
+/// A sender that doesn't keep the channel connected, created by
+/// [`Sender::downgrade`].
+///
+/// A registry can hold the weak senders of the consumers without stopping
+/// them from seeing the disconnection when all the real senders are gone.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::mpsc::channel;
+///
+/// let (tx, rx) = channel();
+/// let weak = tx.downgrade();
+/// weak.upgrade().unwrap().send(1).unwrap();
+/// drop(tx);
+/// assert_eq!(rx.recv(), Ok(1));
+/// assert!(rx.recv().is_err());
+/// assert!(weak.upgrade().is_none());
+/// ```
+pub struct WeakSender<T> {
+    inner: Weak<InnerQueue<T>>,
+}
+
+unsafe impl<T: Send> Send for WeakSender<T> {}
+unsafe impl<T: Send> Sync for WeakSender<T> {}
+
+impl<T> WeakSender<T> {
+    /// Returns a sender if there is still any sender of the channel.
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        let inner = self.inner.upgrade()?;
+        if inner.upgrade_chan() {
+            Some(Sender::new(inner))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> WeakSender<T> {
+        WeakSender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for WeakSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WeakSender {{ .. }}")
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// WeakReceiver
+// /////////////////////////////////////////////////////////////////////////////
+
+/// A receiver that doesn't keep the channel open, created by
+/// [`Receiver::downgrade`].
+///
+/// There is only one receiver of a channel at any time, so it can only be
+/// upgraded after the receiver is dropped. The channel is closed by then,
+/// the upgraded receiver gets the data that was sent but not received, e.g.
+/// to hand over the pending requests of a consumer that panicked.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::mpsc::channel;
+///
+/// let (tx, rx) = channel();
+/// let weak = rx.downgrade();
+/// assert!(weak.upgrade().is_none());
+/// tx.send(1).unwrap();
+/// drop(rx);
+/// // the channel is closed by dropping the receiver
+/// assert!(tx.send(2).is_err());
+/// let rx = weak.upgrade().unwrap();
+/// assert_eq!(rx.try_recv(), Ok(1));
+/// ```
+pub struct WeakReceiver<T> {
+    inner: Weak<InnerQueue<T>>,
+}
+
+unsafe impl<T: Send> Send for WeakReceiver<T> {}
+unsafe impl<T: Send> Sync for WeakReceiver<T> {}
+
+impl<T> WeakReceiver<T> {
+    /// Returns the receiver if the current one is dropped and no other weak
+    /// receiver has upgraded.
+    pub fn upgrade(&self) -> Option<Receiver<T>> {
+        let inner = self.inner.upgrade()?;
+        inner
+            .rx_alive
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()?;
+        Some(Receiver::new(inner))
+    }
+}
+
+impl<T> Clone for WeakReceiver<T> {
+    fn clone(&self) -> WeakReceiver<T> {
+        if let Some(inner) = self.inner.upgrade() {
+            inner.weak_rx.fetch_add(1, Ordering::AcqRel);
+        }
+        WeakReceiver {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for WeakReceiver<T> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.upgrade() {
+            inner.weak_rx.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl<T> fmt::Debug for WeakReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WeakReceiver {{ .. }}")
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Priority channel
+// /////////////////////////////////////////////////////////////////////////////
+
+// a lower lane that is passed over by this many receives in a row is served
+// once, so the bulk data still moves when the urgent messages keep coming
+const AGING_PASSES: usize = 32;
+
+struct PriorityQueue<T> {
+    // a queue for each level, the last one has the highest priority
+    lanes: Box<[SegQueue<T>]>,
+    // the parked receiver
+    to_wake: AtomicOption<Arc<Blocker>>,
+    // the number of the senders
+    channels: AtomicUsize,
+    // if the receiver is dropped
+    closed: AtomicBool,
+}
+
+impl<T> PriorityQueue<T> {
+    fn send(&self, t: T, priority: usize) -> Result<(), T> {
+        if unlikely(self.closed.load(Ordering::Acquire)) {
+            return Err(t);
+        }
+        let lane = priority.min(self.lanes.len() - 1);
+        self.lanes[lane].push(t);
+        metrics::inc(Counter::ChannelSends);
+        self.wake_receiver();
+        Ok(())
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.to_wake.take(Ordering::Acquire) {
+            w.unpark();
+        }
+    }
+
+    fn drop_chan(&self) {
+        match self.channels.fetch_sub(1, Ordering::AcqRel) {
+            1 => self.wake_receiver(),
+            n if n > 1 => {}
+            n => panic!("bad number of channels left {}", n),
+        }
+    }
+}
+
+impl<T> Drop for PriorityQueue<T> {
+    fn drop(&mut self) {
+        assert_eq!(self.channels.load(Ordering::Acquire), 0);
+        assert!(self.to_wake.is_none());
+    }
+}
+
+/// The sending half of a [`priority_channel`].
+pub struct PrioritySender<T> {
+    inner: Arc<PriorityQueue<T>>,
+}
+
+/// The receiving half of a [`priority_channel`].
+pub struct PriorityReceiver<T> {
+    inner: Arc<PriorityQueue<T>>,
+    // the receives in a row that passed over each non-empty lane
+    passed: Box<[Cell<usize>]>,
+    // the blocker that is parked on, reused by the next blocking recv
+    blocker: AtomicOption<Arc<Blocker>>,
+}
+
+/// Creates an unbounded channel with `levels` priority lanes.
+///
+/// The priority of a send is from `0` to `levels - 1`, a larger one is more
+/// urgent and the ones out of range are treated as the highest. The receiver
+/// always takes the value of the highest priority that is available, the
+/// values of the same priority are received in order. A lower priority that
+/// is passed over by 32 receives in a row is served once, so the bulk data
+/// is not starved by a steady flow of the urgent messages.
+///
+/// # Panics
+///
+/// Panics if `levels` is zero.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::mpsc::priority_channel;
+///
+/// let (tx, rx) = priority_channel(2);
+/// tx.send("bulk", 0).unwrap();
+/// tx.send("control", 1).unwrap();
+/// assert_eq!(rx.recv().unwrap(), "control");
+/// assert_eq!(rx.recv().unwrap(), "bulk");
+/// ```
+pub fn priority_channel<T>(levels: usize) -> (PrioritySender<T>, PriorityReceiver<T>) {
+    assert!(levels > 0, "priority channel needs at least one level");
+    let inner = Arc::new(PriorityQueue {
+        lanes: (0..levels).map(|_| SegQueue::new()).collect(),
+        to_wake: AtomicOption::none(),
+        channels: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    let rx = PriorityReceiver {
+        inner: inner.clone(),
+        passed: (0..levels).map(|_| Cell::new(0)).collect(),
+        blocker: AtomicOption::none(),
+    };
+    (PrioritySender { inner }, rx)
+}
+
+impl<T> PrioritySender<T> {
+    /// Sends the value with the priority, it fails only if the receiver is
+    /// dropped.
+    pub fn send(&self, t: T, priority: usize) -> Result<(), SendError<T>> {
+        self.inner.send(t, priority).map_err(SendError)
+    }
+
+    /// Returns the number of the priority levels.
+    pub fn levels(&self) -> usize {
+        self.inner.lanes.len()
+    }
+}
+
+impl<T> Clone for PrioritySender<T> {
+    fn clone(&self) -> PrioritySender<T> {
+        self.inner.channels.fetch_add(1, Ordering::AcqRel);
+        PrioritySender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for PrioritySender<T> {
+    fn drop(&mut self) {
+        self.inner.drop_chan();
+    }
+}
+
+impl<T> fmt::Debug for PrioritySender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrioritySender {{ .. }}")
+    }
+}
+
+impl<T> PriorityReceiver<T> {
+    /// Returns the number of the priority levels.
+    pub fn levels(&self) -> usize {
+        self.inner.lanes.len()
+    }
+
+    // take from the highest non-empty lane, unless a lower one is starving
+    fn pop(&self) -> Option<T> {
+        let lanes = &self.inner.lanes;
+        let top = lanes.iter().rposition(|lane| !lane.is_empty())?;
+        for i in (0..top).rev() {
+            let passed = &self.passed[i];
+            if lanes[i].is_empty() {
+                passed.set(0);
+            } else if passed.get() >= AGING_PASSES {
+                passed.set(0);
+                return lanes[i].pop();
+            } else {
+                passed.set(passed.get() + 1);
+            }
+        }
+        lanes[top].pop()
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.pop() {
+            Some(data) => Ok(data),
+            None => {
+                if likely(self.inner.channels.load(Ordering::Acquire) > 0) {
+                    Err(TryRecvError::Empty)
+                } else {
+                    // there is no sender any more, should re-check
+                    self.pop().ok_or(TryRecvError::Disconnected)
+                }
+            }
+        }
+    }
+
+    // same as `InnerQueue::recv`
+    fn recv_impl(&self, dur: Option<Duration>) -> Result<T, TryRecvError> {
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            data => return data,
+        }
+
+        let cur = cached_blocker(&self.blocker);
+        self.inner.to_wake.swap(cur.clone(), Ordering::Release);
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {
+                metrics::inc(Counter::ChannelParks);
+                cur.park(dur).ok();
+            }
+            data => {
+                self.inner.to_wake.take(Ordering::Acquire);
+                return data;
+            }
+        }
+        self.try_recv()
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.recv_impl(None) {
+                Err(TryRecvError::Empty) => {}
+                data => return data.map_err(|_| RecvError),
+            }
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = crate::time::now() + timeout;
+        loop {
+            match self.recv_impl(Some(timeout)) {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+            if crate::time::now() >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+}
+
+impl<T> Drop for PriorityReceiver<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        for lane in self.inner.lanes.iter() {
+            while lane.pop().is_some() {}
+        }
+    }
+}
+
+impl<T> fmt::Debug for PriorityReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PriorityReceiver {{ .. }}")
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::redundant_clone)]
 mod tests {
     use super::*;
     use std::env;
-    use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+    use std::sync::mpsc::{RecvTimeoutError, TryRecvError, TrySendError};
     use std::thread;
     use std::time::{Duration, Instant};
 
@@ -315,6 +1262,51 @@ mod tests {
         assert_eq!(rx.recv().unwrap(), 1);
     }
 
+    #[test]
+    fn priority_lanes() {
+        let (tx, rx) = priority_channel::<(usize, usize)>(3);
+        for i in 0..3 {
+            for p in 0..3 {
+                tx.send((p, i), p).unwrap();
+            }
+        }
+        // the out of range priority is the highest one
+        tx.send((2, 3), 10).unwrap();
+        let got: Vec<_> = (0..10).map(|_| rx.recv().unwrap()).collect();
+        let mut want = vec![(2, 0), (2, 1), (2, 2), (2, 3)];
+        want.extend((0..3).map(|i| (1, i)));
+        want.extend((0..3).map(|i| (0, i)));
+        assert_eq!(got, want);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        let h = go!(move || rx.recv());
+        thread::sleep(Duration::from_millis(10));
+        tx.send((0, 0), 0).unwrap();
+        assert_eq!(h.join().unwrap(), Ok((0, 0)));
+        assert!(tx.send((0, 1), 0).is_err());
+    }
+
+    #[test]
+    fn priority_aging() {
+        let (tx, rx) = priority_channel(2);
+        tx.send(0, 0).unwrap();
+        for i in 1..=100 {
+            tx.send(i, 1).unwrap();
+        }
+        // the bulk one is served after 32 urgent ones
+        let pos = (0..101).position(|_| rx.recv().unwrap() == 0);
+        assert_eq!(pos, Some(AGING_PASSES));
+        // the urgent ones after it are still there
+        let rest: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(rest, (AGING_PASSES + 1..=100).collect::<Vec<_>>());
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
     #[test]
     fn drop_full() {
         let (tx, _rx) = channel::<Box<isize>>();
@@ -339,6 +1331,127 @@ mod tests {
         assert_eq!(rx.recv().unwrap(), 1);
     }
 
+    #[test]
+    fn batch_send_recv() {
+        let (tx, rx) = channel::<i32>();
+        let h = go!(move || {
+            let mut buf = Vec::new();
+            while rx.recv_many(&mut buf, 4).is_ok() {}
+            buf
+        });
+        for i in 0..5 {
+            assert_eq!(tx.send_all(i * 10..i * 10 + 10).unwrap(), 10);
+        }
+        drop(tx);
+        let buf = h.join().unwrap();
+        assert_eq!(buf, (0..50).collect::<Vec<_>>());
+
+        let (tx, rx) = channel::<i32>();
+        let mut buf = Vec::new();
+        assert_eq!(rx.recv_many(&mut buf, 0), Ok(0));
+        rx.close();
+        let mut rest = tx.send_all(vec![1, 2]).unwrap_err().0;
+        assert_eq!(rest.next(), Some(1));
+        drop(tx);
+        assert_eq!(rx.recv_many(&mut buf, 10), Err(RecvError));
+    }
+
+    #[test]
+    fn weak_handles() {
+        let (tx, rx) = channel::<i32>();
+        let weak_tx = tx.downgrade();
+        let h = go!(move || rx.iter().collect::<Vec<_>>());
+        let tx2 = weak_tx.upgrade().unwrap();
+        tx2.send(1).unwrap();
+        drop(tx2);
+        tx.send(2).unwrap();
+        // the weak sender doesn't keep the receiver waiting
+        drop(tx);
+        assert_eq!(h.join().unwrap(), [1, 2]);
+        assert!(weak_tx.upgrade().is_none());
+
+        let (tx, rx) = channel::<i32>();
+        let weak_rx = rx.downgrade();
+        let weak_rx2 = weak_rx.clone();
+        assert!(weak_rx.upgrade().is_none());
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        drop(rx);
+        assert!(tx.is_closed());
+        let rx = weak_rx.upgrade().unwrap();
+        assert!(weak_rx2.upgrade().is_none());
+        assert_eq!(rx.recv(), Ok(1));
+        drop(rx);
+        let rx = weak_rx2.upgrade().unwrap();
+        assert_eq!(rx.recv(), Ok(2));
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn send_from_foreign_threads() {
+        let (tx, rx) = channel::<usize>();
+        let tx = Arc::new(SenderFromThread::new(tx));
+        let h = go!(move || {
+            let mut sum = 0;
+            while let Ok(v) = rx.recv() {
+                sum += v;
+            }
+            sum
+        });
+        // let the receiver park first
+        thread::sleep(Duration::from_millis(10));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        tx.send(i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        drop(tx);
+        assert_eq!(h.join().unwrap(), 8 * 999 * 1000 / 2);
+    }
+
+    #[test]
+    fn sync_channel_reserve() {
+        let (tx, rx) = sync_channel::<i32>(2);
+        let p1 = tx.reserve().unwrap();
+        let p2 = tx.try_reserve().unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        // an unused permit gives back the slot
+        drop(p2);
+        let p2 = tx.try_reserve().unwrap();
+        p2.send(2);
+        p1.send(1);
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(rx.recv(), Ok(1));
+
+        // the blocked sender is woken by the receive
+        tx.send(3).unwrap();
+        tx.send(4).unwrap();
+        let tx2 = tx.clone();
+        let h = go!(move || tx2.send(5));
+        thread::sleep(Duration::from_millis(10));
+        assert!(!h.is_done());
+        assert_eq!(rx.recv(), Ok(3));
+        h.join().unwrap().unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [4, 5]);
+
+        // the blocked reserve fails when the receiver is gone
+        tx.send(6).unwrap();
+        tx.send(7).unwrap();
+        let h = go!(move || tx.reserve().is_err());
+        thread::sleep(Duration::from_millis(10));
+        drop(rx);
+        assert!(h.join().unwrap());
+    }
+
     #[test]
     fn smoke_threads() {
         let (tx, rx) = channel::<i32>();
@@ -357,6 +1470,53 @@ mod tests {
         assert_eq!(rx.recv().unwrap(), 1);
     }
 
+    #[test]
+    fn close_receiver() {
+        let (tx, rx) = channel::<i32>();
+        tx.send(1).unwrap();
+        assert!(!tx.is_closed());
+        rx.close();
+        assert!(tx.is_closed() && rx.is_closed());
+        assert_eq!(tx.send(2), Err(SendError(2)));
+        // the sent data is still received
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn close_sender_wakes_receiver() {
+        let (tx, rx) = channel::<i32>();
+        let tx2 = tx.clone();
+        let h = go!(move || rx.recv());
+        thread::sleep(Duration::from_millis(10));
+        tx2.close();
+        assert_eq!(h.join().unwrap(), Err(RecvError));
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+
+    #[test]
+    fn closed_wait() {
+        let (tx, rx) = channel::<i32>();
+        let hs: Vec<_> = (0..4)
+            .map(|_| {
+                let tx = tx.clone();
+                go!(move || tx.closed())
+            })
+            .collect();
+        let t = {
+            let tx = tx.clone();
+            thread::spawn(move || tx.closed())
+        };
+        thread::sleep(Duration::from_millis(10));
+        assert!(hs.iter().all(|h| !h.is_done()));
+        drop(rx);
+        for h in hs {
+            h.join().unwrap();
+        }
+        t.join().unwrap();
+        tx.closed();
+    }
+
     #[test]
     fn smoke_port_gone() {
         let (tx, rx) = channel::<i32>();
@@ -498,6 +1658,32 @@ mod tests {
         t.join().ok().unwrap();
     }
 
+    #[test]
+    fn recv_timeout_waiter_race() {
+        // the senders race with the receivers that time out, a late unpark
+        // of the reused blocker must not lose any data
+        let (tx, rx) = channel::<usize>();
+        let t = thread::spawn(move || {
+            for i in 0..2000 {
+                tx.send(i).unwrap();
+                if i % 16 == 0 {
+                    thread::yield_now();
+                }
+            }
+        });
+        let h = go!(move || {
+            let mut got = 0;
+            while got < 2000 {
+                if rx.recv_timeout(Duration::from_micros(10)).is_ok() {
+                    got += 1;
+                }
+            }
+            rx.recv().is_err()
+        });
+        t.join().unwrap();
+        assert!(h.join().unwrap());
+    }
+
     #[test]
     fn no_runtime() {
         let (tx1, rx1) = channel::<i32>();
@@ -7,8 +7,10 @@ use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{fence, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::{LockResult, TryLockError, TryLockResult};
+use std::time::{Duration, Instant};
 
 use super::blocking::SyncBlocker;
+use super::metrics::Recorder;
 use super::poison;
 use super::queue::mpsc_seg_queue::SegQueue;
 use crate::cancel::trigger_cancel_panic;
@@ -20,6 +22,10 @@ pub struct Mutex<T: ?Sized> {
     // track how many blockers are waiting on the mutex
     cnt: AtomicUsize,
     poison: poison::Flag,
+    metrics: Recorder,
+    // allocated on the first lock, 0 means not allocated yet
+    #[cfg(feature = "lock_order")]
+    id: AtomicUsize,
     data: UnsafeCell<T>,
 }
 
@@ -39,25 +45,107 @@ pub struct MutexGuard<'a, T: ?Sized + 'a> {
 
 impl<T> Mutex<T> {
     /// Creates a new mutex in an unlocked state ready for use.
-    pub fn new(t: T) -> Mutex<T> {
+    ///
+    /// It's a const fn and nothing is allocated until the mutex is
+    /// contended, so the mutex can be put in a `static` directly.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use may::sync::Mutex;
+    ///
+    /// static COUNTER: Mutex<usize> = Mutex::new(0);
+    ///
+    /// *COUNTER.lock().unwrap() += 1;
+    /// assert_eq!(*COUNTER.lock().unwrap(), 1);
+    /// ```
+    #[track_caller]
+    pub const fn new(t: T) -> Mutex<T> {
         Mutex {
             to_wake: SegQueue::new(),
             cnt: AtomicUsize::new(0),
             poison: poison::Flag::new(),
+            metrics: Recorder::new("Mutex", std::panic::Location::caller()),
+            #[cfg(feature = "lock_order")]
+            id: AtomicUsize::new(0),
             data: UnsafeCell::new(t),
         }
     }
 }
 
 impl<T: ?Sized> Mutex<T> {
-    pub fn lock(&self) -> LockResult<MutexGuard<T>> {
+    // the id of the mutex for the lock order checker
+    #[cfg(feature = "lock_order")]
+    fn lock_id(&self) -> usize {
+        match self.id.load(Ordering::Relaxed) {
+            0 => {
+                let id = super::lock_order::new_lock_id();
+                match self
+                    .id
+                    .compare_exchange(0, id, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => id,
+                    Err(id) => id,
+                }
+            }
+            id => id,
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        match self.lock_impl(None) {
+            Ok(g) => Ok(g),
+            Err(TryLockError::Poisoned(e)) => Err(e),
+            Err(TryLockError::WouldBlock) => unreachable!("mutex timeout"),
+        }
+    }
+
+    /// Acquires the mutex, giving up after the timeout.
+    ///
+    /// Returns `Err(TryLockError::WouldBlock)` if the mutex is not acquired
+    /// in time, so the callers can bail out of a contended resource instead
+    /// of waiting forever.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::TryLockError;
+    /// use std::time::Duration;
+    /// use may::sync::Mutex;
+    ///
+    /// static M: Mutex<usize> = Mutex::new(0);
+    ///
+    /// let g = M.lock().unwrap();
+    /// let h = may::go!(|| {
+    ///     let ret = M.lock_timeout(Duration::from_millis(10));
+    ///     assert!(matches!(ret, Err(TryLockError::WouldBlock)));
+    /// });
+    /// h.join().unwrap();
+    /// drop(g);
+    /// assert!(M.lock_timeout(Duration::from_millis(10)).is_ok());
+    /// ```
+    pub fn lock_timeout(&self, dur: Duration) -> TryLockResult<MutexGuard<'_, T>> {
+        self.lock_until(Instant::now() + dur)
+    }
+
+    /// Acquires the mutex, giving up at the deadline.
+    ///
+    /// See [`lock_timeout`](Self::lock_timeout) for the details.
+    pub fn lock_until(&self, deadline: Instant) -> TryLockResult<MutexGuard<'_, T>> {
+        self.lock_impl(Some(deadline))
+    }
+
+    fn lock_impl(&self, deadline: Option<Instant>) -> TryLockResult<MutexGuard<'_, T>> {
+        #[cfg(feature = "lock_order")]
+        super::lock_order::check(self.lock_id());
+
         // try lock first
         match self.try_lock() {
-            Ok(g) => return Ok(g),
             Err(TryLockError::WouldBlock) => {}
-            Err(TryLockError::Poisoned(e)) => return Err(e),
+            ret => return ret,
         }
 
+        let timer = self.metrics.contended();
         let cur = SyncBlocker::current();
         // register blocker first
         self.to_wake.push(cur.clone());
@@ -69,11 +157,24 @@ impl<T: ?Sized> Mutex<T> {
                 .expect("got null blocker!");
         }
         loop {
-            match cur.park(None) {
+            let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            match cur.park(timeout) {
                 Ok(_) => {
                     break;
                 }
-                Err(ParkError::Timeout) => unreachable!("mutex timeout"),
+                Err(ParkError::Timeout) => {
+                    // the mutex may be handed over along with the timeout
+                    if cur.is_unparked() {
+                        break;
+                    }
+                    // let the unlocker pass the mutex on for us
+                    cur.set_release();
+                    if cur.is_unparked() && cur.take_release() {
+                        break;
+                    }
+                    self.metrics.waited(timer);
+                    return Err(TryLockError::WouldBlock);
+                }
                 Err(ParkError::Canceled) => {
                     let b_ignore = if crate::coroutine_impl::is_coroutine() {
                         let cancel = crate::coroutine_impl::current_cancel_data();
@@ -109,16 +210,21 @@ impl<T: ?Sized> Mutex<T> {
             }
         }
 
-        MutexGuard::new(self)
+        self.metrics.waited(timer);
+        self.metrics.acquired();
+        Ok(MutexGuard::new(self)?)
     }
 
-    pub fn try_lock(&self) -> TryLockResult<MutexGuard<T>> {
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
         if self.cnt.load(Ordering::SeqCst) == 0 {
             match self
                 .cnt
                 .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
             {
-                Ok(_) => Ok(MutexGuard::new(self)?),
+                Ok(_) => {
+                    self.metrics.acquired();
+                    Ok(MutexGuard::new(self)?)
+                }
                 Err(_) => Err(TryLockError::WouldBlock),
             }
         } else {
@@ -163,7 +269,8 @@ impl<T: ?Sized> Mutex<T> {
     }
 }
 
-impl<T: ?Sized + Default> Default for Mutex<T> {
+impl<T: Default> Default for Mutex<T> {
+    #[track_caller]
     fn default() -> Mutex<T> {
         Mutex::new(Default::default())
     }
@@ -185,6 +292,8 @@ impl<'mutex, T: ?Sized> MutexGuard<'mutex, T> {
     fn new(lock: &'mutex Mutex<T>) -> LockResult<MutexGuard<'mutex, T>> {
         // after get the lock we should sync the mem
         fence(Ordering::SeqCst);
+        #[cfg(feature = "lock_order")]
+        super::lock_order::acquired(lock.lock_id());
 
         poison::map_result(lock.poison.borrow(), |guard| MutexGuard {
             __lock: lock,
@@ -211,6 +320,8 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
     #[inline]
     fn drop(&mut self) {
         self.__lock.poison.done(&self.__poison);
+        #[cfg(feature = "lock_order")]
+        super::lock_order::released(self.__lock.lock_id());
         self.__lock.unlock();
         // after release the lock we should sync the mem
         fence(Ordering::SeqCst);
@@ -227,6 +338,8 @@ impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'a, T> {
 
 // below functions are used by condvar but not exported to user
 pub fn unlock_mutex<T: ?Sized>(lock: &Mutex<T>) {
+    #[cfg(feature = "lock_order")]
+    super::lock_order::released(lock.lock_id());
     lock.unlock();
 }
 
@@ -303,6 +416,31 @@ mod tests {
         *m.try_lock().unwrap() = ();
     }
 
+    #[test]
+    fn lock_timeout() {
+        let m = Arc::new(Mutex::new(0));
+        let g = m.lock().unwrap();
+        let m1 = m.clone();
+        let h = go!(move || {
+            let start = std::time::Instant::now();
+            let ret = m1.lock_timeout(Duration::from_millis(20));
+            assert!(matches!(ret, Err(TryLockError::WouldBlock)));
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            // the waiter that gave up doesn't block the later ones
+            *m1.lock_timeout(Duration::from_secs(10)).unwrap() += 1;
+        });
+        let m2 = m.clone();
+        let t = thread::spawn(move || {
+            let ret = m2.lock_until(std::time::Instant::now() + Duration::from_millis(10));
+            assert!(matches!(ret, Err(TryLockError::WouldBlock)));
+        });
+        t.join().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        drop(g);
+        h.join().unwrap();
+        assert_eq!(*m.lock().unwrap(), 1);
+    }
+
     #[test]
     fn test_into_inner() {
         let m = Mutex::new(NonCopy(10));
@@ -376,13 +514,13 @@ mod tests {
         let _t = thread::spawn(move || {
             // wait until parent gets in
             rx.recv().unwrap();
-            let &(ref lock, ref cvar) = &*packet2.0;
+            let (lock, cvar) = &*packet2.0;
             let mut lock = lock.lock().unwrap();
             *lock = true;
             cvar.notify_one();
         });
 
-        let &(ref lock, ref cvar) = &*packet.0;
+        let (lock, cvar) = &*packet.0;
         let mut lock = lock.lock().unwrap();
         tx.send(()).unwrap();
         assert!(!*lock);
@@ -399,14 +537,14 @@ mod tests {
 
         let _t = thread::spawn(move || {
             rx.recv().unwrap();
-            let &(ref lock, ref cvar) = &*packet2.0;
+            let (lock, cvar) = &*packet2.0;
             let _g = lock.lock().unwrap();
             cvar.notify_one();
             // Parent should fail when it wakes up.
             panic!();
         });
 
-        let &(ref lock, ref cvar) = &*packet.0;
+        let (lock, cvar) = &*packet.0;
         let mut lock = lock.lock().unwrap();
         tx.send(()).unwrap();
         while *lock == 1 {
@@ -556,4 +694,26 @@ mod tests {
         let g = mutex1.lock().unwrap();
         assert_eq!(*g, 1);
     }
+
+    #[test]
+    fn static_lock() {
+        use crate::sync::RwLock;
+
+        static M: Mutex<usize> = Mutex::new(0);
+        static C: Condvar = Condvar::new();
+        static L: RwLock<usize> = RwLock::new(0);
+
+        let h = go!(|| {
+            let mut g = M.lock().unwrap();
+            while *g == 0 {
+                g = C.wait(g).unwrap();
+            }
+            *L.write().unwrap() = *g;
+        });
+
+        *M.lock().unwrap() = 42;
+        C.notify_one();
+        h.join().unwrap();
+        assert_eq!(*L.read().unwrap(), 42);
+    }
 }
@@ -0,0 +1,352 @@
+//! compatible with the `once_cell` crate except for both thread and coroutine
+//! the waiting initializers are parked instead of blocking the worker thread
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::blocking::Blocker;
+use super::queue::seg_queue::SegQueue;
+use crate::cancel::trigger_cancel_panic;
+use crate::park::ParkError;
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+
+/// A cell which can be written to only once.
+///
+/// Unlike `std::sync::OnceLock`, the initialization can fail and be retried,
+/// and the coroutines that wait for a running initialization are parked
+/// instead of blocking their worker thread, so the initializer can do I/O.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::OnceCell;
+///
+/// static CELL: OnceCell<String> = OnceCell::new();
+///
+/// let h = may::go!(|| {
+///     CELL.get_or_try_init(|| -> Result<_, std::io::Error> {
+///         // e.g. connect to the database
+///         Ok("connected".to_owned())
+///     })
+///     .unwrap()
+///     .len()
+/// });
+/// assert_eq!(h.join().unwrap(), 9);
+/// assert_eq!(CELL.get().map(|s| s.as_str()), Some("connected"));
+/// ```
+pub struct OnceCell<T> {
+    state: AtomicUsize,
+    // the coroutines or threads that wait for the running initializer
+    waiters: SegQueue<Arc<Blocker>>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+unsafe impl<T: Send> Send for OnceCell<T> {}
+impl<T: RefUnwindSafe + UnwindSafe> RefUnwindSafe for OnceCell<T> {}
+impl<T: UnwindSafe> UnwindSafe for OnceCell<T> {}
+
+// reset the state if the initializer failed or panicked
+struct InitGuard<'a, T> {
+    cell: &'a OnceCell<T>,
+    state: usize,
+}
+
+impl<'a, T> Drop for InitGuard<'a, T> {
+    fn drop(&mut self) {
+        self.cell.state.store(self.state, Ordering::SeqCst);
+        // wake up all the waiters to check the new state
+        while let Some(w) = self.cell.waiters.pop() {
+            w.unpark();
+        }
+    }
+}
+
+impl<T> OnceCell<T> {
+    /// Creates a new empty cell.
+    pub const fn new() -> OnceCell<T> {
+        OnceCell {
+            state: AtomicUsize::new(INCOMPLETE),
+            waiters: SegQueue::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Gets the reference to the underlying value.
+    ///
+    /// Returns `None` if the cell is empty, or being initialized.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// Gets the mutable reference to the underlying value.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if *self.state.get_mut() == COMPLETE {
+            Some(unsafe { (*self.value.get()).assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// Returns `Err(value)` if the cell is already initialized.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty.
+    ///
+    /// If several coroutines or threads call this concurrently, only one of
+    /// them runs `f`, the others are parked until it's done. If `f` panics,
+    /// the panic is propagated and the cell remains uninitialized.
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        enum Void {}
+        match self.get_or_try_init(|| Ok::<T, Void>(f())) {
+            Ok(v) => v,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty. If `f` fails, the error is returned and the cell remains
+    /// uninitialized, one of the waiters would retry the initialization.
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(v) = self.get() {
+            return Ok(v);
+        }
+
+        let mut f = Some(f);
+        loop {
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    let mut guard = InitGuard {
+                        cell: self,
+                        state: INCOMPLETE,
+                    };
+                    let value = (f.take().unwrap())()?;
+                    unsafe { (*self.value.get()).write(value) };
+                    guard.state = COMPLETE;
+                    drop(guard);
+                    return Ok(unsafe { self.get_unchecked() });
+                }
+                Err(COMPLETE) => return Ok(unsafe { self.get_unchecked() }),
+                Err(_) => {
+                    let cur = Blocker::current();
+                    // the waiters queue is unbounded, never fails
+                    let _ = self.waiters.push(cur.clone());
+                    // re-check the state in case the initializer is just done
+                    if self.state.load(Ordering::SeqCst) == RUNNING {
+                        if let Err(ParkError::Canceled) = cur.park(None) {
+                            trigger_cancel_panic();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Consumes the cell, returning the wrapped value.
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    /// Takes the value out of the cell, moving it back to an uninitialized state.
+    pub fn take(&mut self) -> Option<T> {
+        if *self.state.get_mut() == COMPLETE {
+            *self.state.get_mut() = INCOMPLETE;
+            Some(unsafe { (*self.value.get()).assume_init_read() })
+        } else {
+            None
+        }
+    }
+
+    unsafe fn get_unchecked(&self) -> &T {
+        (*self.value.get()).assume_init_ref()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> OnceCell<T> {
+        OnceCell::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(v) => f.debug_tuple("OnceCell").field(v).finish(),
+            None => f.write_str("OnceCell(Uninit)"),
+        }
+    }
+}
+
+impl<T> From<T> for OnceCell<T> {
+    fn from(value: T) -> OnceCell<T> {
+        let cell = OnceCell::new();
+        let _ = cell.set(value);
+        cell
+    }
+}
+
+/// A value which is initialized on the first access.
+///
+/// The initialization is done by [`OnceCell::get_or_init`], so the
+/// concurrent accessors are parked until the value is ready.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use may::sync::Lazy;
+///
+/// static MAP: Lazy<HashMap<u32, &str>> = Lazy::new(|| {
+///     let mut m = HashMap::new();
+///     m.insert(1, "one");
+///     m
+/// });
+///
+/// let h = may::go!(|| MAP.get(&1).copied());
+/// assert_eq!(h.join().unwrap(), Some("one"));
+/// ```
+///
+/// [`OnceCell::get_or_init`]: struct.OnceCell.html#method.get_or_init
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+// the init function is only accessed by the single initializer
+unsafe impl<T, F: Send> Sync for Lazy<T, F> where OnceCell<T>: Sync {}
+impl<T, F: RefUnwindSafe> RefUnwindSafe for Lazy<T, F> where OnceCell<T>: RefUnwindSafe {}
+
+impl<T, F> Lazy<T, F> {
+    /// Creates a new lazy value with the given initializing function.
+    pub const fn new(f: F) -> Lazy<T, F> {
+        Lazy {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(f)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Forces the evaluation of this lazy value and returns a reference to
+    /// the result.
+    pub fn force(this: &Lazy<T, F>) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("Lazy instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+impl<T: Default> Default for Lazy<T> {
+    fn default() -> Lazy<T> {
+        Lazy::new(T::default)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Lazy")
+            .field("cell", &self.cell)
+            .field("init", &"..")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[test]
+    fn once_cell_concurrent_init() {
+        static CELL: OnceCell<usize> = OnceCell::new();
+        static CNT: AtomicUsize = AtomicUsize::new(0);
+
+        let hs: Vec<_> = (0..10)
+            .map(|i| {
+                go!(move || {
+                    *CELL.get_or_init(|| {
+                        CNT.fetch_add(1, Ordering::SeqCst);
+                        // the other coroutines are parked
+                        crate::coroutine::sleep(Duration::from_millis(20));
+                        i
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = hs.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(CNT.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|v| *v == values[0]));
+        assert_eq!(CELL.set(100), Err(100));
+    }
+
+    #[test]
+    fn once_cell_try_init() {
+        let cell = Arc::new(OnceCell::new());
+        let c = cell.clone();
+        let h = go!(move || c.get_or_try_init(|| "1x".parse::<u32>()).is_err());
+        assert!(h.join().unwrap());
+        assert!(cell.get().is_none());
+
+        assert_eq!(cell.get_or_try_init(|| "10".parse::<u32>()), Ok(&10));
+        assert_eq!(cell.get_or_try_init(|| "11".parse::<u32>()), Ok(&10));
+        assert_eq!(Arc::try_unwrap(cell).unwrap().into_inner(), Some(10));
+    }
+
+    #[test]
+    fn lazy_poisoned() {
+        let lazy: Lazy<u32> = Lazy::new(|| panic!("init failed"));
+        let ret = std::panic::catch_unwind(|| *lazy);
+        assert!(ret.is_err());
+        let ret = std::panic::catch_unwind(|| *lazy);
+        assert!(ret.is_err());
+    }
+}
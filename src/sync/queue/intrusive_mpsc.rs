@@ -0,0 +1,263 @@
+//! intrusive multi-producer single-consumer queue
+//!
+//! the link is embedded in the user's own allocation, so pushing a boxed
+//! message into the queue doesn't need any extra allocation
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crossbeam::utils::{Backoff, CachePadded};
+
+/// The link field that must be embedded in every queue node.
+pub struct Link<T> {
+    next: AtomicPtr<T>,
+}
+
+impl<T> Link<T> {
+    /// Creates a new unlinked `Link`.
+    pub const fn new() -> Self {
+        Link {
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<T> Default for Link<T> {
+    fn default() -> Self {
+        Link::new()
+    }
+}
+
+impl<T> fmt::Debug for Link<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Link { .. }")
+    }
+}
+
+/// A type that can be linked into an [`IntrusiveMpsc`] queue.
+///
+/// # Safety
+///
+/// `link` must always return a reference to the same `Link` field that lives
+/// inside `self`, and the field must not be used by anything else while the
+/// node is in a queue.
+pub unsafe trait Node: Sized {
+    /// Returns the link embedded in this node.
+    fn link(&self) -> &Link<Self>;
+}
+
+/// An unbounded intrusive multi-producer single-consumer queue.
+///
+/// Unlike the segment queues, the queue doesn't own any storage. Each message
+/// is a `Box<T>` that embeds a [`Link`], pushing it only writes pointers so
+/// there is no allocation per message besides the user's own one.
+///
+/// Any number of threads or coroutines can push concurrently, but there must
+/// be at most one popper at a time.
+///
+/// # Examples
+///
+/// ```
+/// use may::sync::queue::{IntrusiveMpsc, Link, Node};
+///
+/// struct Msg {
+///     link: Link<Msg>,
+///     data: usize,
+/// }
+///
+/// unsafe impl Node for Msg {
+///     fn link(&self) -> &Link<Msg> {
+///         &self.link
+///     }
+/// }
+///
+/// let q = IntrusiveMpsc::new();
+/// q.push(Box::new(Msg { link: Link::new(), data: 1 }));
+/// q.push(Box::new(Msg { link: Link::new(), data: 2 }));
+///
+/// assert_eq!(q.pop().map(|m| m.data), Some(1));
+/// assert_eq!(q.pop().map(|m| m.data), Some(2));
+/// assert!(q.pop().is_none());
+/// ```
+pub struct IntrusiveMpsc<T: Node> {
+    /// The node that would be popped next, only null when empty.
+    head: CachePadded<AtomicPtr<T>>,
+
+    /// The last pushed node, producers append after it.
+    tail: CachePadded<AtomicPtr<T>>,
+
+    /// Indicates that dropping a `IntrusiveMpsc<T>` may drop values of type `T`.
+    _marker: PhantomData<Box<T>>,
+}
+
+unsafe impl<T: Node + Send> Send for IntrusiveMpsc<T> {}
+unsafe impl<T: Node + Send> Sync for IntrusiveMpsc<T> {}
+
+impl<T: Node> IntrusiveMpsc<T> {
+    /// Creates a new empty queue.
+    pub const fn new() -> Self {
+        IntrusiveMpsc {
+            head: CachePadded::new(AtomicPtr::new(ptr::null_mut())),
+            tail: CachePadded::new(AtomicPtr::new(ptr::null_mut())),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Pushes a node into the queue.
+    pub fn push(&self, node: Box<T>) {
+        let node = Box::into_raw(node);
+        unsafe {
+            (*node)
+                .link()
+                .next
+                .store(ptr::null_mut(), Ordering::Relaxed)
+        };
+
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+        if prev.is_null() {
+            // the queue was empty, the consumer would see it from the head
+            self.head.store(node, Ordering::Release);
+        } else {
+            unsafe { (*prev).link().next.store(node, Ordering::Release) };
+        }
+    }
+
+    /// Pops a node from the queue.
+    ///
+    /// This must not be called concurrently from more than one consumer.
+    /// If the queue is empty, or the first push is still in progress,
+    /// `None` is returned.
+    pub fn pop(&self) -> Option<Box<T>> {
+        let head = self.head.load(Ordering::Acquire);
+        if head.is_null() {
+            return None;
+        }
+
+        let link = unsafe { (*head).link() };
+        let mut next = link.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // head is the last node, try to detach it from the tail
+            self.head.store(ptr::null_mut(), Ordering::Relaxed);
+            if self
+                .tail
+                .compare_exchange(head, ptr::null_mut(), Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return Some(unsafe { Box::from_raw(head) });
+            }
+
+            // a producer is appending after head, wait it finish the link
+            let backoff = Backoff::new();
+            loop {
+                next = link.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                backoff.snooze();
+            }
+        }
+
+        self.head.store(next, Ordering::Release);
+        Some(unsafe { Box::from_raw(head) })
+    }
+
+    /// Returns `true` if the queue is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tail.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T: Node> Default for IntrusiveMpsc<T> {
+    fn default() -> Self {
+        IntrusiveMpsc::new()
+    }
+}
+
+impl<T: Node> Drop for IntrusiveMpsc<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T: Node> fmt::Debug for IntrusiveMpsc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("IntrusiveMpsc { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Msg {
+        link: Link<Msg>,
+        id: usize,
+    }
+
+    unsafe impl Node for Msg {
+        fn link(&self) -> &Link<Msg> {
+            &self.link
+        }
+    }
+
+    fn msg(id: usize) -> Box<Msg> {
+        Box::new(Msg {
+            link: Link::new(),
+            id,
+        })
+    }
+
+    #[test]
+    fn intrusive_mpsc_order() {
+        let q = IntrusiveMpsc::new();
+        assert!(q.is_empty());
+        q.push(msg(1));
+        q.push(msg(2));
+        assert_eq!(q.pop().unwrap().id, 1);
+        q.push(msg(3));
+        assert_eq!(q.pop().unwrap().id, 2);
+        assert_eq!(q.pop().unwrap().id, 3);
+        assert!(q.pop().is_none());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn intrusive_mpsc_threads() {
+        let nthreads = 8;
+        let nmsgs = 1000;
+        let q = Arc::new(IntrusiveMpsc::new());
+
+        let producers: Vec<_> = (0..nthreads)
+            .map(|t| {
+                let q = q.clone();
+                thread::spawn(move || {
+                    for i in 0..nmsgs {
+                        q.push(msg(t * nmsgs + i));
+                    }
+                })
+            })
+            .collect();
+
+        // messages from the same producer must keep their order
+        let mut last = vec![None; nthreads];
+        let mut cnt = 0;
+        while cnt < nthreads * nmsgs {
+            if let Some(m) = q.pop() {
+                let t = m.id / nmsgs;
+                assert!(last[t] < Some(m.id));
+                last[t] = Some(m.id);
+                cnt += 1;
+            }
+        }
+
+        for p in producers {
+            p.join().unwrap();
+        }
+        assert!(q.pop().is_none());
+    }
+}
@@ -1,4 +1,20 @@
+//! the lock free queues used by the channels and the scheduler
+//!
+//! [`Spsc`] and [`Mpmc`] are the stable api, their handles encode the
+//! contracts of the underlying queues. Prefer [`Spsc`] to the raw
+//! [`spsc_seg_queue`], which leaves the single producer and the single
+//! consumer contracts to the caller.
+//!
+//! [`seg_queue`], [`array_queue`] and [`Spsc`] come from the `may_queue`
+//! crate, which can be used without `std` to reuse them out of the runtime.
+
+mod mpmc;
+
+pub mod intrusive_mpsc;
 pub mod mpsc_seg_queue;
-pub mod seg_queue;
-pub mod spsc_seg_queue;
 pub mod tokio_queue;
+
+pub use self::intrusive_mpsc::{IntrusiveMpsc, Link, Node};
+pub use self::mpmc::Mpmc;
+pub use may_queue::split_spsc::{Spsc, SpscConsumer, SpscProducer};
+pub use may_queue::{array_queue, seg_queue, spsc_seg_queue};
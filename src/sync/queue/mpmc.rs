@@ -0,0 +1,114 @@
+//! the multi-producer multi-consumer queue
+//!
+//! a stable wrapper of the segment queue, all the methods can be called from
+//! any number of threads, share it with an `Arc`.
+
+use std::fmt;
+
+use super::seg_queue::SegQueue;
+
+/// A multi-producer multi-consumer queue, unbounded unless it's created by
+/// [`with_capacity`].
+///
+/// [`with_capacity`]: Mpmc::with_capacity
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use may::sync::queue::Mpmc;
+///
+/// let q = Arc::new(Mpmc::new());
+/// let handles: Vec<_> = (0..4)
+///     .map(|i| {
+///         let q = q.clone();
+///         std::thread::spawn(move || q.push(i).unwrap())
+///     })
+///     .collect();
+/// for h in handles {
+///     h.join().unwrap();
+/// }
+///
+/// let mut all: Vec<_> = std::iter::from_fn(|| q.pop()).collect();
+/// all.sort();
+/// assert_eq!(all, [0, 1, 2, 3]);
+/// ```
+pub struct Mpmc<T> {
+    queue: SegQueue<T>,
+}
+
+impl<T> Mpmc<T> {
+    /// Creates a new unbounded queue.
+    pub const fn new() -> Self {
+        Mpmc {
+            queue: SegQueue::new(),
+        }
+    }
+
+    /// Creates a new queue that holds at most `cap` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is 0.
+    pub const fn with_capacity(cap: usize) -> Self {
+        Mpmc {
+            queue: SegQueue::with_capacity(cap),
+        }
+    }
+
+    /// Pushes an element into the queue, the element is returned back if
+    /// the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.queue.push(value)
+    }
+
+    /// Pops an element from the queue, `None` if it's empty.
+    pub fn pop(&self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns the capacity of the queue, `None` if it's unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.queue.capacity()
+    }
+}
+
+impl<T> Default for Mpmc<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for Mpmc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mpmc").field("len", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mpmc_bounded() {
+        let q = Mpmc::with_capacity(2);
+        assert_eq!(q.push(1), Ok(()));
+        assert_eq!(q.push(2), Ok(()));
+        assert_eq!(q.push(3), Err(3));
+        assert_eq!(q.capacity(), Some(2));
+        assert_eq!(q.len(), 2);
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.pop(), Some(2));
+        assert!(q.pop().is_none());
+    }
+}
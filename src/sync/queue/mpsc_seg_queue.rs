@@ -17,10 +17,8 @@ use smallvec::SmallVec;
 // * If the block is being destroyed, `DESTROY` is set.
 const WRITE: usize = 1;
 
-// Each block covers one "lap" of indices.
-const LAP: usize = 32;
-// The maximum number of values a block can hold.
-const BLOCK_CAP: usize = LAP - 1;
+/// The default number of indices a block covers, see [`SegQueue`].
+pub const DEFAULT_LAP: usize = 32;
 // How many lower bits are reserved for metadata.
 const SHIFT: usize = 1;
 // Indicates that the block is not the last one.
@@ -36,10 +34,12 @@ struct Slot<T> {
 }
 
 impl<T> Slot<T> {
-    const UNINIT: Self = Self {
-        value: UnsafeCell::new(MaybeUninit::uninit()),
-        state: AtomicUsize::new(0),
-    };
+    fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicUsize::new(0),
+        }
+    }
 
     /// Waits until a value is written into the slot.
     fn wait_write(&self) {
@@ -52,26 +52,36 @@ impl<T> Slot<T> {
 
 /// A block in a linked list.
 ///
-/// Each block in the list can hold up to `BLOCK_CAP` values.
-struct Block<T> {
+/// Each block covers one "lap" of indices and can hold up to `LAP - 1` values, the last slot
+/// is never used. `LAP` must be a power of 2 and at least 2.
+struct Block<T, const LAP: usize> {
     /// The next block in the linked list.
-    next: AtomicPtr<Block<T>>,
+    next: AtomicPtr<Block<T, LAP>>,
 
     /// Slots for values.
-    slots: [Slot<T>; BLOCK_CAP],
+    slots: [Slot<T>; LAP],
 }
 
-impl<T> Block<T> {
+impl<T, const LAP: usize> Block<T, LAP> {
+    /// The maximum number of values a block can hold.
+    const CAP: usize = {
+        assert!(
+            LAP >= 2 && LAP.is_power_of_two(),
+            "lap must be a power of 2"
+        );
+        LAP - 1
+    };
+
     /// Creates an empty block that starts at `start_index`.
-    fn new() -> Block<T> {
+    fn new() -> Block<T, LAP> {
         Self {
             next: AtomicPtr::new(ptr::null_mut()),
-            slots: [Slot::UNINIT; BLOCK_CAP],
+            slots: std::array::from_fn(|_| Slot::new()),
         }
     }
 
     /// Waits until the next pointer is set.
-    fn wait_next(&self) -> *mut Block<T> {
+    fn wait_next(&self) -> *mut Block<T, LAP> {
         let backoff = Backoff::new();
         loop {
             let next = self.next.load(Ordering::Acquire);
@@ -83,15 +93,15 @@ impl<T> Block<T> {
     }
 
     /// Sets the `DESTROY` bit in slots starting from `start` and destroys the block.
-    unsafe fn destroy(this: *mut Block<T>) {
+    unsafe fn destroy(this: *mut Block<T, LAP>) {
         // No thread is using the block, now it is safe to destroy it.
         drop(Box::from_raw(this));
     }
 }
 
-impl<T> Block<T> {
-    fn copy_to_bulk(this: *mut Block<T>, mut start: usize, end: usize) -> SmallVec<[T; BLOCK_CAP]> {
-        let mut ret = SmallVec::<[T; BLOCK_CAP]>::new();
+impl<T, const LAP: usize> Block<T, LAP> {
+    fn copy_to_bulk(this: *mut Block<T, LAP>, mut start: usize, end: usize) -> SmallVec<[T; LAP]> {
+        let mut ret = SmallVec::<[T; LAP]>::new();
         while start < end {
             // Read the value.
             let slot = unsafe { (*this).slots.get_unchecked(start) };
@@ -105,31 +115,27 @@ impl<T> Block<T> {
 }
 
 /// A position in a queue.
-struct Position<T> {
+struct Position<T, const LAP: usize> {
     /// The index in the queue.
     index: AtomicUsize,
 
     /// The block in the linked list.
-    block: AtomicPtr<Block<T>>,
+    block: AtomicPtr<Block<T, LAP>>,
 }
 
-impl<T> Position<T> {
+impl<T, const LAP: usize> Position<T, LAP> {
+    // the head is only changed by the consumer, so the relaxed ordering is
+    // enough for its own accesses
     fn load_index(&self) -> usize {
-        #[allow(clippy::cast_ref_to_mut)]
-        let index = unsafe { &mut *(&self.index as *const _ as *mut AtomicUsize) };
-        *index.get_mut()
+        self.index.load(Ordering::Relaxed)
     }
 
     fn set_index(&self, index: usize) {
-        #[allow(clippy::cast_ref_to_mut)]
-        let idx = unsafe { &mut *(&self.index as *const _ as *mut AtomicUsize) };
-        *idx.get_mut() = index;
+        self.index.store(index, Ordering::Relaxed);
     }
 
-    fn set_block(&self, block: *mut Block<T>) {
-        #[allow(clippy::cast_ref_to_mut)]
-        let blk = unsafe { &mut *(&self.block as *const _ as *mut AtomicPtr<Block<T>>) };
-        *blk.get_mut() = block;
+    fn set_block(&self, block: *mut Block<T, LAP>) {
+        self.block.store(block, Ordering::Relaxed);
     }
 }
 
@@ -156,19 +162,19 @@ impl<T> Position<T> {
 /// assert_eq!(q.pop(), Some('b'));
 /// assert!(q.pop().is_none());
 /// ```
-pub struct SegQueue<T> {
+pub struct SegQueue<T, const LAP: usize = DEFAULT_LAP> {
     /// The head of the queue.
-    head: CachePadded<Position<T>>,
+    head: CachePadded<Position<T, LAP>>,
 
     /// The tail of the queue.
-    tail: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T, LAP>>,
 
     /// Indicates that dropping a `SegQueue<T>` may drop values of type `T`.
     _marker: PhantomData<T>,
 }
 
-unsafe impl<T: Send> Send for SegQueue<T> {}
-unsafe impl<T: Send> Sync for SegQueue<T> {}
+unsafe impl<T: Send, const LAP: usize> Send for SegQueue<T, LAP> {}
+unsafe impl<T: Send, const LAP: usize> Sync for SegQueue<T, LAP> {}
 
 impl<T> SegQueue<T> {
     /// Creates a new unbounded queue.
@@ -181,6 +187,25 @@ impl<T> SegQueue<T> {
     /// let q = SegQueue::<i32>::new();
     /// ```
     pub const fn new() -> SegQueue<T> {
+        SegQueue::unbounded()
+    }
+}
+
+impl<T, const LAP: usize> SegQueue<T, LAP> {
+    /// Creates a new unbounded queue with the block size of `LAP`, which must
+    /// be a power of 2, the default is [`DEFAULT_LAP`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use may::sync::queue::mpsc_seg_queue::SegQueue;
+    ///
+    /// // the large messages waste less memory in the small blocks
+    /// let q = SegQueue::<[u8; 4096], 4>::unbounded();
+    /// q.push([0; 4096]);
+    /// assert_eq!(q.pop_bulk().unwrap().len(), 1);
+    /// ```
+    pub const fn unbounded() -> SegQueue<T, LAP> {
         SegQueue {
             head: CachePadded::new(Position {
                 block: AtomicPtr::new(ptr::null_mut()),
@@ -217,7 +242,7 @@ impl<T> SegQueue<T> {
             let offset = (tail >> SHIFT) % LAP;
 
             // If we reached the end of the block, wait until the next one is installed.
-            if offset == BLOCK_CAP {
+            if offset == Block::<T, LAP>::CAP {
                 backoff.snooze();
                 tail = self.tail.index.load(Ordering::Acquire);
                 block = self.tail.block.load(Ordering::Acquire);
@@ -226,13 +251,13 @@ impl<T> SegQueue<T> {
 
             // If we're going to have to install the next block, allocate it in advance in order to
             // make the wait for other threads as short as possible.
-            if offset + 1 == BLOCK_CAP && next_block.is_none() {
-                next_block = Some(Box::new(Block::<T>::new()));
+            if offset + 1 == Block::<T, LAP>::CAP && next_block.is_none() {
+                next_block = Some(Box::new(Block::<T, LAP>::new()));
             }
 
             // If this is the first push operation, we need to allocate the first block.
             if block.is_null() {
-                let new = Box::into_raw(Box::new(Block::<T>::new()));
+                let new = Box::into_raw(Box::new(Block::<T, LAP>::new()));
 
                 if self
                     .tail
@@ -261,7 +286,7 @@ impl<T> SegQueue<T> {
             ) {
                 Ok(_) => unsafe {
                     // If we've reached the end of the block, install the next one.
-                    if offset + 1 == BLOCK_CAP {
+                    if offset + 1 == Block::<T, LAP>::CAP {
                         let next_block = Box::into_raw(next_block.unwrap());
                         let next_index = new_tail.wrapping_add(1 << SHIFT);
 
@@ -340,7 +365,7 @@ impl<T> SegQueue<T> {
 
             unsafe {
                 // If we've reached the end of the block, move to the next one.
-                if offset + 1 == BLOCK_CAP {
+                if offset + 1 == Block::<T, LAP>::CAP {
                     let next = (*block).wait_next();
                     let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
                     if !(*next).next.load(Ordering::Relaxed).is_null() {
@@ -358,7 +383,7 @@ impl<T> SegQueue<T> {
 
                 // Destroy the block if we've reached the end, or if another thread wanted to
                 // destroy but couldn't because we were busy reading from the slot.
-                if offset + 1 == BLOCK_CAP {
+                if offset + 1 == Block::<T, LAP>::CAP {
                     Block::destroy(block);
                 }
 
@@ -392,7 +417,7 @@ impl<T> SegQueue<T> {
     /// assert_eq!(bulk.pop(), Some(12));
     /// assert_eq!(bulk.pop(), None);
     /// ```
-    pub fn pop_bulk(&self) -> Option<SmallVec<[T; BLOCK_CAP]>> {
+    pub fn pop_bulk(&self) -> Option<SmallVec<[T; LAP]>> {
         let backoff = Backoff::new();
         let mut head = self.head.load_index();
         let mut block = self.head.block.load(Ordering::Acquire);
@@ -414,7 +439,7 @@ impl<T> SegQueue<T> {
 
                 // If head and tail are not in the same block, set `HAS_NEXT` in head.
                 if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
-                    new_head = head | (BLOCK_CAP << SHIFT) | HAS_NEXT;
+                    new_head = head | (Block::<T, LAP>::CAP << SHIFT) | HAS_NEXT;
                 } else {
                     // take all the elements in the same block
                     new_head = tail;
@@ -435,7 +460,7 @@ impl<T> SegQueue<T> {
             unsafe {
                 let end = (new_head >> SHIFT) % LAP;
                 // If we've reached the end of the block, move to the next one.
-                if end == BLOCK_CAP {
+                if end == Block::<T, LAP>::CAP {
                     let next = (*block).wait_next();
                     let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
                     if !(*next).next.load(Ordering::Relaxed).is_null() {
@@ -450,7 +475,7 @@ impl<T> SegQueue<T> {
 
                 // Destroy the block if we've reached the end, or if another thread wanted to
                 // destroy but couldn't because we were busy reading from the slot.
-                if end == BLOCK_CAP {
+                if end == Block::<T, LAP>::CAP {
                     Block::destroy(block);
                 }
                 return Some(value);
@@ -529,7 +554,7 @@ impl<T> SegQueue<T> {
     }
 }
 
-impl<T> Drop for SegQueue<T> {
+impl<T, const LAP: usize> Drop for SegQueue<T, LAP> {
     fn drop(&mut self) {
         let mut head = *self.head.index.get_mut();
         let mut tail = *self.tail.index.get_mut();
@@ -544,7 +569,7 @@ impl<T> Drop for SegQueue<T> {
             while head != tail {
                 let offset = (head >> SHIFT) % LAP;
 
-                if offset < BLOCK_CAP {
+                if offset < Block::<T, LAP>::CAP {
                     // Drop the value in the slot.
                     let slot = (*block).slots.get_unchecked(offset);
                     let p = &mut *slot.value.get();
@@ -567,22 +592,22 @@ impl<T> Drop for SegQueue<T> {
     }
 }
 
-impl<T> fmt::Debug for SegQueue<T> {
+impl<T, const LAP: usize> fmt::Debug for SegQueue<T, LAP> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.pad("SegQueue { .. }")
     }
 }
 
-impl<T> Default for SegQueue<T> {
-    fn default() -> SegQueue<T> {
-        SegQueue::new()
+impl<T, const LAP: usize> Default for SegQueue<T, LAP> {
+    fn default() -> SegQueue<T, LAP> {
+        SegQueue::unbounded()
     }
 }
 
-impl<T> IntoIterator for SegQueue<T> {
+impl<T, const LAP: usize> IntoIterator for SegQueue<T, LAP> {
     type Item = T;
 
-    type IntoIter = IntoIter<T>;
+    type IntoIter = IntoIter<T, LAP>;
 
     fn into_iter(self) -> Self::IntoIter {
         IntoIter { value: self }
@@ -590,11 +615,11 @@ impl<T> IntoIterator for SegQueue<T> {
 }
 
 #[derive(Debug)]
-pub struct IntoIter<T> {
-    value: SegQueue<T>,
+pub struct IntoIter<T, const LAP: usize = DEFAULT_LAP> {
+    value: SegQueue<T, LAP>,
 }
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, const LAP: usize> Iterator for IntoIter<T, LAP> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -616,7 +641,7 @@ impl<T> Iterator for IntoIter<T> {
                 let p = &mut *slot.value.get();
                 p.as_mut_ptr().read()
             };
-            if offset + 1 == BLOCK_CAP {
+            if offset + 1 == Block::<T, LAP>::CAP {
                 // Deallocate the block and move to the next one.
                 // SAFETY: The block is initialized because we've been reading
                 // from it this entire time. We can drop it b/c everything has
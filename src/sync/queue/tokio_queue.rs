@@ -208,6 +208,19 @@ impl<T> Local<T> {
 unsafe impl<T> Send for Local<T> {}
 
 impl<T> Steal<T> {
+    /// Returns the number of the tasks in the queue, it's only a hint when
+    /// the queue is being pushed or stolen.
+    pub fn len(&self) -> usize {
+        let head = unpack(self.0.head.load(Acquire)).1;
+        let tail = self.0.tail.load(Acquire);
+        tail.wrapping_sub(head) as usize
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Steals half the tasks from self and place them into `dst`.
     pub fn steal_into(&self, dst: &Local<T>) -> Result<T, StealError> {
         // Safety: the caller is the only thread that mutates `dst.tail` and
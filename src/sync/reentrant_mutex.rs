@@ -0,0 +1,231 @@
+//! a mutex that can be locked again by the coroutine or thread holding it
+use std::cell::Cell;
+use std::fmt;
+use std::ops::Deref;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::mutex::{unlock_mutex, Mutex};
+use crate::local::get_co_local_data;
+
+// the thread owners start from the high bit, never collide with the coroutines
+const THREAD_OWNER_BASE: u64 = 1 << 63;
+
+// the id of the current coroutine, or a unique id of the current thread
+pub(crate) fn current_owner() -> u64 {
+    if let Some(local) = get_co_local_data() {
+        return unsafe { local.as_ref() }.get_co().id();
+    }
+    static NEXT_THREAD_OWNER: AtomicU64 = AtomicU64::new(THREAD_OWNER_BASE);
+    thread_local! {
+        static THREAD_OWNER: u64 = NEXT_THREAD_OWNER.fetch_add(1, Ordering::Relaxed);
+    }
+    THREAD_OWNER.with(|id| *id)
+}
+
+/// A mutex that can be locked again by the coroutine or thread holding it.
+///
+/// The owner is tracked by the coroutine id in coroutine context, so the
+/// lock is still held when the coroutine is resumed on another worker. The
+/// guard only gives the shared access, use a `RefCell` inside for the
+/// mutation. Unlike [`Mutex`], the lock is never poisoned.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::cell::RefCell;
+/// use may::sync::ReentrantMutex;
+///
+/// let lock = ReentrantMutex::new(RefCell::new(0));
+/// let h = may::go!(move || {
+///     let outer = lock.lock();
+///     // lock again in the same coroutine
+///     let inner = lock.lock();
+///     *inner.borrow_mut() += 1;
+///     drop(inner);
+///     *outer.borrow_mut() += 1;
+///     let v = *outer.borrow();
+///     v
+/// });
+/// assert_eq!(h.join().unwrap(), 2);
+/// ```
+///
+/// [`Mutex`]: struct.Mutex.html
+pub struct ReentrantMutex<T: ?Sized> {
+    lock: Mutex<()>,
+    // 0 means not locked
+    owner: AtomicU64,
+    // only accessed by the owner
+    count: Cell<usize>,
+    data: T,
+}
+
+unsafe impl<T: ?Sized + Send> Send for ReentrantMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for ReentrantMutex<T> {}
+impl<T: ?Sized> UnwindSafe for ReentrantMutex<T> {}
+impl<T: ?Sized> RefUnwindSafe for ReentrantMutex<T> {}
+
+/// The guard of a [`ReentrantMutex`], the lock is released when all the
+/// guards of the owner are dropped.
+///
+/// [`ReentrantMutex`]: struct.ReentrantMutex.html
+pub struct ReentrantMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a ReentrantMutex<T>,
+}
+
+impl<T> ReentrantMutex<T> {
+    /// Creates a new reentrant mutex in an unlocked state.
+    #[track_caller]
+    pub fn new(t: T) -> ReentrantMutex<T> {
+        ReentrantMutex {
+            lock: Mutex::new(()),
+            owner: AtomicU64::new(0),
+            count: Cell::new(0),
+            data: t,
+        }
+    }
+
+    /// Consumes the mutex, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T: ?Sized> ReentrantMutex<T> {
+    /// Acquires the lock, parking the caller until it's available.
+    ///
+    /// Returns immediately if the lock is already held by the caller.
+    pub fn lock(&self) -> ReentrantMutexGuard<'_, T> {
+        let me = current_owner();
+        if self.owner.load(Ordering::Relaxed) != me {
+            // the poison is never set, the guard is not used for unlock
+            let g = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+            std::mem::forget(g);
+            self.owner.store(me, Ordering::Relaxed);
+        }
+        self.count.set(self.count.get() + 1);
+        ReentrantMutexGuard { lock: self }
+    }
+
+    /// Attempts to acquire the lock without blocking.
+    ///
+    /// Returns `None` if the lock is held by another coroutine or thread.
+    pub fn try_lock(&self) -> Option<ReentrantMutexGuard<'_, T>> {
+        let me = current_owner();
+        if self.owner.load(Ordering::Relaxed) != me {
+            let g = self.lock.try_lock().ok()?;
+            std::mem::forget(g);
+            self.owner.store(me, Ordering::Relaxed);
+        }
+        self.count.set(self.count.get() + 1);
+        Some(ReentrantMutexGuard { lock: self })
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    fn unlock(&self) {
+        let count = self.count.get() - 1;
+        self.count.set(count);
+        if count == 0 {
+            self.owner.store(0, Ordering::Relaxed);
+            unlock_mutex(&self.lock);
+        }
+    }
+}
+
+impl<T: Default> Default for ReentrantMutex<T> {
+    #[track_caller]
+    fn default() -> ReentrantMutex<T> {
+        ReentrantMutex::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ReentrantMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "ReentrantMutex {{ data: {:?} }}", &*guard),
+            None => write!(f, "ReentrantMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ReentrantMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.lock.data
+    }
+}
+
+impl<'a, T: ?Sized> Drop for ReentrantMutexGuard<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for ReentrantMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReentrantMutexGuard")
+            .field("data", &&self.lock.data)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn reentrant_lock_nested() {
+        let m = ReentrantMutex::new(RefCell::new(Vec::new()));
+        let a = m.lock();
+        a.borrow_mut().push(1);
+        {
+            let b = m.lock();
+            b.borrow_mut().push(2);
+            let c = m.try_lock().unwrap();
+            c.borrow_mut().push(3);
+        }
+        a.borrow_mut().push(4);
+        drop(a);
+        assert_eq!(m.into_inner().into_inner(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reentrant_lock_exclusive() {
+        let m = Arc::new(ReentrantMutex::new(RefCell::new(0)));
+        let g = m.lock();
+        let hs: Vec<_> = (0..10)
+            .map(|_| {
+                let m = m.clone();
+                go!(move || {
+                    let a = m.lock();
+                    let b = m.lock();
+                    // yield with the lock held, may resume on another worker
+                    crate::coroutine::yield_now();
+                    *b.borrow_mut() += 1;
+                    drop(a);
+                })
+            })
+            .collect();
+
+        let m2 = m.clone();
+        let t = std::thread::spawn(move || m2.try_lock().is_none());
+        assert!(t.join().unwrap());
+        crate::coroutine::sleep(Duration::from_millis(10));
+        assert_eq!(*g.borrow(), 0);
+        drop(g);
+
+        for h in hs {
+            h.join().unwrap();
+        }
+        assert_eq!(*m.lock().borrow(), 10);
+    }
+}
@@ -0,0 +1,279 @@
+//! a typed request/response channel
+//!
+//! the usual way to ask another coroutine for something is to send the
+//! request together with a reply channel, and wait on the reply channel.
+//! [`Client::call`] wraps the pattern: each request carries a oneshot reply
+//! slot, and the caller gets a [`CallError`] instead of hanging when the
+//! server is gone, or the request is dropped without a response, e.g. when
+//! the handling coroutine panics. the wait is a normal blocking point, so
+//! the calling coroutine can be cancelled, and the responder can tell that
+//! the caller has given up by [`Request::is_abandoned`].
+
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+use super::mpsc::{self, Receiver, Sender};
+
+/// Creates a new rpc channel, returning the client and server halves.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::rpc;
+///
+/// let (client, server) = rpc::channel::<u32, u32>();
+/// let h = may::go!(move || {
+///     for req in server.iter() {
+///         let n = *req.get();
+///         req.respond(n * 2).ok();
+///     }
+/// });
+///
+/// assert_eq!(client.call(21).unwrap(), 42);
+/// drop(client);
+/// h.join().unwrap();
+/// ```
+pub fn channel<Req, Resp>() -> (Client<Req, Resp>, Server<Req, Resp>) {
+    let (tx, rx) = mpsc::channel();
+    (Client { tx }, Server { rx })
+}
+
+/// An error returned by [`Client::call`].
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum CallError<Req> {
+    /// The server is dropped or closed, the request is returned back.
+    Disconnected(Req),
+    /// The request is dropped by the server without a response.
+    NoResponse,
+    /// The response doesn't come back within the timeout.
+    Timeout,
+}
+
+impl<Req> CallError<Req> {
+    /// Returns the request if it was never delivered to the server.
+    pub fn into_request(self) -> Option<Req> {
+        match self {
+            CallError::Disconnected(req) => Some(req),
+            _ => None,
+        }
+    }
+}
+
+impl<Req> fmt::Debug for CallError<Req> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::Disconnected(_) => "Disconnected(..)".fmt(f),
+            CallError::NoResponse => "NoResponse".fmt(f),
+            CallError::Timeout => "Timeout".fmt(f),
+        }
+    }
+}
+
+impl<Req> fmt::Display for CallError<Req> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::Disconnected(_) => "calling on a closed rpc channel".fmt(f),
+            CallError::NoResponse => "the request is dropped without a response".fmt(f),
+            CallError::Timeout => "timed out waiting for the response".fmt(f),
+        }
+    }
+}
+
+impl<Req> Error for CallError<Req> {}
+
+/// The calling half of an rpc [`channel`], it can be cloned.
+pub struct Client<Req, Resp> {
+    tx: Sender<Request<Req, Resp>>,
+}
+
+/// The serving half of an rpc [`channel`].
+pub struct Server<Req, Resp> {
+    rx: Receiver<Request<Req, Resp>>,
+}
+
+/// A request received by the [`Server`], waiting for its response.
+///
+/// Dropping it without calling [`respond`] fails the call with
+/// [`CallError::NoResponse`].
+///
+/// [`respond`]: Request::respond
+pub struct Request<Req, Resp> {
+    req: Req,
+    reply: Sender<Resp>,
+}
+
+impl<Req, Resp> Client<Req, Resp> {
+    /// Sends the request and parks the caller until the response comes back.
+    pub fn call(&self, req: Req) -> Result<Resp, CallError<Req>> {
+        let rx = self.send(req)?;
+        rx.recv().map_err(|RecvError| CallError::NoResponse)
+    }
+
+    /// Same as [`call`], but gives up waiting after the timeout.
+    ///
+    /// The request may still be handled by the server after the timeout,
+    /// its response is discarded.
+    ///
+    /// [`call`]: Client::call
+    pub fn call_timeout(&self, req: Req, timeout: Duration) -> Result<Resp, CallError<Req>> {
+        let rx = self.send(req)?;
+        rx.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => CallError::Timeout,
+            RecvTimeoutError::Disconnected => CallError::NoResponse,
+        })
+    }
+
+    /// Returns true if the server is dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    fn send(&self, req: Req) -> Result<Receiver<Resp>, CallError<Req>> {
+        let (reply, rx) = mpsc::channel();
+        self.tx
+            .send(Request { req, reply })
+            .map_err(|e| CallError::Disconnected(e.0.req))?;
+        Ok(rx)
+    }
+}
+
+impl<Req, Resp> Clone for Client<Req, Resp> {
+    fn clone(&self) -> Self {
+        Client {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Server<Req, Resp> {
+    /// Receives a request, parking the caller until there is one.
+    ///
+    /// Returns an error when all the clients are dropped.
+    pub fn recv(&self) -> Result<Request<Req, Resp>, RecvError> {
+        self.rx.recv()
+    }
+
+    /// Attempts to receive a request without blocking.
+    pub fn try_recv(&self) -> Result<Request<Req, Resp>, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Receives a request, waiting at most for the timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Request<Req, Resp>, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    /// Returns an iterator that receives the requests until all the clients
+    /// are dropped.
+    pub fn iter(&self) -> mpsc::Iter<'_, Request<Req, Resp>> {
+        self.rx.iter()
+    }
+
+    /// Stops accepting new requests, the pending ones can still be received.
+    pub fn close(&self) {
+        self.rx.close()
+    }
+}
+
+impl<Req, Resp> Request<Req, Resp> {
+    /// Returns a reference to the request.
+    pub fn get(&self) -> &Req {
+        &self.req
+    }
+
+    /// Sends the response back to the caller.
+    ///
+    /// The response is returned back in the error if the caller has given
+    /// up waiting.
+    pub fn respond(self, resp: Resp) -> Result<(), Resp> {
+        self.reply.send(resp).map_err(|e| e.0)
+    }
+
+    /// Returns true if the caller has given up waiting, e.g. it's timed out
+    /// or cancelled, so that the response is not needed anymore.
+    pub fn is_abandoned(&self) -> bool {
+        self.reply.is_closed()
+    }
+
+    /// Splits the request into the payload and a responder that sends the
+    /// response later.
+    pub fn into_parts(self) -> (Req, Responder<Resp>) {
+        (self.req, Responder { reply: self.reply })
+    }
+}
+
+/// The reply slot of a [`Request`], see [`Request::into_parts`].
+pub struct Responder<Resp> {
+    reply: Sender<Resp>,
+}
+
+impl<Resp> Responder<Resp> {
+    /// Sends the response back to the caller.
+    ///
+    /// The response is returned back in the error if the caller has given
+    /// up waiting.
+    pub fn respond(self, resp: Resp) -> Result<(), Resp> {
+        self.reply.send(resp).map_err(|e| e.0)
+    }
+
+    /// Returns true if the caller has given up waiting.
+    pub fn is_abandoned(&self) -> bool {
+        self.reply.is_closed()
+    }
+}
+
+impl<Req, Resp> fmt::Debug for Client<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Client { .. }")
+    }
+}
+
+impl<Req, Resp> fmt::Debug for Server<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Server { .. }")
+    }
+}
+
+impl<Req: fmt::Debug, Resp> fmt::Debug for Request<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Request").field("req", &self.req).finish()
+    }
+}
+
+impl<Resp> fmt::Debug for Responder<Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Responder { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_errors() {
+        let (client, server) = channel::<u32, u32>();
+
+        // the request is dropped without a response
+        let h = go!(move || {
+            let req = server.recv().unwrap();
+            drop(req);
+            let req = server.recv().unwrap();
+            crate::coroutine::sleep(Duration::from_millis(100));
+            assert!(req.is_abandoned());
+            assert_eq!(req.respond(1), Err(1));
+        });
+        assert_eq!(client.call(1), Err(CallError::NoResponse));
+        assert_eq!(
+            client.call_timeout(2, Duration::from_millis(10)),
+            Err(CallError::Timeout)
+        );
+        h.join().unwrap();
+
+        // the server is gone, the request is returned back
+        assert!(client.is_closed());
+        assert_eq!(client.call(3).unwrap_err().into_request(), Some(3));
+    }
+}
@@ -7,6 +7,7 @@ use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::time::{Duration, Instant};
 
 use crate::cancel::trigger_cancel_panic;
 use crate::park::ParkError;
@@ -55,7 +56,10 @@ pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
 // impl<'a, T: ?Sized> !marker::Send for RwLockWriteGuard<'a, T> {}
 
 impl<T> RwLock<T> {
-    pub fn new(t: T) -> RwLock<T> {
+    /// Creates a new rwlock in an unlocked state ready for use.
+    ///
+    /// It's a const fn, so the rwlock can be put in a `static` directly.
+    pub const fn new(t: T) -> RwLock<T> {
         RwLock {
             to_wake: SegQueue::new(),
             cnt: AtomicUsize::new(0),
@@ -67,13 +71,11 @@ impl<T> RwLock<T> {
 }
 
 impl<T: ?Sized> RwLock<T> {
-    // global mutex lock without return a guard
-    fn lock(&self) -> Result<(), ParkError> {
-        // try lock first
-        match self.try_lock() {
-            Ok(_) => return Ok(()),
-            Err(TryLockError::WouldBlock) => {}
-            Err(TryLockError::Poisoned(_)) => return Err(ParkError::Timeout),
+    // global mutex lock without return a guard, give up at the deadline
+    fn lock(&self, deadline: Option<Instant>) -> Result<(), ParkError> {
+        // try lock first, the poison is reported by the guards
+        if self.try_lock().is_ok() {
+            return Ok(());
         }
 
         let cur = SyncBlocker::current();
@@ -86,9 +88,21 @@ impl<T: ?Sized> RwLock<T> {
                 .map(|w| self.unpark_one(&w))
                 .expect("got null blocker!");
         }
-        match cur.park(None) {
+        let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        match cur.park(timeout) {
             Ok(_) => Ok(()),
-            Err(ParkError::Timeout) => unreachable!("rwlock timeout"),
+            Err(ParkError::Timeout) => {
+                // the lock may be handed over along with the timeout
+                if cur.is_unparked() {
+                    return Ok(());
+                }
+                // let the unlocker pass the lock on for us
+                cur.set_release();
+                if cur.is_unparked() && cur.take_release() {
+                    return Ok(());
+                }
+                Err(ParkError::Timeout)
+            }
             Err(ParkError::Canceled) => {
                 // check the unpark status
                 if cur.is_unparked() {
@@ -142,24 +156,76 @@ impl<T: ?Sized> RwLock<T> {
         }
     }
 
-    pub fn read(&self) -> LockResult<RwLockReadGuard<T>> {
-        let mut r = self.rlock.lock().expect("rwlock read");
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        match self.read_impl(None) {
+            Ok(g) => Ok(g),
+            Err(TryLockError::Poisoned(e)) => Err(e),
+            Err(TryLockError::WouldBlock) => unreachable!("rwlock timeout"),
+        }
+    }
+
+    /// Locks the rwlock with shared read access, giving up after the
+    /// timeout.
+    ///
+    /// Returns `Err(TryLockError::WouldBlock)` if the lock is not acquired
+    /// in time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::TryLockError;
+    /// use std::time::Duration;
+    /// use may::sync::RwLock;
+    ///
+    /// static LOCK: RwLock<usize> = RwLock::new(0);
+    ///
+    /// let w = LOCK.write().unwrap();
+    /// let h = may::go!(|| {
+    ///     let ret = LOCK.read_timeout(Duration::from_millis(10));
+    ///     assert!(matches!(ret, Err(TryLockError::WouldBlock)));
+    /// });
+    /// h.join().unwrap();
+    /// drop(w);
+    /// assert!(LOCK.read_timeout(Duration::from_millis(10)).is_ok());
+    /// ```
+    pub fn read_timeout(&self, dur: Duration) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        self.read_until(Instant::now() + dur)
+    }
+
+    /// Locks the rwlock with shared read access, giving up at the deadline.
+    pub fn read_until(&self, deadline: Instant) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        self.read_impl(Some(deadline))
+    }
+
+    fn read_impl(&self, deadline: Option<Instant>) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        let mut r = match deadline {
+            None => self.rlock.lock().expect("rwlock read"),
+            Some(d) => match self.rlock.lock_until(d) {
+                Ok(r) => r,
+                Err(TryLockError::WouldBlock) => return Err(TryLockError::WouldBlock),
+                Err(TryLockError::Poisoned(_)) => panic!("rwlock read"),
+            },
+        };
         if *r == 0 {
-            if let Err(ParkError::Canceled) = self.lock() {
-                // don't set the poison flag
-                ::std::mem::forget(r);
-                // release the mutex to let other run
-                mutex::unlock_mutex(&self.rlock);
-                // now we can safely go with the cancel panic
-                trigger_cancel_panic();
+            match self.lock(deadline) {
+                Err(ParkError::Canceled) => {
+                    // don't set the poison flag
+                    ::std::mem::forget(r);
+                    // release the mutex to let other run
+                    mutex::unlock_mutex(&self.rlock);
+                    // now we can safely go with the cancel panic
+                    trigger_cancel_panic();
+                }
+                Err(ParkError::Timeout) => return Err(TryLockError::WouldBlock),
+                // the Poisoned case would be covered by the RwLockReadGuard::new()
+                Ok(_) => {}
             }
-            // else the Poisoned case would be covered by the RwLockReadGuard::new()
         }
         *r += 1;
-        RwLockReadGuard::new(self)
+        Ok(RwLockReadGuard::new(self)?)
     }
 
-    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<T>> {
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
         let mut r = match self.rlock.try_lock() {
             Ok(r) => r,
             Err(TryLockError::Poisoned(_)) => {
@@ -190,15 +256,40 @@ impl<T: ?Sized> RwLock<T> {
         }
     }
 
-    pub fn write(&self) -> LockResult<RwLockWriteGuard<T>> {
-        if let Err(ParkError::Canceled) = self.lock() {
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        match self.write_impl(None) {
+            Ok(g) => Ok(g),
+            Err(TryLockError::Poisoned(e)) => Err(e),
+            Err(TryLockError::WouldBlock) => unreachable!("rwlock timeout"),
+        }
+    }
+
+    /// Locks the rwlock with exclusive write access, giving up after the
+    /// timeout.
+    ///
+    /// Returns `Err(TryLockError::WouldBlock)` if the lock is not acquired
+    /// in time.
+    pub fn write_timeout(&self, dur: Duration) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        self.write_until(Instant::now() + dur)
+    }
+
+    /// Locks the rwlock with exclusive write access, giving up at the
+    /// deadline.
+    pub fn write_until(&self, deadline: Instant) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        self.write_impl(Some(deadline))
+    }
+
+    fn write_impl(&self, deadline: Option<Instant>) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        match self.lock(deadline) {
             // now we can safely go with the cancel panic
-            trigger_cancel_panic();
+            Err(ParkError::Canceled) => trigger_cancel_panic(),
+            Err(ParkError::Timeout) => return Err(TryLockError::WouldBlock),
+            Ok(_) => {}
         }
-        RwLockWriteGuard::new(self)
+        Ok(RwLockWriteGuard::new(self)?)
     }
 
-    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<T>> {
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
         if let Err(TryLockError::WouldBlock) = self.try_lock() {
             return Err(TryLockError::WouldBlock);
         }
@@ -506,6 +597,31 @@ mod tests {
         drop(read_guard);
     }
 
+    #[test]
+    fn test_rwlock_timeout() {
+        use std::time::Duration;
+
+        let lock = Arc::new(RwLock::new(0));
+        let r = lock.read().unwrap();
+        let l = lock.clone();
+        let h = go!(move || {
+            let ret = l.write_timeout(Duration::from_millis(20));
+            assert!(matches!(ret, Err(TryLockError::WouldBlock)));
+            // the readers are not blocked by the writer that gave up
+            assert!(l.read_timeout(Duration::from_millis(20)).is_ok());
+            *l.write_timeout(Duration::from_secs(10)).unwrap() += 1;
+        });
+        thread::sleep(Duration::from_millis(50));
+        drop(r);
+        h.join().unwrap();
+
+        let w = lock.write().unwrap();
+        let ret = lock.read_timeout(Duration::from_millis(10));
+        assert!(matches!(ret, Err(TryLockError::WouldBlock)));
+        drop(w);
+        assert_eq!(*lock.read_timeout(Duration::from_millis(10)).unwrap(), 1);
+    }
+
     #[test]
     fn test_into_inner() {
         let m = RwLock::new(NonCopy(10));
@@ -593,7 +709,7 @@ mod tests {
                 // println!("got wlock, id={}", i);
 
                 // wait the mater to let it go
-                let &(ref lock, ref cond) = &*sync;
+                let (lock, cond) = &*sync;
                 let mut cnt = lock.lock().unwrap();
                 while *cnt != i {
                     cnt = cond.wait(cnt).unwrap();
@@ -624,7 +740,7 @@ mod tests {
         unsafe { vec[cancel_id - 1].coroutine().cancel() };
 
         // let all coroutine to continue
-        let &(ref lock, ref cond) = &*sync;
+        let (lock, cond) = &*sync;
         for _ in 1..N {
             let mut cnt = lock.lock().unwrap();
             *cnt = id;
@@ -0,0 +1,419 @@
+//! a concurrent hash map split into shards
+//!
+//! a server usually keeps its connections or sessions in a map shared by all
+//! the coroutines, and a single `Mutex<HashMap>` serializes every lookup.
+//! [`ShardedMap`] splits the map into shards by the hash of the key, each one
+//! guarded by a coroutine [`RwLock`], so the coroutines working on different
+//! shards don't contend with each other.
+//!
+//! [`RwLock`]: super::RwLock
+
+use std::borrow::Borrow;
+use std::collections::hash_map::{self, HashMap, RandomState};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+use std::sync::PoisonError;
+
+use super::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A concurrent hash map with the keys split into shards.
+///
+/// Each method locks only the shard of the key. The guards returned by
+/// [`get`], [`get_mut`] and [`entry`] hold the shard lock, don't keep them
+/// across a long blocking operation or lock another key of the same map
+/// while holding them.
+///
+/// A panic while holding a shard doesn't poison the map, the shard is still
+/// a valid map.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use may::sync::ShardedMap;
+///
+/// let sessions = Arc::new(ShardedMap::new());
+/// let hs: Vec<_> = (0..4)
+///     .map(|i| {
+///         let sessions = sessions.clone();
+///         may::go!(move || {
+///             *sessions.entry(i % 2).or_insert(0) += 1;
+///         })
+///     })
+///     .collect();
+/// for h in hs {
+///     h.join().unwrap();
+/// }
+/// assert_eq!(*sessions.get(&0).unwrap(), 2);
+/// assert_eq!(sessions.len(), 2);
+/// ```
+///
+/// [`get`]: ShardedMap::get
+/// [`get_mut`]: ShardedMap::get_mut
+/// [`entry`]: ShardedMap::entry
+pub struct ShardedMap<K, V, S = RandomState> {
+    shards: Box<[RwLock<HashMap<K, V, S>>]>,
+    // the shard index is taken from the high bits of the hash
+    shift: u32,
+    hasher: S,
+}
+
+/// A reference to a value in a [`ShardedMap`], the shard is read locked
+/// until it's dropped.
+pub struct Ref<'a, K, V, S> {
+    _guard: RwLockReadGuard<'a, HashMap<K, V, S>>,
+    value: *const V,
+}
+
+/// A mutable reference to a value in a [`ShardedMap`], the shard is write
+/// locked until it's dropped.
+pub struct RefMut<'a, K, V, S> {
+    _guard: RwLockWriteGuard<'a, HashMap<K, V, S>>,
+    value: *mut V,
+}
+
+/// An entry of a [`ShardedMap`] returned by [`ShardedMap::entry`], the shard
+/// is write locked until it's dropped.
+pub struct Entry<'a, K, V, S> {
+    guard: RwLockWriteGuard<'a, HashMap<K, V, S>>,
+    key: K,
+}
+
+impl<K: Eq + Hash, V> ShardedMap<K, V> {
+    /// Creates an empty map, the number of the shards is four times the
+    /// number of the cpus.
+    pub fn new() -> Self {
+        Self::with_shards(num_cpus::get() * 4)
+    }
+
+    /// Creates an empty map with the number of the shards, it's rounded up
+    /// to a power of two.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher + Clone> ShardedMap<K, V, S> {
+    /// Creates an empty map with the number of the shards and the hasher.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        assert!(shards > 0, "the number of shards must be non-zero");
+        let shards = shards.next_power_of_two();
+        let shift = usize::BITS - shards.trailing_zeros();
+        let shards = (0..shards)
+            .map(|_| RwLock::new(HashMap::with_hasher(hasher.clone())))
+            .collect();
+        ShardedMap {
+            shards,
+            shift,
+            hasher,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> ShardedMap<K, V, S> {
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<HashMap<K, V, S>> {
+        if self.shards.len() == 1 {
+            return &self.shards[0];
+        }
+        // skip the top 7 bits which are used by the map inside the shard
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[(hash << 7) >> self.shift]
+    }
+
+    fn read<Q: Hash + ?Sized>(&self, key: &Q) -> RwLockReadGuard<'_, HashMap<K, V, S>> {
+        let shard = self.shard(key);
+        shard.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write<Q: Hash + ?Sized>(&self, key: &Q) -> RwLockWriteGuard<'_, HashMap<K, V, S>> {
+        let shard = self.shard(key);
+        shard.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the number of the shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Inserts a key-value pair, returns the old value of the key.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.write(&key).insert(key, value)
+    }
+
+    /// Removes a key, returns its value.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write(key).remove(key)
+    }
+
+    /// Returns true if the map contains the key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read(key).contains_key(key)
+    }
+
+    /// Returns a reference to the value of the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = self.read(key);
+        let value = guard.get(key)? as *const V;
+        Some(Ref {
+            _guard: guard,
+            value,
+        })
+    }
+
+    /// Returns a mutable reference to the value of the key.
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut guard = self.write(key);
+        let value = guard.get_mut(key)? as *mut V;
+        Some(RefMut {
+            _guard: guard,
+            value,
+        })
+    }
+
+    /// Gets the entry of the key for the in-place manipulation.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        let guard = self.write(&key);
+        Entry { guard, key }
+    }
+
+    /// Returns the number of the elements, the shards are locked one by one
+    /// so it's not a snapshot when the map is modified concurrently.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.read().unwrap_or_else(PoisonError::into_inner).len())
+            .sum()
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Retains only the elements specified by the predicate.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for s in self.shards.iter() {
+            s.write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(&mut f);
+        }
+    }
+
+    /// Removes all the elements.
+    pub fn clear(&self) {
+        for s in self.shards.iter() {
+            s.write().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    /// Calls the closure for each element, the shards are locked one by one.
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        for s in self.shards.iter() {
+            let shard = s.read().unwrap_or_else(PoisonError::into_inner);
+            shard.iter().for_each(|(k, v)| f(k, v));
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        ShardedMap::new()
+    }
+}
+
+impl<K, V, S> fmt::Debug for ShardedMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedMap")
+            .field("shards", &self.shards.len())
+            .finish()
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> Entry<'a, K, V, S> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Inserts the value if the key is vacant, returns the mutable reference
+    /// to the value.
+    pub fn or_insert(self, value: V) -> RefMut<'a, K, V, S> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Inserts the value returned by the closure if the key is vacant,
+    /// returns the mutable reference to the value.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> RefMut<'a, K, V, S> {
+        let mut guard = self.guard;
+        let value = guard.entry(self.key).or_insert_with(f) as *mut V;
+        RefMut {
+            _guard: guard,
+            value,
+        }
+    }
+
+    /// Inserts the default value if the key is vacant, returns the mutable
+    /// reference to the value.
+    pub fn or_default(self) -> RefMut<'a, K, V, S>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modifies the value in place if the key is occupied.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Some(v) = self.guard.get_mut(&self.key) {
+            f(v);
+        }
+        self
+    }
+
+    /// Inserts the value and returns the old one.
+    pub fn insert(self, value: V) -> Option<V> {
+        let mut guard = self.guard;
+        match guard.entry(self.key) {
+            hash_map::Entry::Occupied(mut e) => Some(e.insert(value)),
+            hash_map::Entry::Vacant(e) => {
+                e.insert(value);
+                None
+            }
+        }
+    }
+
+    /// Removes the value of the key if it's occupied.
+    pub fn remove(self) -> Option<V> {
+        let mut guard = self.guard;
+        guard.remove(&self.key)
+    }
+}
+
+// the value pointers are valid as long as the guards are held, and the map
+// of the shard is not modified while a guard exists
+unsafe impl<'a, K: Sync, V: Sync, S: Sync> Sync for Ref<'a, K, V, S> {}
+unsafe impl<'a, K: Sync, V: Sync, S: Sync> Sync for RefMut<'a, K, V, S> {}
+
+impl<'a, K, V, S> Deref for Ref<'a, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        unsafe { &*self.value }
+    }
+}
+
+impl<'a, K, V, S> Deref for RefMut<'a, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        unsafe { &*self.value }
+    }
+}
+
+impl<'a, K, V, S> DerefMut for RefMut<'a, K, V, S> {
+    fn deref_mut(&mut self) -> &mut V {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<'a, K, V: fmt::Debug, S> fmt::Debug for Ref<'a, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, K, V: fmt::Debug, S> fmt::Debug for RefMut<'a, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, K: fmt::Debug, V, S> fmt::Debug for Entry<'a, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Entry").field("key", &self.key).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn sharded_map_basic() {
+        let map = ShardedMap::with_shards(3);
+        assert_eq!(map.shards(), 4);
+        for i in 0..100 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.insert(1, 10), Some(1));
+        *map.get_mut(&2).unwrap() += 20;
+        assert_eq!(*map.get(&2).unwrap(), 22);
+        assert!(map.get(&100).is_none());
+
+        map.entry(3).and_modify(|v| *v = 30).or_insert(0);
+        map.entry(100).and_modify(|v| *v = 30).or_insert(0);
+        assert_eq!(*map.get(&3).unwrap(), 30);
+        assert_eq!(*map.get(&100).unwrap(), 0);
+        assert_eq!(map.entry(100).remove(), Some(0));
+        assert_eq!(map.entry(100).insert(1), None);
+
+        map.retain(|k, _| k % 2 == 0 && *k < 100);
+        assert_eq!(map.len(), 50);
+        let mut sum = 0;
+        map.for_each(|_, v| sum += v);
+        assert_eq!(sum, (0..100).step_by(2).sum::<i32>() + 20);
+        map.clear();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn sharded_map_concurrent() {
+        let map = Arc::new(ShardedMap::<usize, usize>::new());
+        let hs: Vec<_> = (0..10)
+            .map(|_| {
+                let map = map.clone();
+                go!(move || {
+                    for i in 0..100 {
+                        *map.entry(i).or_default() += 1;
+                        if i % 10 == 0 {
+                            crate::coroutine::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in hs {
+            h.join().unwrap();
+        }
+        assert_eq!(map.len(), 100);
+        map.for_each(|_, v| assert_eq!(*v, 10));
+    }
+}
@@ -4,12 +4,14 @@ use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use super::queue::spsc_seg_queue::SegQueue;
 use super::{AtomicOption, Blocker};
 use crate::likely::{likely, unlikely};
 
+pub use super::spsc_ring::{bounded, ring_channel, RingIter, RingReceiver, RingSender};
+
 /// /////////////////////////////////////////////////////////////////////////////
 /// InnerQueue
 /// /////////////////////////////////////////////////////////////////////////////
@@ -119,6 +121,9 @@ pub struct Receiver<T> {
 
 unsafe impl<T: Send> Send for Receiver<T> {}
 // impl<T> !Sync for Receiver<T> {}
+// an interrupted recv never loses the data
+impl<T: Send> UnwindSafe for Receiver<T> {}
+impl<T: Send> RefUnwindSafe for Receiver<T> {}
 
 pub struct Iter<'a, T: 'a> {
     rx: &'a Receiver<T>,
@@ -146,9 +151,9 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     (Sender::new(a.clone()), Receiver::new(a))
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Sender
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Sender
+// /////////////////////////////////////////////////////////////////////////////
 
 impl<T> Sender<T> {
     fn new(inner: Arc<InnerQueue<T>>) -> Sender<T> {
@@ -179,9 +184,9 @@ impl<T> fmt::Debug for Sender<T> {
     }
 }
 
-/// /////////////////////////////////////////////////////////////////////////////
-/// Receiver
-/// /////////////////////////////////////////////////////////////////////////////
+// /////////////////////////////////////////////////////////////////////////////
+// Receiver
+// /////////////////////////////////////////////////////////////////////////////
 
 impl<T> Receiver<T> {
     fn new(inner: Arc<InnerQueue<T>>) -> Receiver<T> {
@@ -212,7 +217,7 @@ impl<T> Receiver<T> {
     }
 
     fn recv_max_until(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
-        let deadline = Instant::now() + timeout;
+        let deadline = crate::time::now() + timeout;
         loop {
             match self.inner.recv(Some(timeout)) {
                 Ok(t) => return Ok(t),
@@ -222,17 +227,17 @@ impl<T> Receiver<T> {
 
             // If we're already passed the deadline, and we're here without
             // data, return a timeout, else try again.
-            if Instant::now() >= deadline {
+            if crate::time::now() >= deadline {
                 return Err(RecvTimeoutError::Timeout);
             }
         }
     }
 
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
         Iter { rx: self }
     }
 
-    pub fn try_iter(&self) -> TryIter<T> {
+    pub fn try_iter(&self) -> TryIter<'_, T> {
         TryIter { rx: self }
     }
 }
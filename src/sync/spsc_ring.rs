@@ -0,0 +1,390 @@
+//! single producer single consumer channel with inline ring storage
+//!
+//! the messages are stored in a fixed size ring buffer allocated together with
+//! the channel, so sending and receiving never allocate after the channel is
+//! created. the blockers used for waiting are cached by each side and reused.
+//!
+//! each side also caches the last seen index of the other side, the shared
+//! index is only loaded when the ring looks full to the sender or empty to
+//! the receiver, so the two cores don't bounce the cache line of the other
+//! index on every message.
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, SendError, TryRecvError, TrySendError};
+use std::sync::Arc;
+
+use super::blocking::cached_blocker;
+use super::{AtomicOption, Blocker};
+use crate::cancel::trigger_cancel_panic;
+use crate::park::ParkError;
+use crossbeam::utils::CachePadded;
+
+struct Ring<T, const N: usize> {
+    // next position to read, only updated by the receiver
+    head: CachePadded<AtomicUsize>,
+    // next position to write, only updated by the sender
+    tail: CachePadded<AtomicUsize>,
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    // the receiver waiting for data
+    rx_wake: AtomicOption<Arc<Blocker>>,
+    // the sender waiting for free slots
+    tx_wake: AtomicOption<Arc<Blocker>>,
+    tx_dropped: AtomicBool,
+    rx_dropped: AtomicBool,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Ring<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for Ring<T, N> {}
+
+impl<T, const N: usize> Ring<T, N> {
+    fn new() -> Self {
+        assert!(N > 0, "ring channel capacity must be greater than 0");
+        Ring {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            slots: std::array::from_fn(|_| UnsafeCell::new(MaybeUninit::uninit())),
+            rx_wake: AtomicOption::none(),
+            tx_wake: AtomicOption::none(),
+            tx_dropped: AtomicBool::new(false),
+            rx_dropped: AtomicBool::new(false),
+        }
+    }
+
+    // only called by the sender, `head` is the cached head of the sender
+    fn push(&self, t: T, head: &Cell<usize>) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(head.get()) == N {
+            head.set(self.head.load(Ordering::Acquire));
+            if tail.wrapping_sub(head.get()) == N {
+                return Err(t);
+            }
+        }
+        unsafe { (*self.slots.get_unchecked(tail % N).get()).write(t) };
+        self.tail.store(tail.wrapping_add(1), Ordering::SeqCst);
+        if let Some(w) = self.rx_wake.take(Ordering::SeqCst) {
+            w.unpark();
+        }
+        Ok(())
+    }
+
+    // only called by the receiver, `tail` is the cached tail of the receiver
+    fn pop(&self, tail: &Cell<usize>) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == tail.get() {
+            tail.set(self.tail.load(Ordering::Acquire));
+            if head == tail.get() {
+                return None;
+            }
+        }
+        let t = unsafe { (*self.slots.get_unchecked(head % N).get()).assume_init_read() };
+        self.head.store(head.wrapping_add(1), Ordering::SeqCst);
+        if let Some(w) = self.tx_wake.take(Ordering::SeqCst) {
+            w.unpark();
+        }
+        Some(t)
+    }
+
+    fn is_full(&self) -> bool {
+        let head = self.head.load(Ordering::SeqCst);
+        self.tail.load(Ordering::Relaxed).wrapping_sub(head) == N
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) == self.tail.load(Ordering::SeqCst)
+    }
+}
+
+impl<T, const N: usize> Drop for Ring<T, N> {
+    fn drop(&mut self) {
+        // drop the messages left between the head and the tail
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        let mut i = head;
+        while i != tail {
+            unsafe { self.slots[i % N].get_mut().assume_init_drop() };
+            i = i.wrapping_add(1);
+        }
+    }
+}
+
+// park on the blocker, the waker slot is cleared if canceled
+fn park(blocker: &Blocker, waker: &AtomicOption<Arc<Blocker>>) {
+    if let Err(ParkError::Canceled) = blocker.park(None) {
+        waker.take(Ordering::Acquire);
+        trigger_cancel_panic();
+    }
+}
+
+/// Creates a new channel that buffers at most `N` messages inline.
+///
+/// Unlike [`channel`], the messages are stored in a fixed ring buffer that is
+/// allocated once at construction, so neither side allocates afterwards. The
+/// sender is parked when the ring is full until the receiver takes a message.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::sync::spsc;
+///
+/// let (tx, rx) = spsc::bounded::<u32, 4>();
+/// let h = may::go!(move || {
+///     for i in 0..100 {
+///         tx.send(i).unwrap();
+///     }
+/// });
+/// assert_eq!(rx.iter().sum::<u32>(), 4950);
+/// h.join().unwrap();
+/// ```
+///
+/// [`channel`]: fn.channel.html
+pub fn bounded<T, const N: usize>() -> (RingSender<T, N>, RingReceiver<T, N>) {
+    let ring = Arc::new(Ring::new());
+    let tx = RingSender {
+        ring: ring.clone(),
+        head: Cell::new(0),
+        blocker: AtomicOption::none(),
+    };
+    let rx = RingReceiver {
+        ring,
+        tail: Cell::new(0),
+        blocker: AtomicOption::none(),
+    };
+    (tx, rx)
+}
+
+/// Creates a new channel that buffers at most `N` messages inline, it's the
+/// same as [`bounded`].
+///
+/// [`bounded`]: fn.bounded.html
+pub fn ring_channel<T, const N: usize>() -> (RingSender<T, N>, RingReceiver<T, N>) {
+    bounded()
+}
+
+/// The sending half of a [`ring_channel`].
+///
+/// [`ring_channel`]: fn.ring_channel.html
+pub struct RingSender<T, const N: usize> {
+    ring: Arc<Ring<T, N>>,
+    // the last seen head of the receiver
+    head: Cell<usize>,
+    blocker: AtomicOption<Arc<Blocker>>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for RingSender<T, N> {}
+
+impl<T, const N: usize> RingSender<T, N> {
+    /// Sends a message, parks the caller if the ring is full.
+    ///
+    /// Returns an error if the receiver is dropped.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        let ring = &*self.ring;
+        let mut t = t;
+        loop {
+            if ring.rx_dropped.load(Ordering::Acquire) {
+                return Err(SendError(t));
+            }
+            t = match ring.push(t, &self.head) {
+                Ok(()) => return Ok(()),
+                Err(t) => t,
+            };
+
+            let cur = cached_blocker(&self.blocker);
+            ring.tx_wake.swap(cur.clone(), Ordering::SeqCst);
+            // re-check after registered, the receiver may just take one
+            if ring.is_full() && !ring.rx_dropped.load(Ordering::Acquire) {
+                park(&cur, &ring.tx_wake);
+            } else {
+                ring.tx_wake.take(Ordering::Acquire);
+            }
+        }
+    }
+
+    /// Attempts to send a message without blocking.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        if self.ring.rx_dropped.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(t));
+        }
+        self.ring.push(t, &self.head).map_err(TrySendError::Full)
+    }
+}
+
+impl<T, const N: usize> Drop for RingSender<T, N> {
+    fn drop(&mut self) {
+        self.ring.tx_dropped.store(true, Ordering::SeqCst);
+        if let Some(w) = self.ring.rx_wake.take(Ordering::SeqCst) {
+            w.unpark();
+        }
+    }
+}
+
+impl<T, const N: usize> fmt::Debug for RingSender<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RingSender {{ .. }}")
+    }
+}
+
+/// The receiving half of a [`ring_channel`].
+///
+/// [`ring_channel`]: fn.ring_channel.html
+pub struct RingReceiver<T, const N: usize> {
+    ring: Arc<Ring<T, N>>,
+    // the last seen tail of the sender
+    tail: Cell<usize>,
+    blocker: AtomicOption<Arc<Blocker>>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for RingReceiver<T, N> {}
+
+impl<T, const N: usize> RingReceiver<T, N> {
+    /// Receives a message, parks the caller if the ring is empty.
+    ///
+    /// Returns an error if the ring is empty and the sender is dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let ring = &*self.ring;
+        loop {
+            match self.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let cur = cached_blocker(&self.blocker);
+            ring.rx_wake.swap(cur.clone(), Ordering::SeqCst);
+            // re-check after registered, the sender may just push one
+            if ring.is_empty() && !ring.tx_dropped.load(Ordering::SeqCst) {
+                park(&cur, &ring.rx_wake);
+            } else {
+                ring.rx_wake.take(Ordering::Acquire);
+            }
+        }
+    }
+
+    /// Attempts to receive a message without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(t) = self.ring.pop(&self.tail) {
+            return Ok(t);
+        }
+        if self.ring.tx_dropped.load(Ordering::SeqCst) {
+            // the sender may push the last one before dropped
+            self.ring.pop(&self.tail).ok_or(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Returns an iterator that blocks waiting for messages until the sender
+    /// is dropped.
+    pub fn iter(&self) -> RingIter<'_, T, N> {
+        RingIter { rx: self }
+    }
+}
+
+impl<T, const N: usize> Drop for RingReceiver<T, N> {
+    fn drop(&mut self) {
+        self.ring.rx_dropped.store(true, Ordering::SeqCst);
+        if let Some(w) = self.ring.tx_wake.take(Ordering::SeqCst) {
+            w.unpark();
+        }
+    }
+}
+
+impl<T, const N: usize> fmt::Debug for RingReceiver<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RingReceiver {{ .. }}")
+    }
+}
+
+/// An iterator over the messages of a [`RingReceiver`].
+///
+/// [`RingReceiver`]: struct.RingReceiver.html
+pub struct RingIter<'a, T, const N: usize> {
+    rx: &'a RingReceiver<T, N>,
+}
+
+impl<'a, T, const N: usize> Iterator for RingIter<'a, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T, const N: usize> fmt::Debug for RingIter<'a, T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RingIter {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn ring_full() {
+        let (tx, rx) = ring_channel::<i32, 2>();
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(rx.recv(), Ok(1));
+        tx.try_send(3).unwrap();
+        drop(tx);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn bounded_stale_cached_index() {
+        let (tx, rx) = bounded::<u8, 1>();
+        for i in 0..10 {
+            // the cached head is stale after each recv
+            tx.try_send(i).unwrap();
+            assert_eq!(tx.try_send(i), Err(TrySendError::Full(i)));
+            // the cached tail is stale after each send
+            assert_eq!(rx.try_recv(), Ok(i));
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        }
+    }
+
+    #[test]
+    fn ring_thread_to_coroutine() {
+        let (tx, rx) = bounded::<usize, 8>();
+        let h = go!(move || rx.iter().sum::<usize>());
+        let t = thread::spawn(move || {
+            for i in 0..10000 {
+                tx.send(i).unwrap();
+            }
+        });
+        t.join().unwrap();
+        assert_eq!(h.join().unwrap(), 49995000);
+    }
+
+    #[test]
+    fn ring_drop_rx() {
+        let (tx, rx) = ring_channel::<Box<i32>, 1>();
+        tx.send(Box::new(1)).unwrap();
+        let h = go!(move || tx.send(Box::new(2)).unwrap_err().0);
+        crate::coroutine::sleep(std::time::Duration::from_millis(10));
+        drop(rx);
+        assert_eq!(*h.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn ring_drop_wrapped() {
+        let msg = Arc::new(());
+        let (tx, rx) = bounded::<Box<Arc<()>>, 3>();
+        for _ in 0..5 {
+            tx.send(Box::new(msg.clone())).unwrap();
+            drop(rx.recv().unwrap());
+        }
+        // the left ones wrap around the end of the ring
+        tx.send(Box::new(msg.clone())).unwrap();
+        tx.send(Box::new(msg.clone())).unwrap();
+        assert_eq!(Arc::strong_count(&msg), 3);
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&msg), 1);
+    }
+}
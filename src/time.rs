@@ -0,0 +1,478 @@
+//! timer utilities for coroutines
+//!
+//! the periodic work done by calling `sleep` in a loop drifts, because the
+//! time spent by the work and the scheduling delay are added to each period.
+//! [`Interval`] keeps track of the deadlines so the ticks stay on schedule.
+//!
+//! a [`Timer`] is a one-shot timer that can be cancelled or moved from other
+//! coroutines or threads through its [`TimerHandle`], which is useful for the
+//! idle timeouts that are pushed back on every activity.
+//!
+//! the sleeps, timeouts and intervals read the time from a [`Clock`]. with
+//! the `mock_clock` feature a [`MockClock`] can be installed by [`set_clock`],
+//! so the tests of the timeout logic don't wait for the real time.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+#[cfg(feature = "mock_clock")]
+use crate::scheduler::get_scheduler;
+use crate::sleep::sleep;
+use crate::sync::{Cancelled, Parker};
+
+/// How the next deadline of an [`Interval`] is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntervalMode {
+    /// The ticks are aligned to the multiples of the period from the start,
+    /// the missed ticks are skipped instead of being fired at once. This is
+    /// the default.
+    #[default]
+    FixedRate,
+    /// The next tick is one period after the previous tick returned, so the
+    /// time between two ticks is at least one period.
+    FixedDelay,
+}
+
+/// A periodic timer created by [`interval`].
+///
+/// [`tick`] parks the caller until the next deadline, it works in both
+/// coroutine and thread contexts.
+///
+/// [`tick`]: Interval::tick
+#[derive(Debug)]
+pub struct Interval {
+    deadline: Instant,
+    period: Duration,
+    mode: IntervalMode,
+}
+
+/// Creates a new [`Interval`] that ticks every `period`, the first tick
+/// completes immediately.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::time::interval;
+///
+/// let h = may::go!(|| {
+///     let mut ticker = interval(Duration::from_millis(10));
+///     for _ in 0..3 {
+///         ticker.tick();
+///         // the periodic work
+///     }
+/// });
+/// h.join().unwrap();
+/// ```
+pub fn interval(period: Duration) -> Interval {
+    interval_at(now(), period)
+}
+
+/// Creates a new [`Interval`] that ticks every `period`, the first tick
+/// completes at `start`.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        deadline: start,
+        period,
+        mode: IntervalMode::FixedRate,
+    }
+}
+
+impl Interval {
+    /// Parks the caller until the next tick, returns the deadline of the
+    /// tick.
+    pub fn tick(&mut self) -> Instant {
+        loop {
+            if let Some(deadline) = self.try_tick() {
+                return deadline;
+            }
+            sleep(self.deadline - now());
+        }
+    }
+
+    /// Returns the deadline of the tick if it's due, this never blocks.
+    pub fn try_tick(&mut self) -> Option<Instant> {
+        let deadline = self.deadline;
+        let now = now();
+        if now < deadline {
+            return None;
+        }
+        self.deadline = self.next_deadline(deadline, now);
+        Some(deadline)
+    }
+
+    /// Resets the interval so that the next tick completes after one period
+    /// from now.
+    pub fn reset(&mut self) {
+        self.deadline = now() + self.period;
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns how the next deadline is computed.
+    pub fn mode(&self) -> IntervalMode {
+        self.mode
+    }
+
+    /// Sets how the next deadline is computed.
+    pub fn set_mode(&mut self, mode: IntervalMode) {
+        self.mode = mode;
+    }
+
+    fn next_deadline(&self, deadline: Instant, now: Instant) -> Instant {
+        match self.mode {
+            IntervalMode::FixedDelay => now + self.period,
+            IntervalMode::FixedRate => {
+                let next = deadline + self.period;
+                if next > now {
+                    return next;
+                }
+                // skip the missed ticks
+                let period = self.period.as_nanos();
+                let missed = (now - deadline).as_nanos() / period;
+                let skip = (missed + 1) * period;
+                deadline + Duration::from_nanos(skip.min(u64::MAX as u128) as u64)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TimerState {
+    deadline: Instant,
+    cancelled: bool,
+    // the parker of the waiting coroutine or thread
+    waiter: Option<Parker>,
+}
+
+/// A one-shot timer created by [`timer`] or [`timer_at`].
+///
+/// [`wait`] parks the caller until the deadline, the deadline can be moved
+/// or the timer cancelled by the [`TimerHandle`]s at any time. Moving the
+/// deadline later doesn't wake up the waiter, it just parks again for the
+/// remaining time when the old deadline is reached, so resetting the timer
+/// on every activity is cheap.
+///
+/// [`wait`]: Timer::wait
+#[derive(Debug)]
+pub struct Timer {
+    state: Arc<Mutex<TimerState>>,
+}
+
+/// A handle to cancel or reset a [`Timer`], it can be cloned and sent to
+/// other coroutines or threads.
+#[derive(Debug, Clone)]
+pub struct TimerHandle {
+    state: Arc<Mutex<TimerState>>,
+}
+
+/// Creates a new [`Timer`] that expires after `dur`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::time::timer;
+///
+/// let h = may::go!(|| {
+///     let idle = Duration::from_millis(50);
+///     let timer = timer(idle);
+///     let handle = timer.handle();
+///     let watchdog = may::go!(move || timer.wait().is_ok());
+///     for _ in 0..3 {
+///         // some activity on the connection pushes back the idle timeout
+///         may::coroutine::sleep(Duration::from_millis(10));
+///         handle.reset_after(idle);
+///     }
+///     // close the connection without waiting for the timeout
+///     handle.cancel();
+///     assert!(!watchdog.join().unwrap());
+/// });
+/// h.join().unwrap();
+/// ```
+pub fn timer(dur: Duration) -> Timer {
+    timer_at(now() + dur)
+}
+
+/// Creates a new [`Timer`] that expires at `deadline`.
+pub fn timer_at(deadline: Instant) -> Timer {
+    let state = TimerState {
+        deadline,
+        cancelled: false,
+        waiter: None,
+    };
+    Timer {
+        state: Arc::new(Mutex::new(state)),
+    }
+}
+
+impl Timer {
+    /// Returns a handle to cancel or reset the timer.
+    pub fn handle(&self) -> TimerHandle {
+        TimerHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Parks the caller until the deadline, returns `Err(Cancelled)` if the
+    /// timer is cancelled before that.
+    pub fn wait(&self) -> Result<(), Cancelled> {
+        let parker = Parker::new();
+        loop {
+            let deadline = {
+                let mut state = self.state.lock();
+                if state.cancelled {
+                    state.waiter = None;
+                    return Err(Cancelled);
+                }
+                if now() >= state.deadline {
+                    state.waiter = None;
+                    return Ok(());
+                }
+                state.waiter = Some(parker.clone());
+                state.deadline
+            };
+            // a reset or cancel unparks it, the state is checked again
+            parker.park_timeout(deadline.saturating_duration_since(now()));
+        }
+    }
+
+    /// Returns true if the deadline is reached and the timer is not
+    /// cancelled, this never blocks.
+    pub fn is_expired(&self) -> bool {
+        let state = self.state.lock();
+        !state.cancelled && now() >= state.deadline
+    }
+}
+
+impl TimerHandle {
+    /// Cancels the timer, the pending and later [`Timer::wait`] return
+    /// `Err(Cancelled)`.
+    pub fn cancel(&self) {
+        let mut state = self.state.lock();
+        state.cancelled = true;
+        if let Some(waiter) = state.waiter.take() {
+            waiter.unpark();
+        }
+    }
+
+    /// Moves the deadline of the timer, the waiter is woken up only if the
+    /// new deadline is earlier. This has no effect once the timer is
+    /// cancelled.
+    pub fn reset(&self, deadline: Instant) {
+        let mut state = self.state.lock();
+        let earlier = deadline < state.deadline;
+        state.deadline = deadline;
+        if earlier {
+            if let Some(waiter) = state.waiter.as_ref() {
+                waiter.unpark();
+            }
+        }
+    }
+
+    /// Moves the deadline of the timer to `dur` from now.
+    pub fn reset_after(&self, dur: Duration) {
+        self.reset(now() + dur)
+    }
+
+    /// Returns the current deadline of the timer.
+    pub fn deadline(&self) -> Instant {
+        self.state.lock().deadline
+    }
+
+    /// Returns true if the timer is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().cancelled
+    }
+}
+
+/// The source of the current time of the timers.
+///
+/// All the sleeps, timeouts and intervals read the time by [`now`]. The
+/// default is [`SystemClock`].
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time, it must never go backward.
+    fn now(&self) -> Instant;
+}
+
+/// The monotonic clock of the system, this is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(feature = "mock_clock")]
+static CLOCK: parking_lot::RwLock<Option<Arc<dyn Clock>>> = parking_lot::const_rwlock(None);
+
+/// Returns the current time of the clock used by the timers.
+#[inline]
+pub fn now() -> Instant {
+    #[cfg(feature = "mock_clock")]
+    if let Some(clock) = CLOCK.read().as_ref() {
+        return clock.now();
+    }
+    Instant::now()
+}
+
+/// Sets the clock used by the timers.
+///
+/// The timers already added are checked with the new clock, so it should
+/// not be earlier than the old one. Only available with the `mock_clock`
+/// feature.
+#[cfg(feature = "mock_clock")]
+pub fn set_clock<C: Clock>(clock: C) {
+    *CLOCK.write() = Some(Arc::new(clock));
+    get_scheduler().wake_timers();
+}
+
+/// A clock that only moves when it's advanced.
+///
+/// The coroutines sleeping or waiting with a timeout are woken up by
+/// [`advance`] once their deadlines are passed, without waiting for the real
+/// time. A thread blocked with a timeout still waits for the real duration.
+/// Only available with the `mock_clock` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use may::time::{set_clock, MockClock};
+///
+/// let clock = MockClock::new();
+/// set_clock(clock.clone());
+/// let h = may::go!(|| may::coroutine::sleep(Duration::from_secs(3600)));
+/// // an hour passes in no time
+/// while !h.is_done() {
+///     clock.advance(Duration::from_secs(60));
+///     std::thread::yield_now();
+/// }
+/// h.join().unwrap();
+/// ```
+///
+/// [`advance`]: MockClock::advance
+#[cfg(feature = "mock_clock")]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+#[cfg(feature = "mock_clock")]
+impl MockClock {
+    /// Creates a clock that starts from the current time of the system.
+    pub fn new() -> Self {
+        MockClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves the clock forward by `dur`, the expired timers of the current
+    /// scheduler are fired.
+    pub fn advance(&self, dur: Duration) {
+        *self.now.lock() += dur;
+        get_scheduler().wake_timers();
+    }
+}
+
+#[cfg(feature = "mock_clock")]
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+#[cfg(feature = "mock_clock")]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_fixed_rate_skip() {
+        let h = go!(|| {
+            let period = Duration::from_millis(20);
+            let mut ticker = interval(period);
+            let start = ticker.tick();
+            // block for 2.5 periods, the missed ticks are skipped
+            std::thread::sleep(period * 5 / 2);
+            let t = ticker.tick();
+            assert_eq!(t, start + period);
+            let t = ticker.tick();
+            assert_eq!(t, start + period * 3);
+            assert!(Instant::now() >= t);
+        });
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn interval_fixed_delay_reset() {
+        let period = Duration::from_millis(20);
+        let mut ticker = interval(period);
+        ticker.set_mode(IntervalMode::FixedDelay);
+        ticker.tick();
+        std::thread::sleep(period * 2);
+        let t = ticker.tick();
+        let next = ticker.tick();
+        assert!(next - t >= period);
+
+        ticker.reset();
+        let now = Instant::now();
+        ticker.tick();
+        assert!(now.elapsed() >= period - Duration::from_millis(1));
+    }
+
+    #[test]
+    fn timer_reset_and_cancel() {
+        let h = go!(|| {
+            let dur = Duration::from_millis(50);
+            let start = Instant::now();
+            let t = timer(dur);
+            let handle = t.handle();
+            // move the deadline earlier from another thread
+            let h1 = handle.clone();
+            let th = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                h1.reset_after(Duration::from_millis(5));
+            });
+            assert_eq!(t.wait(), Ok(()));
+            assert!(start.elapsed() < dur);
+            assert!(t.is_expired());
+            th.join().unwrap();
+
+            // push back the deadline and cancel it
+            handle.reset_after(Duration::from_secs(10));
+            assert!(!t.is_expired());
+            let h2 = handle.clone();
+            go!(move || {
+                crate::coroutine::sleep(Duration::from_millis(10));
+                h2.cancel();
+            });
+            assert_eq!(t.wait(), Err(Cancelled));
+            assert!(handle.is_cancelled());
+        });
+        h.join().unwrap();
+    }
+}
@@ -33,7 +33,7 @@ pub const fn ns_to_dur(ns: u64) -> Duration {
 #[allow(dead_code)]
 #[inline]
 pub const fn ns_to_ms(ns: u64) -> u64 {
-    (ns + NANOS_PER_MILLI - 1) / NANOS_PER_MILLI
+    ns.div_ceil(NANOS_PER_MILLI)
 }
 
 #[inline]
@@ -43,7 +43,7 @@ fn get_instant() -> &'static Instant {
     // static START_TIME: MaybeUninit<Instant> = MaybeUninit::zeroed();
     // unsafe {&*START_TIME.as_ptr() }
     lazy_static::lazy_static! {
-        static ref START_TIME: Instant = Instant::now();
+        static ref START_TIME: Instant = crate::time::now();
     }
     &START_TIME
 }
@@ -51,7 +51,10 @@ fn get_instant() -> &'static Instant {
 #[inline]
 pub fn now() -> u64 {
     // we need a Monotonic Clock here
-    get_instant().elapsed().as_nanos() as u64
+    let start = *get_instant();
+    crate::time::now()
+        .saturating_duration_since(start)
+        .as_nanos() as u64
 }
 
 // timeout event data
@@ -297,6 +300,11 @@ impl<T> TimerThread<T> {
 
     pub fn del_timer(&self, handle: TimeoutHandle<T>) {
         self.remove_list.push(handle);
+        self.wakeup();
+    }
+
+    // wake up the timer thread to check the timers again
+    pub fn wakeup(&self) {
         if let Some(t) = self.wakeup.take() {
             t.unpark();
         }
@@ -0,0 +1,54 @@
+//! `tracing` span propagation across the coroutine context switches
+//!
+//! the subscribers keep the entered spans in a thread local stack, but a
+//! coroutine can be suspended with spans entered and resumed on another
+//! worker. so the spans entered by the coroutine are exited when it yields,
+//! saved in the coroutine local storage, and entered again when it's resumed.
+
+use std::cell::RefCell;
+
+use tracing::Span;
+
+/// the spans entered by a coroutine, the outermost first
+pub(crate) struct SpanStack(RefCell<Vec<Span>>);
+
+impl SpanStack {
+    // the root span of the coroutine is entered at the first resume
+    pub fn new(root: Span) -> Self {
+        SpanStack(RefCell::new(vec![root]))
+    }
+
+    // enter the saved spans on the current thread before resuming
+    pub fn restore(&self) {
+        for span in self.0.borrow().iter() {
+            span.with_subscriber(|(id, dispatch)| dispatch.enter(id));
+        }
+    }
+
+    // exit all the spans entered by the coroutine after it's suspended
+    pub fn save(&self) {
+        let mut stack = self.0.borrow_mut();
+        // the old spans are dropped after exited, so they are not closed
+        // while still entered on the thread
+        let _old = std::mem::take(&mut *stack);
+        loop {
+            let span = Span::current();
+            let id = match span.id() {
+                Some(id) => id,
+                None => break,
+            };
+            span.with_subscriber(|(id, dispatch)| dispatch.exit(id));
+            stack.push(span);
+            // the subscriber doesn't track the span stack
+            if Span::current().id() == Some(id) {
+                break;
+            }
+        }
+        stack.reverse();
+    }
+}
+
+// create the root span for a new coroutine
+pub(crate) fn coroutine_span(id: u64, name: Option<&str>) -> Span {
+    tracing::info_span!("coroutine", id, name)
+}
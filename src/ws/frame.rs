@@ -0,0 +1,135 @@
+//! the websocket frame codec, see RFC 6455 section 5
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+
+pub const OP_CONTINUATION: u8 = 0x0;
+pub const OP_TEXT: u8 = 0x1;
+pub const OP_BINARY: u8 = 0x2;
+pub const OP_CLOSE: u8 = 0x8;
+pub const OP_PING: u8 = 0x9;
+pub const OP_PONG: u8 = 0xa;
+
+// the max payload size of the control frames
+const MAX_CONTROL_SIZE: usize = 125;
+
+#[derive(Debug)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn is_control(&self) -> bool {
+        self.opcode & 0x8 != 0
+    }
+}
+
+// a random value for the masking keys and the handshake keys
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= key[i & 3];
+    }
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads the frame after the first byte of its header, the client frames
+/// must be masked and the server frames must not.
+pub fn read_frame<R: Read>(
+    r: &mut R,
+    first: u8,
+    masked: bool,
+    max_size: usize,
+) -> io::Result<Frame> {
+    let fin = first & 0x80 != 0;
+    if first & 0x70 != 0 {
+        return Err(invalid_data("websocket reserved bits are set"));
+    }
+    let opcode = first & 0x0f;
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    if (b[0] & 0x80 != 0) != masked {
+        return Err(invalid_data("invalid websocket frame mask"));
+    }
+
+    let len = match b[0] & 0x7f {
+        126 => {
+            let mut n = [0u8; 2];
+            r.read_exact(&mut n)?;
+            u16::from_be_bytes(n) as u64
+        }
+        127 => {
+            let mut n = [0u8; 8];
+            r.read_exact(&mut n)?;
+            u64::from_be_bytes(n)
+        }
+        n => n as u64,
+    };
+
+    let frame = Frame {
+        fin,
+        opcode,
+        payload: Vec::new(),
+    };
+    if frame.is_control() && (!fin || len > MAX_CONTROL_SIZE as u64) {
+        return Err(invalid_data("invalid websocket control frame"));
+    }
+    if len > max_size as u64 {
+        return Err(invalid_data("websocket message is too large"));
+    }
+
+    let mut key = [0u8; 4];
+    if masked {
+        r.read_exact(&mut key)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    r.read_exact(&mut payload)?;
+    if masked {
+        apply_mask(&mut payload, key);
+    }
+    Ok(Frame { payload, ..frame })
+}
+
+/// Writes a final frame in one write, the payload is masked by a random key
+/// if `masked` is true.
+pub fn write_frame<W: Write>(
+    w: &mut W,
+    opcode: u8,
+    payload: &[u8],
+    masked: bool,
+) -> io::Result<()> {
+    let len = payload.len();
+    let mut buf = Vec::with_capacity(len + 14);
+    buf.push(0x80 | opcode);
+    let mask_bit = if masked { 0x80 } else { 0 };
+    if len < 126 {
+        buf.push(mask_bit | len as u8);
+    } else if len <= u16::MAX as usize {
+        buf.push(mask_bit | 126);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(mask_bit | 127);
+        buf.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+
+    if masked {
+        let key = (random_u64() as u32).to_be_bytes();
+        buf.extend_from_slice(&key);
+        let start = buf.len();
+        buf.extend_from_slice(payload);
+        apply_mask(&mut buf[start..], key);
+    } else {
+        buf.extend_from_slice(payload);
+    }
+    w.write_all(&buf)?;
+    w.flush()
+}
@@ -0,0 +1,190 @@
+//! the websocket opening handshake over http/1.1, see RFC 6455 section 4
+
+use std::io::{self, Read, Write};
+
+use super::frame::{invalid_data, random_u64};
+use crate::net::proxy::base64;
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// the max size of the handshake request or response head
+const MAX_HEAD_SIZE: usize = 8192;
+
+// the request or response head of the handshake
+struct Head {
+    // the request line or the status line
+    start: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // true if the comma separated header value contains the token
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    }
+}
+
+// read the head byte by byte, the frames after it must be left in the stream
+fn read_head<S: Read>(s: &mut S) -> io::Result<Head> {
+    let mut head = Vec::with_capacity(256);
+    let mut b = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_SIZE {
+            return Err(invalid_data("websocket handshake is too large"));
+        }
+        s.read_exact(&mut b)?;
+        head.push(b[0]);
+    }
+    let head = String::from_utf8(head).map_err(|_| invalid_data("invalid websocket handshake"))?;
+    let mut lines = head.split("\r\n").filter(|l| !l.is_empty());
+    let start = lines.next().unwrap_or_default().to_owned();
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+        .collect();
+    Ok(Head { start, headers })
+}
+
+fn accept_key(key: &str) -> String {
+    let mut data = key.as_bytes().to_vec();
+    data.extend_from_slice(WS_GUID.as_bytes());
+    base64(&sha1(&data))
+}
+
+/// Sends the handshake request and checks the response.
+pub fn client<S: Read + Write>(s: &mut S, host: &str, path: &str) -> io::Result<()> {
+    let mut nonce = [0u8; 16];
+    nonce[..8].copy_from_slice(&random_u64().to_ne_bytes());
+    nonce[8..].copy_from_slice(&random_u64().to_ne_bytes());
+    let key = base64(&nonce);
+    let req = format!(
+        "GET {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {key}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    s.write_all(req.as_bytes())?;
+    s.flush()?;
+
+    let rsp = read_head(s)?;
+    let code = rsp.start.split(' ').nth(1);
+    if code != Some("101") {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("websocket handshake failed: {}", rsp.start),
+        ));
+    }
+    if !rsp.has_token("Upgrade", "websocket") || !rsp.has_token("Connection", "upgrade") {
+        return Err(invalid_data("invalid websocket upgrade response"));
+    }
+    if rsp.header("Sec-WebSocket-Accept") != Some(accept_key(&key).as_str()) {
+        return Err(invalid_data("invalid Sec-WebSocket-Accept"));
+    }
+    Ok(())
+}
+
+/// Reads the handshake request and sends the response, returns the path of
+/// the request.
+pub fn server<S: Read + Write>(s: &mut S) -> io::Result<String> {
+    let req = read_head(s)?;
+    let mut parts = req.start.split(' ');
+    let (method, path) = (parts.next(), parts.next());
+    let key = match req.header("Sec-WebSocket-Key") {
+        Some(key)
+            if method == Some("GET")
+                && req.has_token("Upgrade", "websocket")
+                && req.has_token("Connection", "upgrade")
+                && req.header("Sec-WebSocket-Version") == Some("13") =>
+        {
+            key
+        }
+        _ => {
+            s.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(invalid_data("invalid websocket upgrade request"));
+        }
+    };
+    let rsp = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    s.write_all(rsp.as_bytes())?;
+    s.flush()?;
+    Ok(path.unwrap_or("/").to_owned())
+}
+
+// the handshake is the only user of sha1, it's not worth a dependency
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (o, x) in out.chunks_mut(4).zip(h) {
+        o.copy_from_slice(&x.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ws_accept_key() {
+        // the example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+}
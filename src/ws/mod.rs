@@ -0,0 +1,381 @@
+//! websocket streams on top of the coroutine io
+//!
+//! [`WsStream`] works on any blocking `Read + Write` stream, e.g. a
+//! [`TcpStream`] or a tls stream built on it, so sending and receiving the
+//! messages park the coroutine instead of the worker thread. the handshake is
+//! done by [`client`] or [`accept`], and [`connect`] is a shortcut for the
+//! `ws://` urls.
+//!
+//! the pings from the peer are answered in [`WsStream::recv`], and with
+//! [`WsStream::set_keepalive`] the stream sends its own pings when the peer is
+//! idle, the idle time is watched by the io timer of the stream.
+//!
+//! only available with the `ws` feature.
+
+mod frame;
+mod handshake;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+#[cfg(feature = "io_timeout")]
+use std::time::Duration;
+
+use self::frame::*;
+#[cfg(feature = "io_timeout")]
+use crate::io::TimeoutIo;
+use crate::net::TcpStream;
+
+// the default max size of a message
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// A websocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A utf-8 text message
+    Text(String),
+    /// A binary message
+    Binary(Vec<u8>),
+    /// A ping, the peer answers it with a pong of the same payload
+    Ping(Vec<u8>),
+    /// A pong
+    Pong(Vec<u8>),
+    /// The close message with an optional status code and reason
+    Close(Option<(u16, String)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+// send the pings when the peer is idle
+#[cfg(feature = "io_timeout")]
+struct Keepalive<S> {
+    interval: Duration,
+    // a ping is sent and no frame is received since then
+    pending: bool,
+    read: fn(&mut S, &mut [u8], Duration) -> io::Result<usize>,
+}
+
+/// A websocket connection over the stream `S`.
+///
+/// # Examples
+///
+/// ```rust
+/// use may::net::TcpListener;
+/// use may::ws::{self, Message};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let h = may::go!(move || {
+///     let (s, _) = listener.accept().unwrap();
+///     let mut ws = ws::accept(s).unwrap();
+///     let msg = ws.recv().unwrap();
+///     ws.send(msg).unwrap();
+/// });
+///
+/// let mut ws = ws::connect(&format!("ws://{addr}/echo")).unwrap();
+/// ws.send(Message::Text("hello".into())).unwrap();
+/// assert_eq!(ws.recv().unwrap(), Message::Text("hello".into()));
+/// h.join().unwrap();
+/// ```
+pub struct WsStream<S> {
+    stream: S,
+    role: Role,
+    max_message_size: usize,
+    // the close message is sent
+    close_sent: bool,
+    // the close message is received
+    close_received: bool,
+    #[cfg(feature = "io_timeout")]
+    keepalive: Option<Keepalive<S>>,
+}
+
+/// Does the client handshake on the stream, `host` and `path` are used in the
+/// upgrade request.
+pub fn client<S: Read + Write>(mut stream: S, host: &str, path: &str) -> io::Result<WsStream<S>> {
+    handshake::client(&mut stream, host, path)?;
+    Ok(WsStream::new(stream, Role::Client))
+}
+
+/// Does the server handshake on the accepted stream.
+pub fn accept<S: Read + Write>(mut stream: S) -> io::Result<WsStream<S>> {
+    handshake::server(&mut stream)?;
+    Ok(WsStream::new(stream, Role::Server))
+}
+
+/// Connects to a `ws://host:port/path` url and does the client handshake.
+pub fn connect(url: &str) -> io::Result<WsStream<TcpStream>> {
+    let rest = url.strip_prefix("ws://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only the ws:// urls are supported, wrap a tls stream by client()",
+        )
+    })?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let has_port = match host.rfind(']') {
+        // ipv6 in brackets
+        Some(i) => host[i..].contains(':'),
+        None => host.contains(':'),
+    };
+    let addr = if has_port {
+        host.to_owned()
+    } else {
+        format!("{host}:80")
+    };
+    let stream = TcpStream::connect(addr)?;
+    client(stream, host, path)
+}
+
+impl<S> WsStream<S> {
+    fn new(stream: S, role: Role) -> Self {
+        WsStream {
+            stream,
+            role,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            close_sent: false,
+            close_received: false,
+            #[cfg(feature = "io_timeout")]
+            keepalive: None,
+        }
+    }
+
+    /// Sets the max size of the received messages, the default is 64MB.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(feature = "io_timeout")]
+impl<S: TimeoutIo> WsStream<S> {
+    /// Sends a ping when nothing is received for the `interval`, and fails
+    /// [`recv`] with [`io::ErrorKind::TimedOut`] if still nothing comes back
+    /// after another `interval`. Pass `None` to disable it.
+    ///
+    /// [`recv`]: WsStream::recv
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive = interval.map(|interval| Keepalive {
+            interval,
+            pending: false,
+            read: S::read_timeout_op,
+        });
+    }
+}
+
+impl<S: Read + Write> WsStream<S> {
+    /// Sends a message.
+    pub fn send(&mut self, msg: Message) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "websocket is closed",
+            ));
+        }
+        match msg {
+            Message::Text(s) => self.write(OP_TEXT, s.as_bytes()),
+            Message::Binary(b) => self.write(OP_BINARY, &b),
+            Message::Ping(b) => self.write(OP_PING, &b),
+            Message::Pong(b) => self.write(OP_PONG, &b),
+            Message::Close(reason) => {
+                let mut payload = Vec::new();
+                if let Some((code, reason)) = reason {
+                    payload.extend_from_slice(&code.to_be_bytes());
+                    payload.extend_from_slice(reason.as_bytes());
+                }
+                self.close_sent = true;
+                self.write(OP_CLOSE, &payload)
+            }
+        }
+    }
+
+    /// Starts the closing handshake, [`recv`] returns the close message of
+    /// the peer after that.
+    ///
+    /// [`recv`]: WsStream::recv
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        self.send(Message::Close(Some((code, reason.to_owned()))))
+    }
+
+    /// Receives a message, parking the caller until there is one.
+    ///
+    /// The pings are answered here and the pongs are dropped, the fragmented
+    /// messages are assembled. When the peer closes the connection its close
+    /// message is returned, and it's answered if the close is not started
+    /// by us.
+    pub fn recv(&mut self) -> io::Result<Message> {
+        if self.close_received {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "websocket is closed",
+            ));
+        }
+        // the opcode and data of the fragmented message
+        let mut partial: Option<(u8, Vec<u8>)> = None;
+        loop {
+            let first = self.read_first_byte()?;
+            let masked = self.role == Role::Server;
+            let frame = read_frame(&mut self.stream, first, masked, self.max_message_size)?;
+
+            match frame.opcode {
+                OP_PING => {
+                    if !self.close_sent {
+                        self.write(OP_PONG, &frame.payload)?;
+                    }
+                }
+                OP_PONG => {}
+                OP_CLOSE => {
+                    self.close_received = true;
+                    let reason = match frame.payload.len() {
+                        0 => None,
+                        1 => return Err(invalid_data("invalid websocket close frame")),
+                        _ => {
+                            let code = u16::from_be_bytes([frame.payload[0], frame.payload[1]]);
+                            let reason = String::from_utf8(frame.payload[2..].to_vec())
+                                .map_err(|_| invalid_data("invalid utf-8 close reason"))?;
+                            Some((code, reason))
+                        }
+                    };
+                    if !self.close_sent {
+                        self.close_sent = true;
+                        self.write(OP_CLOSE, &frame.payload)?;
+                    }
+                    return Ok(Message::Close(reason));
+                }
+                OP_TEXT | OP_BINARY if partial.is_none() => {
+                    if frame.fin {
+                        return to_message(frame.opcode, frame.payload);
+                    }
+                    partial = Some((frame.opcode, frame.payload));
+                }
+                OP_CONTINUATION if partial.is_some() => {
+                    let (opcode, mut data) = partial.take().unwrap();
+                    if data.len() + frame.payload.len() > self.max_message_size {
+                        return Err(invalid_data("websocket message is too large"));
+                    }
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return to_message(opcode, data);
+                    }
+                    partial = Some((opcode, data));
+                }
+                _ => return Err(invalid_data("unexpected websocket frame")),
+            }
+        }
+    }
+
+    fn write(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        write_frame(&mut self.stream, opcode, payload, self.role == Role::Client)
+    }
+
+    // wait for the next frame, send the keepalive pings while waiting
+    fn read_first_byte(&mut self) -> io::Result<u8> {
+        let mut b = [0u8; 1];
+        #[cfg(feature = "io_timeout")]
+        while let Some(k) = self.keepalive.as_mut() {
+            match (k.read)(&mut self.stream, &mut b, k.interval) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {
+                    k.pending = false;
+                    return Ok(b[0]);
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if k.pending {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "websocket keepalive timed out",
+                        ));
+                    }
+                    k.pending = true;
+                    self.write(OP_PING, &[])?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.stream.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+}
+
+fn to_message(opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+    match opcode {
+        OP_TEXT => String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| invalid_data("invalid utf-8 text message")),
+        _ => Ok(Message::Binary(data)),
+    }
+}
+
+impl<S> fmt::Debug for WsStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WsStream")
+            .field("role", &self.role)
+            .field("close_sent", &self.close_sent)
+            .field("close_received", &self.close_received)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::TcpListener;
+
+    #[test]
+    fn ws_fragment_and_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let h = go!(move || {
+            // the accepted side plays the client, it sends a masked message
+            // in two fragments
+            let (mut s, _) = listener.accept().unwrap();
+            handshake::client(&mut s, "localhost", "/").unwrap();
+            let mut frames = Vec::new();
+            let key = [1u8, 2, 3, 4];
+            for (first, data) in [(0x01u8, &b"hel"[..]), (0x80, &b"lo"[..])] {
+                frames.push(first);
+                frames.push(0x80 | data.len() as u8);
+                frames.extend_from_slice(&key);
+                frames.extend(data.iter().enumerate().map(|(i, b)| b ^ key[i & 3]));
+            }
+            s.write_all(&frames).unwrap();
+            let mut ws = WsStream::new(s, Role::Client);
+            ws.send(Message::Ping(b"p".to_vec())).unwrap();
+            assert_eq!(
+                ws.recv().unwrap(),
+                Message::Close(Some((1000, "bye".into())))
+            );
+            assert!(ws.send(Message::Text("late".into())).is_err());
+        });
+
+        let s = TcpStream::connect(addr).unwrap();
+        let mut ws = accept(s).unwrap();
+        assert_eq!(ws.recv().unwrap(), Message::Text("hello".into()));
+        // the ping is answered inside recv
+        ws.close(1000, "bye").unwrap();
+        assert_eq!(
+            ws.recv().unwrap(),
+            Message::Close(Some((1000, "bye".into())))
+        );
+        h.join().unwrap();
+    }
+}
@@ -1,3 +1,6 @@
+use std::cell::Cell;
+
+use crate::config::config;
 use crate::coroutine_impl::{current_cancel_data, is_coroutine};
 use crate::coroutine_impl::{CoroutineImpl, EventResult, EventSource, EventSubscriber};
 use crate::likely::{likely, unlikely};
@@ -7,6 +10,11 @@ use generator::{co_get_yield, co_set_para, co_yield_with};
 
 struct Yield {}
 
+thread_local! {
+    // the `maybe_yield` checks passed since the running coroutine is resumed
+    static YIELD_CHECKS: Cell<usize> = const { Cell::new(0) };
+}
+
 impl EventSource for Yield {
     fn subscribe(&mut self, co: CoroutineImpl) {
         // just re-push the coroutine to the ready list
@@ -14,45 +22,63 @@ impl EventSource for Yield {
     }
 }
 
+/// wrap the event source that lives on the stack of the caller
+#[inline]
+fn event_subscriber<T: EventSource>(resource: &T) -> EventSubscriber {
+    let r: *mut (dyn EventSource + '_) = resource as &dyn EventSource as *const _ as *mut _;
+    // SAFETY: only the lifetime of the trait object is erased, the pointer
+    // is not changed. the subscriber is consumed by the scheduler while the
+    // caller is suspended, the coroutine is not resumed (or the thread not
+    // unparked) before `subscribe` is called, so the resource is still alive
+    // on its stack. and the sources that may wake the caller in the middle
+    // of `subscribe`, like `Park`, wait in their drop until it returns.
+    let r = unsafe {
+        std::mem::transmute::<*mut (dyn EventSource + '_), *mut (dyn EventSource + 'static)>(r)
+    };
+    EventSubscriber::new(r)
+}
+
 /// yield internal `EventSource` ref
 /// it's ok to return a ref of object on the generator's stack
 /// just like return the ref of a struct member
 #[inline]
 pub fn yield_with<T: EventSource>(resource: &T) {
+    #[cfg(debug_assertions)]
+    crate::sync::debug::check_yield();
+    #[cfg(all(feature = "core_dump", unix))]
+    crate::debug::save_sp();
     let cancel = current_cancel_data();
     // if cancel detected in user space
     // no need to get into kernel any more
     if unlikely(cancel.is_canceled()) {
-        co_set_para(::std::io::Error::new(
-            ::std::io::ErrorKind::Other,
-            "Canceled",
-        ));
+        co_set_para(::std::io::Error::other("Canceled"));
         return resource.yield_back(cancel);
     }
 
-    let r = resource as &dyn EventSource as *const _ as *mut _;
-    let es = EventSubscriber::new(r);
+    let es = event_subscriber(resource);
     co_yield_with(es);
 
+    let _clear = cancel.clear_guard();
     resource.yield_back(cancel);
-    cancel.clear();
 }
 
 #[inline]
 pub fn yield_with_io<T: EventSource>(resource: &T, is_coroutine: bool) {
     if likely(is_coroutine) {
+        #[cfg(all(debug_assertions, not(feature = "io_cancel")))]
+        crate::sync::debug::check_yield();
         #[cfg(feature = "io_cancel")]
         yield_with(resource);
         #[cfg(not(feature = "io_cancel"))]
         {
-            let r = resource as &dyn EventSource as *const _ as *mut _;
-            let es = EventSubscriber::new(r);
+            #[cfg(all(feature = "core_dump", unix))]
+            crate::debug::save_sp();
+            let es = event_subscriber(resource);
             co_yield_with(es);
         }
     } else {
         // for thread is only park the thread
-        let r = resource as &dyn EventSource as *const _ as *mut _;
-        let es = EventSubscriber::new(r);
+        let es = event_subscriber(resource);
         crate::io::thread::PROXY_CO_SENDER.with(|tx| {
             tx.send(es).unwrap();
         });
@@ -82,3 +108,30 @@ pub fn yield_now() {
     // it's safe to use the stack value here
     yield_with(&y);
 }
+
+// called before the worker resumes a coroutine
+#[inline]
+pub(crate) fn reset_yield_checks() {
+    YIELD_CHECKS.with(|c| c.set(0));
+}
+
+/// Yields the coroutine if it has passed too many checks since it's resumed.
+///
+/// The budget of the checks is set by `config().set_yield_budget`. It does
+/// nothing in a thread context. Use the [`maybe_yield!`] macro or the
+/// [`cooperative`] attribute to insert the checks into the cpu bound loops.
+///
+/// [`maybe_yield!`]: crate::maybe_yield
+/// [`cooperative`]: crate::cooperative
+#[inline]
+pub fn maybe_yield() {
+    let checks = YIELD_CHECKS.with(|c| {
+        let n = c.get() + 1;
+        c.set(n);
+        n
+    });
+    let budget = config().get_yield_budget();
+    if unlikely(budget != 0 && checks >= budget) && is_coroutine() {
+        yield_now();
+    }
+}
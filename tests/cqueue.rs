@@ -228,3 +228,93 @@ fn cqueue_loop() {
 
     assert_eq!(result, 50);
 }
+
+#[test]
+fn cqueue_dynamic_selectors() {
+    use may::sync::mpsc::channel;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Token {
+        Chan(usize),
+        Timer,
+    }
+
+    cqueue::typed_scope(|cqueue| {
+        for i in 0..100 {
+            let (tx, rx) = channel();
+            // a selector that never fires, removed later
+            let timer = cqueue_add_oneshot!(cqueue, Token::Timer, _ = coroutine::sleep(Duration::from_secs(10)) => {});
+            cqueue_add_oneshot!(cqueue, Token::Chan(i), v = rx.recv() => assert_eq!(v, Ok(i)));
+            assert_eq!(cqueue.len(), 2);
+
+            tx.send(i).unwrap();
+            match cqueue.poll(None) {
+                Ok(ev) => assert_eq!(ev.token, Token::Chan(i)),
+                Err(e) => panic!("poll error {:?}", e),
+            }
+
+            timer.remove();
+            match cqueue.poll(Some(Duration::from_millis(100))) {
+                Err(Finished) => {}
+                r => panic!("unexpected poll result {:?}", r),
+            }
+            assert!(cqueue.is_empty());
+        }
+    });
+}
+
+#[test]
+fn cqueue_selectable() {
+    use may::cqueue::Selectable;
+    use may::sync::mpsc::channel;
+
+    let (tx, mut rx) = channel();
+    let (done_tx, done_rx) = channel::<()>();
+    let mut h = go!(move || coroutine::sleep(Duration::from_millis(50)));
+    let mut ticker = may::time::interval(Duration::from_secs(10));
+    assert!(ticker.try_select().is_some());
+    assert!(rx.try_select().is_none());
+
+    cqueue::scope(|cqueue| {
+        cqueue.add_select(0, &mut rx, |v| assert_eq!(v, Ok(1)));
+        cqueue.add_select(1, &mut h, |_| {});
+        // the next tick is far away, removed by the drop of the cqueue
+        cqueue.add_select(2, &mut ticker, |_| unreachable!());
+        tx.send(1).unwrap();
+        assert_eq!(cqueue.poll(None).unwrap().token, 0);
+        assert_eq!(cqueue.poll(None).unwrap().token, 1);
+        drop(done_rx);
+    });
+    assert!(h.is_done());
+    h.join().unwrap();
+
+    // the sender is ready when the receiver is dropped
+    assert_eq!(done_tx.clone().try_select(), Some(()));
+    drop(tx);
+    assert_eq!(rx.try_select(), Some(Err(std::sync::mpsc::RecvError)));
+}
+
+#[test]
+fn cqueue_nested() {
+    use may::cqueue::Selectable;
+    use may::sync::mpsc::channel;
+
+    let (tx, rx) = channel();
+    cqueue::scope(|inner| {
+        let mut inner = inner;
+        assert!(matches!(inner.try_select(), Some(Err(Finished))));
+        cqueue_add_oneshot!(inner, 7, v = rx.recv() => assert_eq!(v, Ok(3)));
+        assert_eq!(inner.try_poll().unwrap_err(), Timeout);
+        cqueue::scope(|outer| {
+            outer.add_select(0, &mut inner, |ev| assert_eq!(ev.unwrap().token, 7));
+            go!(outer, 1, |es| {
+                coroutine::sleep(Duration::from_millis(10));
+                es.send(0);
+            });
+            assert_eq!(outer.poll(None).unwrap().token, 1);
+            tx.send(3).unwrap();
+            assert_eq!(outer.poll(None).unwrap().token, 0);
+        });
+        assert_eq!(inner.try_poll().unwrap_err(), Finished);
+    });
+}
@@ -50,6 +50,42 @@ fn cancel_coroutine() {
     }
 }
 
+#[test]
+fn join_on_complete() {
+    use std::sync::mpsc::channel;
+
+    let (tx, rx) = channel();
+    let tx1 = tx.clone();
+    go!(|| 42).on_complete(move |ret| tx1.send(ret.ok()).unwrap());
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Some(42));
+
+    // the panic is passed to the callback
+    let tx1 = tx.clone();
+    let j = go!(|| -> u32 { panic!("on_complete panic") });
+    j.on_complete(move |ret| {
+        let panic = ret.unwrap_err();
+        tx1.send(panic.downcast_ref::<&str>().map(|_| 0)).unwrap()
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Some(0));
+
+    // called right away if the coroutine is already done
+    let j = go!(|| 7);
+    j.wait();
+    let tx1 = tx.clone();
+    j.on_complete(move |ret| tx1.send(ret.ok()).unwrap());
+    assert_eq!(rx.try_recv().unwrap(), Some(7));
+
+    // the cancelled coroutine
+    let j = go!(|| {
+        coroutine::park();
+        1
+    });
+    thread::sleep(Duration::from_millis(10));
+    unsafe { j.coroutine().cancel() };
+    j.on_complete(move |ret| tx.send(ret.ok()).unwrap());
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), None);
+}
+
 #[test]
 #[cfg(feature = "io_cancel")]
 fn cancel_io_coroutine() {
@@ -82,6 +118,88 @@ fn cancel_io_coroutine() {
     }
 }
 
+#[test]
+#[cfg(all(feature = "io_cancel", feature = "io_timeout"))]
+fn cancel_io_with_timeout() {
+    use may::coroutine::catch_cancel;
+    use may::net::{TcpListener, TcpStream};
+    use std::io::{Read, Write};
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut peer = TcpStream::connect(addr).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    let h = go!(move || {
+        let mut stream = stream;
+        stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut buf = [0; 4];
+        assert!(catch_cancel(|| stream.read(&mut buf)).is_err());
+        stream
+    });
+    thread::sleep(Duration::from_millis(10));
+    unsafe { h.coroutine().cancel() };
+    let stream = h.join().unwrap();
+
+    // the timer of the cancelled read must not time out the next one
+    let h = go!(move || {
+        let mut stream = stream;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).map(|_| buf)
+    });
+    thread::sleep(Duration::from_millis(200));
+    peer.write_all(b"ping").unwrap();
+    assert_eq!(&h.join().unwrap().unwrap(), b"ping");
+}
+
+#[test]
+fn cancel_blocking_primitives() {
+    use may::coroutine::catch_cancel;
+    use may::sync::{mpmc, mpsc, Cancelled, Condvar, Mutex, RwLock};
+    use std::sync::Arc;
+
+    fn check<F: FnOnce() + Send + 'static>(f: F) {
+        let h = go!(move || catch_cancel(f));
+        thread::sleep(Duration::from_millis(10));
+        unsafe { h.coroutine().cancel() };
+        assert_eq!(h.join().unwrap(), Err(Cancelled));
+    }
+
+    let (tx, rx) = mpsc::channel::<()>();
+    check(move || {
+        let _ = rx.recv();
+    });
+    let (tx1, rx) = mpmc::channel::<()>();
+    check(move || {
+        let _ = rx.recv();
+    });
+
+    let m = Arc::new(Mutex::new(()));
+    let rw = Arc::new(RwLock::new(()));
+    let (g, w) = (m.lock().unwrap(), rw.write().unwrap());
+    let m1 = m.clone();
+    check(move || drop(m1.lock()));
+    let rw1 = rw.clone();
+    check(move || drop(rw1.read()));
+    drop((g, w));
+
+    let pair = Arc::new((Mutex::new(()), Condvar::new()));
+    let pair1 = pair.clone();
+    check(move || drop(pair1.1.wait(pair1.0.lock().unwrap())));
+    // the locks are released by the interrupted coroutines
+    assert!(m.try_lock().is_ok());
+    assert!(pair.0.try_lock().is_ok());
+
+    let sleeper = go!(|| coroutine::sleep(Duration::from_secs(100)));
+    check(move || sleeper.wait());
+    drop((tx, tx1));
+}
+
 #[test]
 fn one_coroutine() {
     let j = go!(move || {
@@ -241,6 +359,88 @@ fn unpark() {
     assert_eq!(a, 10);
 }
 
+#[test]
+fn unparker() {
+    let (tx, rx) = may::sync::mpsc::channel();
+    let h = go!(move || {
+        let unparker = coroutine::unparker();
+        // the token is consumed by the first park
+        unparker.unpark();
+        coroutine::park();
+        tx.send(unparker).unwrap();
+        coroutine::park();
+        10
+    });
+
+    let unparker = rx.recv().unwrap();
+    let other = unparker.clone();
+    thread::spawn(move || other.unpark()).join().unwrap();
+    assert_eq!(h.join().unwrap(), 10);
+}
+
+#[test]
+fn coroutine_metadata() {
+    let builder = coroutine::Builder::new()
+        .name("worker".to_owned())
+        .meta("request_id", "42")
+        .meta("user", "foo");
+    let h = unsafe {
+        builder
+            .spawn(|| {
+                let co = coroutine::current();
+                assert_eq!(co.name(), Some("worker"));
+                assert_eq!(co.meta("request_id"), Some("42"));
+                assert_eq!(co.meta("user"), Some("foo"));
+                assert_eq!(co.meta("none"), None);
+                co.id()
+            })
+            .unwrap()
+    };
+    let co = h.coroutine().clone();
+    let other = go!(|| {});
+    assert_ne!(co.id(), other.coroutine().id());
+    assert_eq!(co.metadata().len(), 2);
+    assert_eq!(h.join().unwrap(), co.id());
+    other.join().unwrap();
+}
+
+#[test]
+fn pin_to_worker() {
+    use std::rc::Rc;
+
+    let ret = unsafe { coroutine::Builder::new().pin_to_worker(!0).spawn(|| {}) };
+    assert_eq!(ret.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+    let h = unsafe {
+        coroutine::Builder::new()
+            .pin_to_worker(0)
+            .spawn(|| {
+                let id = thread::current().id();
+                let cnt = Rc::new(std::cell::Cell::new(0));
+                let hs: Vec<_> = (0..10)
+                    .map(|_| {
+                        let cnt = cnt.clone();
+                        coroutine::spawn_local(move || {
+                            for _ in 0..10 {
+                                yield_now();
+                                coroutine::sleep(Duration::from_millis(1));
+                                cnt.set(cnt.get() + 1);
+                            }
+                            thread::current().id()
+                        })
+                    })
+                    .collect();
+                for h in hs {
+                    assert_eq!(h.join().unwrap(), id);
+                }
+                assert_eq!(thread::current().id(), id);
+                cnt.get()
+            })
+            .unwrap()
+    };
+    assert_eq!(h.join().unwrap(), 100);
+}
+
 #[test]
 fn park_timeout() {
     let mut a = 0;
@@ -284,6 +484,238 @@ fn test_sleep() {
     });
 }
 
+#[test]
+fn join_timeout() {
+    use may::coroutine::JoinStatus;
+
+    let h = go!(|| {
+        coroutine::sleep(Duration::from_millis(100));
+        panic!("panic in coroutine");
+    });
+    assert!(!h.is_finished());
+    let h = match h.join_timeout(Duration::from_millis(10)) {
+        JoinStatus::TimedOut(h) => h,
+        s => panic!("unexpected status {:?}", s),
+    };
+    let hs = [h];
+    assert!(!coroutine::wait_all(&hs, Duration::from_millis(10)));
+    assert!(coroutine::wait_all(&hs, Duration::from_secs(10)));
+    let [h] = hs;
+    match h.join_timeout(Duration::from_millis(10)) {
+        JoinStatus::Panicked(e) => {
+            assert_eq!(e.downcast_ref::<&str>(), Some(&"panic in coroutine"))
+        }
+        s => panic!("unexpected status {:?}", s),
+    }
+}
+
+#[test]
+fn spawn_blocking() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    let h = go!(move || {
+        coroutine::spawn_blocking(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::SeqCst);
+            10
+        })
+    });
+    assert_eq!(h.join().unwrap(), 10);
+    assert!(done.load(Ordering::SeqCst));
+
+    let h = go!(|| coroutine::spawn_blocking(|| panic!("panic in blocking")));
+    let e = h.join().unwrap_err();
+    assert_eq!(e.downcast_ref::<&str>(), Some(&"panic in blocking"));
+}
+
+#[test]
+fn spawn_blocking_dependent() {
+    // each waiting job needs another thread for the job it waits for
+    for _ in 0..10 {
+        let mut waiters = Vec::new();
+        let mut senders = Vec::new();
+        for _ in 0..8 {
+            let (tx, rx) = std::sync::mpsc::channel();
+            waiters.push(go!(move || {
+                coroutine::spawn_blocking(move || rx.recv_timeout(Duration::from_secs(5)))
+            }));
+            senders.push(go!(move || coroutine::spawn_blocking(move || tx.send(()))));
+        }
+        for h in waiters {
+            assert_eq!(h.join().unwrap(), Ok(()));
+        }
+        for h in senders {
+            assert_eq!(h.join().unwrap(), Ok(()));
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "co_stats")]
+fn coroutine_stats() {
+    let h = go!(|| {
+        for _ in 0..10 {
+            coroutine::yield_now();
+        }
+        // busy loop for a while
+        let now = Instant::now();
+        while now.elapsed() < Duration::from_millis(20) {}
+        coroutine::yield_now();
+    });
+    let co = h.coroutine().clone();
+    h.join().unwrap();
+    let stats = co.stats();
+    assert_eq!(stats.yields, 11);
+    assert!(stats.cpu_time >= Duration::from_millis(20));
+}
+
+#[test]
+#[cfg(feature = "leak_detect")]
+fn coroutine_leaked() {
+    let line = line!() + 1;
+    let h = go!(coroutine::park);
+    let id = h.coroutine().id();
+    thread::sleep(Duration::from_millis(20));
+    let leaked = coroutine::leaked(Duration::from_millis(10));
+    let co = leaked.iter().find(|co| co.id() == id).unwrap();
+    assert_eq!(co.spawn_location().file(), file!());
+    assert_eq!(co.spawn_location().line(), line);
+    assert!(co.spawned_at().elapsed() >= Duration::from_millis(20));
+    // the young ones are not reported
+    assert!(coroutine::leaked(Duration::from_secs(3600)).is_empty());
+
+    h.coroutine().unpark();
+    h.join().unwrap();
+    assert!(coroutine::leaked(Duration::ZERO)
+        .iter()
+        .all(|co| co.id() != id));
+}
+
+#[test]
+#[cfg(feature = "tracing")]
+fn tracing_span_switch() {
+    use tracing::Span;
+
+    let _ = tracing::subscriber::set_global_default(tracing_subscriber::registry());
+    let hs: Vec<_> = (0..4)
+        .map(|i| {
+            go!(move || {
+                let root = Span::current();
+                assert_eq!(root.metadata().unwrap().name(), "coroutine");
+                let span = tracing::info_span!("request", i);
+                let guard = span.enter();
+                for _ in 0..10 {
+                    coroutine::yield_now();
+                    assert_eq!(Span::current().id(), span.id());
+                }
+                drop(guard);
+                assert_eq!(Span::current().id(), root.id());
+            })
+        })
+        .collect();
+    for h in hs {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn panic_policy_restart() {
+    use may::PanicPolicy;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    // the service fails twice before it's done
+    fn start(runs: Arc<AtomicUsize>, tx: mpsc::Sender<usize>) {
+        let (r, t) = (runs.clone(), tx.clone());
+        let policy = PanicPolicy::restart(move |_, _| start(r.clone(), t.clone()));
+        let builder = coroutine::Builder::new().panic_policy(policy);
+        unsafe {
+            builder
+                .spawn(move || {
+                    let n = runs.fetch_add(1, Ordering::SeqCst) + 1;
+                    if n < 3 {
+                        panic!("service failed");
+                    }
+                    tx.send(n).unwrap();
+                })
+                .unwrap();
+        }
+    }
+
+    let (tx, rx) = mpsc::channel();
+    start(Arc::new(AtomicUsize::new(0)), tx);
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(3));
+}
+
+#[test]
+fn join_set_abort() {
+    use may::coroutine::JoinSet;
+
+    let mut set = JoinSet::new();
+    for i in 0..10u64 {
+        unsafe {
+            set.spawn(move || {
+                if i % 2 == 1 {
+                    coroutine::sleep(Duration::from_secs(100));
+                }
+                i
+            })
+        };
+    }
+    assert_eq!(set.len(), 10);
+
+    // the quick ones are finished first
+    let mut done = Vec::new();
+    for _ in 0..5 {
+        done.push(set.join_next().unwrap().unwrap());
+    }
+    done.sort();
+    assert_eq!(done, [0, 2, 4, 6, 8]);
+    assert!(set.try_join_next().is_none());
+
+    set.abort_all();
+    let mut cancelled = 0;
+    while let Some(ret) = set.join_next() {
+        assert!(ret.is_err());
+        cancelled += 1;
+    }
+    assert_eq!(cancelled, 5);
+    assert!(set.is_empty());
+}
+
+#[test]
+fn global_queue_fairness() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // keep all the workers busy with the coroutines that yield in a loop
+    let stop = Arc::new(AtomicBool::new(false));
+    let spinners: Vec<_> = (0..may::config().get_workers() * 2)
+        .map(|_| {
+            let stop = stop.clone();
+            go!(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    yield_now();
+                }
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(10));
+
+    // the coroutine sent by a thread is not starved
+    let h = go!(|| 42);
+    let finished = h.wait_timeout(Duration::from_secs(5));
+    stop.store(true, Ordering::Relaxed);
+    for s in spinners {
+        s.join().unwrap();
+    }
+    assert!(finished);
+    assert_eq!(h.join().unwrap(), 42);
+}
+
 #[test]
 fn join_macro() {
     use may::sync::mpsc::channel;
@@ -346,3 +778,773 @@ fn go_with_macro() {
         assert_eq!(stack_size, 10240);
     }
 }
+
+#[test]
+#[cfg(unix)]
+fn foreign_io_register() {
+    use may::io::{AsIoData, IoData, WaitIo};
+    use std::io::{self, Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    struct Conn(IoData, UnixStream);
+
+    impl AsIoData for Conn {
+        fn as_io_data(&self) -> &IoData {
+            &self.0
+        }
+    }
+
+    let (a, mut b) = UnixStream::pair().unwrap();
+    a.set_nonblocking(true).unwrap();
+    let mut conn = Conn(IoData::register(&a).unwrap(), a);
+    let h = go!(move || {
+        let mut data = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            conn.reset_io();
+            match conn.1.read(&mut buf) {
+                Ok(0) => return data,
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => conn.wait_io(),
+                Err(e) => panic!("read error: {}", e),
+            }
+        }
+    });
+
+    for i in 0..3u8 {
+        thread::sleep(Duration::from_millis(10));
+        b.write_all(&[i; 4]).unwrap();
+    }
+    drop(b);
+    assert_eq!(h.join().unwrap(), [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2]);
+}
+
+#[test]
+#[cfg(unix)]
+fn tcp_bind_reuseport() {
+    use may::net::{TcpListener, TcpStream};
+    use may::sync::mpsc::channel;
+
+    let listeners = TcpListener::bind_reuseport("127.0.0.1:0", 2).unwrap();
+    assert_eq!(listeners.len(), 2);
+    let addr = listeners[0].local_addr().unwrap();
+    assert_eq!(listeners[1].local_addr().unwrap(), addr);
+
+    let (tx, rx) = channel();
+    for listener in listeners {
+        let tx = tx.clone();
+        go!(move || {
+            for stream in listener.incoming() {
+                tx.send(stream.is_ok()).unwrap();
+            }
+        });
+    }
+
+    let streams: Vec<_> = (0..8).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for _ in 0..streams.len() {
+        assert!(rx.recv().unwrap());
+    }
+}
+
+#[test]
+fn tcp_accept_exclusive() {
+    use may::net::{TcpListener, TcpStream};
+    use may::sync::mpsc::channel;
+
+    may::config().set_accept_exclusive(true);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let listeners = vec![listener.try_clone().unwrap(), listener];
+    may::config().set_accept_exclusive(false);
+    let addr = listeners[0].local_addr().unwrap();
+
+    let (tx, rx) = channel();
+    for listener in listeners {
+        let tx = tx.clone();
+        go!(move || {
+            for stream in listener.incoming() {
+                tx.send(stream.is_ok()).unwrap();
+            }
+        });
+    }
+
+    let streams: Vec<_> = (0..8).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for _ in 0..streams.len() {
+        assert!(rx.recv().unwrap());
+    }
+}
+
+#[test]
+fn udp_recv_send_multiple() {
+    use may::net::{MsgBuf, UdpSocket};
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let h = go!(move || {
+        let mut bufs = vec![MsgBuf::new(64); 16];
+        let mut received = Vec::new();
+        while received.len() < 100 {
+            let n = server.recv_multiple(&mut bufs).unwrap();
+            received.extend(bufs[..n].iter().map(|m| m.data()[0]));
+        }
+        received
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(addr).unwrap();
+    let msgs: Vec<_> = (0..100u8).map(|i| MsgBuf::with_data(&[i], None)).collect();
+    let mut sent = 0;
+    while sent < msgs.len() {
+        sent += client.send_multiple(&msgs[sent..]).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(h.join().unwrap(), (0..100).collect::<Vec<u8>>());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn udp_segment_offload() {
+    use may::net::UdpSocket;
+
+    let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    rx.set_gro(true).unwrap();
+    assert!(rx.gro().unwrap());
+    let addr = rx.local_addr().unwrap();
+
+    let h = go!(move || {
+        let mut buf = vec![0; 4096];
+        let mut data = Vec::new();
+        while data.len() < 300 {
+            let (n, _, segment) = rx.recv_gro(&mut buf).unwrap();
+            assert!(segment <= 100);
+            data.extend_from_slice(&buf[..n]);
+        }
+        data
+    });
+
+    let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    tx.connect(addr).unwrap();
+    tx.set_segment_size(100).unwrap();
+    assert_eq!(tx.segment_size().unwrap(), 100);
+    // split into 3 datagrams by the kernel
+    let payload: Vec<u8> = (0..300).map(|i| (i / 100) as u8).collect();
+    assert_eq!(tx.send(&payload).unwrap(), 300);
+    assert_eq!(h.join().unwrap(), payload);
+}
+
+#[test]
+fn tcp_serve_max_conns() {
+    use may::net::{Server, TcpListener, TcpStream};
+    use may::sync::CancellationToken;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let token = CancellationToken::new();
+    let active = Arc::new(AtomicUsize::new(0));
+    let max_active = Arc::new(AtomicUsize::new(0));
+
+    let server = Server::new()
+        .max_conns(2)
+        .stack_size(0x2000)
+        .shutdown(token.clone());
+    let (their_active, their_max) = (active.clone(), max_active.clone());
+    let h = go!(move || {
+        server.serve(listener, move |_s| {
+            let n = their_active.fetch_add(1, Ordering::SeqCst) + 1;
+            their_max.fetch_max(n, Ordering::SeqCst);
+            coroutine::sleep(Duration::from_millis(20));
+            their_active.fetch_sub(1, Ordering::SeqCst);
+        })
+    });
+
+    let clients: Vec<_> = (0..6)
+        .map(|_| {
+            go!(move || {
+                let mut s = TcpStream::connect(addr).unwrap();
+                // wait the server to close the connection
+                let mut buf = Vec::new();
+                s.read_to_end(&mut buf).unwrap();
+            })
+        })
+        .collect();
+    for c in clients {
+        c.join().unwrap();
+    }
+
+    token.cancel();
+    h.join().unwrap().unwrap();
+    assert_eq!(max_active.load(Ordering::SeqCst), 2);
+    assert_eq!(active.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn tcp_half_close() {
+    use may::io::SplitIo;
+    use may::net::{TcpListener, TcpStream};
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // echo server that replies after the request is done
+    let server = go!(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        let mut req = Vec::new();
+        s.read_to_end(&mut req).unwrap();
+        assert!(s.read_closed());
+        assert!(!s.write_closed());
+        s.write_all(&req).unwrap();
+        s.shutdown(Shutdown::Write).unwrap();
+    });
+
+    let client = go!(move || {
+        let s = TcpStream::connect(addr).unwrap();
+        let (mut r, mut w) = s.split().unwrap();
+        w.write_all(b"hello").unwrap();
+        w.inner().shutdown(Shutdown::Write).unwrap();
+        // the state is shared by the split halves
+        assert!(r.inner().write_closed());
+        assert!(!r.inner().read_closed());
+
+        let mut rsp = Vec::new();
+        r.read_to_end(&mut rsp).unwrap();
+        assert!(r.inner().read_closed());
+        rsp
+    });
+
+    assert_eq!(client.join().unwrap(), b"hello");
+    server.join().unwrap();
+}
+
+#[test]
+fn tcp_into_split() {
+    use may::net::{TcpListener, TcpStream};
+    use std::io::{Read, Write};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = go!(move || {
+        let (s, _) = listener.accept().unwrap();
+        let (mut r, mut w) = s.into_split().unwrap();
+        // echo until the peer shuts down the write half
+        std::io::copy(&mut r, &mut w).unwrap();
+    });
+
+    // big enough to block both the writer and the reader
+    let data = vec![7u8; 4 << 20];
+    let mut s = TcpStream::connect(addr).unwrap();
+    let echo = coroutine::scope(|scope| {
+        let (mut r, mut w) = s.split().unwrap();
+        let data = &data;
+        go!(scope, move || w.write_all(data).unwrap());
+        let mut echo = vec![0; data.len()];
+        r.read_exact(&mut echo).unwrap();
+        echo
+    });
+    assert_eq!(echo, data);
+
+    // the stream works after the borrowed halves are dropped
+    s.write_all(b"hello").unwrap();
+    let (mut r, w) = s.into_split().unwrap();
+    drop(w);
+    let mut rest = Vec::new();
+    r.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"hello");
+    server.join().unwrap();
+}
+
+#[test]
+fn tcp_shutdown_wake_reader() {
+    use may::net::{TcpListener, TcpStream};
+    use std::io::Read;
+    use std::net::Shutdown;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _peer = TcpStream::connect(addr).unwrap();
+    let (s, _) = listener.accept().unwrap();
+
+    let mut reader = s.try_clone().unwrap();
+    let h = go!(move || {
+        let mut buf = [0; 16];
+        let n = reader.read(&mut buf).unwrap();
+        (n, reader.read_closed())
+    });
+
+    coroutine::sleep(Duration::from_millis(50));
+    s.shutdown(Shutdown::Read).unwrap();
+    assert_eq!(h.join().unwrap(), (0, true));
+    assert!(s.read_closed());
+}
+
+#[test]
+#[cfg(unix)]
+fn tcp_peer_hung_up() {
+    use may::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let peer = TcpStream::connect(addr).unwrap();
+    let (s, _) = listener.accept().unwrap();
+    assert!(!s.peer_hung_up());
+
+    // no io is needed to find out the idle connection is gone
+    drop(peer);
+    let mut n = 0;
+    while !s.peer_hung_up() && n < 100 {
+        coroutine::sleep(Duration::from_millis(10));
+        n += 1;
+    }
+    assert!(s.peer_hung_up());
+}
+
+#[test]
+#[cfg(all(unix, feature = "io_timeout"))]
+fn tcp_timeout_op() {
+    use may::io::TimeoutIo;
+    use may::net::{TcpListener, TcpStream};
+    use std::io::{ErrorKind, Read, Write};
+
+    let h = go!(|| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let err = listener
+            .accept_timeout_op(Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        let now = Instant::now();
+        let err = server
+            .read_timeout_op(&mut buf, Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(now.elapsed() >= Duration::from_millis(20));
+        // the socket-wide timeout is not changed
+        assert_eq!(server.read_timeout().unwrap(), None);
+
+        // the deadline is shared by the reads of read_exact
+        client.write_all(b"ab").unwrap();
+        let err = server
+            .timeout(Duration::from_millis(20))
+            .read_exact(&mut buf)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        client.write_all(b"cdef").unwrap();
+        server
+            .timeout(Duration::from_secs(1))
+            .read_exact(&mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"cdef");
+    });
+    h.join().unwrap();
+}
+
+#[test]
+fn runtime_isolated() {
+    use may::sync::mpsc::channel;
+    use may::{Runtime, RuntimeConfig};
+
+    let rt = Runtime::new(RuntimeConfig::new().workers(1));
+    assert_eq!(rt.workers(), 1);
+
+    let (tx, rx) = channel();
+    let h = unsafe {
+        rt.spawn(move || {
+            let outer = thread::current().id();
+            let worker = || go!(|| thread::current().id()).join().unwrap();
+            // the nested coroutines stay in the runtime with a single worker
+            let inner = worker();
+            coroutine::sleep(Duration::from_millis(10));
+            assert_eq!(worker(), outer);
+            // woken up by a coroutine of the global scheduler
+            let v: i32 = rx.recv().unwrap();
+            assert_eq!(worker(), outer);
+            (outer, inner, v)
+        })
+    };
+
+    let global = go!(move || {
+        coroutine::sleep(Duration::from_millis(20));
+        tx.send(42).unwrap();
+        thread::current().id()
+    });
+    let (outer, inner, v) = h.join().unwrap();
+    assert_eq!(inner, outer);
+    assert_eq!(v, 42);
+    assert_ne!(global.join().unwrap(), outer);
+}
+
+#[test]
+fn runtime_set_workers() {
+    use may::{Runtime, RuntimeConfig};
+    use std::collections::HashSet;
+
+    // the ids of the threads that run the blocking coroutines
+    fn run_threads(rt: &Runtime) -> HashSet<thread::ThreadId> {
+        let hs: Vec<_> = (0..8)
+            .map(|_| unsafe {
+                rt.spawn(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    thread::current().id()
+                })
+            })
+            .collect();
+        hs.into_iter().map(|h| h.join().unwrap()).collect()
+    }
+
+    let rt = Runtime::new(RuntimeConfig::new().workers(1).max_workers(4));
+    assert_eq!(rt.workers(), 1);
+    assert_eq!(run_threads(&rt).len(), 1);
+
+    rt.set_workers(4);
+    assert_eq!(rt.workers(), 4);
+    assert!(run_threads(&rt).len() > 1);
+
+    // a coroutine waiting on a timer keeps running after its worker retires
+    let h = unsafe { rt.spawn(|| coroutine::sleep(Duration::from_millis(50))) };
+    rt.set_workers(1);
+    assert_eq!(run_threads(&rt).len(), 1);
+    h.join().unwrap();
+
+    rt.set_workers(100);
+    assert_eq!(rt.workers(), 4);
+}
+
+#[test]
+fn runtime_autoscale() {
+    use may::{AutoscaleConfig, Runtime, RuntimeConfig};
+    use std::time::Instant;
+
+    let autoscale = AutoscaleConfig::new(Duration::from_millis(5))
+        .interval(Duration::from_millis(10))
+        .scale_down_after(Duration::from_millis(50));
+    let rt = Runtime::new(
+        RuntimeConfig::new()
+            .workers(1)
+            .max_workers(4)
+            .autoscale(autoscale),
+    );
+
+    // the worker is hogged, the probes are late
+    let h = unsafe {
+        rt.spawn(|| {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_millis(300) {}
+        })
+    };
+    thread::sleep(Duration::from_millis(200));
+    assert!(rt.workers() > 1);
+    h.join().unwrap();
+
+    // the idle workers are retired one by one
+    let start = Instant::now();
+    while rt.workers() > 1 && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(rt.workers(), 1);
+}
+
+#[cfg(feature = "io_timeout")]
+#[test]
+fn high_res_timer() {
+    use may::net::UdpSocket;
+    use may::{Runtime, RuntimeConfig};
+
+    // only the pollers of the new runtime use the kernel timers
+    may::config().set_high_res_timer(true);
+    let rt = Runtime::new(RuntimeConfig::new().workers(1));
+    may::config().set_high_res_timer(false);
+
+    let h = unsafe {
+        rt.spawn(|| {
+            let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let timeout = Duration::from_micros(2500);
+            sock.set_read_timeout(Some(timeout)).unwrap();
+            let mut buf = [0u8; 8];
+            for _ in 0..5 {
+                let start = Instant::now();
+                let err = sock.recv(&mut buf).unwrap_err();
+                assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+                assert!(start.elapsed() >= timeout);
+            }
+        })
+    };
+    h.join().unwrap();
+}
+
+#[test]
+fn cancel_graceful() {
+    use may::coroutine::{cancel_safe, is_cancel_requested};
+    use may::sync::Cancelled;
+
+    // the request only takes effect within the cancel safe section
+    let (tx, rx) = may::sync::mpsc::channel::<()>();
+    let h = go!(move || {
+        rx.recv().unwrap();
+        assert!(is_cancel_requested());
+        // the pending request stops the next section right away
+        let ret = cancel_safe(|| unreachable!());
+        assert_eq!(ret, Err::<(), _>(Cancelled));
+        cancel_safe(|| coroutine::sleep(Duration::from_secs(100)))
+    });
+    h.cancel_graceful();
+    assert!(h.coroutine().is_cancel_requested());
+    tx.send(()).unwrap();
+    assert_eq!(h.join().unwrap(), Err(Cancelled));
+
+    // a coroutine that never enters a section runs to completion
+    let h = go!(|| {
+        coroutine::sleep(Duration::from_millis(20));
+        is_cancel_requested()
+    });
+    thread::sleep(Duration::from_millis(5));
+    h.cancel_graceful();
+    assert!(h.join().unwrap());
+    assert!(!is_cancel_requested());
+}
+
+#[test]
+fn poll_batch() {
+    use may::net::UdpSocket;
+    use may::{Runtime, RuntimeConfig};
+
+    // the runtime polls one event at a time
+    may::config().set_poll_batch(1);
+    let rt = Runtime::new(RuntimeConfig::new().workers(1));
+
+    let h = unsafe {
+        rt.spawn(|| {
+            let socks: Vec<_> = (0..4)
+                .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+                .collect();
+            let readers: Vec<_> = socks
+                .iter()
+                .map(|s| {
+                    let s = s.try_clone().unwrap();
+                    go!(move || {
+                        let mut buf = [0u8; 8];
+                        s.recv(&mut buf).unwrap()
+                    })
+                })
+                .collect();
+            coroutine::sleep(Duration::from_millis(10));
+            // all the sockets are ready at the same time
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for s in &socks {
+                sender.send_to(b"ping", s.local_addr().unwrap()).unwrap();
+            }
+            readers
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .sum::<usize>()
+        })
+    };
+    assert_eq!(h.join().unwrap(), 16);
+
+    may::config().set_poll_batch(0);
+    assert_eq!(may::config().get_poll_batch(), 128);
+}
+
+#[test]
+fn tcp_connect_proxy() {
+    use may::net::proxy::{Proxy, Socks5Connector};
+    use may::net::TcpListener;
+    use std::io::{Read, Write};
+
+    let socks = TcpListener::bind("127.0.0.1:0").unwrap();
+    let socks_addr = socks.local_addr().unwrap();
+    let http = TcpListener::bind("127.0.0.1:0").unwrap();
+    let http_addr = http.local_addr().unwrap();
+
+    // a socks5 proxy that echoes the tunnel data
+    let socks_server = go!(move || {
+        let (mut s, _) = socks.accept().unwrap();
+        let mut buf = [0u8; 3];
+        s.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [5, 1, 0]);
+        s.write_all(&[5, 0]).unwrap();
+        let mut head = [0u8; 5];
+        s.read_exact(&mut head).unwrap();
+        assert_eq!(head[..4], [5, 1, 0, 3]);
+        let mut host = vec![0u8; head[4] as usize + 2];
+        s.read_exact(&mut host).unwrap();
+        assert_eq!(host, b"example.test\0\x50");
+        s.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80]).unwrap();
+        let mut data = [0u8; 4];
+        s.read_exact(&mut data).unwrap();
+        s.write_all(&data).unwrap();
+    });
+
+    // an http proxy that echoes the tunnel data
+    let http_server = go!(move || {
+        let (mut s, _) = http.accept().unwrap();
+        let mut req = Vec::new();
+        let mut b = [0u8; 1];
+        while !req.ends_with(b"\r\n\r\n") {
+            s.read_exact(&mut b).unwrap();
+            req.push(b[0]);
+        }
+        let req = String::from_utf8(req).unwrap();
+        assert!(req.starts_with("CONNECT example.test:443 HTTP/1.1\r\n"));
+        assert!(req.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+        s.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .unwrap();
+        let mut data = [0u8; 4];
+        s.read_exact(&mut data).unwrap();
+        s.write_all(&data).unwrap();
+    });
+
+    let mut s = Socks5Connector::new(socks_addr)
+        .unwrap()
+        .connect("example.test", 80)
+        .unwrap();
+    s.write_all(b"ping").unwrap();
+    let mut buf = [0u8; 4];
+    s.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    let url = format!("http://user:pass@{http_addr}");
+    let proxy = Proxy::from_url(&url).unwrap();
+    assert!(matches!(proxy, Proxy::Http(_)));
+    let mut s = proxy.connect("example.test", 443).unwrap();
+    s.write_all(b"pong").unwrap();
+    s.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+
+    socks_server.join().unwrap();
+    http_server.join().unwrap();
+}
+
+#[test]
+fn tcp_bind_all() {
+    use may::net::{TcpListenerBuilder, TcpStream};
+    use std::net::SocketAddr;
+
+    let addrs: [SocketAddr; 2] = ["127.0.0.1:0".parse().unwrap(); 2];
+    let listener = TcpListenerBuilder::new().bind_all(&addrs[..]).unwrap();
+    let local = listener.local_addrs().unwrap();
+    assert_eq!(local.len(), 2);
+
+    // connect to both of them at the same time
+    let _a = TcpStream::connect(local[0]).unwrap();
+    let _b = TcpStream::connect(local[1]).unwrap();
+    let mut ports: Vec<_> = (0..2)
+        .map(|_| listener.accept().unwrap().0.local_addr().unwrap().port())
+        .collect();
+    ports.sort_unstable();
+    let mut expected: Vec<_> = local.iter().map(|a| a.port()).collect();
+    expected.sort_unstable();
+    assert_eq!(ports, expected);
+
+    // the dual-stack listener accepts the ipv4 connections
+    if let Ok(l) = TcpListenerBuilder::new().only_v6(false).bind("[::]:0") {
+        let port = l.local_addr().unwrap().port();
+        let _c = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        l.accept().unwrap();
+    }
+}
+
+#[may::cooperative]
+fn spin_until(flag: &std::sync::atomic::AtomicBool) -> bool {
+    use std::sync::atomic::Ordering;
+    // bounded in case the other coroutine never runs
+    for _ in 0..100_000_000u64 {
+        if flag.load(Ordering::Acquire) {
+            return true;
+        }
+    }
+    false
+}
+
+#[test]
+fn cooperative_loop() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let flag = Arc::new(AtomicBool::new(false));
+    // both run on the same worker, the spinning one must yield
+    let f = flag.clone();
+    let spinner = unsafe {
+        coroutine::Builder::new()
+            .pin_to_worker(0)
+            .spawn(move || spin_until(&f))
+            .unwrap()
+    };
+    let f = flag.clone();
+    let setter = unsafe {
+        coroutine::Builder::new()
+            .pin_to_worker(0)
+            .spawn(move || {
+                let mut n = 0u64;
+                for i in 0..10_000 {
+                    n += i;
+                    maybe_yield!();
+                }
+                f.store(true, Ordering::Release);
+                n
+            })
+            .unwrap()
+    };
+    assert_eq!(setter.join().unwrap(), 49_995_000);
+    assert!(spinner.join().unwrap());
+}
+
+#[test]
+fn wake_order() {
+    use may::sync::mpsc::channel;
+    use may::{Runtime, RuntimeConfig, WakeOrder};
+    use std::sync::{Arc, Mutex};
+
+    // the order that a woken coroutine and a queued one run on one worker
+    fn run_order(order: WakeOrder) -> Vec<&'static str> {
+        let rt = Runtime::new(RuntimeConfig::new().workers(1));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let l = log.clone();
+        let h = unsafe {
+            rt.spawn(move || {
+                let (tx1, rx1) = channel();
+                let (tx2, rx2) = channel();
+                let (ready_tx, ready_rx) = channel();
+                let (l1, r1) = (l.clone(), ready_tx.clone());
+                let queued = go!(move || {
+                    r1.send(()).unwrap();
+                    rx1.recv().unwrap();
+                    l1.lock().unwrap().push("queued");
+                });
+                let l2 = l.clone();
+                let waiter = coroutine::Builder::new()
+                    .wake_order(order)
+                    .spawn(move || {
+                        ready_tx.send(()).unwrap();
+                        rx2.recv().unwrap();
+                        l2.lock().unwrap().push("woken");
+                    })
+                    .unwrap();
+                // both of them block on the channels right after the ready
+                // message, there is no other worker to run this one before
+                ready_rx.recv().unwrap();
+                ready_rx.recv().unwrap();
+                // the first one is queued in the local queue of the worker
+                tx1.send(()).unwrap();
+                tx2.send(()).unwrap();
+                (waiter, queued)
+            })
+        };
+        let (waiter, queued) = h.join().unwrap();
+        waiter.join().unwrap();
+        queued.join().unwrap();
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    assert_eq!(run_order(WakeOrder::Fifo), ["queued", "woken"]);
+    assert_eq!(run_order(WakeOrder::Lifo), ["woken", "queued"]);
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "mock_clock")]
+#[macro_use]
+extern crate may;
+
+use std::time::Duration;
+
+use may::coroutine;
+use may::sync::mpsc::channel;
+use may::time::{self, MockClock};
+
+// the clock is global, so all the checks are in one test
+#[test]
+#[allow(clippy::manual_is_multiple_of)]
+fn mock_clock_timers() {
+    let clock = MockClock::new();
+    time::set_clock(clock.clone());
+    let start = time::now();
+
+    let (tx, rx) = channel::<u32>();
+    let sleeper = go!(|| coroutine::sleep(Duration::from_secs(3600)));
+    let receiver = go!(move || rx.recv_timeout(Duration::from_secs(60)).is_err());
+    let ticker = go!(move || {
+        let mut interval = time::interval_at(start, Duration::from_secs(10));
+        let mut last = interval.tick();
+        for _ in 0..3 {
+            last = interval.tick();
+        }
+        last
+    });
+
+    let real = std::time::Instant::now();
+    while !(sleeper.is_done() && receiver.is_done() && ticker.is_done()) {
+        clock.advance(Duration::from_secs(1));
+        std::thread::yield_now();
+    }
+    // the real time is not waited
+    assert!(real.elapsed() < Duration::from_secs(60));
+    assert!(time::now() - start >= Duration::from_secs(3600));
+    sleeper.join().unwrap();
+    assert!(receiver.join().unwrap());
+    // the missed ticks may be skipped, but they are still on schedule
+    let last = (ticker.join().unwrap() - start).as_secs();
+    assert!(last >= 30 && last % 10 == 0);
+    drop(tx);
+}